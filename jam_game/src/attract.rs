@@ -0,0 +1,80 @@
+use std::f64::consts::PI;
+
+use quicksilver::geom::Vector;
+
+// Seconds without any input before the game starts showing itself off
+pub const IDLE_SECONDS: f64 = 90.0;
+
+// Showcase world is always the same one, a base built from this seed
+pub const SHOWCASE_SEED: u64 = 2019;
+pub const SHOWCASE_STRUCTURES: usize = 60;
+
+// One slow lap around the showcase base
+const LAP_SECONDS: f64 = 120.0;
+// Far enough out that the edge of the base and the rock around it drift through the view
+const LAP_RADIUS: f64 = 30.0;
+
+// Counts up between inputs, events only mark that something happened so update can decide what it means
+#[derive(Default)]
+pub struct IdleTimer {
+    idle: f64,
+    had_input: bool
+}
+
+impl IdleTimer {
+    pub fn new() -> IdleTimer {
+        IdleTimer::default()
+    }
+
+    pub fn note_input(&mut self) {
+        self.had_input = true;
+    }
+
+    // True if there's been input since the last call, and starts the count again if so
+    pub fn take_input(&mut self) -> bool {
+        let had_input = self.had_input;
+        if had_input {
+            self.idle = 0.0;
+            self.had_input = false;
+        }
+        had_input
+    }
+
+    // True once the player has been away long enough
+    pub fn advance(&mut self, delta_time: f64) -> bool {
+        self.idle += delta_time;
+        self.idle >= IDLE_SECONDS
+    }
+}
+
+// Camera path while attract mode is showing, along with what to put back once the player returns
+pub struct AttractMode {
+    elapsed: f64,
+    center: Vector,
+    pub return_level: usize,
+    pub return_camera_position: Vector,
+    pub return_camera_height: f32
+}
+
+impl AttractMode {
+    pub fn new(center: Vector, return_level: usize, return_camera_position: Vector, return_camera_height: f32) -> AttractMode {
+        AttractMode { elapsed: 0.0, center, return_level, return_camera_position, return_camera_height }
+    }
+
+    // Real time rather than a fixed step like benchmarks, this only has to look smooth
+    pub fn advance(&mut self, delta_time: f64) {
+        self.elapsed += delta_time;
+    }
+
+    // World position the camera should be centered on, an ellipse around the base so it never sits still
+    pub fn camera_focus(&self) -> Vector {
+        let phase = 2.0 * PI * self.elapsed / LAP_SECONDS;
+        self.center + Vector::new((LAP_RADIUS * phase.cos()) as f32, (LAP_RADIUS * 0.6 * phase.sin()) as f32)
+    }
+
+    // Drifts in and out twice a lap
+    pub fn camera_height(&self) -> f32 {
+        let phase = 4.0 * PI * self.elapsed / LAP_SECONDS;
+        (22.0 - 8.0 * phase.cos()) as f32
+    }
+}
@@ -0,0 +1,125 @@
+use std::f64::consts::PI;
+use std::time::{Duration, Instant};
+
+use tilemap::tile_world::{
+    TileMap, TileValue, GridCoord
+};
+
+use quicksilver::geom::Vector;
+
+// Simulation step used while benchmarking, fixed so every run covers exactly the same path regardless of frame rate
+pub const BENCHMARK_TIME_STEP: f64 = 1.0 / 60.0;
+
+// The canned base is a square grid of hab modules with single tile corridors between them
+const BASE_MODULES_PER_SIDE: i64 = 24;
+const BASE_MODULE_SPACING: i64 = 4;
+
+#[derive(Default)]
+pub struct BenchmarkRun {
+    // Length of the scripted camera fly-through in simulated seconds, the path is stretched to fit
+    duration: f64,
+    elapsed: f64,
+    last_frame: Option<Instant>,
+    frame_times: Vec<Duration>,
+    // Kept as a list rather than a map so the report comes out in the order systems first ran
+    system_times: Vec<(&'static str, Vec<Duration>)>
+}
+
+impl BenchmarkRun {
+    pub fn new(duration: f64) -> BenchmarkRun {
+        BenchmarkRun { duration, ..BenchmarkRun::default() }
+    }
+
+    // Lays out the same large base every time, centered on the origin
+    pub fn build_base(world: &mut TileMap) {
+        let base_extent = BASE_MODULES_PER_SIDE * BASE_MODULE_SPACING;
+        let base_min = -base_extent / 2;
+
+        world.set_area(&GridCoord{x: base_min, y: base_min}, &GridCoord{x: base_extent, y: base_extent}, TileValue::Empty);
+
+        for module_y in 0..BASE_MODULES_PER_SIDE {
+            for module_x in 0..BASE_MODULES_PER_SIDE {
+                // Hab modules are placed by their center tile
+                let x = base_min + module_x * BASE_MODULE_SPACING + 1;
+                let y = base_min + module_y * BASE_MODULE_SPACING + 1;
+                world.make_change(&GridCoord{x, y}, &TileValue::HabModule);
+            }
+        }
+    }
+
+    // Moves the scripted run along by one fixed step, returns false once the run is over
+    pub fn advance(&mut self) -> bool {
+        self.elapsed += BENCHMARK_TIME_STEP;
+        !self.is_finished()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+
+    // World position the camera should be centered on at this point of the run
+    // Traces a figure eight across the base so both dense and untouched partitions get drawn
+    pub fn camera_focus(&self) -> Vector {
+        let radius = (BASE_MODULES_PER_SIDE * BASE_MODULE_SPACING) as f64 * 0.75;
+        let phase = 2.0 * PI * self.elapsed / self.duration;
+        Vector::new((radius * phase.cos()) as f32, (radius * 0.5 * (2.0 * phase).sin()) as f32)
+    }
+
+    // Zooms out to the widest view halfway through the run and back in again
+    pub fn camera_height(&self) -> f32 {
+        let phase = 2.0 * PI * self.elapsed / self.duration;
+        (25.0 - 15.0 * phase.cos()) as f32
+    }
+
+    // Call once per drawn frame
+    pub fn record_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame {
+            self.frame_times.push(now - last_frame);
+        }
+        self.last_frame = Some(now);
+    }
+
+    pub fn record_system(&mut self, name: &'static str, duration: Duration) {
+        match self.system_times.iter_mut().find(|(system_name, _)| *system_name == name) {
+            Some((_, times)) => times.push(duration),
+            None => self.system_times.push((name, vec![duration]))
+        }
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+
+        report.push_str(&format!("Benchmark finished: {} frames over {} simulated seconds\n", self.frame_times.len(), self.duration));
+
+        let mut sorted_frames = self.frame_times.clone();
+        sorted_frames.sort();
+        report.push_str(&format!("Frame time avg: {:.3}ms\n", average_ms(&sorted_frames)));
+        for percentile in [50.0, 95.0, 99.0].iter() {
+            report.push_str(&format!("Frame time p{}: {:.3}ms\n", percentile, percentile_ms(&sorted_frames, *percentile)));
+        }
+        report.push_str(&format!("Frame time max: {:.3}ms\n", percentile_ms(&sorted_frames, 100.0)));
+
+        for (name, times) in self.system_times.iter() {
+            report.push_str(&format!("System {} avg: {:.3}ms\n", name, average_ms(times)));
+        }
+
+        report
+    }
+}
+
+fn duration_ms(duration: &Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1_000_000.0
+}
+
+fn average_ms(times: &[Duration]) -> f64 {
+    if times.is_empty() { return 0.0; }
+    times.iter().map(duration_ms).sum::<f64>() / times.len() as f64
+}
+
+// Expects times to already be sorted
+fn percentile_ms(sorted_times: &[Duration], percentile: f64) -> f64 {
+    if sorted_times.is_empty() { return 0.0; }
+    let index = ((percentile / 100.0) * (sorted_times.len() - 1) as f64).round() as usize;
+    duration_ms(&sorted_times[index])
+}
@@ -0,0 +1,39 @@
+use std::collections::HashSet;
+
+// Collects problems from the game loop so a single bad entity or missing component doesn't take the whole game down
+// Recoverable problems get logged (once each, so a broken entity doesn't spam every frame),
+// problems the game can't carry on from put up the error screen instead
+#[derive(Default)]
+pub struct ErrorCapture {
+    logged_warnings: HashSet<String>,
+    failure: Option<String>
+}
+
+impl ErrorCapture {
+    pub fn new() -> ErrorCapture {
+        ErrorCapture::default()
+    }
+
+    pub fn warn(&mut self, message: String) {
+        if !self.logged_warnings.contains(&message) {
+            eprintln!("Warning: {}", message);
+            self.logged_warnings.insert(message);
+        }
+    }
+
+    // Only the first failure is kept, later ones are usually knock on effects of it
+    pub fn fail(&mut self, message: String) {
+        eprintln!("Error: {}", message);
+        if self.failure.is_none() {
+            self.failure = Some(message);
+        }
+    }
+
+    pub fn has_failed(&self) -> bool {
+        self.failure.is_some()
+    }
+
+    pub fn failure(&self) -> Option<&str> {
+        self.failure.as_deref()
+    }
+}
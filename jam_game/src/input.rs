@@ -0,0 +1,168 @@
+use quicksilver::input::{ButtonState, Key, Keyboard};
+
+// Everything the player can do from the keyboard, gameplay code asks about these rather than about keys directly
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+    ZoomOut,
+    ZoomIn,
+    LessRock,
+    MoreRock,
+    LessPrefetch,
+    MorePrefetch,
+    CursorUp,
+    CursorDown,
+    CursorLeft,
+    CursorRight,
+    Place,
+    Undo,
+    Redo,
+    MarkForbidden,
+    Connect,
+    PlaceLadder,
+    LevelUp,
+    LevelDown,
+    RenameStructure,
+    FindStructure,
+    QuickSave,
+    SaveMapImage,
+    ToggleLowPower,
+    CycleFrameCap,
+    ToggleHelp
+}
+
+impl Action {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::PanUp => "Pan camera up",
+            Action::PanDown => "Pan camera down",
+            Action::PanLeft => "Pan camera left",
+            Action::PanRight => "Pan camera right",
+            Action::ZoomOut => "Zoom out",
+            Action::ZoomIn => "Zoom in",
+            Action::LessRock => "Less rock",
+            Action::MoreRock => "More rock",
+            Action::LessPrefetch => "Generate fewer tiles ahead of the camera",
+            Action::MorePrefetch => "Generate more tiles ahead of the camera",
+            Action::CursorUp => "Move cursor up",
+            Action::CursorDown => "Move cursor down",
+            Action::CursorLeft => "Move cursor left",
+            Action::CursorRight => "Move cursor right",
+            Action::Place => "Place hab module",
+            Action::Undo => "Undo last placement",
+            Action::Redo => "Redo",
+            Action::MarkForbidden => "Start/finish a forbidden zone (or drag with right mouse)",
+            Action::Connect => "Pick two buildings to plan a corridor between, again to cancel",
+            Action::PlaceLadder => "Place a ladder down to the level below",
+            Action::LevelUp => "Show the level above",
+            Action::LevelDown => "Show the level below",
+            Action::RenameStructure => "Name the building under the cursor",
+            Action::FindStructure => "Find a building by name, the same search again goes to the next match",
+            Action::QuickSave => "Save every level in the background, the game carries on while it writes",
+            Action::SaveMapImage => "Save the map on screen as a PNG, one pixel per tile",
+            Action::ToggleLowPower => "Low power mode on/off (30 fps, simpler effects)",
+            Action::CycleFrameCap => "Cycle the frame rate cap (30/60/120/uncapped)",
+            Action::ToggleHelp => "Show/hide this help"
+        }
+    }
+}
+
+// A key, optionally with Ctrl held
+// Plain bindings don't fire while Ctrl is held, so Ctrl+Z doesn't also trigger whatever Z is bound to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Binding {
+    pub key: Key,
+    pub ctrl: bool
+}
+
+impl Binding {
+    pub fn key(key: Key) -> Binding {
+        Binding { key, ctrl: false }
+    }
+
+    pub fn ctrl(key: Key) -> Binding {
+        Binding { key, ctrl: true }
+    }
+
+    pub fn name(&self) -> String {
+        if self.ctrl { format!("Ctrl+{:?}", self.key) } else { format!("{:?}", self.key) }
+    }
+
+    fn modifiers_match(&self, keyboard: &Keyboard) -> bool {
+        let ctrl_down = keyboard[Key::LControl].is_down() || keyboard[Key::RControl].is_down();
+        ctrl_down == self.ctrl
+    }
+}
+
+pub struct InputMap {
+    // Kept in a list so the help overlay lists actions in a sensible order
+    bindings: Vec<(Action, Vec<Binding>)>
+}
+
+impl Default for InputMap {
+    fn default() -> InputMap {
+        InputMap {
+            bindings: vec![
+                (Action::PanUp, vec![Binding::key(Key::W)]),
+                (Action::PanDown, vec![Binding::key(Key::S)]),
+                (Action::PanLeft, vec![Binding::key(Key::A)]),
+                (Action::PanRight, vec![Binding::key(Key::D)]),
+                (Action::ZoomOut, vec![Binding::key(Key::Q)]),
+                (Action::ZoomIn, vec![Binding::key(Key::E)]),
+                (Action::LessRock, vec![Binding::key(Key::N)]),
+                (Action::MoreRock, vec![Binding::key(Key::M)]),
+                (Action::LessPrefetch, vec![Binding::key(Key::LBracket)]),
+                (Action::MorePrefetch, vec![Binding::key(Key::RBracket)]),
+                (Action::CursorUp, vec![Binding::key(Key::Up)]),
+                (Action::CursorDown, vec![Binding::key(Key::Down)]),
+                (Action::CursorLeft, vec![Binding::key(Key::Left)]),
+                (Action::CursorRight, vec![Binding::key(Key::Right)]),
+                (Action::Place, vec![Binding::key(Key::Return)]),
+                (Action::Undo, vec![Binding::ctrl(Key::Z)]),
+                (Action::Redo, vec![Binding::ctrl(Key::Y)]),
+                (Action::MarkForbidden, vec![Binding::key(Key::F)]),
+                (Action::Connect, vec![Binding::key(Key::C)]),
+                (Action::PlaceLadder, vec![Binding::key(Key::L)]),
+                (Action::LevelUp, vec![Binding::key(Key::PageUp)]),
+                (Action::LevelDown, vec![Binding::key(Key::PageDown)]),
+                (Action::RenameStructure, vec![Binding::key(Key::R)]),
+                (Action::FindStructure, vec![Binding::key(Key::Slash)]),
+                (Action::QuickSave, vec![Binding::key(Key::F5)]),
+                (Action::SaveMapImage, vec![Binding::key(Key::F12)]),
+                (Action::ToggleLowPower, vec![Binding::key(Key::P)]),
+                (Action::CycleFrameCap, vec![Binding::key(Key::F2)]),
+                (Action::ToggleHelp, vec![Binding::key(Key::H), Binding::key(Key::F1)])
+            ]
+        }
+    }
+}
+
+impl InputMap {
+    pub fn new() -> InputMap {
+        InputMap::default()
+    }
+
+    pub fn keys(&self, action: Action) -> &[Binding] {
+        match self.bindings.iter().find(|(bound_action, _)| *bound_action == action) {
+            Some((_, keys)) => keys,
+            None => &[]
+        }
+    }
+
+    pub fn bindings(&self) -> &[(Action, Vec<Binding>)] {
+        &self.bindings
+    }
+
+    // Held down this frame, for continuous actions like panning
+    pub fn is_down(&self, action: Action, keyboard: &Keyboard) -> bool {
+        self.keys(action).iter().any(|binding| keyboard[binding.key].is_down() && binding.modifiers_match(keyboard))
+    }
+
+    // Only true on the frame the key went down, for one-shot actions like placing
+    pub fn was_pressed(&self, action: Action, keyboard: &Keyboard) -> bool {
+        self.keys(action).iter().any(|binding| keyboard[binding.key] == ButtonState::Pressed && binding.modifiers_match(keyboard))
+    }
+}
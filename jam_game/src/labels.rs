@@ -0,0 +1,56 @@
+use quicksilver::{
+    geom::{Rectangle, Transform, Vector},
+    graphics::{Background::Col, Background::Img, Color, Font, FontStyle, Image},
+    lifecycle::{Asset, Window},
+};
+
+// Space between the edge of a label's backing and its text, in pixels
+const LABEL_PADDING: f32 = 4.0;
+
+// A line of text rendered once and drawn from the image until the text changes
+// Rendering through the font every frame is far too slow for anything shown all the time
+#[derive(Default)]
+pub struct CachedLabel {
+    text: String,
+    image: Option<Image>
+}
+
+impl CachedLabel {
+    pub fn new() -> CachedLabel {
+        CachedLabel::default()
+    }
+
+    // None while the font is still loading
+    pub fn image(&mut self, font: &mut Asset<Font>, text: &str, size: f32) -> Option<&Image> {
+        if self.image.is_none() || self.text != text {
+            self.image = Some(render_lines(font, &[text], size).pop()?);
+            self.text = text.to_string();
+        }
+        self.image.as_ref()
+    }
+}
+
+// One image per line in white, or none at all while the font is still loading or if any line fails to render
+// quicksilver decides what error execute's closure returns, so its size can't be helped here
+#[allow(clippy::result_large_err)]
+pub fn render_lines(font: &mut Asset<Font>, lines: &[&str], size: f32) -> Vec<Image> {
+    let mut images = Vec::with_capacity(lines.len());
+    let _ = font.execute(|font| {
+        let style = FontStyle::new(size, Color::WHITE);
+        for line in lines.iter() {
+            // Fonts can't render an empty string, so blank lines are a single space
+            let line = if line.is_empty() { " " } else { line };
+            images.push(font.render(line, &style)?);
+        }
+        Ok(())
+    });
+    if images.len() < lines.len() { images.clear(); }
+    images
+}
+
+// In screen space, over everything in the world but under the help overlay
+pub fn draw_label(window: &mut Window, image: &Image, top_left: Vector) {
+    let backing_size = image.area().size() + Vector::new(LABEL_PADDING * 2.0, LABEL_PADDING * 2.0);
+    window.draw_ex(&Rectangle::new(top_left, backing_size), Col(Color::BLACK.with_alpha(0.7)), Transform::IDENTITY, 8);
+    window.draw_ex(&Rectangle::new(top_left + Vector::new(LABEL_PADDING, LABEL_PADDING), image.area().size()), Img(image), Transform::IDENTITY, 9);
+}
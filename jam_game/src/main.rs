@@ -3,19 +3,57 @@ extern crate quicksilver;
 extern crate recs;
 use recs::{Ecs, EntityId};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 extern crate tilemap;
 
+mod options;
+use options::LaunchOptions;
+
+mod benchmark;
+use benchmark::BenchmarkRun;
+
+mod errors;
+use errors::ErrorCapture;
+
+mod input;
+use input::{Action, InputMap};
+
+mod tint;
+use tint::{TintChain, TintContext};
+
+mod shadows;
+
+mod labels;
+use labels::CachedLabel;
+
+mod attract;
+use attract::{AttractMode, IdleTimer};
+
+mod stress;
+
+mod power;
+use power::{FrameCap, PowerMode};
+
+mod saved_view;
+use saved_view::SavedView;
+
+mod settings;
+use settings::DisplaySettings;
+
 use tilemap::tile_world::{
-    TileMap, TileValue, GridCoord
+    TileMap, TileValue, GridCoord, MapLayer, WorldBounds, GeneratorConfig, SaveOptions, SaveWorker, TileRules, Minimap
 };
+use tilemap::pathfinding::{plan_corridor, is_buildable, CorridorPlan};
+use tilemap::raster::default_palette;
 
 use quicksilver::{
     Result,
-    geom::{Circle, Rectangle, Vector, Transform},
-    graphics::{Background::Col, Background::Img, Color, View, Image},
-    input::{Key, MouseButton},
-    lifecycle::{Settings, State, Window, Asset, run},
+    geom::{Circle, Line, Rectangle, Vector, Transform},
+    graphics::{Background::Blended, Background::Col, Background::Img, Color, View, Image, Font, PixelFormat},
+    input::{ButtonState, Key, MouseButton},
+    lifecycle::{Settings, State, Event, Window, Asset, run},
 };
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -42,6 +80,12 @@ struct KeyboardMove {
     speed: f32
 }
 
+// World units per second, written by the movement system each update
+#[derive(Clone, Debug, PartialEq)]
+struct Velocity {
+    value: Vector
+}
+
 #[derive(Clone, Debug, PartialEq)]
 struct Camera {
     height: f32
@@ -49,7 +93,10 @@ struct Camera {
 
 struct GameplayState {
     system: Ecs,
-    world: TileMap,
+    // Stacked top to bottom, the surface first, with ladders joining each one to the next
+    levels: Vec<TileMap>,
+    // Which of the levels is on screen, everything the player does happens on this one
+    level: usize,
     camera_id: EntityId,
     tile_textures: HashMap<TileValue, Image>,
     _tile_cursor: Asset<Image>,
@@ -57,7 +104,114 @@ struct GameplayState {
     hab_asset: Asset<Image>,
     rock_asset: Asset<Image>,
     selected_tile: GridCoord,
-    can_place: bool
+    can_place: bool,
+    // Set while the arrow keys are steering the selection, cleared again as soon as the mouse moves
+    keyboard_cursor: Option<GridCoord>,
+    // In screen space, since the world position under a still mouse changes whenever the camera moves
+    last_mouse_screen_pos: Vector,
+    // How many tiles beyond the leading screen edge get generated ahead of the camera
+    prefetch_margin: f32,
+    benchmark: Option<BenchmarkRun>,
+    errors: ErrorCapture,
+    font: Asset<Font>,
+    // Rendered once when the error screen first comes up, one image per line of text
+    error_screen_lines: Vec<Image>,
+    input_map: InputMap,
+    show_help: bool,
+    // Rendered the first time the overlay is opened, clear it if the bindings change so it gets rebuilt
+    help_lines: Vec<Image>,
+    tile_tints: TintChain,
+    // Corner the forbidden zone being marked out started from, the other corner follows the selection
+    forbidden_zone_start: Option<GridCoord>,
+    // First building picked with the connect tool, waiting on the second
+    connect_from: Option<GridCoord>,
+    // Planned but not built yet, confirm digs it out
+    corridor_preview: Option<CorridorPlan>,
+    power_mode: PowerMode,
+    frame_cap: FrameCap,
+    // Line being typed for a rename or a search, the rest of the controls wait until it's finished
+    text_entry: Option<TextEntry>,
+    // Offered again the next time a search is started, so Return steps on to the next match
+    last_search: String,
+    text_entry_label: CachedLabel,
+    hover_label: CachedLabel,
+    idle: IdleTimer,
+    // Set while attract mode is showing off the showcase base, which sits on the end of levels until it's over
+    attract: Option<AttractMode>,
+    // Writes quick saves off the main thread, only the snapshot is taken during the frame
+    save_worker: SaveWorker,
+    // Angle of the spinner shown while a save is being written, in degrees
+    save_spinner_angle: f32,
+    tile_rules: TileRules,
+    // Counts up to TILE_RULE_SECONDS, then the rules run on every level
+    tile_rule_timer: f64,
+    // How many times the rules have run, so each run rolls differently
+    tile_rule_tick: u64,
+    // Tags undo steps on different levels that come and go together, like the two ends of a ladder
+    next_undo_group: u64,
+    // Of the level on screen, None until it's first drawn and again after switching levels
+    minimap: Option<Minimap>,
+    // Made again from the minimap's pixels whenever any of them change
+    minimap_image: Option<Image>,
+    exit_save: ExitSave
+}
+
+// Saving from the error screen, the game closes once the save is written
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ExitSave {
+    NotAsked,
+    // A save from before the failure is still being written, the new one starts once it's done
+    Waiting,
+    Saving
+}
+
+// Every save slot is a directory in here, with one file per level, --load takes the slot's name
+const SAVE_DIRECTORY: &str = "saves";
+const QUICK_SAVE_SLOT: &str = "quicksave";
+
+// Degrees a second the saving spinner turns at
+const SAVE_SPINNER_SPEED: f32 = 360.0;
+
+// Weathering and decay are slow, running the rules any more often would only cost frames
+const TILE_RULE_SECONDS: f64 = 10.0;
+
+// Pixels along each side of the corner minimap, how many tiles across each one covers, and how big each one is drawn
+const MINIMAP_PIXELS: i64 = 96;
+const MINIMAP_TILES_PER_PIXEL: i64 = 4;
+const MINIMAP_SCALE: f32 = 2.0;
+
+// What a line being typed in is for, applied once Return is pressed
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum TextPurpose {
+    RenameStructure(GridCoord),
+    FindStructure
+}
+
+struct TextEntry {
+    purpose: TextPurpose,
+    text: String
+}
+
+// Keeps names short enough that a label doesn't cover half the screen
+const MAX_TYPED_LENGTH: usize = 32;
+
+// How far the corridor planner searches before deciding two buildings can't be joined
+const CORRIDOR_SEARCH_LIMIT: usize = 20000;
+
+// Top left and size of the rectangle with these two tiles as opposite corners
+fn tile_rect_between(a: &GridCoord, b: &GridCoord) -> (GridCoord, GridCoord) {
+    let top_left = GridCoord{x: a.x.min(b.x), y: a.y.min(b.y)};
+    let size = GridCoord{x: (a.x - b.x).abs() + 1, y: (a.y - b.y).abs() + 1};
+    (top_left, size)
+}
+
+// Tinted with a stripe through the corner, adjacent tiles line their stripes up into hatching
+// Low power mode leaves the stripes out since they double the draw calls
+fn draw_forbidden_tile(window: &mut Window, pos: &GridCoord, color: Color, low_power: bool) {
+    let corner = Vector::new(pos.x as f32, pos.y as f32);
+    window.draw_ex(&Rectangle::new(corner, (1, 1)), Col(color.with_alpha(0.2)), Transform::IDENTITY, 1);
+    if low_power { return; }
+    window.draw_ex(&Line::new(corner + Vector::new(0, 1), corner + Vector::new(1, 0)).with_thickness(0.1), Col(color.with_alpha(0.6)), Transform::IDENTITY, 1);
 }
 
 fn draw(window: &mut Window, sprite: &Sprite, transform: &TransformComponent) {
@@ -67,14 +221,44 @@ fn draw(window: &mut Window, sprite: &Sprite, transform: &TransformComponent) {
     }
 }
 
-fn draw_tile(window: &mut Window, tile_textures: &HashMap<TileValue, Image>, pos: &GridCoord, value: &TileValue, size: &GridCoord) {
+fn draw_tile(window: &mut Window, tile_textures: &HashMap<TileValue, Image>, pos: &GridCoord, value: &TileValue, size: &GridCoord, tint: Color) {
         let rect = Rectangle::new_sized((1, 1)); 
         match value {
             TileValue::Subtile(_) => {}, // Don't render subtiles
+            TileValue::Void => {}, // Past the edge of a bounded world, leave the background showing
+            TileValue::Ladder => {
+                // No texture for these yet, a light strip down the middle reads as a shaft either way
+                let transform = Transform::translate((pos.x as f32, pos.y as f32)) * Transform::scale((size.x as f32, size.y as f32));
+                window.draw_ex(&rect, Col(Color::from_rgba(60, 50, 40, 1.0).multiply(tint)), transform, 0);
+                window.draw_ex(&Rectangle::new((0.35, 0.0), (0.3, 1.0)), Col(Color::from_rgba(190, 150, 90, 1.0).multiply(tint)), transform, 0);
+            },
+            TileValue::IronOre | TileValue::IceDeposit => {
+                // No textures for ores yet either, flat colours are enough to tell them from rock
+                let color = if *value == TileValue::IronOre { Color::from_rgba(150, 80, 50, 1.0) } else { Color::from_rgba(170, 220, 240, 1.0) };
+                let transform = Transform::translate((pos.x as f32, pos.y as f32)) * Transform::scale((size.x as f32, size.y as f32));
+                window.draw_ex(&rect, Col(color.multiply(tint)), transform, 0);
+            },
+            TileValue::CrashedProbe | TileValue::CrystalCluster | TileValue::AbandonedModule => {
+                // Placeholder art until these get textures, one flat block over the whole footprint
+                let color = match value {
+                    TileValue::CrashedProbe => Color::from_rgba(200, 200, 210, 1.0),
+                    TileValue::CrystalCluster => Color::from_rgba(170, 90, 220, 1.0),
+                    _ => Color::from_rgba(110, 130, 110, 1.0)
+                };
+                let top_left = Vector::new((pos.x - size.x / 2) as f32, (pos.y - size.y / 2) as f32);
+                let transform = Transform::translate(top_left) * Transform::scale((size.x as f32, size.y as f32));
+                window.draw_ex(&rect, Col(color.multiply(tint)), transform, 0);
+            },
+            TileValue::Generating => {
+                // Still being generated in the background, show it as unexplored rather than guessing
+                let transform = Transform::translate((pos.x as f32, pos.y as f32)) * Transform::scale((size.x as f32, size.y as f32));
+                window.draw_ex(&rect, Col(Color::from_rgba(30, 30, 36, 1.0).multiply(tint)), transform, 0);
+            },
             _ => {
                 let transform = Transform::translate((pos.x as f32, pos.y as f32)) * Transform::scale((size.x as f32, size.y as f32));
                 match tile_textures.get(value) {
-                    Some(image) => window.draw_ex(&rect, Img(&image), transform, 0),
+                    Some(image) if tint == Color::WHITE => window.draw_ex(&rect, Img(image), transform, 0),
+                    Some(image) => window.draw_ex(&rect, Blended(image, tint), transform, 0),
                     None => window.draw_ex(&rect, Col(Color::MAGENTA), transform, 0)
                 };
             }
@@ -83,23 +267,56 @@ fn draw_tile(window: &mut Window, tile_textures: &HashMap<TileValue, Image>, pos
 
 impl State for GameplayState {
     fn new() -> Result<GameplayState> {
+        // Arguments were already validated in main before the window was opened
+        let launch_options = LaunchOptions::from_env().unwrap_or_default();
+
         let mut system = Ecs::new();
         let camera_ent: EntityId = system.create_entity();
 
         // Ignore result since this ID should be valid, we literally just made it
-        let _ = system.set(camera_ent, TransformComponent { position: Vector::new(100, 100), rotation: 0.0, scale: Vector::new(100, 100) });
+        let _ = system.set(camera_ent, TransformComponent { position: CAMERA_START, rotation: 0.0, scale: Vector::new(100, 100) });
         let _ = system.set(camera_ent, KeyboardMove { speed: 2.5 });
-        let _ = system.set(camera_ent, Camera { height: 10.0 });
+        let _ = system.set(camera_ent, Velocity { value: Vector::ZERO });
+        let _ = system.set(camera_ent, Camera { height: CAMERA_START_HEIGHT });
         
         let tile_textures:  HashMap<TileValue, Image> = HashMap::new();
 
+        let mut tile_tints = TintChain::new();
+        tile_tints.push(tint::selection_highlight);
+
         let empty_asset = Asset::new(Image::load("tile_textures/empty.png"));
         let hab_asset = Asset::new(Image::load("tile_textures/hab.png"));
         let rock_asset = Asset::new(Image::load("tile_textures/rock.png"));
 
-        Ok( GameplayState{ 
-            system, world: 
-            TileMap::new(), 
+        let mut levels = match create_levels(&launch_options) {
+            Ok(levels) => levels,
+            // main already checked the slot's files are there, so the save is damaged or from a newer build
+            Err(message) => {
+                eprintln!("{}", message);
+                std::process::exit(2);
+            }
+        };
+        // Printed so a world worth revisiting can be started again with --seed
+        println!("World seed: {}", levels[0].seed());
+        if let Some(structures) = launch_options.stress_base {
+            build_stress_base(&mut levels[0], structures);
+        }
+        let benchmark = if launch_options.benchmark {
+            BenchmarkRun::build_base(&mut levels[0]);
+            Some(BenchmarkRun::new(launch_options.benchmark_seconds))
+        }
+        else {
+            // Benchmarks keep generating on the main thread so every run draws exactly the same frames
+            for level in levels.iter_mut() {
+                level.enable_background_generation();
+            }
+            None
+        };
+
+        let mut state = GameplayState{ 
+            system, 
+            levels,
+            level: 0,
             camera_id: camera_ent, 
             tile_textures, 
             _tile_cursor: Asset::new(Image::load("selection.png")),
@@ -107,61 +324,130 @@ impl State for GameplayState {
             hab_asset,
             rock_asset,
             selected_tile: GridCoord{x: 0, y: 0},
-            can_place: false
-        } )
+            can_place: false,
+            keyboard_cursor: None,
+            last_mouse_screen_pos: Vector::ZERO,
+            prefetch_margin: launch_options.prefetch_margin,
+            benchmark,
+            errors: ErrorCapture::new(),
+            font: Asset::new(Font::load("SourceCodePro.ttf")),
+            error_screen_lines: Vec::new(),
+            input_map: InputMap::new(),
+            show_help: false,
+            help_lines: Vec::new(),
+            tile_tints,
+            forbidden_zone_start: None,
+            connect_from: None,
+            corridor_preview: None,
+            power_mode: PowerMode::new(launch_options.low_power),
+            frame_cap: display_settings(&launch_options).frame_cap,
+            text_entry: None,
+            last_search: String::new(),
+            text_entry_label: CachedLabel::new(),
+            hover_label: CachedLabel::new(),
+            idle: IdleTimer::new(),
+            attract: None,
+            save_worker: SaveWorker::spawn(),
+            save_spinner_angle: 0.0,
+            tile_rules: TileRules::default(),
+            tile_rule_timer: 0.0,
+            tile_rule_tick: 0,
+            next_undo_group: 0,
+            minimap: None,
+            minimap_image: None,
+            exit_save: ExitSave::NotAsked
+        };
+        if let Some(slot) = &launch_options.load_slot {
+            state.restore_view(slot);
+        }
+        // Straight in for soak tests, which leave it running with nobody touching anything
+        if launch_options.attract && state.benchmark.is_none() {
+            state.start_attract();
+        }
+        Ok(state)
     }
 
       
 
     fn draw(&mut self, window: &mut Window) -> Result<()> {
-        // Load images we don't have yet if they're ready
-        let mut newly_loaded_assets: HashMap<TileValue, Image> = HashMap::new();
-        if !self.tile_textures.contains_key(&TileValue::Empty) {
-            self.empty_asset.execute(|image| { newly_loaded_assets.insert(TileValue::Empty, image.clone()); Ok(()) })?;
-        }
-        if !self.tile_textures.contains_key(&TileValue::Rock) {
-            self.rock_asset.execute(|image| { newly_loaded_assets.insert(TileValue::Rock, image.clone()); Ok(()) })?;
-        }
-        if !self.tile_textures.contains_key(&TileValue::HabModule) {
-            self.hab_asset.execute(|image| { newly_loaded_assets.insert(TileValue::HabModule, image.clone()); Ok(()) })?;
-        }
-        if !newly_loaded_assets.is_empty() {
-            for (key, val) in newly_loaded_assets.iter() {
-                self.tile_textures.insert(*key, val.clone());
-            }
+        if self.errors.has_failed() {
+            self.draw_error_screen(window);
+            return Ok(());
         }
 
-        window.clear(Color::BLACK)?;
+        self.load_tile_textures();
 
-        //Prepare the camera
-        // Calculate the aspect ratio of the displaysa
-        let screen_size = window.screen_size();
-        let aspect_ratio = screen_size.x / screen_size.y;
+        window.clear(Color::BLACK)?;
 
         // Feed the camera to the view controller on the window
-        let camera: &Camera = self.system.borrow(self.camera_id).unwrap();
-        let transform: &TransformComponent = self.system.borrow(self.camera_id).unwrap();
-        let cam_rect = Rectangle::new(transform.position, (camera.height * aspect_ratio, camera.height));
+        let cam_rect = match self.camera_rect(window) {
+            Ok(cam_rect) => cam_rect,
+            Err(error) => {
+                self.errors.fail(format!("The camera is missing a component: {:?}", error));
+                return Ok(());
+            }
+        };
         window.set_view(View::new(cam_rect));
+        self.levels[self.level].resize_cache_for_view(&cam_rect, self.prefetch_margin);
+
+        // Attract mode shows the world with none of the player's tools drawn over it
+        // Anything half picked was dropped when it switched to the showcase level
+        let show_ui = self.attract.is_none();
 
         // Draw the tilemap first as a background
-        self.world.for_each_tile_rect(&cam_rect, |pos: &GridCoord, value: &TileValue, size: &GridCoord| {
-            draw_tile(window, &self.tile_textures, pos, value, size);
-        });
+        let tilemap_start = Instant::now();
+        let tint_context = TintContext { selected_tile: Some(self.selected_tile).filter(|_| show_ui) };
+        for (pos, value, size) in self.levels[self.level].tiles_in_rect(&cam_rect) {
+            let tint = self.tile_tints.tint_for(&tint_context, &pos, &value);
+            draw_tile(window, &self.tile_textures, &pos, &value, &size, tint);
+        }
+        if let Some(benchmark) = &mut self.benchmark {
+            benchmark.record_system("draw_tilemap", tilemap_start.elapsed());
+        }
+
+        let view_top_left = GridCoord{x: cam_rect.pos.x.floor() as i64, y: cam_rect.pos.y.floor() as i64};
+        let view_size = GridCoord{x: cam_rect.size.x.ceil() as i64 + 1, y: cam_rect.size.y.ceil() as i64 + 1};
+
+        // Shadows at rock edges go between the tiles and everything drawn over them, low power mode leaves them out
+        if !self.power_mode.is_low_power() {
+            let shadows_start = Instant::now();
+            let world = &self.levels[self.level];
+            let view_tiles = world.sample_rect(&view_top_left, &view_size);
+            let solid_masks = world.neighbor_masks_in_area(&view_top_left, &view_size, TileValue::is_solid);
+            shadows::draw_edge_shadows(window.mesh(), &view_top_left, &view_size, view_tiles.tiles(), &solid_masks, 0.5);
+            if let Some(benchmark) = &mut self.benchmark {
+                benchmark.record_system("draw_shadows", shadows_start.elapsed());
+            }
+        }
+        for (pos, _) in self.levels[self.level].layer_tiles_in_area(MapLayer::Designation, &view_top_left, &view_size) {
+            draw_forbidden_tile(window, &pos, Color::RED, self.power_mode.is_low_power());
+        }
+        // Preview of the zone being marked out
+        if let Some(start) = self.forbidden_zone_start {
+            let (top_left, size) = tile_rect_between(&start, &self.selected_tile);
+            for y in top_left.y..(top_left.y + size.y) {
+                for x in top_left.x..(top_left.x + size.x) {
+                    draw_forbidden_tile(window, &GridCoord{x, y}, Color::YELLOW, self.power_mode.is_low_power());
+                }
+            }
+        }
+        // Ghost of the planned corridor, tiles that need digging out stand out
+        if let Some(plan) = &self.corridor_preview {
+            for pos in plan.tiles.iter() {
+                let color = if self.levels[self.level].sample(pos).is_solid() { Color::ORANGE } else { Color::CYAN };
+                window.draw_ex(&Rectangle::new((pos.x as f32, pos.y as f32), (1, 1)), Col(color.with_alpha(0.4)), Transform::IDENTITY, 1);
+            }
+        }
+        if let Some(from) = self.connect_from {
+            window.draw_ex(&Rectangle::new((from.x as f32, from.y as f32), (1, 1)), Col(Color::CYAN.with_alpha(0.6)), Transform::IDENTITY, 1);
+        }
         
         // Draw a circle on the currently highlighted tile
-        if self.can_place {
+        if show_ui {
+            let (radius, color) = if self.can_place { (1.5, Color::GREEN) } else { (0.5, Color::RED) };
             window.draw_ex(
-                &Circle::new((0, 0), 1.5), 
-                Col(Color::GREEN),
-                Transform::translate((self.selected_tile.x as f32 + 0.5, self.selected_tile.y as f32 + 0.5)),
-                1
-                );
-        }
-        else {
-            window.draw_ex(
-                &Circle::new((0, 0), 0.5), 
-                Col(Color::RED),
+                &Circle::new((0, 0), radius), 
+                Col(color),
                 Transform::translate((self.selected_tile.x as f32 + 0.5, self.selected_tile.y as f32 + 0.5)),
                 1
                 );
@@ -172,10 +458,34 @@ impl State for GameplayState {
         let drawable_filter = component_filter!(Sprite, TransformComponent);
         self.system.collect_with(&drawable_filter, &mut drawable_ids);
         // Draw everything that we can draw
+        let entities_start = Instant::now();
         for drawable in drawable_ids {
-            let sprite: &Sprite = self.system.borrow(drawable).unwrap();
-            let transform: &TransformComponent = self.system.borrow(drawable).unwrap();
-            draw(window, sprite, transform);
+            match (self.system.borrow::<Sprite>(drawable), self.system.borrow::<TransformComponent>(drawable)) {
+                (Ok(sprite), Ok(transform)) => draw(window, sprite, transform),
+                (Err(error), _) | (_, Err(error)) => self.errors.warn(format!("Skipped drawing entity {:?}: {:?}", drawable, error))
+            }
+        }
+        if let Some(benchmark) = &mut self.benchmark {
+            benchmark.record_system("draw_entities", entities_start.elapsed());
+            benchmark.record_frame();
+        }
+
+        if show_ui {
+            self.draw_labels(window);
+            self.draw_minimap(window, &cam_rect);
+        }
+        if self.save_worker.is_saving() && show_ui {
+            self.draw_save_spinner(window);
+        }
+        if self.show_help && show_ui {
+            self.draw_help_overlay(window);
+        }
+
+        // Benchmark runs get compared with each other, so they always draw at full rate
+        if self.benchmark.is_none() && window.current_fps() >= 1.0 && self.power_mode.record_frame(1.0 / window.current_fps()) {
+            let key_names: Vec<String> = self.input_map.keys(Action::ToggleLowPower).iter().map(|binding| binding.name()).collect();
+            println!("Frames are running slow, switching to low power mode (toggle with {})", key_names.join("/"));
+            self.apply_power_mode(window);
         }
 
         Ok(())
@@ -187,62 +497,1065 @@ impl State for GameplayState {
         // First frame has framerate of 0 and that makes for a sad division time so catch that fucker here before it fucks everything up
         let delta_time = if framerate < 1.0 { 0.0 } else { 1.0 / framerate };
 
+        if self.errors.has_failed() {
+            if self.exit_save == ExitSave::NotAsked && self.input_map.was_pressed(Action::QuickSave, window.keyboard()) {
+                self.exit_save = ExitSave::Waiting;
+            }
+            self.update_saving(delta_time);
+            if self.exit_save == ExitSave::Waiting && !self.save_worker.is_saving() {
+                self.quick_save();
+                self.exit_save = ExitSave::Saving;
+            }
+            let saved = self.exit_save == ExitSave::Saving && !self.save_worker.is_saving();
+            if saved || window.keyboard()[Key::Escape].is_down() {
+                window.close();
+            }
+            return Ok(());
+        }
+
+        // Levels that aren't on screen still take their finished partitions and page out, so they don't hold on to memory
+        let on_screen = self.level;
+        for (index, level) in self.levels.iter_mut().enumerate() {
+            level.receive_generated_partitions();
+            if let Err(error) = level.update_paging() {
+                self.errors.warn(format!("Couldn't page a partition back in: {:?}", error));
+            }
+            // Taken from every level so they don't pile up, only the one on screen has a minimap to pass them on to
+            let dirty = level.take_dirty_regions();
+            if let Some(minimap) = self.minimap.as_mut().filter(|_| index == on_screen) {
+                if minimap.update(level, &dirty) {
+                    self.minimap_image = None;
+                }
+            }
+        }
+        self.update_saving(delta_time);
+
+        if self.benchmark.is_some() {
+            self.update_benchmark(window);
+            return Ok(());
+        }
+
+        // Whatever brought the player back is swallowed rather than also clicking or typing something
+        let had_input = self.idle.take_input();
+        if self.attract.is_some() {
+            if had_input { self.stop_attract(); } else { self.update_attract(window, delta_time); }
+            return Ok(());
+        }
+        if self.idle.advance(delta_time) {
+            self.start_attract();
+            return Ok(());
+        }
+        self.update_tile_rules(delta_time);
+
+        if self.text_entry.is_some() {
+            self.update_text_entry(window);
+            return Ok(());
+        }
+
          // Get the ids of components that have both a transform and a keyboard mover
          let mut updatable_ids: Vec<EntityId> = Vec::new();
          let updatable_filter = component_filter!(KeyboardMove, TransformComponent);
          self.system.collect_with(&updatable_filter, &mut updatable_ids);
          for updateable in updatable_ids {
-            let mover: &KeyboardMove = self.system.borrow(updateable).unwrap();
+            let mover: &KeyboardMove = match self.system.borrow(updateable) {
+                Ok(mover) => mover,
+                Err(error) => {
+                    self.errors.warn(format!("Skipped moving entity {:?}: {:?}", updateable, error));
+                    continue;
+                }
+            };
             let mut x_move = 0.0;
             let mut y_move = 0.0;
 
-            if window.keyboard()[Key::W].is_down() { y_move -= mover.speed; }
-            if window.keyboard()[Key::S].is_down() { y_move += mover.speed; }
-            if window.keyboard()[Key::A].is_down() { x_move -= mover.speed; }
-            if window.keyboard()[Key::D].is_down() { x_move += mover.speed; }
+            if self.input_map.is_down(Action::PanUp, window.keyboard()) { y_move -= mover.speed; }
+            if self.input_map.is_down(Action::PanDown, window.keyboard()) { y_move += mover.speed; }
+            if self.input_map.is_down(Action::PanLeft, window.keyboard()) { x_move -= mover.speed; }
+            if self.input_map.is_down(Action::PanRight, window.keyboard()) { x_move += mover.speed; }
             
+            let _ = self.system.set(updateable, Velocity { value: Vector::new(x_move, y_move) });
+
             x_move *= delta_time as f32;
             y_move *= delta_time as f32;
 
-            if x_move != 0.0 {
-                self.system.borrow_mut::<TransformComponent>(updateable).map(|transform| transform.position.x += x_move).unwrap();
-            }
-            if y_move != 0.0 {
-                self.system.borrow_mut::<TransformComponent>(updateable).map(|transform| transform.position.y += y_move).unwrap();
+            if x_move != 0.0 || y_move != 0.0 {
+                if let Err(error) = self.system.borrow_mut::<TransformComponent>(updateable).map(|transform| transform.position += Vector::new(x_move, y_move)) {
+                    self.errors.warn(format!("Skipped moving entity {:?}: {:?}", updateable, error));
+                }
             }
          }
 
-        if window.keyboard()[Key::Q].is_down() {
-            self.system.borrow_mut::<Camera>(self.camera_id).map(|cam| cam.height += delta_time as f32).unwrap();
+        let mut zoom = 0.0;
+        if self.input_map.is_down(Action::ZoomOut, window.keyboard()) { zoom += delta_time as f32; }
+        if self.input_map.is_down(Action::ZoomIn, window.keyboard()) { zoom -= delta_time as f32; }
+        if zoom != 0.0 {
+            if let Err(error) = self.system.borrow_mut::<Camera>(self.camera_id).map(|cam| cam.height += zoom) {
+                self.errors.fail(format!("The camera is missing a component: {:?}", error));
+                return Ok(());
+            }
+        }
+
+        let mut generator_config = self.levels[self.level].generator_config();
+        if self.input_map.is_down(Action::LessRock, window.keyboard()) { generator_config.rock_density -= delta_time; }
+        if self.input_map.is_down(Action::MoreRock, window.keyboard()) { generator_config.rock_density += delta_time; }
+        if generator_config != self.levels[self.level].generator_config() {
+            self.levels[self.level].set_generator_config(generator_config);
+            println!("Rock Density: {}", generator_config.rock_density);
         }
-        if window.keyboard()[Key::E].is_down() {
-            self.system.borrow_mut::<Camera>(self.camera_id).map(|cam| cam.height -= delta_time as f32).unwrap();
+
+        let mut prefetch_margin = self.prefetch_margin;
+        if self.input_map.was_pressed(Action::LessPrefetch, window.keyboard()) { prefetch_margin -= 1.0; }
+        if self.input_map.was_pressed(Action::MorePrefetch, window.keyboard()) { prefetch_margin += 1.0; }
+        prefetch_margin = prefetch_margin.clamp(0.0, options::MAX_PREFETCH_MARGIN);
+        if prefetch_margin != self.prefetch_margin {
+            self.prefetch_margin = prefetch_margin;
+            println!("Prefetch margin: {} tiles", prefetch_margin);
         }
 
-        if window.keyboard()[Key::N].is_down() {
-            self.world.rock_density -= delta_time;
-            println!("Rock Density: {}", self.world.rock_density);
+        self.prefetch_ahead_of_camera(window);
+        self.update_selection(window);
+
+        if self.input_map.was_pressed(Action::ToggleHelp, window.keyboard()) {
+            self.show_help = !self.show_help;
+        }
+        if self.input_map.was_pressed(Action::ToggleLowPower, window.keyboard()) {
+            self.power_mode.toggle();
+            self.apply_power_mode(window);
+        }
+        if self.input_map.was_pressed(Action::CycleFrameCap, window.keyboard()) {
+            self.frame_cap = self.frame_cap.next();
+            println!("Frame rate cap: {}", self.frame_cap.name());
+            self.apply_power_mode(window);
+            self.save_frame_cap();
         }
 
-        if window.keyboard()[Key::M].is_down() {
-            self.world.rock_density += delta_time;
-            println!("Rock Density: {}", self.world.rock_density);
+        if self.input_map.was_pressed(Action::LevelUp, window.keyboard()) && self.level > 0 {
+            self.switch_level(self.level - 1);
+        }
+        if self.input_map.was_pressed(Action::LevelDown, window.keyboard()) && self.level + 1 < self.levels.len() {
+            self.switch_level(self.level + 1);
+        }
+        if self.input_map.was_pressed(Action::PlaceLadder, window.keyboard()) {
+            self.place_ladder();
+        }
+        if self.input_map.was_pressed(Action::RenameStructure, window.keyboard()) {
+            self.start_rename();
+        }
+        if self.input_map.was_pressed(Action::FindStructure, window.keyboard()) {
+            self.text_entry = Some(TextEntry { purpose: TextPurpose::FindStructure, text: self.last_search.clone() });
         }
 
-        self.selected_tile = self.world.pos_to_grid(window.mouse().pos().x, window.mouse().pos().y);
-        let selection_area_left = self.selected_tile.x - 1;
-        let selection_area_top = self.selected_tile.y - 1;
+        if self.input_map.was_pressed(Action::QuickSave, window.keyboard()) {
+            self.quick_save();
+        }
+        if self.input_map.was_pressed(Action::SaveMapImage, window.keyboard()) {
+            self.save_map_image(window);
+        }
 
-        self.can_place = self.world.area_clear(&GridCoord{x: selection_area_left, y: selection_area_top}, &GridCoord{x: 3, y: 3});
+        if self.input_map.was_pressed(Action::Undo, window.keyboard()) {
+            self.undo();
+        }
+        if self.input_map.was_pressed(Action::Redo, window.keyboard()) {
+            self.redo();
+        }
 
-        if window.mouse()[MouseButton::Left].is_down() && self.can_place {
-            self.world.make_change(&self.selected_tile, &TileValue::HabModule);
+        let mut confirm_pressed = self.input_map.was_pressed(Action::Place, window.keyboard());
+        if confirm_pressed && self.corridor_preview.is_some() {
+            self.build_corridor();
+            confirm_pressed = false;
         }
+        self.update_connect_tool(window);
 
+        if (window.mouse()[MouseButton::Left].is_down() || confirm_pressed) && self.can_place {
+            self.place_selected();
+        }
+
+        self.update_forbidden_zone(window);
+
+        Ok(())
+    }
+
+    // Only typed text and whether there was any input at all come from events, everything else reads the keyboard state in update
+    fn event(&mut self, event: &Event, _window: &mut Window) -> Result<()> {
+        if let Event::Key(..) | Event::Typed(_) | Event::MouseMoved(_) | Event::MouseWheel(_) | Event::MouseButton(..) = event {
+            self.idle.note_input();
+        }
+        if let (Some(entry), Event::Typed(character)) = (&mut self.text_entry, event) {
+            if !character.is_control() && entry.text.chars().count() < MAX_TYPED_LENGTH {
+                entry.text.push(*character);
+            }
+        }
         Ok(())
     }
 }
 
+impl GameplayState {
+    fn place_selected(&mut self) {
+        // can_place is from this frame's selection, try_place checks again against the map as it is now
+        if let Err(error) = self.levels[self.level].try_place(&self.selected_tile, &TileValue::HabModule) {
+            self.errors.warn(format!("Couldn't place hab module at {:?}: {:?}", self.selected_tile, error));
+            return;
+        }
+
+        // Full scan is too slow to leave on in release builds, but catches footprint bugs right when they happen
+        if cfg!(debug_assertions) {
+            if let Err(errors) = self.levels[self.level].validate_integrity() {
+                println!("World integrity problems after placing at {:?}: {:?}", self.selected_tile, errors);
+            }
+        }
+    }
+
+    // Anything half picked on the old level would point at the wrong tiles on the new one
+    fn switch_level(&mut self, level: usize) {
+        self.level = level;
+        self.minimap = None;
+        self.forbidden_zone_start = None;
+        self.connect_from = None;
+        self.corridor_preview = None;
+        println!("Level: {}", level_name(level));
+    }
+
+    // Goes down to the level below, or up from the bottom one, digging out the far end if it's still rock
+    // Both ends are checked before either is written, and undo or redo on either level takes both of them
+    fn place_ladder(&mut self) {
+        let other = if self.level + 1 < self.levels.len() { self.level + 1 } else if self.level > 0 { self.level - 1 } else { return };
+        let pos = self.selected_tile;
+
+        if let Err(error) = self.levels[self.level].can_place(&pos, &TileValue::Ladder) {
+            self.errors.warn(format!("Couldn't place a ladder at {:?}: {:?}", pos, error));
+            return;
+        }
+        if !is_buildable(&self.levels[other], &pos) {
+            self.errors.warn(format!("Couldn't place a ladder at {:?}, the {} is in the way", pos, level_name(other)));
+            return;
+        }
+
+        self.levels[self.level].make_change(&pos, &TileValue::Ladder);
+        self.levels[other].make_change(&pos, &TileValue::Ladder);
+        let group = self.next_undo_group;
+        self.next_undo_group += 1;
+        self.levels[self.level].set_undo_group(group);
+        self.levels[other].set_undo_group(group);
+    }
+
+    // Steps linked to the one being undone are undone on every other level too, as long as nothing was done there since
+    fn undo(&mut self) {
+        let group = self.levels[self.level].undo_group();
+        if !self.levels[self.level].undo() { return; }
+        if let Some(group) = group {
+            for level in self.levels.iter_mut().filter(|level| level.undo_group() == Some(group)) {
+                level.undo();
+            }
+        }
+    }
+
+    fn redo(&mut self) {
+        let group = self.levels[self.level].redo_group();
+        if !self.levels[self.level].redo() { return; }
+        if let Some(group) = group {
+            for level in self.levels.iter_mut().filter(|level| level.redo_group() == Some(group)) {
+                level.redo();
+            }
+        }
+    }
+
+    // Starts off with the building's current name, so it can be touched up rather than typed out again
+    fn start_rename(&mut self) {
+        let world = &self.levels[self.level];
+        let value = world.sample(&self.selected_tile);
+        if MapLayer::for_value(&value) != MapLayer::Structure {
+            return;
+        }
+        let text = world.structure_name(&self.selected_tile).unwrap_or("").to_string();
+        self.text_entry = Some(TextEntry { purpose: TextPurpose::RenameStructure(self.selected_tile), text });
+    }
+
+    // Typed characters are added in event, this handles the keys that edit or finish the line
+    fn update_text_entry(&mut self, window: &Window) {
+        let keyboard = window.keyboard();
+        if keyboard[Key::Escape] == ButtonState::Pressed {
+            self.text_entry = None;
+            return;
+        }
+        if keyboard[Key::Back] == ButtonState::Pressed {
+            if let Some(entry) = &mut self.text_entry {
+                entry.text.pop();
+            }
+        }
+        if keyboard[Key::Return] == ButtonState::Pressed {
+            if let Some(entry) = self.text_entry.take() {
+                self.finish_text_entry(entry, window);
+            }
+        }
+    }
+
+    fn finish_text_entry(&mut self, entry: TextEntry, window: &Window) {
+        match entry.purpose {
+            TextPurpose::RenameStructure(anchor) => {
+                // Blank names take the label off
+                if !self.levels[self.level].set_structure_name(&anchor, &entry.text) {
+                    self.errors.warn(format!("Couldn't name the building at {:?}, it isn't there any more", anchor));
+                }
+            }
+            TextPurpose::FindStructure => {
+                self.find_structure(&entry.text, window);
+                self.last_search = entry.text;
+            }
+        }
+    }
+
+    // Goes to the match after the selected one, so searching for the same thing again steps through all of them
+    // Only looks on the level being shown
+    fn find_structure(&mut self, query: &str, window: &Window) {
+        let matches = self.levels[self.level].find_structures_named(query);
+        let found = match matches.iter().position(|pos| *pos == self.selected_tile) {
+            Some(index) => matches[(index + 1) % matches.len()],
+            None => match matches.first() {
+                Some(first) => *first,
+                None => {
+                    println!("No buildings on the {} with '{}' in their name", level_name(self.level), query);
+                    return;
+                }
+            }
+        };
+
+        // Held on the keyboard cursor so the selection stays on it until the mouse moves
+        self.keyboard_cursor = Some(found);
+        self.center_camera_on_tile(window, &found);
+    }
+
+    fn center_camera_on_tile(&mut self, window: &Window, tile: &GridCoord) {
+        let cam_rect = match self.camera_rect(window) {
+            Ok(cam_rect) => cam_rect,
+            Err(_) => return
+        };
+        let position = Vector::new(tile.x as f32 + 0.5, tile.y as f32 + 0.5) - cam_rect.size / 2.0;
+        if let Err(error) = self.system.borrow_mut::<TransformComponent>(self.camera_id).map(|transform| transform.position = position) {
+            self.errors.warn(format!("Couldn't move the camera to {:?}: {:?}", tile, error));
+        }
+    }
+
+    // Right mouse drags out a zone, or the key marks one corner and then the other
+    // Zones started on a forbidden tile unmark instead, so the same controls take zones away again
+    fn update_forbidden_zone(&mut self, window: &Window) {
+        let key_pressed = self.input_map.was_pressed(Action::MarkForbidden, window.keyboard());
+        let right_mouse = window.mouse()[MouseButton::Right];
+
+        match self.forbidden_zone_start {
+            None => {
+                if key_pressed || right_mouse == ButtonState::Pressed {
+                    self.forbidden_zone_start = Some(self.selected_tile);
+                }
+            }
+            Some(start) => {
+                if key_pressed || right_mouse == ButtonState::Released {
+                    let (top_left, size) = tile_rect_between(&start, &self.selected_tile);
+                    let forbidden = !self.levels[self.level].is_forbidden(&start);
+                    self.levels[self.level].set_forbidden(&top_left, &size, forbidden);
+                    self.forbidden_zone_start = None;
+                }
+            }
+        }
+    }
+
+    // First press picks a building, the second plans a corridor to another one, pressing again with a plan up throws it away
+    fn update_connect_tool(&mut self, window: &Window) {
+        if !self.input_map.was_pressed(Action::Connect, window.keyboard()) {
+            return;
+        }
+
+        if self.corridor_preview.is_some() {
+            self.corridor_preview = None;
+            return;
+        }
+
+        let from = match self.connect_from.take() {
+            Some(from) => from,
+            None => {
+                if self.levels[self.level].sample_layer(MapLayer::Structure, &self.selected_tile).is_some() {
+                    self.connect_from = Some(self.selected_tile);
+                }
+                return;
+            }
+        };
+
+        match plan_corridor(&self.levels[self.level], &from, &self.selected_tile, CORRIDOR_SEARCH_LIMIT) {
+            Some(plan) => {
+                println!("Corridor: {} tiles, {} to dig, cost {}", plan.tiles.len(), plan.rock_tiles, plan.cost);
+                self.corridor_preview = Some(plan);
+            }
+            None => self.errors.warn(format!("Couldn't plan a corridor from {:?} to {:?}", from, self.selected_tile))
+        }
+    }
+
+    // There are no construction jobs yet, so the corridor is dug out straight away, as one undo step
+    fn build_corridor(&mut self) {
+        let plan = match self.corridor_preview.take() {
+            Some(plan) => plan,
+            None => return
+        };
+
+        let mut edit = self.levels[self.level].begin_edit();
+        for pos in plan.tiles.iter() {
+            if edit.sample(pos).is_solid() {
+                edit.set(pos, TileValue::Empty);
+            }
+        }
+        if let Err(error) = edit.commit() {
+            self.errors.warn(format!("Couldn't build the corridor: {:?}", error));
+        }
+    }
+
+    // Kept for the next run, only the cap changes so the rest of the file stays as the player left it
+    fn save_frame_cap(&mut self) {
+        let path = Path::new(settings::SETTINGS_PATH);
+        let saved = DisplaySettings::load(path).unwrap_or_default();
+        if let Err(message) = (DisplaySettings { frame_cap: self.frame_cap, ..saved }).save(path) {
+            self.errors.warn(message);
+        }
+    }
+
+    fn apply_power_mode(&self, window: &mut Window) {
+        window.set_draw_rate(draw_rate_for(self.frame_cap, self.power_mode.is_low_power()));
+    }
+
+    fn camera_rect(&self, window: &Window) -> std::result::Result<Rectangle, recs::NotFound> {
+        // Calculate the aspect ratio of the display
+        let screen_size = window.screen_size();
+        let aspect_ratio = screen_size.x / screen_size.y;
+
+        let camera: &Camera = self.system.borrow(self.camera_id)?;
+        let transform: &TransformComponent = self.system.borrow(self.camera_id)?;
+        Ok(Rectangle::new(transform.position, (camera.height * aspect_ratio, camera.height)))
+    }
+
+    // Picks up tile images that have finished loading since the last frame
+    // quicksilver decides what error execute's closure returns, so its size can't be helped here
+    #[allow(clippy::result_large_err)]
+    fn load_tile_textures(&mut self) {
+        let tile_assets = [(TileValue::Empty, &mut self.empty_asset), (TileValue::Rock, &mut self.rock_asset), (TileValue::HabModule, &mut self.hab_asset)];
+        for (tile, asset) in tile_assets {
+            if self.tile_textures.contains_key(&tile) {
+                continue;
+            }
+            let tile_textures = &mut self.tile_textures;
+            if let Err(error) = asset.execute(|image| { tile_textures.insert(tile, image.clone()); Ok(()) }) {
+                // Missing textures already fall back to a placeholder color when drawing
+                self.errors.warn(format!("Couldn't load the {:?} tile texture: {:?}", tile, error));
+            }
+        }
+    }
+
+    // Shown in place of the game once something has gone wrong that it can't carry on from
+    // Problems drawing it are ignored since there's nowhere left to report them
+    fn draw_error_screen(&mut self, window: &mut Window) {
+        let _ = window.clear(Color::from_rgba(40, 0, 0, 1.0));
+        window.set_view(View::new(Rectangle::new_sized(window.screen_size())));
+
+        if self.error_screen_lines.is_empty() {
+            let message = self.errors.failure().unwrap_or("Unknown error").to_string();
+            let save_keys: Vec<String> = self.input_map.keys(Action::QuickSave).iter().map(|binding| binding.name()).collect();
+            let exit_text = format!("Press {} to save and exit, or Escape to exit without saving", save_keys.join("/"));
+            self.error_screen_lines = labels::render_lines(&mut self.font, &["Something went wrong and the game can't continue", &message, &exit_text], 18.0);
+        }
+
+        let mut line_pos = Vector::new(20, 20);
+        for line in self.error_screen_lines.iter() {
+            window.draw(&Rectangle::new(line_pos, line.area().size()), Img(line));
+            line_pos.y += line.area().height() + 10.0;
+        }
+        if self.save_worker.is_saving() {
+            self.draw_save_spinner(window);
+        }
+    }
+
+    // Bottom right corner, built in full the first time and whenever the camera gets a quarter of the way to its edge
+    // Only the pixels over changed partitions are drawn again otherwise, see update
+    fn draw_minimap(&mut self, window: &mut Window, cam_rect: &Rectangle) {
+        let camera_center = cam_rect.pos + cam_rect.size / 2.0;
+        let camera_center = GridCoord{x: camera_center.x.floor() as i64, y: camera_center.y.floor() as i64};
+        let recenter_distance = MINIMAP_PIXELS * MINIMAP_TILES_PER_PIXEL / 4;
+        let world = &self.levels[self.level];
+        match &mut self.minimap {
+            Some(minimap) => {
+                let center = minimap.center();
+                if (center.x - camera_center.x).abs() > recenter_distance || (center.y - camera_center.y).abs() > recenter_distance {
+                    minimap.rebuild(world, &camera_center);
+                    self.minimap_image = None;
+                }
+            }
+            None => {
+                self.minimap = Some(Minimap::new(world, &camera_center, MINIMAP_TILES_PER_PIXEL, &GridCoord{x: MINIMAP_PIXELS, y: MINIMAP_PIXELS}));
+                self.minimap_image = None;
+            }
+        }
+
+        if self.minimap_image.is_none() {
+            if let Some(minimap) = &self.minimap {
+                match Image::from_raw(minimap.pixels(), MINIMAP_PIXELS as u32, MINIMAP_PIXELS as u32, PixelFormat::RGBA) {
+                    Ok(image) => self.minimap_image = Some(image),
+                    Err(error) => self.errors.warn(format!("Couldn't make the minimap image: {:?}", error))
+                }
+            }
+        }
+        if let Some(image) = &self.minimap_image {
+            window.set_view(View::new(Rectangle::new_sized(window.screen_size())));
+            let size = MINIMAP_PIXELS as f32 * MINIMAP_SCALE;
+            let top_left = window.screen_size() - Vector::new(size + 10.0, size + 10.0);
+            window.draw_ex(&Rectangle::new(top_left, (size, size)), Img(image), Transform::IDENTITY, 5);
+        }
+    }
+
+    // Name of the building under the cursor just above it, and whatever is being typed along the bottom of the screen
+    fn draw_labels(&mut self, window: &mut Window) {
+        let world = &self.levels[self.level];
+        let hovered_name = world.structure_name(&self.selected_tile).map(|name| name.to_string());
+        // Worked out while the view is still on the world
+        let size = world.get_tile_size(&world.sample(&self.selected_tile));
+        let label_pos = window.unproject() * Vector::new((self.selected_tile.x - size.x / 2) as f32, (self.selected_tile.y - size.y / 2 - 1) as f32);
+
+        window.set_view(View::new(Rectangle::new_sized(window.screen_size())));
+
+        if let Some(name) = hovered_name {
+            if let Some(image) = self.hover_label.image(&mut self.font, &name, 16.0) {
+                labels::draw_label(window, image, label_pos);
+            }
+        }
+        if let Some(entry) = &self.text_entry {
+            let prompt = match entry.purpose {
+                TextPurpose::RenameStructure(_) => "Name",
+                TextPurpose::FindStructure => "Find"
+            };
+            let text = format!("{}: {}_", prompt, entry.text);
+            if let Some(image) = self.text_entry_label.image(&mut self.font, &text, 18.0) {
+                let pos = Vector::new(20.0, window.screen_size().y - image.area().height() - 30.0);
+                labels::draw_label(window, image, pos);
+            }
+        }
+    }
+
+    fn draw_help_overlay(&mut self, window: &mut Window) {
+        if self.help_lines.is_empty() {
+            let mut text: Vec<String> = vec!["Controls".to_string()];
+            for (action, keys) in self.input_map.bindings().iter() {
+                let key_names: Vec<String> = keys.iter().map(|binding| binding.name()).collect();
+                text.push(format!("{:<12}{}", key_names.join("/"), action.description()));
+            }
+            text.push(format!("{:<12}{}", "Left click", Action::Place.description()));
+            text.push(String::new());
+            text.push("Hab modules need a clear 3x3 area, the cursor turns green where one fits".to_string());
+
+            let text: Vec<&str> = text.iter().map(|line| line.as_str()).collect();
+            // Empty while the font is still loading, tried again next frame
+            self.help_lines = labels::render_lines(&mut self.font, &text, 16.0);
+        }
+
+        window.set_view(View::new(Rectangle::new_sized(window.screen_size())));
+
+        let line_spacing = 4.0;
+        let overlay_width = self.help_lines.iter().map(|line| line.area().width()).fold(0.0, f32::max) + 40.0;
+        let overlay_height = self.help_lines.iter().map(|line| line.area().height() + line_spacing).sum::<f32>() + 40.0;
+        window.draw_ex(&Rectangle::new((10, 10), (overlay_width, overlay_height)), Col(Color::BLACK.with_alpha(0.8)), Transform::IDENTITY, 10);
+
+        let mut line_pos = Vector::new(30, 30);
+        for line in self.help_lines.iter() {
+            window.draw_ex(&Rectangle::new(line_pos, line.area().size()), Img(line), Transform::IDENTITY, 11);
+            line_pos.y += line.area().height() + line_spacing;
+        }
+    }
+
+    // Generates the strips just past whichever screen edges the camera is moving towards
+    // so fast panning finds them already in the tile cache
+    fn prefetch_ahead_of_camera(&mut self, window: &Window) {
+        let velocity = match self.system.borrow::<Velocity>(self.camera_id) {
+            Ok(velocity) => velocity.value,
+            Err(_) => return
+        };
+        // A missing camera gets reported by draw
+        let cam_rect = match self.camera_rect(window) {
+            Ok(cam_rect) => cam_rect,
+            Err(_) => return
+        };
+        let margin = self.prefetch_margin;
+
+        if velocity.x > 0.0 {
+            self.levels[self.level].prefetch_rect(&Rectangle::new((cam_rect.pos.x + cam_rect.size.x, cam_rect.pos.y), (margin, cam_rect.size.y)));
+        }
+        else if velocity.x < 0.0 {
+            self.levels[self.level].prefetch_rect(&Rectangle::new((cam_rect.pos.x - margin, cam_rect.pos.y), (margin, cam_rect.size.y)));
+        }
+
+        if velocity.y > 0.0 {
+            self.levels[self.level].prefetch_rect(&Rectangle::new((cam_rect.pos.x, cam_rect.pos.y + cam_rect.size.y), (cam_rect.size.x, margin)));
+        }
+        else if velocity.y < 0.0 {
+            self.levels[self.level].prefetch_rect(&Rectangle::new((cam_rect.pos.x, cam_rect.pos.y - margin), (cam_rect.size.x, margin)));
+        }
+    }
+
+    fn update_selection(&mut self, window: &Window) {
+        let mouse_pos = window.mouse().pos();
+        let mouse_screen_pos = window.unproject() * mouse_pos;
+        // Converting back to screen space isn't exact, so ignore sub-pixel differences
+        if (mouse_screen_pos - self.last_mouse_screen_pos).len2() > 1.0 {
+            self.keyboard_cursor = None;
+            self.last_mouse_screen_pos = mouse_screen_pos;
+        }
+
+        let mut cursor_move = GridCoord{x: 0, y: 0};
+        if self.input_map.was_pressed(Action::CursorLeft, window.keyboard()) { cursor_move.x -= 1; }
+        if self.input_map.was_pressed(Action::CursorRight, window.keyboard()) { cursor_move.x += 1; }
+        if self.input_map.was_pressed(Action::CursorUp, window.keyboard()) { cursor_move.y -= 1; }
+        if self.input_map.was_pressed(Action::CursorDown, window.keyboard()) { cursor_move.y += 1; }
+
+        if cursor_move.x != 0 || cursor_move.y != 0 {
+            // First arrow press picks up from wherever the mouse was pointing
+            let cursor = self.keyboard_cursor.unwrap_or(GridCoord{x: mouse_pos.x as i64, y: mouse_pos.y as i64});
+            let cursor = GridCoord{x: cursor.x + cursor_move.x, y: cursor.y + cursor_move.y};
+            self.keyboard_cursor = Some(cursor);
+            self.keep_camera_on_tile(window, &cursor);
+        }
+
+        // Cursor holds the raw tile rather than the resolved building so stepping across a building doesn't get stuck on it
+        self.selected_tile = match self.keyboard_cursor {
+            Some(cursor) => self.levels[self.level].pos_to_grid(cursor.x as f32, cursor.y as f32),
+            None => self.levels[self.level].pos_to_grid(mouse_pos.x, mouse_pos.y)
+        };
+        self.can_place = self.levels[self.level].can_place(&self.selected_tile, &TileValue::HabModule).is_ok();
+    }
+
+    // Scrolls the camera just far enough that the tile is on screen, with a one tile border
+    fn keep_camera_on_tile(&mut self, window: &Window, tile: &GridCoord) {
+        let cam_rect = match self.camera_rect(window) {
+            Ok(cam_rect) => cam_rect,
+            Err(_) => return
+        };
+
+        let mut offset = Vector::ZERO;
+        let (tile_x, tile_y) = (tile.x as f32, tile.y as f32);
+        if tile_x < cam_rect.pos.x + 1.0 { offset.x = tile_x - 1.0 - cam_rect.pos.x; }
+        if tile_x + 2.0 > cam_rect.pos.x + cam_rect.size.x { offset.x = tile_x + 2.0 - (cam_rect.pos.x + cam_rect.size.x); }
+        if tile_y < cam_rect.pos.y + 1.0 { offset.y = tile_y - 1.0 - cam_rect.pos.y; }
+        if tile_y + 2.0 > cam_rect.pos.y + cam_rect.size.y { offset.y = tile_y + 2.0 - (cam_rect.pos.y + cam_rect.size.y); }
+
+        if offset != Vector::ZERO {
+            if let Err(error) = self.system.borrow_mut::<TransformComponent>(self.camera_id).map(|transform| transform.position += offset) {
+                self.errors.warn(format!("Couldn't move the camera to the cursor: {:?}", error));
+            }
+        }
+    }
+
+    // Levels that aren't on screen weather too, the world doesn't wait for the player to look at it
+    fn update_tile_rules(&mut self, delta_time: f64) {
+        self.tile_rule_timer += delta_time;
+        if self.tile_rule_timer < TILE_RULE_SECONDS { return; }
+        self.tile_rule_timer -= TILE_RULE_SECONDS;
+
+        self.tile_rule_tick += 1;
+        for level in self.levels.iter_mut() {
+            level.apply_tile_rules(&self.tile_rules, self.tile_rule_tick);
+        }
+    }
+
+    // Snapshots every level now and leaves the worker to write them out, the files are whole once it reports back
+    fn quick_save(&mut self) {
+        if self.save_worker.is_saving() {
+            println!("Still writing the last save, try again once it's finished");
+            return;
+        }
+        let directory = Path::new(SAVE_DIRECTORY).join(QUICK_SAVE_SLOT);
+        if let Err(error) = std::fs::create_dir_all(&directory) {
+            self.errors.warn(format!("Couldn't make the save directory {}: {:?}", directory.display(), error));
+            return;
+        }
+        // Attract mode's showcase level is on the end of levels while it's running, it's thrown away afterwards so isn't saved
+        for index in 0..LEVEL_COUNT.min(self.levels.len()) {
+            let path = save_path(QUICK_SAVE_SLOT, index);
+            let save_worker = &mut self.save_worker;
+            let saved = self.levels[index].snapshot().and_then(|snapshot| save_worker.save(snapshot, path, SaveOptions { compress: true }));
+            if let Err(error) = saved {
+                self.errors.warn(format!("Couldn't save the {}: {:?}", level_name(index), error));
+            }
+        }
+
+        // Only a few bytes, so it's written right away rather than queued behind the levels
+        let written = self.saved_view().and_then(|view| std::fs::write(view_path(QUICK_SAVE_SLOT), view.to_text()).map_err(|error| error.to_string()));
+        if let Err(message) = written {
+            self.errors.warn(format!("Couldn't save the camera position: {}", message));
+        }
+    }
+
+    fn saved_view(&self) -> std::result::Result<SavedView, String> {
+        let camera: &Camera = self.system.borrow(self.camera_id).map_err(|error| format!("{:?}", error))?;
+        let transform: &TransformComponent = self.system.borrow(self.camera_id).map_err(|error| format!("{:?}", error))?;
+        // The showcase level doesn't get saved, so a save made during attract mode opens on the surface
+        let level = if self.level < LEVEL_COUNT { self.level } else { 0 };
+        Ok(SavedView { camera_position: transform.position, camera_height: camera.height, level, show_help: self.show_help })
+    }
+
+    // Puts the camera back where it was when the slot was saved, slots saved before there was a view file just start at the spawn point
+    fn restore_view(&mut self, slot: &str) {
+        let path = view_path(slot);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => return
+        };
+        let view = match SavedView::from_text(&text) {
+            Ok(view) => view,
+            Err(message) => {
+                self.errors.warn(format!("Couldn't restore the camera from {}, {}", path.display(), message));
+                return;
+            }
+        };
+        if let Ok(transform) = self.system.borrow_mut::<TransformComponent>(self.camera_id) {
+            transform.position = view.camera_position;
+        }
+        if let Ok(camera) = self.system.borrow_mut::<Camera>(self.camera_id) {
+            camera.height = view.camera_height;
+        }
+        self.level = view.level.min(self.levels.len() - 1);
+        self.show_help = view.show_help;
+    }
+
+    // Reports saves the worker has finished with, runs every frame so nothing it says gets missed
+    fn update_saving(&mut self, delta_time: f64) {
+        for finished in self.save_worker.finished() {
+            match finished.result {
+                Ok(()) => println!("Saved to {}", finished.path.display()),
+                Err(error) => self.errors.warn(format!("Couldn't save to {}: {:?}", finished.path.display(), error))
+            }
+        }
+        if self.save_worker.is_saving() {
+            self.save_spinner_angle = (self.save_spinner_angle + SAVE_SPINNER_SPEED * delta_time as f32) % 360.0;
+        }
+    }
+
+    // Bar turning in the top right corner of the screen
+    fn draw_save_spinner(&mut self, window: &mut Window) {
+        window.set_view(View::new(Rectangle::new_sized(window.screen_size())));
+        let center = Vector::new(window.screen_size().x - 30.0, 30.0);
+        window.draw_ex(&Circle::new((0, 0), 14), Col(Color::BLACK.with_alpha(0.7)), Transform::translate(center), 8);
+        window.draw_ex(
+            &Rectangle::new((-10, -2), (20, 4)),
+            Col(Color::WHITE),
+            Transform::translate(center) * Transform::rotate(self.save_spinner_angle),
+            9
+            );
+    }
+
+    // What's on screen has all been generated already, so the image has no unexplored gaps
+    // Named after the seed and level so it can be shared along with the seed
+    fn save_map_image(&mut self, window: &Window) {
+        let cam_rect = match self.camera_rect(window) {
+            Ok(cam_rect) => cam_rect,
+            Err(_) => return
+        };
+        let top_left = GridCoord{x: cam_rect.pos.x.floor() as i64, y: cam_rect.pos.y.floor() as i64};
+        let size = GridCoord{x: cam_rect.size.x.ceil() as i64 + 1, y: cam_rect.size.y.ceil() as i64 + 1};
+        let world = &self.levels[self.level];
+        let path = format!("map_{}_{}.png", world.seed(), level_name(self.level));
+        match world.render_region_to_image(&top_left, &size, default_palette).save_png(&path) {
+            Ok(()) => println!("Saved the map on screen to {}", path),
+            Err(error) => self.errors.warn(format!("Couldn't save the map image to {}: {:?}", path, error))
+        }
+    }
+
+    // Camera transforms hold the top left of the view, so offset by half the view to center on the focus
+    // Returns false if the camera is broken, which stops the game
+    fn point_camera_at(&mut self, focus: Vector, height: f32, aspect_ratio: f32) -> bool {
+        let top_left = focus - Vector::new(height * aspect_ratio / 2.0, height / 2.0);
+        self.set_camera(top_left, height)
+    }
+
+    fn set_camera(&mut self, position: Vector, height: f32) -> bool {
+        let moved_camera = self.system.borrow_mut::<TransformComponent>(self.camera_id).map(|transform| transform.position = position)
+            .and_then(|_| self.system.borrow_mut::<Camera>(self.camera_id).map(|cam| cam.height = height));
+        if let Err(error) = moved_camera {
+            self.errors.fail(format!("The camera is missing a component: {:?}", error));
+            return false;
+        }
+        true
+    }
+
+    // Pans around a showcase base on a level of its own, so nothing the player built gets touched
+    // Doubles as a soak test, it runs the same drawing, generation and paging as play does for as long as it's left
+    fn start_attract(&mut self) {
+        let camera = self.system.borrow::<TransformComponent>(self.camera_id).map(|transform| transform.position)
+            .and_then(|position| self.system.borrow::<Camera>(self.camera_id).map(|cam| (position, cam.height)));
+        let (camera_position, camera_height) = match camera {
+            Ok(camera) => camera,
+            Err(error) => {
+                self.errors.fail(format!("The camera is missing a component: {:?}", error));
+                return;
+            }
+        };
+
+        let return_level = self.level;
+        self.text_entry = None;
+        self.levels.push(create_showcase());
+        self.switch_level(self.levels.len() - 1);
+
+        let center = spawn_point();
+        self.attract = Some(AttractMode::new(Vector::new(center.x as f32, center.y as f32), return_level, camera_position, camera_height));
+    }
+
+    fn update_attract(&mut self, window: &Window, delta_time: f64) {
+        let (focus, height) = match &mut self.attract {
+            Some(attract) => {
+                attract.advance(delta_time);
+                (attract.camera_focus(), attract.camera_height())
+            }
+            None => return
+        };
+        let screen_size = window.screen_size();
+        self.point_camera_at(focus, height, screen_size.x / screen_size.y);
+    }
+
+    // Back to the level and view the player left, the showcase is thrown away
+    fn stop_attract(&mut self) {
+        let attract = match self.attract.take() {
+            Some(attract) => attract,
+            None => return
+        };
+        self.levels.pop();
+        self.switch_level(attract.return_level);
+        self.set_camera(attract.return_camera_position, attract.return_camera_height);
+    }
+
+    // Replaces player input with the scripted fly-through while a benchmark is running
+    fn update_benchmark(&mut self, window: &mut Window) {
+        let still_running = match &mut self.benchmark {
+            Some(benchmark) => benchmark.advance(),
+            None => return
+        };
+
+        if !still_running {
+            if let Some(benchmark) = &self.benchmark {
+                println!("{}", benchmark.report());
+            }
+            window.close();
+            return;
+        }
+
+        let (focus, height) = match &self.benchmark {
+            Some(benchmark) => (benchmark.camera_focus(), benchmark.camera_height()),
+            None => return
+        };
+
+        let screen_size = window.screen_size();
+        if !self.point_camera_at(focus, height, screen_size.x / screen_size.y) {
+            return;
+        }
+
+        let selection_start = Instant::now();
+        self.update_selection(window);
+        if let Some(benchmark) = &mut self.benchmark {
+            benchmark.record_system("update_selection", selection_start.elapsed());
+        }
+    }
+}
+
+// Low power mode holds drawing to 30 fps on top of whatever cap was picked
+// The settings file with any launch flags over the top, a broken file is reported and left for the defaults
+fn display_settings(launch_options: &LaunchOptions) -> DisplaySettings {
+    let saved = DisplaySettings::load(Path::new(settings::SETTINGS_PATH)).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        DisplaySettings::default()
+    });
+    saved.overridden_by(launch_options)
+}
+
+fn draw_rate_for(frame_cap: FrameCap, low_power: bool) -> f64 {
+    if low_power { frame_cap.draw_rate().max(power::LOW_POWER_DRAW_RATE) } else { frame_cap.draw_rate() }
+}
+
+// Top left of the view a new game opens on
+const CAMERA_START: Vector = Vector { x: 100.0, y: 100.0 };
+const CAMERA_START_HEIGHT: f32 = 10.0;
+// Wide enough for a few hab modules, and to reach the edges of the starting view at most aspect ratios
+const SPAWN_CLEARING_RADIUS: f64 = 6.0;
+// About one landmark per screen at the default zoom
+const POINT_OF_INTEREST_CHANCE: f64 = 0.1;
+
+// Middle of the starting view, where the ground is always kept clear
+fn spawn_point() -> GridCoord {
+    let half_height = CAMERA_START_HEIGHT / 2.0;
+    GridCoord{x: (CAMERA_START.x + half_height) as i64, y: (CAMERA_START.y + half_height) as i64}
+}
+
+// Has to differ from the surface's seed, or the caverns would have the surface's rock in exactly the same places
+const CAVERN_SEED_MIX: u64 = 0x9e37_79b9_7f4a_7c15;
+
+// The surface and the caverns
+const LEVEL_COUNT: usize = 2;
+
+fn level_name(level: usize) -> &'static str {
+    if level == 0 { "surface" } else { "caverns" }
+}
+
+// Where a slot keeps one level's save
+fn save_path(slot: &str, level: usize) -> PathBuf {
+    Path::new(SAVE_DIRECTORY).join(slot).join(format!("{}_{}.sav", level, level_name(level)))
+}
+
+// Where a slot keeps the camera and what was open on screen, see SavedView
+fn view_path(slot: &str) -> PathBuf {
+    Path::new(SAVE_DIRECTORY).join(slot).join("view.txt")
+}
+
+// Every level create_levels makes, read back from a save slot
+fn load_levels(slot: &str) -> std::result::Result<Vec<TileMap>, String> {
+    let mut levels = Vec::with_capacity(LEVEL_COUNT);
+    for level in 0..LEVEL_COUNT {
+        let path = save_path(slot, level);
+        let file = std::fs::File::open(&path).map_err(|error| format!("Couldn't open {}: {}", path.display(), error))?;
+        levels.push(TileMap::load_from_reader(std::io::BufReader::new(file)).map_err(|error| format!("Couldn't load {}: {:?}", path.display(), error))?);
+    }
+    Ok(levels)
+}
+
+// The surface and a cavern level under it, denser rock with caves and ore veins running through it
+// Or both of them as they were saved, if --load was given a slot
+fn create_levels(launch_options: &LaunchOptions) -> std::result::Result<Vec<TileMap>, String> {
+    if let Some(slot) = &launch_options.load_slot {
+        return load_levels(slot);
+    }
+    let surface = create_world(launch_options);
+    let cavern_config = GeneratorConfig {
+        rock_density: -0.1,
+        cave_density: 0.3,
+        iron_ore_density: 0.25,
+        ice_density: 0.15,
+        ..surface.generator_config()
+    };
+    let mut caverns = TileMap::new_with_config(surface.seed() ^ CAVERN_SEED_MIX, cavern_config);
+    // Ladders join tiles straight above each other, so both levels need to wrap at the same place
+    caverns.set_bounds(surface.bounds());
+    Ok(vec![surface, caverns])
+}
+
+fn create_world(launch_options: &LaunchOptions) -> TileMap {
+    let seed = match launch_options.seed {
+        Some(seed) => seed,
+        // Benchmark runs get compared with each other, so they always fly over the same world
+        None if launch_options.benchmark => 0,
+        None => random_seed()
+    };
+    let mut world = TileMap::with_seed(seed);
+    if !launch_options.benchmark {
+        world.set_generator_config(GeneratorConfig {
+            spawn_clearing_radius: SPAWN_CLEARING_RADIUS,
+            spawn_point: spawn_point(),
+            point_of_interest_chance: POINT_OF_INTEREST_CHANCE,
+            ..world.generator_config()
+        });
+    }
+    if let Some((width, height)) = launch_options.wrap_size {
+        let size = GridCoord{x: width as i64, y: height as i64};
+        world.set_bounds(Some(WorldBounds { top_left: GridCoord{x: -size.x / 2, y: -size.y / 2}, size, wrap: true }));
+    }
+    world
+}
+
+// Same world and base every time, attract mode pans around it
+fn create_showcase() -> TileMap {
+    let mut showcase = create_world(&LaunchOptions { seed: Some(attract::SHOWCASE_SEED), ..LaunchOptions::default() });
+    stress::build_stress_base(&mut showcase, &spawn_point(), attract::SHOWCASE_STRUCTURES, attract::SHOWCASE_SEED);
+    showcase.enable_background_generation();
+    showcase
+}
+
+// Built around the spawn point, where the ground was cleared for the first modules anyway
+fn build_stress_base(world: &mut TileMap, structures: usize) {
+    let seed = world.seed();
+    let report = stress::build_stress_base(world, &spawn_point(), structures, seed);
+    println!("Stress base: {} of {} structures, {} corridors, {} tiles dug out in {:?}",
+        report.structures, structures, report.corridors, report.dug_tiles, report.elapsed);
+}
+
+// Nowhere near a good random number, but plenty for picking a world
+fn random_seed() -> u64 {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    since_epoch.as_secs() ^ ((since_epoch.subsec_nanos() as u64) << 32)
+}
+
+// Tiles along each side of the image --map-image writes, big enough to see how caves and ore veins join up
+const MAP_IMAGE_SIZE: i64 = 512;
+
+// Builds or loads the world without opening a window and prints a summary of the area around the origin
+fn run_headless(launch_options: &LaunchOptions) {
+    // Only the surface is summarised, from the --load slot if there is one
+    let mut world = match create_levels(launch_options) {
+        Ok(mut levels) => levels.swap_remove(0),
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+    if let Some(structures) = launch_options.stress_base {
+        build_stress_base(&mut world, structures);
+    }
+
+    let area = world.sample_rect(&GridCoord{x: -32, y: -32}, &GridCoord{x: 64, y: 64});
+    let rock_tiles = area.tiles().iter().filter(|value| **value == TileValue::Rock).count();
+    let empty_tiles = area.tiles().iter().filter(|value| **value == TileValue::Empty).count();
+
+    println!("Seed: {}", world.seed());
+    println!("Rock tiles near origin: {}", rock_tiles);
+    println!("Empty tiles near origin: {}", empty_tiles);
+
+    if let Some(path) = &launch_options.map_image {
+        let center = spawn_point();
+        let top_left = GridCoord{x: center.x - MAP_IMAGE_SIZE / 2, y: center.y - MAP_IMAGE_SIZE / 2};
+        let image = world.render_region_to_image(&top_left, &GridCoord{x: MAP_IMAGE_SIZE, y: MAP_IMAGE_SIZE}, default_palette);
+        match image.save_png(path) {
+            Ok(()) => println!("Map image written to {}", path),
+            Err(error) => {
+                eprintln!("Couldn't write the map image to {}: {}", path, error);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
 fn main() {
-    run::<GameplayState>("Game Test", Vector::new(800, 600), Settings::default());
+    let launch_options = match LaunchOptions::from_env() {
+        Ok(launch_options) => launch_options,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("{}", options::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    // Checked before the window opens, actually loading the levels waits for GameplayState::new
+    if let Some(slot) = &launch_options.load_slot {
+        if let Some(missing) = (0..LEVEL_COUNT).map(|level| save_path(slot, level)).find(|path| !path.is_file()) {
+            eprintln!("No save in slot {}, {} doesn't exist", slot, missing.display());
+            std::process::exit(2);
+        }
+    }
+
+    if launch_options.map_image.is_some() && !launch_options.headless {
+        let key_names: Vec<String> = InputMap::new().keys(Action::SaveMapImage).iter().map(|binding| binding.name()).collect();
+        eprintln!("--map-image only works along with --headless, in game {} saves the map on screen", key_names.join("/"));
+        std::process::exit(2);
+    }
+
+    if launch_options.headless {
+        run_headless(&launch_options);
+        return;
+    }
+
+    let window_size = match launch_options.window_size {
+        Some((width, height)) => Vector::new(width, height),
+        None => Vector::new(800, 600)
+    };
+
+    let display = display_settings(&launch_options);
+    let settings = Settings {
+        draw_rate: draw_rate_for(display.frame_cap, launch_options.low_power),
+        vsync: display.vsync,
+        ..Settings::default()
+    };
+    run::<GameplayState>("Game Test", window_size, settings);
 }
\ No newline at end of file
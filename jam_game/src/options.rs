@@ -0,0 +1,231 @@
+use crate::power::FrameCap;
+
+// Command line options read before the game window is created
+// Lets automated tests, speedrunners, and bug reports start the game in an exact configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct LaunchOptions {
+    pub seed: Option<u64>,
+    // Save slot (a directory in saves) to start from instead of a new world, quick saves go in the quicksave slot
+    pub load_slot: Option<String>,
+    pub window_size: Option<(u32, u32)>,
+    pub headless: bool,
+    pub benchmark: bool,
+    // How long the benchmark fly-through lasts, in simulated seconds
+    pub benchmark_seconds: f64,
+    pub low_power: bool,
+    // These two are None unless given, leaving them to the settings file
+    pub frame_cap: Option<FrameCap>,
+    pub vsync: Option<bool>,
+    // Tiles past the leading screen edge to generate ahead of the camera
+    pub prefetch_margin: f32,
+    // Width and height in tiles of a world that wraps around at its edges, centred on the origin
+    pub wrap_size: Option<(u32, u32)>,
+    // Developer option, builds a base of this many structures at the start for profiling
+    pub stress_base: Option<usize>,
+    // Starts straight in attract mode instead of waiting for the player to go idle, for soak tests
+    pub attract: bool,
+    // Headless runs write an image of the generated terrain around the spawn point here
+    pub map_image: Option<String>
+}
+
+pub const USAGE: &str = "Usage: jam_game [--seed <number>] [--load <slot>] [--windowed <W>x<H>] [--headless] [--benchmark] [--benchmark-seconds <seconds>] [--low-power] [--fps-cap <30|60|120|uncapped>] [--vsync|--no-vsync] [--prefetch-margin <tiles>] [--wrap-world <W>x<H>] [--stress-base <structures>] [--attract] [--map-image <file.png>]";
+
+pub const DEFAULT_PREFETCH_MARGIN: f32 = 4.0;
+// Prefetching much more than a screen ahead generates far more than the camera can reach before it turns around
+pub const MAX_PREFETCH_MARGIN: f32 = 64.0;
+
+pub const DEFAULT_BENCHMARK_SECONDS: f64 = 20.0;
+
+impl Default for LaunchOptions {
+    fn default() -> LaunchOptions {
+        LaunchOptions {
+            seed: None,
+            load_slot: None,
+            window_size: None,
+            headless: false,
+            benchmark: false,
+            benchmark_seconds: DEFAULT_BENCHMARK_SECONDS,
+            low_power: false,
+            frame_cap: None,
+            vsync: None,
+            prefetch_margin: DEFAULT_PREFETCH_MARGIN,
+            wrap_size: None,
+            stress_base: None,
+            attract: false,
+            map_image: None
+        }
+    }
+}
+
+impl LaunchOptions {
+    pub fn from_env() -> Result<LaunchOptions, String> {
+        // First argument is the executable path
+        LaunchOptions::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse<I>(args: I) -> Result<LaunchOptions, String>
+        where I : IntoIterator<Item = String> {
+        let mut options = LaunchOptions::default();
+        let mut args = args.into_iter();
+        let mut benchmark_seconds_given = false;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let seed = value.parse::<u64>().map_err(|_| format!("Invalid seed '{}', expected a whole number", value))?;
+                    options.seed = Some(seed);
+                }
+                "--load" => {
+                    options.load_slot = Some(next_value(&mut args, &arg)?);
+                }
+                "--map-image" => {
+                    options.map_image = Some(next_value(&mut args, &arg)?);
+                }
+                "--windowed" => {
+                    let value = next_value(&mut args, &arg)?;
+                    options.window_size = Some(parse_window_size(&value)?);
+                }
+                "--wrap-world" => {
+                    let value = next_value(&mut args, &arg)?;
+                    options.wrap_size = Some(parse_size(&value).ok_or_else(|| format!("Invalid world size '{}', expected something like 200x150", value))?);
+                }
+                "--stress-base" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let structures = value.parse::<usize>().ok().filter(|structures| *structures > 0)
+                        .ok_or_else(|| format!("Invalid stress base size '{}', expected a number of structures", value))?;
+                    options.stress_base = Some(structures);
+                }
+                "--prefetch-margin" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let margin = value.parse::<f32>().ok().filter(|margin| (0.0..=MAX_PREFETCH_MARGIN).contains(margin))
+                        .ok_or_else(|| format!("Invalid prefetch margin '{}', expected a number of tiles from 0 to {}", value, MAX_PREFETCH_MARGIN))?;
+                    options.prefetch_margin = margin;
+                }
+                "--benchmark-seconds" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let seconds = value.parse::<f64>().ok().filter(|seconds| seconds.is_finite() && *seconds > 0.0)
+                        .ok_or_else(|| format!("Invalid benchmark length '{}', expected a positive number of seconds", value))?;
+                    options.benchmark_seconds = seconds;
+                    benchmark_seconds_given = true;
+                }
+                "--fps-cap" => {
+                    let value = next_value(&mut args, &arg)?;
+                    options.frame_cap = Some(FrameCap::parse(&value)
+                        .ok_or_else(|| format!("Invalid frame rate cap '{}', expected 30, 60, 120 or uncapped", value))?);
+                }
+                "--vsync" => options.vsync = Some(true),
+                "--no-vsync" => options.vsync = Some(false),
+                "--headless" => options.headless = true,
+                "--benchmark" => options.benchmark = true,
+                "--low-power" => options.low_power = true,
+                "--attract" => options.attract = true,
+                _ => return Err(format!("Unknown argument '{}'", arg))
+            }
+        }
+
+        // Headless runs never draw a frame, so there's nothing for these to do there
+        if options.headless && options.benchmark {
+            return Err("--benchmark needs a window, it can't be used with --headless".to_string());
+        }
+        if options.headless && options.attract {
+            return Err("--attract needs a window, it can't be used with --headless".to_string());
+        }
+        if benchmark_seconds_given && !options.benchmark {
+            return Err("--benchmark-seconds only works along with --benchmark".to_string());
+        }
+        if options.load_slot.is_some() && (options.seed.is_some() || options.wrap_size.is_some()) {
+            return Err("A loaded world keeps the seed and size it was saved with, --seed and --wrap-world can't be used with --load".to_string());
+        }
+
+        Ok(options)
+    }
+}
+
+fn next_value<I>(args: &mut I, flag: &str) -> Result<String, String>
+    where I : Iterator<Item = String> {
+    args.next().ok_or_else(|| format!("Missing value after {}", flag))
+}
+
+fn parse_window_size(value: &str) -> Result<(u32, u32), String> {
+    parse_size(value).ok_or_else(|| format!("Invalid window size '{}', expected something like 1280x720", value))
+}
+
+// <W>x<H> with neither of them zero
+fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let mut parts = value.split(&['x', 'X'][..]);
+    let width = parts.next().and_then(|w| w.parse::<u32>().ok())?;
+    let height = parts.next().and_then(|h| h.parse::<u32>().ok())?;
+
+    if parts.next().is_some() || width == 0 || height == 0 {
+        return None;
+    }
+
+    Some((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::*;
+
+    fn parse(args: &[&str]) -> Result<LaunchOptions, String> {
+        LaunchOptions::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn no_arguments_give_the_defaults() {
+        assert_eq!(parse(&[]), Ok(LaunchOptions::default()));
+    }
+
+    #[test]
+    fn arguments_set_their_options() {
+        let options = parse(&["--seed", "42", "--windowed", "1280x720", "--headless"]).unwrap();
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.window_size, Some((1280, 720)));
+        assert!(options.headless);
+        assert_eq!(parse(&["--load", "quicksave"]).unwrap().load_slot, Some("quicksave".to_string()));
+
+        let options = parse(&["--benchmark", "--benchmark-seconds", "2.5", "--prefetch-margin", "8"]).unwrap();
+        assert!(options.benchmark);
+        assert_eq!(options.benchmark_seconds, 2.5);
+        assert_eq!(options.prefetch_margin, 8.0);
+
+        let options = parse(&["--fps-cap", "60", "--no-vsync"]).unwrap();
+        assert_eq!(options.frame_cap, Some(FrameCap::Fps60));
+        assert_eq!(options.vsync, Some(false));
+        assert_eq!(parse(&["--vsync"]).unwrap().vsync, Some(true));
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        assert!(parse(&["--seed"]).is_err());
+        assert!(parse(&["--seed", "-1"]).is_err());
+        assert!(parse(&["--windowed", "1280"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+        for margin in ["-1", "NaN", "inf", "1e30"].iter() {
+            assert!(parse(&["--prefetch-margin", margin]).is_err(), "{} was accepted", margin);
+        }
+        for seconds in ["0", "-5", "inf"].iter() {
+            assert!(parse(&["--benchmark", "--benchmark-seconds", seconds]).is_err(), "{} was accepted", seconds);
+        }
+    }
+
+    #[test]
+    fn options_that_do_nothing_together_are_errors() {
+        assert!(parse(&["--headless", "--benchmark"]).is_err());
+        assert!(parse(&["--headless", "--attract"]).is_err());
+        assert!(parse(&["--benchmark-seconds", "10"]).is_err());
+        assert!(parse(&["--load", "quicksave", "--seed", "5"]).is_err());
+        assert!(parse(&["--load", "quicksave", "--wrap-world", "200x150"]).is_err());
+        assert!(parse(&["--headless", "--load", "quicksave"]).is_ok());
+    }
+
+    #[test]
+    fn sizes_need_both_sides() {
+        assert_eq!(parse_size("200x150"), Some((200, 150)));
+        assert_eq!(parse_size("640X480"), Some((640, 480)));
+        for size in ["", "200", "200x", "x150", "0x150", "200x0", "200x150x2", "-200x150"].iter() {
+            assert_eq!(parse_size(size), None, "{} was accepted", size);
+        }
+    }
+}
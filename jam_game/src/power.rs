@@ -0,0 +1,96 @@
+// Frames slower than this count towards switching low power mode on, about 40 fps
+const SLOW_FRAME_SECONDS: f64 = 1.0 / 40.0;
+// Frames have to stay slow this long first, so a hitch while a lot of terrain generates doesn't trigger it
+const SUSTAINED_SLOW_SECONDS: f64 = 3.0;
+
+// Milliseconds between draws in low power mode, 30 fps
+pub const LOW_POWER_DRAW_RATE: f64 = 1000.0 / 30.0;
+
+// Limit on how often the game draws, separate from updates which always run at quicksilver's fixed 60 a second
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameCap {
+    Fps30,
+    Fps60,
+    Fps120,
+    Uncapped
+}
+
+impl FrameCap {
+    pub fn parse(value: &str) -> Option<FrameCap> {
+        match value {
+            "30" => Some(FrameCap::Fps30),
+            "60" => Some(FrameCap::Fps60),
+            "120" => Some(FrameCap::Fps120),
+            "uncapped" => Some(FrameCap::Uncapped),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrameCap::Fps30 => "30",
+            FrameCap::Fps60 => "60",
+            FrameCap::Fps120 => "120",
+            FrameCap::Uncapped => "uncapped"
+        }
+    }
+
+    // Milliseconds between draws, the way quicksilver's Settings wants it
+    pub fn draw_rate(&self) -> f64 {
+        match self {
+            FrameCap::Fps30 => 1000.0 / 30.0,
+            FrameCap::Fps60 => 1000.0 / 60.0,
+            FrameCap::Fps120 => 1000.0 / 120.0,
+            FrameCap::Uncapped => 0.0
+        }
+    }
+
+    pub fn next(&self) -> FrameCap {
+        match self {
+            FrameCap::Fps30 => FrameCap::Fps60,
+            FrameCap::Fps60 => FrameCap::Fps120,
+            FrameCap::Fps120 => FrameCap::Uncapped,
+            FrameCap::Uncapped => FrameCap::Fps30
+        }
+    }
+}
+
+// Whether to go easy on the GPU, switches itself on if the game can't keep up until the player picks a mode themselves
+pub struct PowerMode {
+    low_power: bool,
+    chosen_by_player: bool,
+    slow_seconds: f64
+}
+
+impl PowerMode {
+    // Starting in low power from the command line counts as the player choosing it
+    pub fn new(start_low_power: bool) -> PowerMode {
+        PowerMode { low_power: start_low_power, chosen_by_player: start_low_power, slow_seconds: 0.0 }
+    }
+
+    pub fn is_low_power(&self) -> bool {
+        self.low_power
+    }
+
+    pub fn toggle(&mut self) {
+        self.low_power = !self.low_power;
+        self.chosen_by_player = true;
+    }
+
+    // Feeds in how long the last frame took, returns true if that just switched low power mode on
+    pub fn record_frame(&mut self, frame_seconds: f64) -> bool {
+        if self.low_power || self.chosen_by_player {
+            return false;
+        }
+
+        if frame_seconds > SLOW_FRAME_SECONDS {
+            self.slow_seconds += frame_seconds;
+        }
+        else {
+            self.slow_seconds = 0.0;
+        }
+
+        self.low_power = self.slow_seconds >= SUSTAINED_SLOW_SECONDS;
+        self.low_power
+    }
+}
@@ -0,0 +1,55 @@
+use quicksilver::geom::Vector;
+
+// Where the player was looking when a slot was saved, so loading it puts them back there
+// Written next to the level saves as a few lines of text, the level files only hold what the tilemap crate knows about
+#[derive(Clone, Debug, PartialEq)]
+pub struct SavedView {
+    pub camera_position: Vector,
+    pub camera_height: f32,
+    // Index into the game's levels, 0 is the surface
+    pub level: usize,
+    pub show_help: bool
+}
+
+impl SavedView {
+    // One field per line, in the order they're declared
+    pub fn to_text(&self) -> String {
+        format!("{}\n{}\n{}\n{}\n{}\n", self.camera_position.x, self.camera_position.y, self.camera_height, self.level, self.show_help)
+    }
+
+    pub fn from_text(text: &str) -> Result<SavedView, String> {
+        let mut lines = text.lines();
+        let mut next = |name: &str| lines.next().map(str::trim).ok_or_else(|| format!("the {} is missing", name));
+
+        let x: f32 = next("camera x")?.parse().map_err(|_| "the camera x isn't a number".to_string())?;
+        let y: f32 = next("camera y")?.parse().map_err(|_| "the camera y isn't a number".to_string())?;
+        let camera_height: f32 = next("camera height")?.parse().map_err(|_| "the camera height isn't a number".to_string())?;
+        let level = next("level")?.parse().map_err(|_| "the level isn't a level number".to_string())?;
+        let show_help = next("help overlay")?.parse().map_err(|_| "the help overlay isn't true or false".to_string())?;
+
+        if !x.is_finite() || !y.is_finite() || !camera_height.is_finite() || camera_height <= 0.0 {
+            return Err("the camera is somewhere it can't be".to_string());
+        }
+        Ok(SavedView { camera_position: Vector::new(x, y), camera_height, level, show_help })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::saved_view::*;
+
+    #[test]
+    fn views_round_trip_through_text() {
+        let view = SavedView { camera_position: Vector::new(-12.5, 40.25), camera_height: 18.0, level: 1, show_help: true };
+        assert_eq!(SavedView::from_text(&view.to_text()), Ok(view));
+    }
+
+    #[test]
+    fn broken_views_are_errors() {
+        assert!(SavedView::from_text("").is_err());
+        assert!(SavedView::from_text("1\n2\n3\n").is_err());
+        assert!(SavedView::from_text("1\nabc\n3\n0\nfalse\n").is_err());
+        assert!(SavedView::from_text("1\n2\n0\n0\nfalse\n").is_err());
+        assert!(SavedView::from_text("1\n2\ninf\n0\nfalse\n").is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use crate::options::LaunchOptions;
+use crate::power::FrameCap;
+use std::path::Path;
+
+// Where the settings are kept between runs, next to the saves directory
+pub const SETTINGS_PATH: &str = "settings.txt";
+
+// Display settings the player keeps from one run to the next, launch flags win over whatever is in the file
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DisplaySettings {
+    pub frame_cap: FrameCap,
+    // Only read at startup, quicksilver has no way to change it once the window is open
+    pub vsync: bool
+}
+
+impl Default for DisplaySettings {
+    fn default() -> DisplaySettings {
+        DisplaySettings { frame_cap: FrameCap::Uncapped, vsync: true }
+    }
+}
+
+impl DisplaySettings {
+    // One field per line, in the order they're declared
+    pub fn to_text(self) -> String {
+        format!("{}\n{}\n", self.frame_cap.name(), self.vsync)
+    }
+
+    pub fn from_text(text: &str) -> Result<DisplaySettings, String> {
+        let mut lines = text.lines();
+        let mut next = |name: &str| lines.next().map(str::trim).ok_or_else(|| format!("the {} is missing", name));
+
+        let frame_cap = FrameCap::parse(next("frame rate cap")?).ok_or_else(|| "the frame rate cap isn't 30, 60, 120 or uncapped".to_string())?;
+        let vsync = next("vsync setting")?.parse().map_err(|_| "the vsync setting isn't true or false".to_string())?;
+        Ok(DisplaySettings { frame_cap, vsync })
+    }
+
+    // The defaults if there's no file yet, nothing has been changed from them
+    pub fn load(path: &Path) -> Result<DisplaySettings, String> {
+        if !path.exists() {
+            return Ok(DisplaySettings::default());
+        }
+        let text = std::fs::read_to_string(path).map_err(|error| format!("Couldn't read {}: {}", path.display(), error))?;
+        DisplaySettings::from_text(&text).map_err(|error| format!("Couldn't use {}, {}", path.display(), error))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_text()).map_err(|error| format!("Couldn't write {}: {}", path.display(), error))
+    }
+
+    // Flags given at launch replace what was saved, for this run only
+    pub fn overridden_by(&self, launch_options: &LaunchOptions) -> DisplaySettings {
+        DisplaySettings {
+            frame_cap: launch_options.frame_cap.unwrap_or(self.frame_cap),
+            vsync: launch_options.vsync.unwrap_or(self.vsync)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::settings::*;
+
+    #[test]
+    fn settings_round_trip_through_the_file() {
+        let path = std::env::temp_dir().join(format!("jam_game_settings_{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(DisplaySettings::load(&path), Ok(DisplaySettings::default()));
+
+        let settings = DisplaySettings { frame_cap: FrameCap::Fps60, vsync: false };
+        assert_eq!(settings.save(&path), Ok(()));
+        assert_eq!(DisplaySettings::load(&path), Ok(settings));
+
+        std::fs::write(&path, "45\ntrue\n").unwrap();
+        assert!(DisplaySettings::load(&path).is_err());
+        let _ = std::fs::remove_file(&path);
+
+        assert!(DisplaySettings::from_text("").is_err());
+        assert!(DisplaySettings::from_text("30\nmaybe\n").is_err());
+    }
+
+    #[test]
+    fn launch_flags_win_over_the_file() {
+        let saved = DisplaySettings { frame_cap: FrameCap::Fps30, vsync: false };
+        assert_eq!(saved.overridden_by(&LaunchOptions::default()), saved);
+
+        let launch_options = LaunchOptions { frame_cap: Some(FrameCap::Fps120), vsync: Some(true), ..LaunchOptions::default() };
+        assert_eq!(saved.overridden_by(&launch_options), DisplaySettings { frame_cap: FrameCap::Fps120, vsync: true });
+    }
+}
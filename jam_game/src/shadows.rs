@@ -0,0 +1,80 @@
+use tilemap::tile_world::{
+    GridCoord, TileValue,
+    NEIGHBOR_NORTH, NEIGHBOR_EAST, NEIGHBOR_SOUTH, NEIGHBOR_WEST,
+    NEIGHBOR_NORTH_EAST, NEIGHBOR_SOUTH_EAST, NEIGHBOR_SOUTH_WEST, NEIGHBOR_NORTH_WEST
+};
+
+use quicksilver::{
+    geom::Vector,
+    graphics::{Background::Col, Color, GpuTriangle, Mesh, Vertex},
+};
+
+// How far into an open tile the shadow from a rock edge reaches, in tiles
+const SHADOW_DEPTH: f32 = 0.35;
+// Alpha right up against the rock, fading out to nothing at SHADOW_DEPTH
+const SHADOW_STRENGTH: f32 = 0.55;
+
+// Cheap stand-in for ambient occlusion, open tiles darken towards whichever of their neighbours is solid
+// masks are neighbor_masks_in_area for is_solid over the same area as tiles, along rows from top_left
+pub fn draw_edge_shadows(mesh: &mut Mesh, top_left: &GridCoord, size: &GridCoord, tiles: &[TileValue], masks: &[u8], z: f32) {
+    for (index, (tile, mask)) in tiles.iter().zip(masks.iter()).enumerate() {
+        if *mask == 0 || tile.is_solid() || !casts_onto(tile) {
+            continue;
+        }
+        let x = top_left.x + index as i64 % size.x;
+        let y = top_left.y + index as i64 / size.x;
+        draw_tile_shadow(mesh, Vector::new(x as f32, y as f32), *mask, z);
+    }
+}
+
+// Nothing to shade past the world's edge or over tiles still being generated
+fn casts_onto(tile: &TileValue) -> bool {
+    !matches!(tile, TileValue::Void | TileValue::Generating)
+}
+
+fn draw_tile_shadow(mesh: &mut Mesh, corner: Vector, mask: u8, z: f32) {
+    let dark = Color::BLACK.with_alpha(SHADOW_STRENGTH);
+    let clear = Color::BLACK.with_alpha(0.0);
+    let (left, top, right, bottom) = (corner.x, corner.y, corner.x + 1.0, corner.y + 1.0);
+
+    // One strip along each solid side, dark at the edge and clear further in
+    if mask & NEIGHBOR_NORTH != 0 {
+        push_quad(mesh, [(left, top), (right, top), (right, top + SHADOW_DEPTH), (left, top + SHADOW_DEPTH)], [dark, dark, clear, clear], z);
+    }
+    if mask & NEIGHBOR_SOUTH != 0 {
+        push_quad(mesh, [(left, bottom), (right, bottom), (right, bottom - SHADOW_DEPTH), (left, bottom - SHADOW_DEPTH)], [dark, dark, clear, clear], z);
+    }
+    if mask & NEIGHBOR_WEST != 0 {
+        push_quad(mesh, [(left, top), (left, bottom), (left + SHADOW_DEPTH, bottom), (left + SHADOW_DEPTH, top)], [dark, dark, clear, clear], z);
+    }
+    if mask & NEIGHBOR_EAST != 0 {
+        push_quad(mesh, [(right, top), (right, bottom), (right - SHADOW_DEPTH, bottom), (right - SHADOW_DEPTH, top)], [dark, dark, clear, clear], z);
+    }
+
+    // Corners touching rock only diagonally get a little triangle, corners next to a solid side are already covered by its strip
+    let corners = [
+        (NEIGHBOR_NORTH_WEST, NEIGHBOR_NORTH | NEIGHBOR_WEST, (left, top), 1.0, 1.0),
+        (NEIGHBOR_NORTH_EAST, NEIGHBOR_NORTH | NEIGHBOR_EAST, (right, top), -1.0, 1.0),
+        (NEIGHBOR_SOUTH_EAST, NEIGHBOR_SOUTH | NEIGHBOR_EAST, (right, bottom), -1.0, -1.0),
+        (NEIGHBOR_SOUTH_WEST, NEIGHBOR_SOUTH | NEIGHBOR_WEST, (left, bottom), 1.0, -1.0)
+    ];
+    for (diagonal, sides, (x, y), towards_x, towards_y) in corners.iter() {
+        if mask & diagonal != 0 && mask & sides == 0 {
+            let offset = push_vertices(mesh, &[(*x, *y), (x + towards_x * SHADOW_DEPTH, *y), (*x, y + towards_y * SHADOW_DEPTH)], &[dark, clear, clear]);
+            mesh.triangles.push(GpuTriangle::new(offset, [0, 1, 2], z, Col(Color::BLACK)));
+        }
+    }
+}
+
+// Corners in order around the quad, each with its own colour so the GPU blends the gradient between them
+fn push_quad(mesh: &mut Mesh, corners: [(f32, f32); 4], colors: [Color; 4], z: f32) {
+    let offset = push_vertices(mesh, &corners, &colors);
+    mesh.triangles.push(GpuTriangle::new(offset, [0, 1, 2], z, Col(Color::BLACK)));
+    mesh.triangles.push(GpuTriangle::new(offset, [2, 3, 0], z, Col(Color::BLACK)));
+}
+
+fn push_vertices(mesh: &mut Mesh, positions: &[(f32, f32)], colors: &[Color]) -> u32 {
+    let offset = mesh.vertices.len() as u32;
+    mesh.vertices.extend(positions.iter().zip(colors.iter()).map(|(pos, col)| Vertex { pos: Vector::new(pos.0, pos.1), tex_pos: None, col: *col }));
+    offset
+}
@@ -0,0 +1,122 @@
+use std::time::{Duration, Instant};
+
+use tilemap::tile_world::{TileMap, TileValue, GridCoord};
+use tilemap::pathfinding::plan_corridor;
+
+// Room for a hab module and a corridor tile either side, so modules never end up jammed against each other
+const SLOT_SPACING: i64 = 5;
+// Tries per structure before giving up on finding it a free slot
+const ATTEMPTS_PER_STRUCTURE: usize = 8;
+// Neighbouring modules are never far apart, so corridor searches can be kept short
+const CORRIDOR_SEARCH_LIMIT: usize = 5000;
+
+// What build_stress_base managed, printed so runs at different sizes can be compared
+#[derive(Debug, Default)]
+pub struct StressReport {
+    pub structures: usize,
+    pub corridors: usize,
+    pub dug_tiles: usize,
+    pub elapsed: Duration
+}
+
+// Scatters hab modules over a patch of slots around center and joins each one to the one placed before it,
+// only going through the same placement and corridor calls the player's tools make
+// Ground is whatever the seeded world generates, so rock gets dug out for footprints and corridors along the way
+pub fn build_stress_base(world: &mut TileMap, center: &GridCoord, structures: usize, seed: u64) -> StressReport {
+    let start = Instant::now();
+    let mut report = StressReport::default();
+    let mut random = XorShift::new(seed);
+
+    // About half the slots end up used, so the base stays spread out enough to need corridors
+    let slots_per_side = ((structures * 2) as f64).sqrt().ceil() as i64 + 1;
+    let base_min = GridCoord{x: center.x - slots_per_side * SLOT_SPACING / 2, y: center.y - slots_per_side * SLOT_SPACING / 2};
+
+    let mut previous: Option<GridCoord> = None;
+    for _ in 0..(structures * ATTEMPTS_PER_STRUCTURE) {
+        if report.structures >= structures {
+            break;
+        }
+        let slot = GridCoord{x: random.below(slots_per_side as u64) as i64, y: random.below(slots_per_side as u64) as i64};
+        let pos = GridCoord{x: base_min.x + slot.x * SLOT_SPACING, y: base_min.y + slot.y * SLOT_SPACING};
+        if !place_module(world, &pos, &mut report) {
+            continue;
+        }
+
+        if let Some(previous) = previous {
+            connect(world, &previous, &pos, &mut report);
+        }
+        previous = Some(pos);
+    }
+
+    report.elapsed = start.elapsed();
+    report
+}
+
+// Digs out the footprint first if it needs it, the same way a corridor gets dug
+// The dig and the module go in one transaction, so a module that can't be placed leaves no hole behind
+fn place_module(world: &mut TileMap, pos: &GridCoord, report: &mut StressReport) -> bool {
+    let size = world.get_tile_size(&TileValue::HabModule);
+    let top_left = GridCoord{x: pos.x - size.x / 2, y: pos.y - size.y / 2};
+
+    let mut edit = world.begin_edit();
+    let mut dug = 0;
+    for y in top_left.y..(top_left.y + size.y) {
+        for x in top_left.x..(top_left.x + size.x) {
+            let tile = GridCoord{x, y};
+            let value = edit.sample(&tile);
+            if value.is_solid() {
+                edit.set(&tile, TileValue::Empty);
+                dug += 1;
+            } else if value != TileValue::Empty {
+                // Another building or something that can't be dug is in the way
+                edit.rollback();
+                return false;
+            }
+        }
+    }
+    edit.place(pos, &TileValue::HabModule);
+    if edit.commit().is_err() {
+        return false;
+    }
+
+    report.structures += 1;
+    report.dug_tiles += dug;
+    true
+}
+
+fn connect(world: &mut TileMap, from: &GridCoord, to: &GridCoord, report: &mut StressReport) {
+    let plan = match plan_corridor(world, from, to, CORRIDOR_SEARCH_LIMIT) {
+        Some(plan) => plan,
+        None => return
+    };
+
+    let mut edit = world.begin_edit();
+    for pos in plan.tiles.iter() {
+        if edit.sample(pos).is_solid() {
+            edit.set(pos, TileValue::Empty);
+        }
+    }
+    if edit.commit().is_ok() {
+        report.corridors += 1;
+        report.dug_tiles += plan.rock_tiles;
+    }
+}
+
+// Only has to be repeatable from the seed, not any good as random numbers go
+struct XorShift {
+    state: u64
+}
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        // Zero would stay zero forever
+        XorShift { state: seed | 1 }
+    }
+
+    fn below(&mut self, limit: u64) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state % limit.max(1)
+    }
+}
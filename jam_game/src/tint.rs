@@ -0,0 +1,39 @@
+use tilemap::tile_world::{TileValue, GridCoord};
+
+use quicksilver::graphics::Color;
+
+// Per-frame state tint providers can look at
+pub struct TintContext {
+    // None while there's no cursor, like in attract mode
+    pub selected_tile: Option<GridCoord>
+}
+
+// Returns the tint for a tile, or None to leave it alone
+pub type TintProvider = fn(&TintContext, &GridCoord, &TileValue) -> Option<Color>;
+
+// Every provider gets a say on every tile, tints from several providers multiply together
+#[derive(Default)]
+pub struct TintChain {
+    providers: Vec<TintProvider>
+}
+
+impl TintChain {
+    pub fn new() -> TintChain {
+        TintChain::default()
+    }
+
+    pub fn push(&mut self, provider: TintProvider) {
+        self.providers.push(provider);
+    }
+
+    pub fn tint_for(&self, context: &TintContext, pos: &GridCoord, value: &TileValue) -> Color {
+        self.providers.iter()
+            .filter_map(|provider| provider(context, pos, value))
+            .fold(Color::WHITE, |tint, provider_tint| tint.multiply(provider_tint))
+    }
+}
+
+// Warms up whatever is under the cursor, buildings are drawn from their anchor so the whole building gets it
+pub fn selection_highlight(context: &TintContext, pos: &GridCoord, _value: &TileValue) -> Option<Color> {
+    if Some(*pos) == context.selected_tile { Some(Color::from_rgba(255, 240, 160, 1.0)) } else { None }
+}
@@ -0,0 +1,88 @@
+#[macro_use]
+extern crate criterion;
+
+use criterion::{Criterion, black_box};
+use rand::Rng;
+
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, BuildHasherDefault};
+
+use ahash::AHasher;
+use tilemap::hashing::{FxHasher, FastHashMap};
+use tilemap::tile_world::{GridCoord, PartitionKey, PARTITION_SIZE};
+
+// Roughly a screen's worth of partitions around the origin, which is how many the map keeps changes for in a normal game
+const PARTITION_SPAN: i64 = 16;
+
+fn partition_keys() -> Vec<GridCoord> {
+    let mut keys = Vec::new();
+    for y in -PARTITION_SPAN..PARTITION_SPAN {
+        for x in -PARTITION_SPAN..PARTITION_SPAN {
+            keys.push(GridCoord{x: x * PARTITION_SIZE as i64, y: y * PARTITION_SIZE as i64});
+        }
+    }
+    keys
+}
+
+fn bench_hasher<S: BuildHasher + Default>(c: &mut Criterion, name: &str) {
+    let mut rng = rand::thread_rng();
+
+    // Lookups like sample does, one per tile, mostly hitting partitions that have changes
+    let keys = partition_keys();
+    let mut partitions: HashMap<GridCoord, u32, S> = HashMap::default();
+    for (index, key) in keys.iter().enumerate() {
+        partitions.insert(*key, index as u32);
+    }
+    c.bench_function(&format!("partition_lookup_{}", name), |b| b.iter(|| {
+        let key = keys[rng.gen_range(0, keys.len())];
+        black_box(partitions.get(&black_box(key)));
+    }));
+
+    // Sparse partitions key their changes by the tile's index within the partition
+    let mut changes: HashMap<u16, u16, S> = HashMap::default();
+    let partition_tiles = PARTITION_SIZE as u16 * PARTITION_SIZE as u16;
+    for index in (0..partition_tiles).step_by(3) {
+        changes.insert(index, index);
+    }
+    c.bench_function(&format!("change_lookup_{}", name), |b| b.iter(|| {
+        let index = rng.gen_range(0, partition_tiles);
+        black_box(changes.get(&black_box(index)));
+    }));
+
+    c.bench_function(&format!("partition_insert_{}", name), |b| b.iter(|| {
+        let mut fresh: HashMap<GridCoord, u32, S> = HashMap::default();
+        for (index, key) in keys.iter().enumerate() {
+            fresh.insert(*key, index as u32);
+        }
+        black_box(fresh);
+    }));
+}
+
+// Walks every partition in scan order like sample_rect does, keyed by coordinate and by Morton key
+fn bench_partition_keys(c: &mut Criterion) {
+    let keys = partition_keys();
+    let by_coord: FastHashMap<GridCoord, u32> = keys.iter().enumerate().map(|(index, key)| (*key, index as u32)).collect();
+    let by_morton: FastHashMap<PartitionKey, u32> = keys.iter().enumerate().map(|(index, key)| (PartitionKey::new(key), index as u32)).collect();
+
+    c.bench_function("partition_scan_grid_coord", |b| b.iter(|| {
+        for key in keys.iter() {
+            black_box(by_coord.get(&black_box(*key)));
+        }
+    }));
+    c.bench_function("partition_scan_morton", |b| b.iter(|| {
+        for key in keys.iter() {
+            black_box(by_morton.get(&PartitionKey::new(&black_box(*key))));
+        }
+    }));
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    bench_hasher::<RandomState>(c, "siphash");
+    bench_hasher::<BuildHasherDefault<FxHasher>>(c, "fxhash");
+    bench_hasher::<BuildHasherDefault<AHasher>>(c, "ahash");
+    bench_partition_keys(c);
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
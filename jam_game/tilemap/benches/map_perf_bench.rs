@@ -107,6 +107,25 @@ fn criterion_benchmark(c: &mut Criterion) {
         });
     
     }));
+
+    // Same as above with the tile cache off, to see how much the cache is saving
+    let mut uncached_world = TileMap::new();
+    uncached_world.set_caching_enabled(false);
+    c.bench_function("read_large_screen_rect_repeat_place_empty_world_uncached", |b| b.iter(|| {
+        let coord = GridCoord{x: black_box(0), y: black_box(0)};
+        let size = GridCoord{x: black_box(80), y: black_box(50)};
+        uncached_world.for_each_tile(&coord, &size, |pos, value, size| {
+            // Do something the doesn't know about so it can't optimize away these
+            black_box(pos);
+            black_box(value);
+            black_box(size);
+        });
+    }));
+
+    c.bench_function("sample_rect_large_screen_repeat_place_empty_world", |b| b.iter(|| {
+        let buffer = world.sample_rect(&GridCoord{x: black_box(0), y: black_box(0)}, &GridCoord{x: black_box(80), y: black_box(50)});
+        black_box(buffer);
+    }));
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,96 @@
+#ifndef TILEMAP_H
+#define TILEMAP_H
+
+/* Generated by cbindgen from src/ffi.rs with cbindgen.toml, don't edit it by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// What a tile is, the same kinds as TileValue with subtiles all as one
+enum TilemapTile
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : uint32_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  TILEMAP_TILE_EMPTY = 0,
+  TILEMAP_TILE_ROCK = 1,
+  TILEMAP_TILE_ERROR = 2,
+  TILEMAP_TILE_HAB_MODULE = 3,
+  // Part of a bigger building, tilemap_anchor finds the building
+  TILEMAP_TILE_SUBTILE = 4,
+  TILEMAP_TILE_GENERATING = 5,
+  TILEMAP_TILE_FORBIDDEN = 6,
+  TILEMAP_TILE_VOID = 7,
+  TILEMAP_TILE_LADDER = 8,
+  TILEMAP_TILE_IRON_ORE = 9,
+  TILEMAP_TILE_ICE_DEPOSIT = 10,
+  TILEMAP_TILE_CRASHED_PROBE = 11,
+  TILEMAP_TILE_CRYSTAL_CLUSTER = 12,
+  TILEMAP_TILE_ABANDONED_MODULE = 13,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum TilemapTile TilemapTile;
+#else
+typedef uint32_t TilemapTile;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+typedef struct TileMap TileMap;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// A new endless map generated from seed, free it with tilemap_free
+TileMap *tilemap_create(uint64_t seed);
+
+// # Safety
+// map has to be null or a map from tilemap_create or tilemap_load that hasn't been freed yet
+void tilemap_free(TileMap *map);
+
+// Error if map is null
+//
+// # Safety
+// map has to be null or a live map
+TilemapTile tilemap_sample(const TileMap *map, int64_t x, int64_t y);
+
+// Writes the anchor of the building covering x, y to anchor_x and anchor_y, false if there isn't a building there
+//
+// # Safety
+// map has to be null or a live map, anchor_x and anchor_y have to be null or writable
+bool tilemap_anchor(const TileMap *map, int64_t x, int64_t y, int64_t *anchor_x, int64_t *anchor_y);
+
+// Same as TileMap::make_change, tile is a TilemapTile
+// False if map is null, tile is something that can't be written (Subtile, Generating, Forbidden, Void, Error),
+// or the map left the tile alone because some of it would be outside a bounded world
+//
+// # Safety
+// map has to be null or a live map
+bool tilemap_make_change(TileMap *map, int64_t x, int64_t y, uint32_t tile);
+
+// The map's save as bytes, with its length written to length, null if it couldn't be saved
+// Give the buffer back to tilemap_free_buffer along with the same length
+//
+// # Safety
+// map has to be null or a live map, length has to be writable
+uint8_t *tilemap_save(const TileMap *map, uintptr_t *length);
+
+// # Safety
+// buffer has to be null or a buffer from tilemap_save with the length it came with, and not freed already
+void tilemap_free_buffer(uint8_t *buffer, uintptr_t length);
+
+// Loads a save from length bytes at bytes, null if it isn't a save this version can read
+// The bytes are only read during the call, free the map with tilemap_free
+//
+// # Safety
+// bytes has to be null or point to at least length readable bytes
+TileMap *tilemap_load(const uint8_t *bytes, uintptr_t length);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TILEMAP_H */
@@ -0,0 +1,139 @@
+// Prints what differs between two tile map saves, for tracking down saves that broke and checking migrations
+// Usage: save_diff <old save> <new save>
+// Exits with 0 if they hold the same world, 1 if they differ or either has broken buildings, and 2 if either couldn't be loaded
+extern crate tilemap;
+
+use std::collections::{HashMap, HashSet};
+use tilemap::tile_world::{TileMap, TileValue, TileDataValue, GridCoord, MapLayer, PARTITION_SIZE};
+
+// Without the repair a normal load does, so broken buildings show up instead of being quietly fixed
+fn load(path: &str) -> Result<TileMap, String> {
+    let bytes = std::fs::read(path).map_err(|error| format!("Couldn't read {}: {}", path, error))?;
+    TileMap::load_from_reader_unrepaired(&bytes[..]).map_err(|error| format!("Couldn't load {}: {:?}", path, error))
+}
+
+// Returns how many problems were printed
+fn report_integrity(path: &str, map: &TileMap) -> usize {
+    match map.validate_integrity() {
+        Ok(()) => 0,
+        Err(errors) => {
+            println!("{} has {} integrity problems, loading it in the game would repair them", path, errors.len());
+            for error in errors.iter() {
+                println!("    {:?}", error);
+            }
+            errors.len()
+        }
+    }
+}
+
+// Every change on every layer, what sample would see is the generator's business and the same for the same seed
+fn changes(map: &TileMap) -> Result<HashMap<(MapLayer, GridCoord), TileValue>, String> {
+    let mut changes = HashMap::new();
+    for layer in MapLayer::ALL.iter() {
+        map.for_each_changed_tile(*layer, None, |pos: &GridCoord, value: &TileValue| { changes.insert((*layer, *pos), *value); })
+            .map_err(|error| format!("Couldn't read a paged out partition: {:?}", error))?;
+    }
+    Ok(changes)
+}
+
+fn describe<T: std::fmt::Debug>(value: Option<&T>) -> String {
+    match value {
+        Some(value) => format!("{:?}", value),
+        None => "(nothing)".to_string()
+    }
+}
+
+// Returns how many differences were printed
+fn diff_header(old: &TileMap, new: &TileMap) -> usize {
+    let mut differences = 0;
+    if old.seed() != new.seed() {
+        println!("Seed: {} -> {}", old.seed(), new.seed());
+        differences += 1;
+    }
+    if old.generator_config() != new.generator_config() {
+        println!("Generator config: {:?} -> {:?}", old.generator_config(), new.generator_config());
+        differences += 1;
+    }
+    if old.bounds() != new.bounds() {
+        println!("Bounds: {:?} -> {:?}", old.bounds(), new.bounds());
+        differences += 1;
+    }
+    differences
+}
+
+fn diff_tiles(old: &TileMap, new: &TileMap) -> Result<usize, String> {
+    let (old_changes, new_changes) = (changes(old)?, changes(new)?);
+    let keys: HashSet<&(MapLayer, GridCoord)> = old_changes.keys().chain(new_changes.keys()).collect();
+
+    // Grouped by partition so damage limited to one record stands out
+    let partition_mask = !(PARTITION_SIZE as i64 - 1);
+    let mut by_partition: HashMap<GridCoord, Vec<(MapLayer, GridCoord)>> = HashMap::new();
+    for key in keys.into_iter().filter(|key| old_changes.get(key) != new_changes.get(key)) {
+        by_partition.entry(GridCoord{x: key.1.x & partition_mask, y: key.1.y & partition_mask}).or_default().push(*key);
+    }
+    let mut partitions: Vec<GridCoord> = by_partition.keys().copied().collect();
+    partitions.sort_by_key(|partition| (partition.y, partition.x));
+
+    let mut differences = 0;
+    for partition in partitions.iter() {
+        let tiles = by_partition.get_mut(partition).unwrap();
+        tiles.sort_by_key(|(layer, pos)| (*layer as usize, pos.y, pos.x));
+        println!("Partition {}, {}: {} tiles differ", partition.x, partition.y, tiles.len());
+        for key in tiles.iter() {
+            println!("    {:?} at {}, {}: {} -> {}", key.0, key.1.x, key.1.y, describe(old_changes.get(key)), describe(new_changes.get(key)));
+        }
+        differences += tiles.len();
+    }
+    Ok(differences)
+}
+
+fn diff_tile_data(old: &TileMap, new: &TileMap) -> usize {
+    let index = |map: &TileMap| map.tile_data_entries().into_iter().map(|(pos, key, value)| ((pos.y, pos.x, key), value)).collect::<HashMap<(i64, i64, String), TileDataValue>>();
+    let (old_data, new_data) = (index(old), index(new));
+    let mut keys: Vec<&(i64, i64, String)> = old_data.keys().chain(new_data.keys()).collect::<HashSet<_>>().into_iter()
+        .filter(|key| old_data.get(key) != new_data.get(key))
+        .collect();
+    keys.sort();
+    for key in keys.iter() {
+        println!("Tile data {} at {}, {}: {} -> {}", key.2, key.1, key.0, describe(old_data.get(key)), describe(new_data.get(key)));
+    }
+    keys.len()
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("Usage: save_diff <old save> <new save>");
+        std::process::exit(2);
+    }
+
+    let loaded = load(&args[1]).and_then(|old| load(&args[2]).map(|new| (old, new)));
+    let (old, new) = match loaded {
+        Ok(maps) => maps,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+
+    let problems = report_integrity(&args[1], &old) + report_integrity(&args[2], &new);
+    let header_differences = diff_header(&old, &new);
+    let tile_differences = match diff_tiles(&old, &new) {
+        Ok(differences) => differences,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+    let differences = header_differences + tile_differences + diff_tile_data(&old, &new);
+    // Saves only ever hold the tile map, there are no entities or resources in them to compare yet
+    if differences == 0 {
+        println!("No differences");
+    }
+    else {
+        println!("{} differences", differences);
+    }
+    if differences > 0 || problems > 0 {
+        std::process::exit(1);
+    }
+}
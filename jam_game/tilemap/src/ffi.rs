@@ -0,0 +1,292 @@
+// C API for tools outside Rust, built with: cargo rustc --release --features ffi --crate-type cdylib
+// include/tilemap.h is generated from this by cbindgen, regenerate it after any change here (the command is in cbindgen.toml)
+// Maps are opaque to C, every map from tilemap_create or tilemap_load has to go back through tilemap_free
+use crate::tile_world::{TileMap, TileValue, GridCoord};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+/// What a tile is, the same kinds as TileValue with subtiles all as one
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TilemapTile {
+    Empty = 0,
+    Rock = 1,
+    Error = 2,
+    HabModule = 3,
+    /// Part of a bigger building, tilemap_anchor finds the building
+    Subtile = 4,
+    Generating = 5,
+    Forbidden = 6,
+    Void = 7,
+    Ladder = 8,
+    IronOre = 9,
+    IceDeposit = 10,
+    CrashedProbe = 11,
+    CrystalCluster = 12,
+    AbandonedModule = 13
+}
+
+fn tile_of(value: &TileValue) -> TilemapTile {
+    match value {
+        TileValue::Empty => TilemapTile::Empty,
+        TileValue::Rock => TilemapTile::Rock,
+        TileValue::HabModule => TilemapTile::HabModule,
+        TileValue::Subtile(_) => TilemapTile::Subtile,
+        TileValue::Generating => TilemapTile::Generating,
+        TileValue::Forbidden => TilemapTile::Forbidden,
+        TileValue::Void => TilemapTile::Void,
+        TileValue::Ladder => TilemapTile::Ladder,
+        TileValue::IronOre => TilemapTile::IronOre,
+        TileValue::IceDeposit => TilemapTile::IceDeposit,
+        TileValue::CrashedProbe => TilemapTile::CrashedProbe,
+        TileValue::CrystalCluster => TilemapTile::CrystalCluster,
+        TileValue::AbandonedModule => TilemapTile::AbandonedModule,
+        TileValue::Error | TileValue::InternalUnknown => TilemapTile::Error
+    }
+}
+
+// Tiles come in from C as plain numbers, anything that isn't terrain or a building is None
+// Forbidden zones are player orders rather than something on the tile, the C API has no designations
+fn value_of(tile: u32) -> Option<TileValue> {
+    match tile {
+        0 => Some(TileValue::Empty),
+        1 => Some(TileValue::Rock),
+        3 => Some(TileValue::HabModule),
+        8 => Some(TileValue::Ladder),
+        9 => Some(TileValue::IronOre),
+        10 => Some(TileValue::IceDeposit),
+        11 => Some(TileValue::CrashedProbe),
+        12 => Some(TileValue::CrystalCluster),
+        13 => Some(TileValue::AbandonedModule),
+        _ => None
+    }
+}
+
+// Panics can't unwind into C, so every entry point runs its body through this and hands back failed instead
+fn guarded<T>(failed: T, body: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(body)).unwrap_or(failed)
+}
+
+/// A new endless map generated from seed, free it with tilemap_free
+#[no_mangle]
+pub extern "C" fn tilemap_create(seed: u64) -> *mut TileMap {
+    guarded(ptr::null_mut(), || {
+        Box::into_raw(Box::new(TileMap::with_seed(seed)))
+    })
+}
+
+/// # Safety
+/// map has to be null or a map from tilemap_create or tilemap_load that hasn't been freed yet
+#[no_mangle]
+pub unsafe extern "C" fn tilemap_free(map: *mut TileMap) {
+    guarded((), || {
+        if !map.is_null() {
+            drop(Box::from_raw(map));
+        }
+    })
+}
+
+/// Error if map is null
+///
+/// # Safety
+/// map has to be null or a live map
+#[no_mangle]
+pub unsafe extern "C" fn tilemap_sample(map: *const TileMap, x: i64, y: i64) -> TilemapTile {
+    guarded(TilemapTile::Error, || {
+        match map.as_ref() {
+            Some(map) => tile_of(&map.sample(&GridCoord{x, y})),
+            None => TilemapTile::Error
+        }
+    })
+}
+
+/// Writes the anchor of the building covering x, y to anchor_x and anchor_y, false if there isn't a building there
+///
+/// # Safety
+/// map has to be null or a live map, anchor_x and anchor_y have to be null or writable
+#[no_mangle]
+pub unsafe extern "C" fn tilemap_anchor(map: *const TileMap, x: i64, y: i64, anchor_x: *mut i64, anchor_y: *mut i64) -> bool {
+    guarded(false, || {
+        let (map, anchor_x, anchor_y) = match (map.as_ref(), anchor_x.as_mut(), anchor_y.as_mut()) {
+            (Some(map), Some(anchor_x), Some(anchor_y)) => (map, anchor_x, anchor_y),
+            _ => return false
+        };
+        let pos = GridCoord{x, y};
+        let anchor = match map.sample(&pos) {
+            TileValue::Subtile(anchor) => anchor,
+            value if map.get_tile_size(&value) != (GridCoord{x: 1, y: 1}) => pos,
+            _ => return false
+        };
+        *anchor_x = anchor.x;
+        *anchor_y = anchor.y;
+        true
+    })
+}
+
+/// Same as TileMap::make_change, tile is a TilemapTile
+/// False if map is null, tile is something that can't be written (Subtile, Generating, Forbidden, Void, Error),
+/// or the map left the tile alone because some of it would be outside a bounded world
+///
+/// # Safety
+/// map has to be null or a live map
+#[no_mangle]
+pub unsafe extern "C" fn tilemap_make_change(map: *mut TileMap, x: i64, y: i64, tile: u32) -> bool {
+    guarded(false, || {
+        match (map.as_mut(), value_of(tile)) {
+            (Some(map), Some(value)) => map.make_change(&GridCoord{x, y}, &value),
+            _ => false
+        }
+    })
+}
+
+/// The map's save as bytes, with its length written to length, null if it couldn't be saved
+/// Give the buffer back to tilemap_free_buffer along with the same length
+///
+/// # Safety
+/// map has to be null or a live map, length has to be writable
+#[no_mangle]
+pub unsafe extern "C" fn tilemap_save(map: *const TileMap, length: *mut usize) -> *mut u8 {
+    guarded(ptr::null_mut(), || {
+        let (map, length) = match (map.as_ref(), length.as_mut()) {
+            (Some(map), Some(length)) => (map, length),
+            _ => return ptr::null_mut()
+        };
+        match map.to_bytes() {
+            Ok(bytes) => {
+                *length = bytes.len();
+                Box::into_raw(bytes.into_boxed_slice()) as *mut u8
+            }
+            Err(_) => ptr::null_mut()
+        }
+    })
+}
+
+/// # Safety
+/// buffer has to be null or a buffer from tilemap_save with the length it came with, and not freed already
+#[no_mangle]
+pub unsafe extern "C" fn tilemap_free_buffer(buffer: *mut u8, length: usize) {
+    guarded((), || {
+        if !buffer.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buffer, length)));
+        }
+    })
+}
+
+/// Loads a save from length bytes at bytes, null if it isn't a save this version can read
+/// The bytes are only read during the call, free the map with tilemap_free
+///
+/// # Safety
+/// bytes has to be null or point to at least length readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn tilemap_load(bytes: *const u8, length: usize) -> *mut TileMap {
+    guarded(ptr::null_mut(), || {
+        if bytes.is_null() {
+            return ptr::null_mut();
+        }
+        match TileMap::from_bytes(std::slice::from_raw_parts(bytes, length)) {
+            Ok(map) => Box::into_raw(Box::new(map)),
+            Err(_) => ptr::null_mut()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ffi::*;
+    use crate::tile_world::WorldBounds;
+
+    #[test]
+    fn maps_round_trip_through_the_c_api() {
+        unsafe {
+            let map = tilemap_create(11);
+            assert!(tilemap_make_change(map, 4, 4, TilemapTile::Empty as u32));
+            assert!(tilemap_make_change(map, 10, 10, TilemapTile::HabModule as u32));
+            assert!(!tilemap_make_change(map, 0, 0, TilemapTile::Subtile as u32));
+            assert!(!tilemap_make_change(map, 4, 4, TilemapTile::Forbidden as u32));
+            assert!(!tilemap_make_change(map, 0, 0, 99));
+
+            let mut length = 0;
+            let buffer = tilemap_save(map, &mut length);
+            assert!(!buffer.is_null());
+            let loaded = tilemap_load(buffer, length);
+            tilemap_free_buffer(buffer, length);
+            tilemap_free(map);
+
+            assert_eq!(tilemap_sample(loaded, 4, 4), TilemapTile::Empty);
+            assert_eq!(tilemap_sample(loaded, 11, 9), TilemapTile::Subtile);
+            let (mut anchor_x, mut anchor_y) = (0, 0);
+            assert!(tilemap_anchor(loaded, 11, 9, &mut anchor_x, &mut anchor_y));
+            assert_eq!((anchor_x, anchor_y), (10, 10));
+            assert!(!tilemap_anchor(loaded, 4, 4, &mut anchor_x, &mut anchor_y));
+            tilemap_free(loaded);
+
+            assert_eq!(tilemap_sample(std::ptr::null(), 0, 0), TilemapTile::Error);
+            assert!(tilemap_load(b"not a save".as_ptr(), 10).is_null());
+        }
+        // Anything that does panic comes back as the failure value instead of unwinding out
+        assert_eq!(guarded(TilemapTile::Rock, || panic!("tile map bug")), TilemapTile::Rock);
+    }
+
+    #[test]
+    fn writes_the_map_drops_come_back_false() {
+        let mut bounded = TileMap::with_seed(11);
+        bounded.set_bounds(Some(WorldBounds { top_left: GridCoord{x: 0, y: 0}, size: GridCoord{x: 10, y: 10}, wrap: false }));
+        let bytes = bounded.to_bytes().unwrap();
+        unsafe {
+            let map = tilemap_load(bytes.as_ptr(), bytes.len());
+            assert!(!map.is_null());
+            assert!(!tilemap_make_change(map, 12, 4, TilemapTile::Empty as u32));
+            // Anchored inside, but the footprint hangs off the edge
+            assert!(!tilemap_make_change(map, 9, 5, TilemapTile::HabModule as u32));
+            assert_ne!(tilemap_sample(map, 9, 5), TilemapTile::HabModule);
+            assert!(tilemap_make_change(map, 5, 5, TilemapTile::HabModule as u32));
+            assert_eq!(tilemap_sample(map, 5, 5), TilemapTile::HabModule);
+            tilemap_free(map);
+        }
+    }
+
+    // Variant names in the header are screaming snake case with the enum's name in front, the way the C side expects
+    fn header_variant(name: &str) -> String {
+        let mut variant = "TILEMAP_TILE".to_string();
+        for character in name.chars() {
+            if character.is_uppercase() {
+                variant.push('_');
+            }
+            variant.push(character.to_ascii_uppercase());
+        }
+        variant
+    }
+
+    // Catches the checked in header not being regenerated after a change here
+    #[test]
+    fn header_matches_the_c_api() {
+        let header = include_str!("../include/tilemap.h");
+        let source = include_str!("ffi.rs");
+
+        let functions: Vec<&str> = source.lines()
+            .filter_map(|line| line.strip_prefix("pub extern \"C\" fn ").or_else(|| line.strip_prefix("pub unsafe extern \"C\" fn ")))
+            .map(|line| &line[..line.find('(').unwrap()])
+            .collect();
+        assert_eq!(functions.len(), 8);
+        for function in functions.iter() {
+            assert!(header.contains(&format!(" *{}(", function)) || header.contains(&format!(" {}(", function)), "{} isn't declared in tilemap.h", function);
+        }
+        // Nothing in the header that's gone from here
+        let declared = header.lines().filter(|line| line.contains(" tilemap_") || line.contains("*tilemap_")).filter(|line| !line.trim_start().starts_with("//")).count();
+        assert_eq!(declared, functions.len());
+
+        let variants: Vec<(String, u32)> = source.lines()
+            .skip_while(|line| !line.starts_with("pub enum TilemapTile"))
+            .take_while(|line| !line.starts_with('}'))
+            .filter_map(|line| {
+                let mut parts = line.trim().trim_end_matches(',').split(" = ");
+                Some((parts.next()?.to_string(), parts.next()?.parse().ok()?))
+            })
+            .collect();
+        assert_eq!(variants.len(), TilemapTile::AbandonedModule as usize + 1);
+        for (name, code) in variants.iter() {
+            let line = format!("  {} = {},", header_variant(name), code);
+            assert!(header.lines().any(|header_line| header_line == line), "tilemap.h is missing {}", line.trim());
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+
+// The hasher rustc uses for its own tables (FxHash), one multiply per word
+// Map keys here are tile/partition coordinates and packed ids the player can't choose, so SipHash's protection
+// against crafted keys isn't worth what it costs on the map's hottest path
+// benches/hasher_bench.rs has the numbers against SipHash and ahash
+#[derive(Copy, Clone, Default)]
+pub struct FxHasher {
+    hash: u64
+}
+
+const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+impl FxHasher {
+    #[inline]
+    fn add_to_hash(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(SEED);
+    }
+}
+
+impl Hasher for FxHasher {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            self.add_to_hash(u64::from_le_bytes(word));
+        }
+        for byte in chunks.remainder() {
+            self.add_to_hash(u64::from(*byte));
+        }
+    }
+
+    #[inline]
+    fn write_u8(&mut self, value: u8) {
+        self.add_to_hash(u64::from(value));
+    }
+
+    #[inline]
+    fn write_u16(&mut self, value: u16) {
+        self.add_to_hash(u64::from(value));
+    }
+
+    #[inline]
+    fn write_u32(&mut self, value: u32) {
+        self.add_to_hash(u64::from(value));
+    }
+
+    #[inline]
+    fn write_u64(&mut self, value: u64) {
+        self.add_to_hash(value);
+    }
+
+    #[inline]
+    fn write_i64(&mut self, value: i64) {
+        self.add_to_hash(value as u64);
+    }
+
+    #[inline]
+    fn write_usize(&mut self, value: usize) {
+        self.add_to_hash(value as u64);
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+pub type FastHashBuilder = BuildHasherDefault<FxHasher>;
+
+// What the map's partition and change tables are built on, swap the builder here to try another hasher everywhere at once
+pub type FastHashMap<K, V> = HashMap<K, V, FastHashBuilder>;
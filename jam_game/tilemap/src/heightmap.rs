@@ -0,0 +1,144 @@
+use crate::tile_world::{TileMap, TileValue, GridCoord, WorldBounds, Blueprint, EditError, mix_hash};
+
+// How high ground has to be to become rock, and how much of the highest rock is ore
+// Heights run from 0 for black to 1 for white
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HeightmapRules {
+    // Stretches the image's darkest pixel to 0 and its brightest to 1, DEM exports rarely use the whole range
+    pub normalize: bool,
+    // Lower ground is open, the rest is rock
+    pub rock_height: f32,
+    // Rock this high or higher might be iron ore, the chance rising from nothing here to ore_chance at the very top
+    pub ore_height: f32,
+    pub ore_chance: f32,
+    // Picks which rock is ore, the same image and seed always give the same tiles
+    pub seed: u64
+}
+
+impl Default for HeightmapRules {
+    fn default() -> HeightmapRules {
+        HeightmapRules { normalize: true, rock_height: 0.4, ore_height: 0.75, ore_chance: 0.3, seed: 0 }
+    }
+}
+
+#[derive(Debug)]
+pub enum HeightmapError {
+    Image(image::ImageError),
+    // A world has to have at least one tile
+    EmptyImage,
+    Edit(EditError)
+}
+
+impl From<image::ImageError> for HeightmapError {
+    fn from(error: image::ImageError) -> HeightmapError {
+        HeightmapError::Image(error)
+    }
+}
+
+impl From<EditError> for HeightmapError {
+    fn from(error: EditError) -> HeightmapError {
+        HeightmapError::Edit(error)
+    }
+}
+
+// One tile per pixel of a grayscale PNG, colour images are turned grey first
+pub fn blueprint_from_heightmap(png: &[u8], rules: &HeightmapRules) -> Result<Blueprint, HeightmapError> {
+    let heights = image::load_from_memory_with_format(png, image::ImageFormat::PNG)?.to_luma();
+    let (width, height) = heights.dimensions();
+    if width == 0 || height == 0 {
+        return Err(HeightmapError::EmptyImage);
+    }
+
+    let (mut lowest, mut highest) = (0u8, 255u8);
+    if rules.normalize {
+        lowest = heights.pixels().map(|pixel| pixel[0]).min().unwrap_or(0);
+        highest = heights.pixels().map(|pixel| pixel[0]).max().unwrap_or(255);
+    }
+    // A completely flat image is all low ground
+    let range = (highest as f32 - lowest as f32).max(1.0);
+
+    let mut terrain = Vec::with_capacity(width as usize * height as usize);
+    for (x, y, pixel) in heights.enumerate_pixels() {
+        let ground_height = (pixel[0] as f32 - lowest as f32).max(0.0) / range;
+        terrain.push(Some(terrain_for_height(ground_height, &GridCoord{x: x as i64, y: y as i64}, rules)));
+    }
+    // Only fails if the sizes disagree, and terrain has one tile per pixel
+    Blueprint::from_parts(GridCoord{x: width as i64, y: height as i64}, terrain, Vec::new()).map_err(|_| HeightmapError::EmptyImage)
+}
+
+fn terrain_for_height(ground_height: f32, pos: &GridCoord, rules: &HeightmapRules) -> TileValue {
+    if ground_height < rules.rock_height {
+        return TileValue::Empty;
+    }
+    if ground_height >= rules.ore_height && rules.ore_height < 1.0 {
+        let chance = rules.ore_chance * (ground_height - rules.ore_height) / (1.0 - rules.ore_height);
+        let roll = (mix_hash(rules.seed ^ mix_hash(((pos.x as u64) << 32) ^ (pos.y as u64 & 0xFFFF_FFFF))) >> 40) as f32 / (1u64 << 24) as f32;
+        if roll < chance {
+            return TileValue::IronOre;
+        }
+    }
+    TileValue::Rock
+}
+
+// Pastes the heightmap's ground with its top left at pos, as one undo step
+pub fn import_heightmap(map: &mut TileMap, png: &[u8], pos: &GridCoord, rules: &HeightmapRules) -> Result<(), HeightmapError> {
+    let blueprint = blueprint_from_heightmap(png, rules)?;
+    map.paste(&blueprint, pos)?;
+    Ok(())
+}
+
+// A world exactly the size of the heightmap, nothing beyond its edges
+// seed is still the map's seed, points of interest and anything else generated on top come from it
+pub fn world_from_heightmap(png: &[u8], rules: &HeightmapRules, seed: u64) -> Result<TileMap, HeightmapError> {
+    let blueprint = blueprint_from_heightmap(png, rules)?;
+    let mut map = TileMap::with_seed(seed);
+    map.set_bounds(Some(WorldBounds { top_left: GridCoord{x: 0, y: 0}, size: blueprint.size(), wrap: false }));
+    map.paste(&blueprint, &GridCoord{x: 0, y: 0})?;
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tile_world::{TileValue, GridCoord};
+    use crate::raster::RgbaBuffer;
+    use crate::heightmap::{world_from_heightmap, blueprint_from_heightmap, HeightmapRules, HeightmapError};
+
+    fn heightmap_png(width: u32, height: u32, brightness: fn(u32, u32) -> u8) -> Vec<u8> {
+        let mut image = RgbaBuffer::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let value = brightness(x, y);
+                image.set_pixel(x, y, [value, value, value, 255]);
+            }
+        }
+        let mut png = Vec::new();
+        image.write_png(&mut png).unwrap();
+        png
+    }
+
+    #[test]
+    fn heightmaps_become_bounded_worlds() {
+        // A slope from dark on the left to bright on the right
+        let png = heightmap_png(16, 8, |x, _| 20 + (x * 10) as u8);
+        let rules = HeightmapRules { ore_chance: 1.0, ..HeightmapRules::default() };
+        let map = world_from_heightmap(&png, &rules, 7).unwrap();
+
+        assert_eq!(map.sample(&GridCoord{x: 0, y: 3}), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 9, y: 3}), TileValue::Rock);
+        // Brightest column after stretching, so it's ore every time
+        assert_eq!(map.sample(&GridCoord{x: 15, y: 0}), TileValue::IronOre);
+        assert_eq!(map.sample(&GridCoord{x: 16, y: 0}), TileValue::Void);
+        assert_eq!(map.sample(&GridCoord{x: 3, y: -1}), TileValue::Void);
+
+        // Without stretching the same slope never gets bright enough for ore
+        let unstretched = blueprint_from_heightmap(&png, &HeightmapRules { normalize: false, ..rules }).unwrap();
+        assert_eq!(unstretched.size(), GridCoord{x: 16, y: 8});
+        assert_eq!(unstretched.terrain_at(&GridCoord{x: 15, y: 0}), Some(TileValue::Rock));
+        assert_eq!(unstretched.terrain_at(&GridCoord{x: 7, y: 0}), Some(TileValue::Empty));
+
+        match blueprint_from_heightmap(b"not a png", &rules) {
+            Err(HeightmapError::Image(_)) => {}
+            other => panic!("Expected an image error, got {:?}", other.map(|blueprint| blueprint.size()))
+        }
+    }
+}
@@ -1,12 +1,39 @@
 extern crate quicksilver;
 extern crate lru;
+extern crate serde;
+extern crate serde_cbor;
+extern crate serde_json;
+extern crate miniz_oxide;
+#[cfg(feature = "image")]
+extern crate image;
+
+pub mod hashing;
+pub mod pathfinding;
+pub mod tiled;
+pub mod raster;
+#[cfg(feature = "image")]
+pub mod heightmap;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 
 pub mod tile_world {
-    use noise::{NoiseFn, HybridMulti};
-    use std::collections::{HashMap, HashSet};
+    use noise::{NoiseFn, HybridMulti, MultiFractal, Seedable};
+    use std::collections::{HashMap, HashSet, VecDeque};
+    use crate::hashing::FastHashMap;
+    use crate::raster::{RgbaBuffer, TilePalette, default_palette};
+    use std::cell::RefCell;
+    use std::convert::TryFrom;
     use quicksilver::geom::Rectangle;
+    use lru::LruCache;
+    use serde::{Serialize, Deserialize};
+    use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::thread;
 
-    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    #[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
     pub struct GridCoord {
         pub x: i64,
         pub y: i64
@@ -19,7 +46,49 @@ pub mod tile_world {
         }
     }
 
-    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    // Rectangle of tiles a finite world is limited to, everything outside samples as Void and can't be written to
+    // Unless it wraps, then walking off one edge comes back in at the opposite one (a small asteroid rather than a walled in map)
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct WorldBounds {
+        pub top_left: GridCoord,
+        pub size: GridCoord,
+        #[serde(default)]
+        pub wrap: bool
+    }
+
+    impl WorldBounds {
+        pub fn contains(&self, pos: &GridCoord) -> bool {
+            GridCoord::is_within_bounds(&self.top_left, &self.size, pos)
+        }
+
+        // The tile pos is once wrapped back inside the rectangle, pos itself if the world doesn't wrap
+        pub fn wrap(&self, pos: &GridCoord) -> GridCoord {
+            if !self.wrap || self.size.x <= 0 || self.size.y <= 0 {
+                return *pos;
+            }
+            GridCoord {
+                x: self.top_left.x + (pos.x - self.top_left.x).rem_euclid(self.size.x),
+                y: self.top_left.y + (pos.y - self.top_left.y).rem_euclid(self.size.y)
+            }
+        }
+
+        // Whichever copy of pos is closest to near, so a building just over the seam is drawn next to the camera
+        pub fn nearest_copy(&self, pos: &GridCoord, near: &GridCoord) -> GridCoord {
+            if !self.wrap || self.size.x <= 0 || self.size.y <= 0 {
+                return *pos;
+            }
+            let offset_x = (pos.x - near.x + self.size.x / 2).rem_euclid(self.size.x) - self.size.x / 2;
+            let offset_y = (pos.y - near.y + self.size.y / 2).rem_euclid(self.size.y) - self.size.y / 2;
+            GridCoord { x: near.x + offset_x, y: near.y + offset_y }
+        }
+
+        pub fn contains_area(&self, top_left: &GridCoord, size: &GridCoord) -> bool {
+            size.x <= 0 || size.y <= 0 ||
+                (self.contains(top_left) && self.contains(&GridCoord{x: top_left.x + size.x - 1, y: top_left.y + size.y - 1}))
+        }
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
     pub enum TileValue {
         Empty,
         Rock,
@@ -27,663 +96,6182 @@ pub mod tile_world {
         HabModule,
 
         Subtile(GridCoord), // Subtiles have a GridCoord that points at the true position of the metatile 
-        InternalUnknown // Special value for when using dense storage for values that have not yet been computed
+        InternalUnknown, // Special value for when using dense storage for values that have not yet been computed
+        Generating, // Placeholder while background generation is still working on this tile's partition
+        Forbidden, // Designation, player marked this tile as off limits
+        Void, // Outside the world's bounds, nothing can be built or dug there
+        Ladder, // Structure, joins this tile to the same tile on the level below when that has one too
+        IronOre, // Terrain, rock worth mining
+        IceDeposit, // Terrain, frozen water in the rock
+        CrashedProbe, // Point of interest, only ever generated
+        CrystalCluster, // Point of interest, only ever generated
+        AbandonedModule // Point of interest, only ever generated
+    }
+
+    // Each layer keeps its own changes, so a building sits on top of the terrain it was built on instead of replacing it
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    pub enum MapLayer {
+        Terrain,   // Rock and open ground, always has a value since untouched tiles are generated
+        Structure, // Buildings and their subtiles
+        Overlay,    // Wiring and anything else drawn over the top, make_change never writes here
+        Designation // Player orders about what tiles are for (forbidden zones), not a physical thing on the tile
+    }
+
+    impl MapLayer {
+        pub const ALL: [MapLayer; 4] = [MapLayer::Terrain, MapLayer::Structure, MapLayer::Overlay, MapLayer::Designation];
+
+        // Which layer make_change and set_area put a value on
+        pub fn for_value(value: &TileValue) -> MapLayer {
+            match value {
+                TileValue::HabModule | TileValue::Ladder | TileValue::Subtile(_) => MapLayer::Structure,
+                TileValue::CrashedProbe | TileValue::CrystalCluster | TileValue::AbandonedModule => MapLayer::Structure,
+                TileValue::Forbidden => MapLayer::Designation,
+                _ => MapLayer::Terrain
+            }
+        }
+    }
+
+    // Partitions keep tiles as a small id rather than a whole TileValue, which is 24 bytes because of the GridCoord in Subtile
+    // Subtile ids encode a slot in the partition's anchor table instead (PACKED_SUBTILE_BASE + slot)
+    pub(crate) type PackedTile = u16;
+
+    pub(crate) const PACKED_UNKNOWN: PackedTile = 0;
+    const PACKED_EMPTY: PackedTile = 1;
+    const PACKED_ROCK: PackedTile = 2;
+    const PACKED_ERROR: PackedTile = 3;
+    const PACKED_HAB_MODULE: PackedTile = 4;
+    const PACKED_GENERATING: PackedTile = 5;
+    const PACKED_FORBIDDEN: PackedTile = 6;
+    const PACKED_VOID: PackedTile = 7;
+    const PACKED_LADDER: PackedTile = 8;
+    const PACKED_IRON_ORE: PackedTile = 9;
+    const PACKED_ICE_DEPOSIT: PackedTile = 10;
+    const PACKED_CRASHED_PROBE: PackedTile = 11;
+    const PACKED_CRYSTAL_CLUSTER: PackedTile = 12;
+    const PACKED_ABANDONED_MODULE: PackedTile = 13;
+    const PACKED_SUBTILE_BASE: PackedTile = 0x8000;
+
+    impl TileValue {
+        pub(crate) fn pack(&self) -> PackedTile {
+            match self {
+                TileValue::InternalUnknown => PACKED_UNKNOWN,
+                TileValue::Empty => PACKED_EMPTY,
+                TileValue::Rock => PACKED_ROCK,
+                TileValue::Error => PACKED_ERROR,
+                TileValue::HabModule => PACKED_HAB_MODULE,
+                TileValue::Generating => PACKED_GENERATING,
+                TileValue::Forbidden => PACKED_FORBIDDEN,
+                TileValue::Void => PACKED_VOID,
+                TileValue::Ladder => PACKED_LADDER,
+                TileValue::IronOre => PACKED_IRON_ORE,
+                TileValue::IceDeposit => PACKED_ICE_DEPOSIT,
+                TileValue::CrashedProbe => PACKED_CRASHED_PROBE,
+                TileValue::CrystalCluster => PACKED_CRYSTAL_CLUSTER,
+                TileValue::AbandonedModule => PACKED_ABANDONED_MODULE,
+                TileValue::Subtile(_) => PACKED_SUBTILE_BASE
+            }
+        }
+
+        // anchor is only looked at for subtiles
+        pub(crate) fn unpack(packed: PackedTile, anchor: Option<GridCoord>) -> TileValue {
+            match packed {
+                PACKED_UNKNOWN => TileValue::InternalUnknown,
+                PACKED_EMPTY => TileValue::Empty,
+                PACKED_ROCK => TileValue::Rock,
+                PACKED_HAB_MODULE => TileValue::HabModule,
+                PACKED_GENERATING => TileValue::Generating,
+                PACKED_FORBIDDEN => TileValue::Forbidden,
+                PACKED_VOID => TileValue::Void,
+                PACKED_LADDER => TileValue::Ladder,
+                PACKED_IRON_ORE => TileValue::IronOre,
+                PACKED_ICE_DEPOSIT => TileValue::IceDeposit,
+                PACKED_CRASHED_PROBE => TileValue::CrashedProbe,
+                PACKED_CRYSTAL_CLUSTER => TileValue::CrystalCluster,
+                PACKED_ABANDONED_MODULE => TileValue::AbandonedModule,
+                _ if packed >= PACKED_SUBTILE_BASE => match anchor {
+                    Some(anchor) => TileValue::Subtile(anchor),
+                    // A subtile that lost its anchor can't be drawn or resolved, so don't pretend it's fine
+                    None => TileValue::Error
+                },
+                _ => TileValue::Error
+            }
+        }
+
+        // Rock and the ores in it, everything digging turns into open ground
+        pub fn is_solid(&self) -> bool {
+            matches!(self, TileValue::Rock | TileValue::IronOre | TileValue::IceDeposit)
+        }
     }
 
     // Must be power of 2
     pub const PARTITION_SIZE: u8 = (1 << 4);
 
+    // Partition coordinates with their bits interleaved (Morton/Z order) into one word, what the partition tables are keyed by
+    // Hashes in one step instead of two, and partitions next to each other land close together in the key space
+    // Each axis gets 32 bits of partition index, so the world is about 34 billion tiles across before keys wrap around
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub struct PartitionKey(u64);
+
+    impl PartitionKey {
+        // partition_coord is the top left tile of the partition
+        pub fn new(partition_coord: &GridCoord) -> PartitionKey {
+            let shift = PARTITION_SIZE.trailing_zeros();
+            // Flipping the sign bit keeps negative indices ordered below positive ones
+            let x = ((partition_coord.x >> shift) as u32) ^ 0x8000_0000;
+            let y = ((partition_coord.y >> shift) as u32) ^ 0x8000_0000;
+            PartitionKey(spread_bits(x) | (spread_bits(y) << 1))
+        }
+
+        // Top left tile of the partition
+        pub fn partition(self) -> GridCoord {
+            let shift = PARTITION_SIZE.trailing_zeros();
+            let x = (compact_bits(self.0) ^ 0x8000_0000) as i32 as i64;
+            let y = (compact_bits(self.0 >> 1) ^ 0x8000_0000) as i32 as i64;
+            GridCoord { x: x << shift, y: y << shift }
+        }
+    }
+
+    // Puts a zero bit between each bit of value
+    fn spread_bits(value: u32) -> u64 {
+        let mut spread = value as u64;
+        spread = (spread | (spread << 16)) & 0x0000_ffff_0000_ffff;
+        spread = (spread | (spread << 8)) & 0x00ff_00ff_00ff_00ff;
+        spread = (spread | (spread << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+        spread = (spread | (spread << 2)) & 0x3333_3333_3333_3333;
+        (spread | (spread << 1)) & 0x5555_5555_5555_5555
+    }
+
+    // Undoes spread_bits, ignoring the odd bits
+    fn compact_bits(spread: u64) -> u32 {
+        let mut value = spread & 0x5555_5555_5555_5555;
+        value = (value | (value >> 1)) & 0x3333_3333_3333_3333;
+        value = (value | (value >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+        value = (value | (value >> 4)) & 0x00ff_00ff_00ff_00ff;
+        value = (value | (value >> 8)) & 0x0000_ffff_0000_ffff;
+        ((value | (value >> 16)) & 0x0000_0000_ffff_ffff) as u32
+    }
+
     // Length of table at which the storage mode should switch to dense storage
     pub const DENSE_SWITCH_POINT: u32 = ((PARTITION_SIZE as u32) * (PARTITION_SIZE as u32)) / 3;
 
-    pub struct AreaChanges {
-        // TODO: Implement array mode for this structure for areas of dense change
-        changes_map: HashMap<u16, TileValue>,
-        changes_vec: Vec<TileValue>,
-        using_dense_storage: bool
+    // Dense partitions go back to sparse storage once removals take them below this many changes
+    // Well under DENSE_SWITCH_POINT so a partition hovering around the switch point doesn't flip back and forth
+    pub const SPARSE_SWITCH_POINT: u32 = DENSE_SWITCH_POINT / 2;
+
+    // Smallest the tile cache is allowed to shrink to, even for tiny views
+    pub const MIN_TILE_CACHE_SIZE: usize = 256;
+
+    // How many partitions of generated terrain to keep around, 512 bytes each
+    pub const GENERATED_PARTITION_CACHE_SIZE: usize = 1024;
+
+    // Oldest undo steps are forgotten past this many
+    pub const MAX_UNDO_STEPS: usize = 100;
+
+    // How much of a sound gets through each solid tile between it and the listener
+    pub const OCCLUSION_PER_TILE: f32 = 0.5;
+    // Past this many tiles in the way a sound is as good as silent, so sound_attenuation stops counting
+    const MAX_OCCLUDING_TILES: i32 = 8;
+
+    // Most tiles along each side of a minimap pixel that get a say in its colour, zoomed out further they're spread evenly over it
+    pub const MINIMAP_SAMPLES_PER_SIDE: i64 = 4;
+
+    // Tile data key a structure's player given name is kept under, on its anchor tile
+    pub const STRUCTURE_NAME_KEY: &str = "name";
+
+    // Bits of a neighbor_mask, the four sides come first so mask & NEIGHBOR_SIDES is the 4-bit mask
+    pub const NEIGHBOR_NORTH: u8 = 1;
+    pub const NEIGHBOR_EAST: u8 = 1 << 1;
+    pub const NEIGHBOR_SOUTH: u8 = 1 << 2;
+    pub const NEIGHBOR_WEST: u8 = 1 << 3;
+    pub const NEIGHBOR_NORTH_EAST: u8 = 1 << 4;
+    pub const NEIGHBOR_SOUTH_EAST: u8 = 1 << 5;
+    pub const NEIGHBOR_SOUTH_WEST: u8 = 1 << 6;
+    pub const NEIGHBOR_NORTH_WEST: u8 = 1 << 7;
+    pub const NEIGHBOR_SIDES: u8 = 0x0f;
+
+    // (x, y) offset of the neighbour each bit is for, in bit order
+    const NEIGHBOR_OFFSETS: [(i64, i64); 8] = [(0, -1), (1, 0), (0, 1), (-1, 0), (1, -1), (1, 1), (-1, 1), (-1, -1)];
+
+    // Hands out dense partition buffers and takes them back when partitions go sparse again,
+    // so large bases churning between storage modes reuse the same allocations
+    #[derive(Default)]
+    pub struct DenseBufferPool {
+        free_buffers: Vec<Vec<u64>>,
+        stats: DensePoolStats
     }
 
-    pub struct TileMap {
-        pub rock_density: f64,
-        generator_func: HybridMulti,
-        // Concept: Since changes will likely concentrated in a few areas, but there may be small changes all over the map
-        // Spatial partition by zeroing out the last ~4 bits of a position (16x16 groups) and then 
-        // for sparse changes (a few mined rocks) - do a hash table to find any changes within those 256 tiles (sparse storage, slower but less memory used)
-        // for dense areas (a base) - Keep a 2D array of all 256 tiles in this chunk (and save that whole thing)
-        // The exact switchover point should be tuned over time, but things to consider while doing that are:
-        //      - switch should be before the point where hash collisions start being likely
-        //      - should delay as much as sensible, 2D array will be much bigger memory hog and not scale well
-        //      - the main tiles that a players base is on should definitely be in the array once it expands, so looking at common bases seems like a good way to tune this
-        // Game saving thoughts: 
-        //      - Could also use this partitioning to not load whole save files on start up, load more lazily
-        //      - Alternatively, could ignore the partitioning for the save files to make it easier to tweak things like sizes and internal behavior later (don't save 2d arrays just a bunch o changes)
-        map_changes: HashMap<GridCoord, AreaChanges>,
-        // TODO: figure out a way of re-enabling caching behavior without making everything be mutable
-        // Re-generating untouched space and/or re-querying the changes data is expensive, so lets not do that every frame for every visible tile
-        // Cache sizing still needs to be figured out - could be dynamic with camera size or just always big enough for max zoom
-        // tile_cache: LruCache<GridCoord, TileValue>,
-        // caching_enabled: bool,
-        // The x/y size of tiles in grid coordinates
-        // If a tile type is not in this list, it is assumed to be 1x1
-        // When a tile of a given size is placed it will automatically set all tiles within its area to subtiles
-        // When it is removed all tiles within that area become "Empty"
-        tile_type_sizes: HashMap<TileValue, GridCoord> 
+    #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+    pub struct DensePoolStats {
+        pub allocated: u64, // Buffers that had to be freshly allocated
+        pub reused: u64,    // Buffers handed out from the free list
+        pub returned: u64   // Buffers given back to the free list
     }
 
-    impl AreaChanges {
-        pub fn new() -> AreaChanges {
-            AreaChanges { 
-                changes_map: HashMap::new(), 
-                changes_vec: Vec::new(), 
-                using_dense_storage: false 
-            }
+    // Dense partition storage, every distinct packed value gets a slot in the palette and cells only store their slot
+    // Cells grow from 1 bit to 2, 4, 8 or 16 as the palette fills, so a partition of a few tile types takes a few bits per tile
+    // rather than a whole PackedTile each
+    struct PaletteBuffer {
+        // Slot 0 is always PACKED_UNKNOWN, so a freshly zeroed buffer is all unchanged cells
+        palette: Vec<PackedTile>,
+        // How many cells use each slot, slots that drop to 0 get reused (slot 0 isn't counted)
+        palette_uses: Vec<u16>,
+        bits_per_cell: usize,
+        words: Vec<u64>
+    }
+
+    const PARTITION_CELLS: usize = (PARTITION_SIZE as usize) * (PARTITION_SIZE as usize);
+
+    impl PaletteBuffer {
+        fn new(mut words: Vec<u64>) -> PaletteBuffer {
+            words.clear();
+            words.resize(PaletteBuffer::word_count(1), 0);
+            PaletteBuffer { palette: vec![PACKED_UNKNOWN], palette_uses: vec![0], bits_per_cell: 1, words }
         }
 
-        pub fn sample(&self, pos: &GridCoord) -> Option<TileValue> {
-            let internal_pos_x = (pos.x & (PARTITION_SIZE as i64 - 1)) as u8;
-            let internal_pos_y = (pos.y & (PARTITION_SIZE as i64 - 1)) as u8;
+        fn word_count(bits_per_cell: usize) -> usize {
+            PARTITION_CELLS / (64 / bits_per_cell)
+        }
 
-            if self.using_dense_storage {
-                let index = internal_pos_x as usize + ((PARTITION_SIZE as usize) * (internal_pos_y as usize));
-                let lookup_result = self.changes_vec[index];
-                if lookup_result == TileValue::InternalUnknown { None }
-                else { Some(lookup_result) }
-            }
-            else {
-                let internal_key = ((internal_pos_x as u16) << 8) | (internal_pos_y as u16);
-                // For now this just forwards the query to the internal hashmap
-                // In future once a second storage type is available this will have to use the correct one
-                match self.changes_map.get(&internal_key) {
-                    Some(key) => Some(key.clone()),
-                    None => None
-                }
-            }
+        fn slot_at(&self, index: usize) -> usize {
+            let cells_per_word = 64 / self.bits_per_cell;
+            let shift = (index % cells_per_word) * self.bits_per_cell;
+            ((self.words[index / cells_per_word] >> shift) & ((1u64 << self.bits_per_cell) - 1)) as usize
         }
 
-        pub fn add_change(&mut self, pos: &GridCoord, tile_value: &TileValue) {
-            let internal_pos_x = (pos.x & (PARTITION_SIZE as i64 - 1)) as u8;
-            let internal_pos_y = (pos.y & (PARTITION_SIZE as i64 - 1)) as u8;
+        fn set_slot_at(&mut self, index: usize, slot: usize) {
+            let cells_per_word = 64 / self.bits_per_cell;
+            let shift = (index % cells_per_word) * self.bits_per_cell;
+            let mask = ((1u64 << self.bits_per_cell) - 1) << shift;
+            let word = &mut self.words[index / cells_per_word];
+            *word = (*word & !mask) | ((slot as u64) << shift);
+        }
 
-            if self.using_dense_storage {
-                let index = internal_pos_x as usize + ((PARTITION_SIZE as usize) * (internal_pos_y as usize));
-                self.changes_vec[index] = *tile_value;
+        fn get(&self, index: usize) -> PackedTile {
+            self.palette[self.slot_at(index)]
+        }
+
+        fn set(&mut self, index: usize, packed: PackedTile) {
+            let old_slot = self.slot_at(index);
+            if old_slot != 0 {
+                self.palette_uses[old_slot] -= 1;
             }
-            else {
-                if self.changes_map.len() > DENSE_SWITCH_POINT as usize {
-                    self.switch_to_dense();
-                    // Mode switched, go back around
-                    self.add_change(pos, tile_value);
-                }
-                else {
-                    let internal_key = ((internal_pos_x as u16) << 8) | (internal_pos_y as u16);
 
-                    // Insert will overwrite old values with that key, so this is just always the correct option
-                    self.changes_map.insert(internal_key, tile_value.clone());
-                }
+            let slot = self.slot_for(packed);
+            if slot != 0 {
+                self.palette_uses[slot] += 1;
             }
+            self.set_slot_at(index, slot);
         }
 
-        fn switch_to_dense(&mut self) {
-            if self.using_dense_storage { return; }
-
-            self.changes_vec.resize((PARTITION_SIZE as usize) * (PARTITION_SIZE as usize), TileValue::InternalUnknown);
+        fn slot_for(&mut self, packed: PackedTile) -> usize {
+            if packed == PACKED_UNKNOWN { return 0; }
 
-            for (key, val) in self.changes_map.iter() {
-                let internal_pos_x = key >> 8;
-                let internal_pos_y = key & ((1 << 8) - 1);
-                let index = internal_pos_x as usize + ((PARTITION_SIZE as usize) * (internal_pos_y as usize));
-                self.changes_vec[index] = *val;
+            // Only a handful of distinct values in most partitions, so a linear scan is plenty
+            if let Some(slot) = (1..self.palette.len()).find(|slot| self.palette_uses[*slot] > 0 && self.palette[*slot] == packed) {
+                return slot;
+            }
+            if let Some(free_slot) = (1..self.palette.len()).find(|slot| self.palette_uses[*slot] == 0) {
+                self.palette[free_slot] = packed;
+                return free_slot;
             }
 
-            self.changes_map.clear();
-            self.changes_map.shrink_to_fit();
-            self.using_dense_storage = true;
+            self.palette.push(packed);
+            self.palette_uses.push(0);
+            if self.palette.len() > (1 << self.bits_per_cell) {
+                self.widen();
+            }
+            self.palette.len() - 1
         }
 
-        fn _switch_to_sparse(&mut self) {
-            if !self.using_dense_storage { return; }
-
-            for x in 0..PARTITION_SIZE {
-                for y in 0..PARTITION_SIZE {
-                    let index = x as usize + ((PARTITION_SIZE as usize) * (y as usize));
-                    let internal_key = ((x as u16) << 8) | (y as u16);
-                    self.changes_map.insert(internal_key, self.changes_vec[index]);
-                }
+        // Doubles the bits per cell, reusing the same allocation
+        fn widen(&mut self) {
+            let mut slots = [0u16; PARTITION_CELLS];
+            for (index, slot) in slots.iter_mut().enumerate() {
+                *slot = self.slot_at(index) as u16;
             }
 
-            self.changes_vec.clear();
-            self.changes_vec.shrink_to_fit();
-            self.using_dense_storage = false;
+            self.bits_per_cell *= 2;
+            self.words.clear();
+            self.words.resize(PaletteBuffer::word_count(self.bits_per_cell), 0);
+            for (index, slot) in slots.iter().enumerate() {
+                self.set_slot_at(index, *slot as usize);
+            }
         }
     }
 
-    impl TileMap {
-        pub fn new() -> TileMap {
-            let generator_func = HybridMulti::new();
+    pub struct AreaChanges {
+        changes_map: FastHashMap<u16, PackedTile>,
+        // Only there while using dense storage
+        changes_dense: Option<PaletteBuffer>,
+        // Every distinct metatile anchor referenced by subtiles in this partition, shared by both storage modes
+        // A 3x3 building only needs its anchor stored once here instead of in all 8 of its subtiles
+        anchors: Vec<GridCoord>,
+        // How many cells reference each anchor slot, slots that drop to 0 get reused
+        anchor_uses: Vec<u16>,
+        // Cells with a change stored, dense storage can't tell without scanning the whole buffer
+        change_count: u16,
+        using_dense_storage: bool
+    }
 
-            let mut tile_type_sizes: HashMap<TileValue, GridCoord> = HashMap::new();
-            tile_type_sizes.insert(TileValue::HabModule, GridCoord{x: 3, y: 3});
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum IntegrityError {
+        // Subtile points at a tile that isn't a multi-tile value
+        MissingAnchor { subtile: GridCoord, anchor: GridCoord },
+        // Subtile points at a real multi-tile value, but isn't inside its footprint
+        OutsideFootprint { subtile: GridCoord, anchor: GridCoord },
+        // Partition storage refers to an anchor slot that isn't in its anchor table
+        DanglingAnchorSlot { pos: GridCoord },
+        // Part of a multi-tile value's footprint isn't a subtile pointing back at it
+        IncompleteFootprint { anchor: GridCoord, missing: GridCoord },
+        // Two multi-tile values whose footprints share tiles
+        OverlappingMetatiles { anchor: GridCoord, other_anchor: GridCoord }
+    }
 
-            TileMap { 
-                generator_func, 
-                rock_density: 0.25, 
-                map_changes: HashMap::new(), 
-                // tile_cache: LruCache::new(256),
-                // caching_enabled: true,
-                tile_type_sizes
+    // Makes one half of the world a copy of the other, so starts on either side get the same terrain
+    #[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum Symmetry {
+        #[default]
+        None,
+        // Everything right of the center is the left side flipped left to right
+        MirrorX,
+        // Everything below the center is the top half turned half way round it
+        Rotational
+    }
+
+    // Everything that shapes untouched terrain apart from the seed
+    // octaves, frequency, lacunarity, and persistence are handed straight to the HybridMulti noise function
+    #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct GeneratorConfig {
+        pub octaves: usize,
+        pub frequency: f64,
+        pub lacunarity: f64,
+        pub persistence: f64,
+        // Higher values give less rock
+        pub rock_density: f64,
+        // Roughly how much of the rock the caves pass hollows back out, 0 for none
+        #[serde(default)]
+        pub cave_density: f64,
+        // Everything this close to spawn_point generates as open ground whatever the passes make of it,
+        // so there's always somewhere to start building
+        #[serde(default)]
+        pub spawn_clearing_radius: f64,
+        #[serde(default)]
+        pub spawn_point: GridCoord,
+        // How much of the rock turns into each kind of ore, 0 for none
+        #[serde(default)]
+        pub iron_ore_density: f64,
+        #[serde(default)]
+        pub ice_density: f64,
+        // Chance of each partition getting a point of interest, 0 for none
+        #[serde(default)]
+        pub point_of_interest_chance: f64,
+        #[serde(default)]
+        pub symmetry: Symmetry,
+        // Snapped down to a partition corner, so partitions mirror onto whole partitions and points of interest match up too
+        #[serde(default)]
+        pub symmetry_center: GridCoord
+    }
+
+    impl Default for GeneratorConfig {
+        fn default() -> GeneratorConfig {
+            GeneratorConfig {
+                octaves: HybridMulti::DEFAULT_OCTAVES,
+                frequency: HybridMulti::DEFAULT_FREQUENCY,
+                lacunarity: HybridMulti::DEFAULT_LACUNARITY,
+                persistence: HybridMulti::DEFAULT_PERSISTENCE,
+                rock_density: 0.25,
+                cave_density: 0.0,
+                spawn_clearing_radius: 0.0,
+                spawn_point: GridCoord{x: 0, y: 0},
+                iron_ore_density: 0.0,
+                ice_density: 0.0,
+                point_of_interest_chance: 0.0,
+                symmetry: Symmetry::None,
+                symmetry_center: GridCoord{x: 0, y: 0}
             }
         }
+    }
 
-        pub fn sample(&self, pos: &GridCoord) -> TileValue {
-            // Unwrap values from struct
-            let x = pos.x;
-            let y = pos.y;
+    // One step of making untouched terrain, given the tile as the passes before it left it
+    // Runs on the background generation thread as well as the main one, hence Send + Sync
+    pub trait GeneratorPass: Send + Sync {
+        fn apply(&self, x: i64, y: i64, tile: TileValue) -> TileValue;
+    }
 
-            // Mask away the bits 
-            let partition_x = x & !(PARTITION_SIZE as i64 - 1);
-            let partition_y = y & !(PARTITION_SIZE as i64 - 1);
-            let partition_coord = GridCoord { x: partition_x, y: partition_y };
+    // Makes a pass for a world's seed and config, the map rebuilds its passes with these whenever the config changes
+    pub type GeneratorPassBuilder = fn(u64, &GeneratorConfig) -> Box<dyn GeneratorPass>;
 
-            // Check the history for a matching change
-            // First see if there is any changes within this tiles partition
-            if self.map_changes.contains_key(&partition_coord)  {
-                // Ask the partition if there is a value for this tile
-                let tile_value: Option<TileValue> = self.map_changes.get(&partition_coord).unwrap().sample(pos);
-                if tile_value.is_some() {
-                    // There is a changed value in this tile, use that
-                    return tile_value.unwrap();
-                }
-            }
+    // What maps generate with unless they're given something else, and what a loaded map gets
+    // Caves and ores do nothing with the default config, so this is the same terrain there always was
+    pub const STANDARD_GENERATOR_PASSES: [GeneratorPassBuilder; 3] = [BaseRock::build, Caves::build, OreVeins::build];
+
+    // Rock wherever the HybridMulti noise is high enough, open ground everywhere else
+    pub struct BaseRock {
+        noise: HybridMulti,
+        rock_density: f64
+    }
+
+    impl BaseRock {
+        pub fn new(seed: u64, generator_config: &GeneratorConfig) -> BaseRock {
+            let noise = HybridMulti::new()
+                // Noise library only takes 32 bit seeds, so fold the top half in rather than dropping it
+                .set_seed((seed ^ (seed >> 32)) as u32)
+                .set_octaves(generator_config.octaves)
+                .set_frequency(generator_config.frequency)
+                .set_lacunarity(generator_config.lacunarity)
+                .set_persistence(generator_config.persistence);
+            BaseRock { noise, rock_density: generator_config.rock_density }
+        }
 
-            // If no edits have been applied to this tile, sample the noise function to decide what goes here
+        pub fn build(seed: u64, generator_config: &GeneratorConfig) -> Box<dyn GeneratorPass> {
+            Box::new(BaseRock::new(seed, generator_config))
+        }
+    }
+
+    impl GeneratorPass for BaseRock {
+        fn apply(&self, x: i64, y: i64, _tile: TileValue) -> TileValue {
             // Noise is from -1..1 but I only want 0..1 so shift it first
-            let value = ((self.generator_func.get([x as f64, y as f64]) + 1.0) / (2.0 + self.rock_density)).round();
+            let value = ((self.noise.get([x as f64, y as f64]) + 1.0) / (2.0 + self.rock_density)).round();
             let value = if value > 1.0 { 1.0 } else if value < 0.0 { 0.0 } else { value };
-            let tile_val = match value as i32 {
+            match value as i32 {
                 0 => TileValue::Empty,
                 1 => TileValue::Rock,
                 _ => TileValue::Error
-            };
-
-            return tile_val;
+            }
         }
+    }
 
-        pub fn area_clear(&mut self, top_left: &GridCoord, size: &GridCoord) -> bool {
-            let x_min = top_left.x;
-            let x_max = top_left.x + size.x;
-            let y_min = top_left.y;
-            let y_max = top_left.y + size.y;
+    // Hollows out winding open areas in the rock, using its own noise at twice the base frequency so caves are narrower than the rock they're in
+    pub struct Caves {
+        noise: HybridMulti,
+        cave_density: f64
+    }
 
-            let mut clear = true;
+    impl Caves {
+        pub fn new(seed: u64, generator_config: &GeneratorConfig) -> Caves {
+            // Different seed from the base rock, otherwise the caves would line up exactly with its edges
+            let cave_seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let noise = HybridMulti::new()
+                .set_seed((cave_seed ^ (cave_seed >> 32)) as u32)
+                .set_octaves(generator_config.octaves)
+                .set_frequency(generator_config.frequency * 2.0);
+            Caves { noise, cave_density: generator_config.cave_density }
+        }
 
-            for y in y_min..y_max {
-                for x in x_min..x_max {
-                    clear = clear && (self.sample(&GridCoord{x, y}) == TileValue::Empty);
+        pub fn build(seed: u64, generator_config: &GeneratorConfig) -> Box<dyn GeneratorPass> {
+            Box::new(Caves::new(seed, generator_config))
+        }
+    }
 
-                    if !clear { return clear; }
-                }
+    impl GeneratorPass for Caves {
+        fn apply(&self, x: i64, y: i64, tile: TileValue) -> TileValue {
+            if tile != TileValue::Rock || self.cave_density <= 0.0 {
+                return tile;
             }
-
-            return clear;
+            // Caves run along where the noise crosses zero, so they come out as tunnels rather than blobs
+            if self.noise.get([x as f64, y as f64]).abs() < self.cave_density / 2.0 { TileValue::Empty } else { tile }
         }
+    }
 
+    // Makes every untouched tile itself, for worlds the noise can't make like flat ground, checkerboards or imported heightmaps
+    pub type GeneratorFunc = Box<dyn Fn(GridCoord) -> TileValue + Send>;
 
-        pub fn for_each_tile_rect<F>(&self, bounds: &Rectangle, func: F)
-            where F : FnMut(&GridCoord, &TileValue, &GridCoord) {
-            // Bounds to draw between
-            let x_min = bounds.pos.x.floor() as i64;
-            let x_size = bounds.size.x.ceil() as i64 + 1;
-            let y_min = bounds.pos.y.floor() as i64;
-            let y_size = bounds.size.y.ceil() as i64 + 1;
-            
-            self.for_each_tile(&GridCoord{x: x_min, y: y_min}, &GridCoord{x: x_size, y: y_size}, func)
+    // Runs a generator function as the only pass
+    // The function only has to be Send, so it's behind a lock for when the background generator shares it
+    struct CustomGenerator {
+        func: Mutex<GeneratorFunc>
+    }
+
+    impl GeneratorPass for CustomGenerator {
+        fn apply(&self, x: i64, y: i64, _tile: TileValue) -> TileValue {
+            match self.func.lock() {
+                Ok(func) => func(GridCoord{x, y}),
+                // It panicked on another thread already, it'll only do it again
+                Err(_) => TileValue::Error
+            }
         }
+    }
 
-        pub fn for_each_tile<F>(&self, top_left: &GridCoord, size: &GridCoord, mut func: F)
-            where F : FnMut(&GridCoord, &TileValue, &GridCoord) {
-            // Bounds to draw between
-            let x_min = top_left.x;
-            let x_max = top_left.x + size.x;
-            let y_min = top_left.y;
-            let y_max = top_left.y + size.y;
+    // Veins of each resource where its own low frequency noise peaks, only ever replacing rock
+    // Later resources win where veins overlap
+    pub struct OreVeins {
+        veins: Vec<(TileValue, HybridMulti, f64)>
+    }
 
-            // Set of tiles that have already been hit by subtile references (to avoid double hits)
-            let mut refed_tiles: HashSet<GridCoord> = HashSet::new();
+    impl OreVeins {
+        pub fn new(seed: u64, generator_config: &GeneratorConfig) -> OreVeins {
+            let resources = [(TileValue::IronOre, generator_config.iron_ore_density), (TileValue::IceDeposit, generator_config.ice_density)];
+            let veins = resources.iter().enumerate()
+                .filter(|(_, (_, density))| *density > 0.0)
+                .map(|(index, (ore, density))| {
+                    // Its own seed per resource, so ice and iron don't turn up in the same places
+                    let ore_seed = seed.wrapping_add(0x632b_e59b_d9b4_e019u64.wrapping_mul(index as u64 + 1));
+                    let noise = HybridMulti::new()
+                        .set_seed((ore_seed ^ (ore_seed >> 32)) as u32)
+                        .set_octaves(2)
+                        .set_frequency(generator_config.frequency * 0.5);
+                    (*ore, noise, *density)
+                })
+                .collect();
+            OreVeins { veins }
+        }
 
-            // Call func once for each tile within the bounds
-            for y in y_min..y_max {
-                for x in x_min..x_max {
-                    let coord = GridCoord {x, y};
-                    let tile_value = self.sample(&coord);
-                    match tile_value {
-                        TileValue::Subtile(refto) => {
-                            if !GridCoord::is_within_bounds(top_left, size, &refto) && !refed_tiles.contains(&refto) {
-                                refed_tiles.insert(refto);
-                                let ref_value = self.sample(&refto);
-                                let ref_size = self.get_tile_size(&ref_value);
-                                func(&refto, &ref_value, &ref_size);
-                            }
-                        }
-                        _ => {
-                            let size = self.get_tile_size(&tile_value);
-                            func(&coord, &tile_value, &size);
-                        }
-                    }
+        pub fn build(seed: u64, generator_config: &GeneratorConfig) -> Box<dyn GeneratorPass> {
+            Box::new(OreVeins::new(seed, generator_config))
+        }
+    }
 
-                    
+    impl GeneratorPass for OreVeins {
+        fn apply(&self, x: i64, y: i64, tile: TileValue) -> TileValue {
+            if tile != TileValue::Rock {
+                return tile;
+            }
+            let mut result = tile;
+            for (ore, noise, density) in self.veins.iter() {
+                // Noise is roughly -1..1, density is how far down from the top it reaches
+                if noise.get([x as f64, y as f64]) > 1.0 - density {
+                    result = *ore;
                 }
             }
+            result
         }
+    }
 
-        pub fn pos_to_grid(&mut self, world_x: f32 , world_y: f32) -> GridCoord {
-            let pos = GridCoord { x: world_x as i64, y: world_y as i64};
-            match self.sample(&pos) {
-                TileValue::Subtile(ref_position) => ref_position,
-                _ => pos
-            }
-        }
+    // The circle around the spawn point that always comes out open
+    // Not a pass, so passes added after the standard ones can't wall the start back in
+    #[derive(Copy, Clone)]
+    struct SpawnMask {
+        center: GridCoord,
+        radius: f64
+    }
 
-        pub fn make_change(&mut self, pos: &GridCoord, new_value: &TileValue) {
-            let old_value = self.sample(pos);
-            let old_tile_size = self.get_tile_size(&old_value);
+    impl SpawnMask {
+        fn for_config(generator_config: &GeneratorConfig) -> Option<SpawnMask> {
+            if generator_config.spawn_clearing_radius <= 0.0 { return None; }
+            Some(SpawnMask { center: generator_config.spawn_point, radius: generator_config.spawn_clearing_radius })
+        }
 
-            if old_tile_size.x > 1 && old_tile_size.y > 1 {
-                let x_min = pos.x - (old_tile_size.x / 2);
-                let y_min = pos.y - (old_tile_size.y / 2);
+        fn contains(&self, x: i64, y: i64) -> bool {
+            let offset_x = (x - self.center.x) as f64;
+            let offset_y = (y - self.center.y) as f64;
+            offset_x * offset_x + offset_y * offset_y <= self.radius * self.radius
+        }
 
-                self.set_area(&GridCoord{x: x_min, y: y_min}, &old_tile_size, TileValue::Empty );
-            }
+        // Whether any tile of the rectangle is inside, by the tile of it nearest the center
+        fn overlaps(&self, top_left: &GridCoord, size: &GridCoord) -> bool {
+            let nearest_x = self.center.x.max(top_left.x).min(top_left.x + size.x - 1);
+            let nearest_y = self.center.y.max(top_left.y).min(top_left.y + size.y - 1);
+            self.contains(nearest_x, nearest_y)
+        }
+    }
 
-            let tile_size = self.get_tile_size(new_value);
+    // Sends tiles on the copied side of a symmetric world back to the ones they copy
+    // Only generation goes through this, changes are still per tile so a base built on one side stays on that side
+    #[derive(Copy, Clone)]
+    struct SymmetryTransform {
+        flip_x: bool,
+        flip_y: bool,
+        center: GridCoord
+    }
 
-            let x_min = pos.x - (tile_size.x / 2);
-            let y_min = pos.y - (tile_size.y / 2);
+    impl SymmetryTransform {
+        fn for_config(generator_config: &GeneratorConfig) -> Option<SymmetryTransform> {
+            let (flip_x, flip_y) = match generator_config.symmetry {
+                Symmetry::None => return None,
+                Symmetry::MirrorX => (true, false),
+                Symmetry::Rotational => (true, true)
+            };
+            let center = TileMap::partition_of(&generator_config.symmetry_center);
+            Some(SymmetryTransform { flip_x, flip_y, center })
+        }
 
-            self.set_area(&GridCoord{x: x_min, y: y_min}, &tile_size, TileValue::Subtile(*pos) );
-            self.make_single_tile_change(&pos, *new_value);
+        // Mirroring splits left from right, rotating splits top from bottom
+        fn is_copy(&self, x: i64, y: i64) -> bool {
+            if self.flip_y { y >= self.center.y } else { x >= self.center.x }
         }
 
-        pub fn set_area(&mut self, top_left: &GridCoord, size: &GridCoord, new_value: TileValue) {
-            let x_min = top_left.x;
-            let y_min = top_left.y;
+        fn original_of(&self, x: i64, y: i64) -> (i64, i64) {
+            if !self.is_copy(x, y) { return (x, y); }
+            self.flip(x, y)
+        }
 
-            let x_max = x_min + size.x;
-            let y_max = y_min + size.y;
+        // The tile on the other side this one matches, whichever side it's on
+        fn flip(&self, x: i64, y: i64) -> (i64, i64) {
+            let x = if self.flip_x { 2 * self.center.x - 1 - x } else { x };
+            let y = if self.flip_y { 2 * self.center.y - 1 - y } else { y };
+            (x, y)
+        }
 
-            for y in y_min..y_max {
-                for x in x_min..x_max {
-                    self.make_single_tile_change(&GridCoord{x, y}, new_value);
-                }
+        // Top left of where a rectangle ends up when flipped over to the other side
+        fn flip_rect(&self, top_left: &GridCoord, size: &GridCoord) -> GridCoord {
+            GridCoord {
+                x: if self.flip_x { 2 * self.center.x - top_left.x - size.x } else { top_left.x },
+                y: if self.flip_y { 2 * self.center.y - top_left.y - size.y } else { top_left.y }
             }
         }
+    }
 
-        fn make_single_tile_change(&mut self, pos: &GridCoord, new_value: TileValue) {
-            // Unwrap values from struct
-            let x = pos.x;
-            let y = pos.y;
+    // A landmark generated into the terrain, a large tile whose footprint is always inside one partition
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct PointOfInterest {
+        pub anchor: GridCoord,
+        pub value: TileValue
+    }
 
-            // Mask away the bits 
-            let partition_x = x & !(PARTITION_SIZE as i64 - 1);
-            let partition_y = y & !(PARTITION_SIZE as i64 - 1);
-            let partition_coord = GridCoord { x: partition_x, y: partition_y };
+    impl PointOfInterest {
+        pub const KINDS: [TileValue; 3] = [TileValue::CrashedProbe, TileValue::CrystalCluster, TileValue::AbandonedModule];
 
-            // First ensure there is a change table for this partition
-            if !self.map_changes.contains_key(&partition_coord)  {
-                self.map_changes.insert(partition_coord, AreaChanges::new());
+        pub fn size_of(value: &TileValue) -> GridCoord {
+            match value {
+                TileValue::AbandonedModule => GridCoord{x: 3, y: 3},
+                _ => GridCoord{x: 2, y: 2}
             }
+        }
 
-            // Safe to unwrap immediately because we know at this point the key is in the table
-            let partition_changes = self.map_changes.get_mut(&partition_coord).unwrap();
-            partition_changes.add_change(pos, &new_value);
+        // Footprints are laid out around the anchor the same way as every other large tile
+        pub fn footprint_top_left(&self) -> GridCoord {
+            let size = PointOfInterest::size_of(&self.value);
+            GridCoord{x: self.anchor.x - size.x / 2, y: self.anchor.y - size.y / 2}
         }
 
-        pub fn get_tile_size(&self, tile_type: &TileValue) -> GridCoord {
-            match self.tile_type_sizes.get(&tile_type) {
-                Some(size) => *size,
+        // The point of interest itself at the anchor, subtiles of it over the rest of the footprint, None off it
+        fn tile_at(&self, x: i64, y: i64) -> Option<TileValue> {
+            let size = PointOfInterest::size_of(&self.value);
+            let top_left = self.footprint_top_left();
+            if !GridCoord::is_within_bounds(&top_left, &size, &GridCoord{x, y}) {
+                return None;
+            }
+            if x == self.anchor.x && y == self.anchor.y { Some(self.value) } else { Some(TileValue::Subtile(self.anchor)) }
+        }
+    }
+
+    // The structure placement stage, runs after the passes and picks at most one point of interest per partition
+    // Everything comes from hashing the seed with the partition, so a partition always gets the same one however often it's regenerated
+    #[derive(Copy, Clone)]
+    struct StructurePlacement {
+        seed: u64,
+        chance: f64
+    }
+
+    impl StructurePlacement {
+        fn in_partition(&self, partition_coord: &GridCoord, spawn_mask: Option<&SpawnMask>) -> Option<PointOfInterest> {
+            if self.chance <= 0.0 { return None; }
+            let hash = mix_hash(self.seed ^ mix_hash(partition_coord.x as u64 ^ mix_hash(partition_coord.y as u64)));
+            if (hash & 0xffff) as f64 >= self.chance * 65536.0 { return None; }
+
+            let value = PointOfInterest::KINDS[((hash >> 16) % PointOfInterest::KINDS.len() as u64) as usize];
+            let size = PointOfInterest::size_of(&value);
+            // Somewhere the whole footprint stays inside the partition
+            let free_x = PARTITION_SIZE as u64 - size.x as u64 + 1;
+            let free_y = PARTITION_SIZE as u64 - size.y as u64 + 1;
+            let top_left = GridCoord{x: partition_coord.x + ((hash >> 24) % free_x) as i64, y: partition_coord.y + ((hash >> 40) % free_y) as i64};
+            // Half under the spawn clearing it would be a broken building, so it doesn't show up at all
+            if let Some(mask) = spawn_mask {
+                if mask.overlaps(&top_left, &size) { return None; }
+            }
+            Some(PointOfInterest { anchor: GridCoord{x: top_left.x + size.x / 2, y: top_left.y + size.y / 2}, value })
+        }
+    }
+
+    // splitmix64's finaliser, every input bit affects every output bit
+    pub(crate) fn mix_hash(value: u64) -> u64 {
+        let mut value = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        value ^ (value >> 31)
+    }
+
+    // A map's passes, built and ready to generate with
+    // Shared rather than copied with the background generator, passes can hold a fair bit of noise state
+    #[derive(Clone)]
+    struct GeneratorPipeline {
+        passes: Arc<Vec<Box<dyn GeneratorPass>>>,
+        spawn_mask: Option<SpawnMask>,
+        structures: Option<StructurePlacement>,
+        symmetry: Option<SymmetryTransform>
+    }
+
+    impl GeneratorPipeline {
+        fn build(builders: &[GeneratorPassBuilder], seed: u64, generator_config: &GeneratorConfig) -> GeneratorPipeline {
+            GeneratorPipeline {
+                passes: Arc::new(builders.iter().map(|builder| builder(seed, generator_config)).collect()),
+                spawn_mask: SpawnMask::for_config(generator_config),
+                structures: Some(StructurePlacement { seed, chance: generator_config.point_of_interest_chance }),
+                symmetry: SymmetryTransform::for_config(generator_config)
+            }
+        }
+
+        // Generator functions get full control, there is no spawn mask over them
+        fn custom(func: GeneratorFunc) -> GeneratorPipeline {
+            GeneratorPipeline { passes: Arc::new(vec![Box::new(CustomGenerator { func: Mutex::new(func) })]), spawn_mask: None, structures: None, symmetry: None }
+        }
+
+        // Passes start from open ground, so an empty pipeline makes an empty world
+        fn generate_tile(&self, x: i64, y: i64) -> TileValue {
+            let partition_coord = TileMap::partition_of(&GridCoord{x, y});
+            if let Some(tile) = self.point_of_interest(&partition_coord).and_then(|point| point.tile_at(x, y)) {
+                return tile;
+            }
+            self.generate_terrain(x, y)
+        }
+
+        fn generate_terrain(&self, x: i64, y: i64) -> TileValue {
+            // The spawn mask goes over the tile where it really is and over the tile it matches on the other side,
+            // so the spawn is open whichever side it's on and both sides still come out the same
+            if let Some(mask) = &self.spawn_mask {
+                let (flipped_x, flipped_y) = self.symmetry.map_or((x, y), |symmetry| symmetry.flip(x, y));
+                if mask.contains(x, y) || mask.contains(flipped_x, flipped_y) { return TileValue::Empty; }
+            }
+            let (x, y) = match &self.symmetry {
+                Some(symmetry) => symmetry.original_of(x, y),
+                None => (x, y)
+            };
+            self.passes.iter().fold(TileValue::Empty, |tile, pass| pass.apply(x, y, tile))
+        }
+
+        fn point_of_interest(&self, partition_coord: &GridCoord) -> Option<PointOfInterest> {
+            let structures = self.structures?;
+            let symmetry = match &self.symmetry {
+                Some(symmetry) => symmetry,
+                None => return structures.in_partition(partition_coord, self.spawn_mask.as_ref())
+            };
+            let partition_size = GridCoord{x: PARTITION_SIZE as i64, y: PARTITION_SIZE as i64};
+            let is_copy = symmetry.is_copy(partition_coord.x, partition_coord.y);
+            let original_partition = if is_copy { symmetry.flip_rect(partition_coord, &partition_size) } else { *partition_coord };
+            let original = structures.in_partition(&original_partition, self.spawn_mask.as_ref())?;
+            let size = PointOfInterest::size_of(&original.value);
+            let flipped_top_left = symmetry.flip_rect(&original.footprint_top_left(), &size);
+            // The spawn clearing over either copy takes out both of them, same as the terrain
+            if let Some(mask) = &self.spawn_mask {
+                if mask.overlaps(&flipped_top_left, &size) { return None; }
+            }
+            if !is_copy { return Some(original); }
+            // Flipped over from the partition this copies, anchored the usual way in its new footprint
+            Some(PointOfInterest { anchor: GridCoord{x: flipped_top_left.x + size.x / 2, y: flipped_top_left.y + size.y / 2}, value: original.value })
+        }
+    }
+
+    // Bumped whenever SaveHeader or SavedPartition change in a way older saves can't be read as
+    pub const SAVE_FORMAT_VERSION: u32 = 6;
+
+    #[derive(Debug)]
+    pub enum SaveError {
+        Encoding(serde_cbor::Error),
+        UnsupportedVersion(u32),
+        Io(std::io::Error),
+        // The file ends before all the records its header lists
+        Truncated { expected_bytes: u64, found_bytes: u64 },
+        // A partition's record doesn't match the checksum the header has for it
+        ChecksumMismatch { partition: GridCoord },
+        // Checksum was fine but the record wouldn't decompress, so it was written wrong in the first place
+        Decompression { partition: GridCoord }
+    }
+
+    impl From<serde_cbor::Error> for SaveError {
+        fn from(error: serde_cbor::Error) -> SaveError {
+            SaveError::Encoding(error)
+        }
+    }
+
+    impl From<std::io::Error> for SaveError {
+        fn from(error: std::io::Error) -> SaveError {
+            SaveError::Io(error)
+        }
+    }
+
+    #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+    pub struct SaveOptions {
+        // Deflates each partition's record, bases are mostly long runs of the same few tiles so this shrinks them a lot
+        pub compress: bool
+    }
+
+    // A save is this header followed by one SavedPartition record per changed partition, so a load can pick and choose
+    // Changes are flattened out of their partitions so the in-memory layout (sparse/dense switch point, packing) is free to change
+    // without breaking saves, partition size is baked into the record boundaries though
+    #[derive(Serialize, Deserialize)]
+    struct SaveHeader {
+        version: u32,
+        seed: u64,
+        generator_config: GeneratorConfig,
+        tile_data: Vec<(GridCoord, String, TileDataValue)>,
+        compressed: bool,
+        partitions: Vec<SavedRecordEntry>,
+        // Missing from saves of infinite worlds made before bounds existed
+        #[serde(default)]
+        bounds: Option<WorldBounds>
+    }
+
+    #[derive(Copy, Clone, Serialize, Deserialize)]
+    struct SavedRecordEntry {
+        partition: GridCoord,
+        // Counted from the end of the header
+        offset: u64,
+        // Length and checksum are of the bytes as stored, so after compression
+        length: u64,
+        checksum: u32
+    }
+
+    // Plain bitwise CRC-32 (the zlib/PNG one), saves are small enough that a lookup table isn't worth it
+    pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for byte in bytes.iter() {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            }
+        }
+        !crc
+    }
+
+    fn encode_record(partition: &SavedPartition, compress: bool) -> Result<Vec<u8>, SaveError> {
+        let bytes = serde_cbor::to_vec(partition)?;
+        Ok(if compress { miniz_oxide::deflate::compress_to_vec(&bytes, 6) } else { bytes })
+    }
+
+    // The bytes an entry points at, offset and length come straight out of the file so they might point anywhere
+    fn record_bytes<'a>(records: &'a [u8], entry: &SavedRecordEntry) -> Result<&'a [u8], SaveError> {
+        let end = entry.offset.saturating_add(entry.length);
+        if end > records.len() as u64 {
+            return Err(SaveError::Truncated { expected_bytes: end, found_bytes: records.len() as u64 });
+        }
+        Ok(&records[entry.offset as usize..end as usize])
+    }
+
+    fn decode_record(bytes: &[u8], entry: &SavedRecordEntry, compressed: bool) -> Result<SavedPartition, SaveError> {
+        Ok(serde_cbor::from_slice(&checked_record_bytes(bytes, entry, compressed)?)?)
+    }
+
+    // Checks a stored record against its entry, and inflates it if it needs it
+    fn checked_record_bytes(bytes: &[u8], entry: &SavedRecordEntry, compressed: bool) -> Result<Vec<u8>, SaveError> {
+        if bytes.len() as u64 != entry.length {
+            return Err(SaveError::Truncated { expected_bytes: entry.length, found_bytes: bytes.len() as u64 });
+        }
+        if crc32(bytes) != entry.checksum {
+            return Err(SaveError::ChecksumMismatch { partition: entry.partition });
+        }
+        if compressed {
+            return miniz_oxide::inflate::decompress_to_vec(bytes).map_err(|_| SaveError::Decompression { partition: entry.partition });
+        }
+        Ok(bytes.to_vec())
+    }
+
+    // The parts of a save header that aren't about where the records are
+    #[derive(Deserialize)]
+    struct SavedWorld {
+        seed: u64,
+        generator_config: GeneratorConfig,
+        tile_data: Vec<(GridCoord, String, TileDataValue)>,
+        #[serde(default)]
+        bounds: Option<WorldBounds>
+    }
+
+    // A save as plain CBOR values, so a migration can read fields and variants that the current types don't have any more
+    // Records have already been checked and unpacked, before version 5 everything was in the header and partitions is empty
+    pub struct RawSave {
+        pub version: u32,
+        pub header: serde_cbor::Value,
+        pub partitions: Vec<serde_cbor::Value>
+    }
+
+    impl RawSave {
+        fn from_bytes(version: u32, header: serde_cbor::Value, records: &[u8]) -> Result<RawSave, SaveError> {
+            let mut header = header;
+            let index = match &mut header {
+                serde_cbor::Value::Map(fields) => fields.remove(&serde_cbor::Value::Text("partitions".to_string())),
+                _ => None
+            };
+            let compressed = match &header {
+                serde_cbor::Value::Map(fields) => fields.get(&serde_cbor::Value::Text("compressed".to_string())) == Some(&serde_cbor::Value::Bool(true)),
+                _ => false
+            };
+
+            // Version 5 indexed records by (partition, offset, length), from 6 on they have a checksum too
+            let entries: Vec<SavedRecordEntry> = match (version, index) {
+                (_, None) => Vec::new(),
+                (5, Some(index)) => serde_cbor::value::from_value::<Vec<(GridCoord, u64, u64)>>(index)?.into_iter()
+                    .map(|(partition, offset, length)| {
+                        let entry = SavedRecordEntry { partition, offset, length, checksum: 0 };
+                        SavedRecordEntry { checksum: crc32(record_bytes(records, &entry).unwrap_or(&[])), ..entry }
+                    })
+                    .collect(),
+                (_, Some(index)) => serde_cbor::value::from_value(index)?
+            };
+
+            let mut partitions: Vec<serde_cbor::Value> = Vec::with_capacity(entries.len());
+            for entry in entries.iter() {
+                partitions.push(serde_cbor::from_slice(&checked_record_bytes(record_bytes(records, entry)?, entry, compressed)?)?);
+            }
+            Ok(RawSave { version, header, partitions })
+        }
+    }
+
+    // Upgrades saves of one version to the next, loading chains them until the save is current
+    // Add one whenever SAVE_FORMAT_VERSION goes up, or a TileValue change means old saves need their values reworked
+    pub trait Migration {
+        fn source_version(&self) -> u32;
+        // Hands back the save at source_version() + 1
+        fn migrate(&self, save: RawSave) -> Result<RawSave, SaveError>;
+    }
+
+    fn header_field(save: &mut RawSave, name: &str) -> Option<serde_cbor::Value> {
+        match &mut save.header {
+            serde_cbor::Value::Map(fields) => fields.remove(&serde_cbor::Value::Text(name.to_string())),
+            _ => None
+        }
+    }
+
+    fn set_header_field(save: &mut RawSave, name: &str, value: serde_cbor::Value) {
+        if let serde_cbor::Value::Map(fields) = &mut save.header {
+            fields.insert(serde_cbor::Value::Text(name.to_string()), value);
+        }
+    }
+
+    // Version 1 only had rock_density, the rest of the noise settings were the noise library's defaults
+    struct AddGeneratorConfig;
+
+    impl Migration for AddGeneratorConfig {
+        fn source_version(&self) -> u32 { 1 }
+
+        fn migrate(&self, mut save: RawSave) -> Result<RawSave, SaveError> {
+            let rock_density: f64 = match header_field(&mut save, "rock_density") {
+                Some(rock_density) => serde_cbor::value::from_value(rock_density)?,
+                None => GeneratorConfig::default().rock_density
+            };
+            // Only the fields version 2 had, everything added since gets its serde default the same as a version 2 save would
+            let generator_config = [
+                ("octaves", serde_cbor::value::to_value(HybridMulti::DEFAULT_OCTAVES)?),
+                ("frequency", serde_cbor::value::to_value(HybridMulti::DEFAULT_FREQUENCY)?),
+                ("lacunarity", serde_cbor::value::to_value(HybridMulti::DEFAULT_LACUNARITY)?),
+                ("persistence", serde_cbor::value::to_value(HybridMulti::DEFAULT_PERSISTENCE)?),
+                ("rock_density", serde_cbor::value::to_value(rock_density)?)
+            ];
+            set_header_field(&mut save, "generator_config", serde_cbor::Value::Map(
+                generator_config.iter().map(|(name, value)| (serde_cbor::Value::Text(name.to_string()), value.clone())).collect()
+            ));
+            save.version = 2;
+            Ok(save)
+        }
+    }
+
+    // Version 2 had no layers, every change goes on the layer make_change would put it on now
+    struct SplitChangesIntoLayers;
+
+    impl Migration for SplitChangesIntoLayers {
+        fn source_version(&self) -> u32 { 2 }
+
+        fn migrate(&self, mut save: RawSave) -> Result<RawSave, SaveError> {
+            let changes: Vec<(GridCoord, TileValue)> = match header_field(&mut save, "changes") {
+                Some(changes) => serde_cbor::value::from_value(changes)?,
+                None => Vec::new()
+            };
+            let changes: Vec<(MapLayer, GridCoord, TileValue)> = changes.into_iter()
+                .map(|(pos, value)| (MapLayer::for_value(&value), pos, value))
+                .collect();
+            set_header_field(&mut save, "changes", serde_cbor::value::to_value(changes)?);
+            save.version = 3;
+            Ok(save)
+        }
+    }
+
+    // Version 3 had no tile data
+    struct AddTileData;
+
+    impl Migration for AddTileData {
+        fn source_version(&self) -> u32 { 3 }
+
+        fn migrate(&self, mut save: RawSave) -> Result<RawSave, SaveError> {
+            if header_field(&mut save, "tile_data").is_none() {
+                set_header_field(&mut save, "tile_data", serde_cbor::Value::Array(Vec::new()));
+            }
+            save.version = 4;
+            Ok(save)
+        }
+    }
+
+    // Version 4 kept every change in one flat list in the header
+    struct SplitChangesIntoPartitions;
+
+    impl Migration for SplitChangesIntoPartitions {
+        fn source_version(&self) -> u32 { 4 }
+
+        fn migrate(&self, mut save: RawSave) -> Result<RawSave, SaveError> {
+            let changes: Vec<(MapLayer, GridCoord, TileValue)> = match header_field(&mut save, "changes") {
+                Some(changes) => serde_cbor::value::from_value(changes)?,
+                None => Vec::new()
+            };
+
+            let mut partitions: HashMap<GridCoord, Vec<(MapLayer, GridCoord, TileValue)>> = HashMap::new();
+            for change in changes.into_iter() {
+                partitions.entry(TileMap::partition_of(&change.1)).or_default().push(change);
+            }
+            save.partitions = partitions.into_values()
+                .map(|changes| serde_cbor::value::to_value(SavedPartition { changes }))
+                .collect::<Result<Vec<serde_cbor::Value>, serde_cbor::Error>>()?;
+            save.version = 5;
+            Ok(save)
+        }
+    }
+
+    // Version 6 only changed how records are stored (checksums, compression), which RawSave has already dealt with
+    struct AddRecordChecksums;
+
+    impl Migration for AddRecordChecksums {
+        fn source_version(&self) -> u32 { 5 }
+
+        fn migrate(&self, mut save: RawSave) -> Result<RawSave, SaveError> {
+            save.version = 6;
+            Ok(save)
+        }
+    }
+
+    const BUILT_IN_MIGRATIONS: [&dyn Migration; 5] = [&AddGeneratorConfig, &SplitChangesIntoLayers, &AddTileData, &SplitChangesIntoPartitions, &AddRecordChecksums];
+
+    // One partition's changes on every layer, used for save records and for paged out partitions
+    #[derive(Serialize, Deserialize)]
+    struct SavedPartition {
+        changes: Vec<(MapLayer, GridCoord, TileValue)>
+    }
+
+    // A map's changes copied out by TileMap::snapshot, writing it does all the slow parts of saving (encoding, compression, disk)
+    pub struct SaveSnapshot {
+        seed: u64,
+        generator_config: GeneratorConfig,
+        tile_data: Vec<(GridCoord, String, TileDataValue)>,
+        bounds: Option<WorldBounds>,
+        // Along rows of partitions from the top left, each one's changes already sorted
+        partitions: Vec<(GridCoord, SavedPartition)>
+    }
+
+    impl SaveSnapshot {
+        pub fn write_to<W: Write>(&self, mut writer: W, options: &SaveOptions) -> Result<(), SaveError> {
+            let mut records: Vec<u8> = Vec::new();
+            let mut index: Vec<SavedRecordEntry> = Vec::with_capacity(self.partitions.len());
+            for (partition_coord, partition) in self.partitions.iter() {
+                let record = encode_record(partition, options.compress)?;
+                index.push(SavedRecordEntry { partition: *partition_coord, offset: records.len() as u64, length: record.len() as u64, checksum: crc32(&record) });
+                records.extend_from_slice(&record);
+            }
+
+            let header = SaveHeader {
+                version: SAVE_FORMAT_VERSION,
+                seed: self.seed,
+                generator_config: self.generator_config,
+                tile_data: self.tile_data.clone(),
+                compressed: options.compress,
+                partitions: index,
+                bounds: self.bounds
+            };
+            serde_cbor::to_writer(&mut writer, &header)?;
+            writer.write_all(&records)?;
+            Ok(())
+        }
+
+        // Written next to path and then moved over it, so a crash partway through leaves the last good save alone
+        // A load_lazy map still reading partitions out of the old save keeps it open, so it goes on reading the old one
+        pub fn write_to_file<P: Into<PathBuf>>(&self, path: P, options: &SaveOptions) -> Result<(), SaveError> {
+            let path = path.into();
+            let partial_path = path.with_extension("partial");
+            {
+                let mut writer = BufWriter::new(File::create(&partial_path)?);
+                self.write_to(&mut writer, options)?;
+                writer.flush()?;
+            }
+            fs::rename(&partial_path, &path)?;
+            Ok(())
+        }
+    }
+
+    // How a save handed to a SaveWorker went
+    #[derive(Debug)]
+    pub struct SaveResult {
+        pub path: PathBuf,
+        pub result: Result<(), SaveError>
+    }
+
+    // Worker thread that writes snapshots out one at a time in the order they were handed over, so saving never holds up a frame
+    // Dropping it hangs up the request channel, which ends the worker once it's written everything it was given
+    pub struct SaveWorker {
+        requests: Sender<(SaveSnapshot, PathBuf, SaveOptions)>,
+        results: Receiver<SaveResult>,
+        // Handed over but not reported back yet
+        in_flight: usize
+    }
+
+    impl SaveWorker {
+        pub fn spawn() -> SaveWorker {
+            let (requests, worker_requests) = channel::<(SaveSnapshot, PathBuf, SaveOptions)>();
+            let (worker_results, results) = channel::<SaveResult>();
+
+            thread::spawn(move || {
+                for (snapshot, path, options) in worker_requests.iter() {
+                    let result = snapshot.write_to_file(path.clone(), &options);
+                    if worker_results.send(SaveResult { path, result }).is_err() { break; }
+                }
+            });
+
+            SaveWorker { requests, results, in_flight: 0 }
+        }
+
+        pub fn save<P: Into<PathBuf>>(&mut self, snapshot: SaveSnapshot, path: P, options: SaveOptions) -> Result<(), SaveError> {
+            // Worker can only have gone if it panicked partway through an earlier save
+            self.requests.send((snapshot, path.into(), options))
+                .map_err(|_| SaveError::Io(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "the save worker has stopped")))?;
+            self.in_flight += 1;
+            Ok(())
+        }
+
+        pub fn is_saving(&self) -> bool {
+            self.in_flight > 0
+        }
+
+        // Saves that have finished since the last call, without waiting for any still going
+        pub fn finished(&mut self) -> Vec<SaveResult> {
+            let finished: Vec<SaveResult> = self.results.try_iter().collect();
+            self.in_flight -= finished.len();
+            finished
+        }
+    }
+
+    // Keeps track of partitions that aren't in memory, either paged out by page_out_far_from or never read in by load_lazy
+    // Tile data stays in memory, there's little enough of it that paging it isn't worth the bother
+    struct PartitionPager {
+        // Where page_out_far_from writes partitions, one file each, it has nowhere to put them until enable_paging sets this
+        directory: Option<PathBuf>,
+        // Save load_lazy left partitions in, with each record not read yet (offsets made absolute)
+        // Kept open rather than reopened by path, so saving over it (a new file renamed into place) can't change what gets read
+        save_file: Option<RefCell<File>>,
+        save_compressed: bool,
+        save_records: HashMap<GridCoord, SavedRecordEntry>,
+        paged_out: HashSet<GridCoord>,
+        // Paged out partitions something tried to read, update_paging brings them back
+        wanted: RefCell<HashSet<GridCoord>>
+    }
+
+    impl PartitionPager {
+        fn new() -> PartitionPager {
+            PartitionPager { directory: None, save_file: None, save_compressed: false, save_records: HashMap::new(), paged_out: HashSet::new(), wanted: RefCell::new(HashSet::new()) }
+        }
+
+        fn file_for(&self, partition_coord: &GridCoord) -> Option<PathBuf> {
+            self.directory.as_ref().map(|directory| directory.join(format!("{}_{}.cbor", partition_coord.x, partition_coord.y)))
+        }
+
+        fn read(&self, partition_coord: &GridCoord) -> Result<SavedPartition, SaveError> {
+            if let (Some(save_file), Some(entry)) = (&self.save_file, self.save_records.get(partition_coord)) {
+                let mut file = save_file.borrow_mut();
+                file.seek(SeekFrom::Start(entry.offset))?;
+                let mut bytes: Vec<u8> = Vec::new();
+                (&mut *file).take(entry.length).read_to_end(&mut bytes)?;
+                return decode_record(&bytes, entry, self.save_compressed);
+            }
+            match self.file_for(partition_coord) {
+                Some(path) => Ok(serde_cbor::from_reader(File::open(path)?)?),
+                None => Err(SaveError::Io(std::io::Error::new(std::io::ErrorKind::NotFound, "partition isn't in a save or a page file")))
+            }
+        }
+    }
+
+    // Worker thread that generates partitions for a TileMap, requests go out and finished partitions come back over channels
+    // Dropping it hangs up the request channel, which ends the worker once it finishes whatever it was doing
+    struct BackgroundGenerator {
+        requests: Sender<GridCoord>,
+        results: Receiver<(GridCoord, Vec<PackedTile>)>,
+        // Partitions asked for that haven't come back yet, so each one is only requested once
+        pending: RefCell<HashSet<GridCoord>>
+    }
+
+    impl BackgroundGenerator {
+        fn spawn(generator: GeneratorPipeline) -> BackgroundGenerator {
+            let (requests, worker_requests) = channel::<GridCoord>();
+            let (worker_results, results) = channel::<(GridCoord, Vec<PackedTile>)>();
+
+            thread::spawn(move || {
+                for partition_coord in worker_requests.iter() {
+                    let generated = generate_partition(&generator, &partition_coord);
+                    // Map was dropped or switched generators, nobody wants this any more
+                    if worker_results.send((partition_coord, generated)).is_err() { break; }
+                }
+            });
+
+            BackgroundGenerator { requests, results, pending: RefCell::new(HashSet::new()) }
+        }
+
+        fn request(&self, partition_coord: &GridCoord) {
+            if self.pending.borrow_mut().insert(*partition_coord) {
+                // Worker can only have gone if it panicked, in which case the tile just stays Generating
+                let _ = self.requests.send(*partition_coord);
+            }
+        }
+    }
+
+    // Base terrain for a whole partition, laid out the same way as dense partition storage
+    fn generate_partition(generator: &GeneratorPipeline, partition_coord: &GridCoord) -> Vec<PackedTile> {
+        let mut generated: Vec<PackedTile> = Vec::with_capacity((PARTITION_SIZE as usize) * (PARTITION_SIZE as usize));
+        for y in 0..PARTITION_SIZE {
+            for x in 0..PARTITION_SIZE {
+                generated.push(generator.generate_terrain(partition_coord.x + x as i64, partition_coord.y + y as i64).pack());
+            }
+        }
+
+        // Generated partitions have no anchor table, their subtiles keep the anchor's index in the partition instead
+        if let Some(point) = generator.point_of_interest(partition_coord) {
+            let top_left = point.footprint_top_left();
+            let size = PointOfInterest::size_of(&point.value);
+            let anchor_slot = PACKED_SUBTILE_BASE + partition_index(&point.anchor) as PackedTile;
+            for y in top_left.y..(top_left.y + size.y) {
+                for x in top_left.x..(top_left.x + size.x) {
+                    let pos = GridCoord{x, y};
+                    generated[partition_index(&pos)] = if pos == point.anchor { point.value.pack() } else { anchor_slot };
+                }
+            }
+        }
+        generated
+    }
+
+    // Where a tile is in its partition's generated tiles, along rows from the top left
+    fn partition_index(pos: &GridCoord) -> usize {
+        (pos.x & (PARTITION_SIZE as i64 - 1)) as usize + (PARTITION_SIZE as usize) * ((pos.y & (PARTITION_SIZE as i64 - 1)) as usize)
+    }
+
+    fn unpack_generated(packed: PackedTile, partition_coord: &GridCoord) -> TileValue {
+        let anchor = if packed >= PACKED_SUBTILE_BASE {
+            let slot = (packed - PACKED_SUBTILE_BASE) as i64;
+            Some(GridCoord{x: partition_coord.x + slot % PARTITION_SIZE as i64, y: partition_coord.y + slot / PARTITION_SIZE as i64})
+        } else {
+            None
+        };
+        TileValue::unpack(packed, anchor)
+    }
+
+    // Counts bytes as they go past, to find where the save header ends and the records start
+    struct CountingReader<R: Read> {
+        reader: R,
+        count: u64
+    }
+
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let read = self.reader.read(buf)?;
+            self.count += read as u64;
+            Ok(read)
+        }
+    }
+
+    // How far apart two rectangles are along whichever axis they're furthest apart on, 0 if they overlap
+    fn rect_gap(a: &Rectangle, b: &Rectangle) -> f32 {
+        let x_gap = (b.pos.x - (a.pos.x + a.size.x)).max(a.pos.x - (b.pos.x + b.size.x));
+        let y_gap = (b.pos.y - (a.pos.y + a.size.y)).max(a.pos.y - (b.pos.y + b.size.y));
+        x_gap.max(y_gap).max(0.0)
+    }
+
+    // Walks an area row by row, multi-tile values come out once at their anchor even when only part of them is inside
+    pub struct TilesInArea<'a> {
+        map: &'a TileMap,
+        top_left: GridCoord,
+        size: GridCoord,
+        next_pos: GridCoord,
+        // Set of tiles that have already been hit by subtile references (to avoid double hits)
+        refed_tiles: HashSet<GridCoord>
+    }
+
+    impl<'a> Iterator for TilesInArea<'a> {
+        type Item = (GridCoord, TileValue, GridCoord);
+
+        fn next(&mut self) -> Option<(GridCoord, TileValue, GridCoord)> {
+            let x_max = self.top_left.x + self.size.x;
+            let y_max = self.top_left.y + self.size.y;
+
+            while self.next_pos.y < y_max && self.size.x > 0 {
+                let coord = self.next_pos;
+                self.next_pos.x += 1;
+                if self.next_pos.x >= x_max {
+                    self.next_pos = GridCoord{x: self.top_left.x, y: self.next_pos.y + 1};
+                }
+
+                let tile_value = self.map.sample(&coord);
+                match tile_value {
+                    TileValue::Subtile(refto) => {
+                        // Anchors are stored wrapped, this puts buildings over the seam back next to the rest of the area
+                        let refto = self.map.nearest_copy(&refto, &coord);
+                        if !GridCoord::is_within_bounds(&self.top_left, &self.size, &refto) && !self.refed_tiles.contains(&refto) {
+                            self.refed_tiles.insert(refto);
+                            let ref_value = self.map.sample(&refto);
+                            let ref_size = self.map.get_tile_size(&ref_value);
+                            return Some((refto, ref_value, ref_size));
+                        }
+                    }
+                    _ => {
+                        let size = self.map.get_tile_size(&tile_value);
+                        return Some((coord, tile_value, size));
+                    }
+                }
+            }
+
+            None
+        }
+    }
+
+    // A single piece of per-tile state, entity ids and the like go in as Int
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub enum TileDataValue {
+        Int(i64),
+        Float(f64),
+        Text(String)
+    }
+
+    impl TileDataValue {
+        pub fn as_int(&self) -> Option<i64> {
+            if let TileDataValue::Int(value) = self { Some(*value) } else { None }
+        }
+
+        pub fn as_float(&self) -> Option<f64> {
+            if let TileDataValue::Float(value) = self { Some(*value) } else { None }
+        }
+
+        pub fn as_text(&self) -> Option<&str> {
+            if let TileDataValue::Text(value) = self { Some(value) } else { None }
+        }
+    }
+
+    impl From<i64> for TileDataValue {
+        fn from(value: i64) -> TileDataValue { TileDataValue::Int(value) }
+    }
+
+    impl From<f64> for TileDataValue {
+        fn from(value: f64) -> TileDataValue { TileDataValue::Float(value) }
+    }
+
+    impl From<String> for TileDataValue {
+        fn from(value: String) -> TileDataValue { TileDataValue::Text(value) }
+    }
+
+    impl From<&str> for TileDataValue {
+        fn from(value: &str) -> TileDataValue { TileDataValue::Text(value.to_string()) }
+    }
+
+    // Named values attached to individual tiles (HP, mining progress, owner), for state a TileValue can't hold
+    // Only tiles that have something get an entry, so this stays small next to the map itself
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct TileData {
+        values: HashMap<GridCoord, HashMap<String, TileDataValue>>
+    }
+
+    impl TileData {
+        pub fn new() -> TileData {
+            TileData::default()
+        }
+
+        pub fn get(&self, pos: &GridCoord, key: &str) -> Option<&TileDataValue> {
+            self.values.get(pos).and_then(|tile_values| tile_values.get(key))
+        }
+
+        pub fn set(&mut self, pos: &GridCoord, key: &str, value: TileDataValue) {
+            self.values.entry(*pos).or_default().insert(key.to_string(), value);
+        }
+
+        pub fn remove(&mut self, pos: &GridCoord, key: &str) -> Option<TileDataValue> {
+            let tile_values = self.values.get_mut(pos)?;
+            let removed = tile_values.remove(key);
+            if tile_values.is_empty() {
+                self.values.remove(pos);
+            }
+            removed
+        }
+
+        pub fn clear_tile(&mut self, pos: &GridCoord) {
+            self.values.remove(pos);
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.values.is_empty()
+        }
+
+        // Every (position, key, value), sorted so the same data always comes out in the same order
+        pub fn entries(&self) -> Vec<(GridCoord, String, TileDataValue)> {
+            let mut entries: Vec<(GridCoord, String, TileDataValue)> = Vec::new();
+            for (pos, tile_values) in self.values.iter() {
+                for (key, value) in tile_values.iter() {
+                    entries.push((*pos, key.clone(), value.clone()));
+                }
+            }
+            entries.sort_by(|a, b| (a.0.y, a.0.x, &a.1).cmp(&(b.0.y, b.0.x, &b.1)));
+            entries
+        }
+    }
+
+    // Tiles on the straight line between two tiles (Bresenham), from first and to last
+    pub struct GridLine {
+        pos: GridCoord,
+        end: GridCoord,
+        delta: GridCoord,
+        step: GridCoord,
+        error: i64,
+        finished: bool
+    }
+
+    impl GridLine {
+        pub fn new(from: &GridCoord, to: &GridCoord) -> GridLine {
+            let delta = GridCoord{x: (to.x - from.x).abs(), y: -(to.y - from.y).abs()};
+            let step = GridCoord{x: if from.x < to.x { 1 } else { -1 }, y: if from.y < to.y { 1 } else { -1 }};
+            GridLine { pos: *from, end: *to, delta, step, error: delta.x + delta.y, finished: false }
+        }
+    }
+
+    impl Iterator for GridLine {
+        type Item = GridCoord;
+
+        fn next(&mut self) -> Option<GridCoord> {
+            if self.finished { return None; }
+
+            let current = self.pos;
+            if current == self.end {
+                self.finished = true;
+                return Some(current);
+            }
+
+            let doubled_error = 2 * self.error;
+            if doubled_error >= self.delta.y {
+                self.error += self.delta.y;
+                self.pos.x += self.step.x;
+            }
+            if doubled_error <= self.delta.x {
+                self.error += self.delta.x;
+                self.pos.y += self.step.y;
+            }
+            Some(current)
+        }
+    }
+
+    // Everything that changed since the last TileMap::take_dirty_regions
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub struct DirtyRegions {
+        // Tiles that were written to, even if they were set to the value they already had
+        pub tiles: HashSet<GridCoord>,
+        // Partitions containing any of those tiles, plus partitions whose background generated terrain arrived
+        pub partitions: HashSet<GridCoord>,
+        // Set when every untouched tile may have changed (new generator config), too many to list
+        pub everything: bool
+    }
+
+    impl DirtyRegions {
+        pub fn is_empty(&self) -> bool {
+            !self.everything && self.tiles.is_empty() && self.partitions.is_empty()
+        }
+    }
+
+    // A TileMap::minimap kept up to date from take_dirty_regions, only the pixels over partitions that changed are drawn again
+    // Whoever keeps one has to be the only one taking dirty regions from its map, or it misses changes
+    pub struct Minimap {
+        center: GridCoord,
+        tiles_per_pixel: i64,
+        size: GridCoord,
+        pixels: Vec<u8>
+    }
+
+    impl Minimap {
+        pub fn new(map: &TileMap, center: &GridCoord, tiles_per_pixel: i64, size: &GridCoord) -> Minimap {
+            let size = GridCoord{x: size.x.max(0), y: size.y.max(0)};
+            let tiles_per_pixel = tiles_per_pixel.max(1);
+            Minimap { center: *center, tiles_per_pixel, size, pixels: map.minimap(center, tiles_per_pixel, &size) }
+        }
+
+        // RGBA along rows from the top left, the same as TileMap::minimap
+        pub fn pixels(&self) -> &[u8] {
+            &self.pixels
+        }
+
+        pub fn size(&self) -> GridCoord {
+            self.size
+        }
+
+        pub fn center(&self) -> GridCoord {
+            self.center
+        }
+
+        // Draws everything again, for a newly loaded map or when the minimap should show somewhere else
+        pub fn rebuild(&mut self, map: &TileMap, center: &GridCoord) {
+            self.center = *center;
+            self.pixels = map.minimap(center, self.tiles_per_pixel, &self.size);
+        }
+
+        // Draws the blocks of pixels over each dirty partition again, true if anything was drawn
+        pub fn update(&mut self, map: &TileMap, dirty: &DirtyRegions) -> bool {
+            if dirty.is_empty() {
+                return false;
+            }
+            // A wrapping world can show the same partition in more than one place, and a new generator changes every tile
+            if dirty.everything || map.bounds().is_some_and(|bounds| bounds.wrap) {
+                let center = self.center;
+                self.rebuild(map, &center);
+                return true;
+            }
+
+            let tiles_per_pixel = self.tiles_per_pixel;
+            let top_left = GridCoord{x: self.center.x - self.size.x * tiles_per_pixel / 2, y: self.center.y - self.size.y * tiles_per_pixel / 2};
+            let partition_size = PARTITION_SIZE as i64;
+            let mut redrawn = false;
+            for partition_coord in dirty.partitions.iter() {
+                // Every pixel with any of the partition in it
+                let first = GridCoord{x: ((partition_coord.x - top_left.x) / tiles_per_pixel).max(0), y: ((partition_coord.y - top_left.y) / tiles_per_pixel).max(0)};
+                let last = GridCoord{
+                    x: (partition_coord.x + partition_size - 1 - top_left.x).div_euclid(tiles_per_pixel).min(self.size.x - 1),
+                    y: (partition_coord.y + partition_size - 1 - top_left.y).div_euclid(tiles_per_pixel).min(self.size.y - 1)
+                };
+                if first.x > last.x || first.y > last.y {
+                    continue;
+                }
+
+                // Centred so the block's pixels sample exactly the tiles the same pixels of the whole minimap do
+                let block_size = GridCoord{x: last.x - first.x + 1, y: last.y - first.y + 1};
+                let block_center = GridCoord{
+                    x: top_left.x + first.x * tiles_per_pixel + block_size.x * tiles_per_pixel / 2,
+                    y: top_left.y + first.y * tiles_per_pixel + block_size.y * tiles_per_pixel / 2
+                };
+                let block = map.minimap(&block_center, tiles_per_pixel, &block_size);
+                let row_bytes = block_size.x as usize * 4;
+                for (row, block_row) in block.chunks(row_bytes).enumerate() {
+                    let start = ((first.y as usize + row) * self.size.x as usize + first.x as usize) * 4;
+                    self.pixels[start..(start + row_bytes)].copy_from_slice(block_row);
+                }
+                redrawn = true;
+            }
+            redrawn
+        }
+    }
+
+    // Dense row-major copy of a rectangle of tiles, filled by TileMap::sample_rect
+    pub struct TileBuffer {
+        top_left: GridCoord,
+        size: GridCoord,
+        tiles: Vec<TileValue>
+    }
+
+    impl Default for TileBuffer {
+        fn default() -> TileBuffer {
+            TileBuffer { top_left: GridCoord{x: 0, y: 0}, size: GridCoord{x: 0, y: 0}, tiles: Vec::new() }
+        }
+    }
+
+    impl TileBuffer {
+        pub fn new() -> TileBuffer {
+            TileBuffer::default()
+        }
+
+        pub fn top_left(&self) -> GridCoord {
+            self.top_left
+        }
+
+        pub fn size(&self) -> GridCoord {
+            self.size
+        }
+
+        // Takes world coordinates, None outside the sampled rectangle
+        pub fn get(&self, pos: &GridCoord) -> Option<TileValue> {
+            if !GridCoord::is_within_bounds(&self.top_left, &self.size, pos) { return None; }
+            let index = (pos.x - self.top_left.x) + (pos.y - self.top_left.y) * self.size.x;
+            Some(self.tiles[index as usize])
+        }
+
+        // One slice per row, top row first
+        pub fn rows(&self) -> std::slice::Chunks<'_, TileValue> {
+            self.tiles.chunks(self.size.x.max(1) as usize)
+        }
+
+        pub fn tiles(&self) -> &[TileValue] {
+            &self.tiles
+        }
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum PlacementError {
+        // Something other than Empty is under the new footprint, the first one found along rows from the top left
+        Blocked { pos: GridCoord, value: TileValue },
+        // Subtiles, placeholders and designations aren't things that can be built
+        NotPlaceable(TileValue)
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum EditError {
+        // A required_clear area had something other than Empty in it
+        AreaNotClear { top_left: GridCoord, size: GridCoord },
+        // The buffered writes would have left multi-tile values broken
+        Integrity(Vec<IntegrityError>)
+    }
+
+    // Where a tile's value came from, see TileMap::provenance
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum Provenance {
+        Generated,
+        // Dug, built on, or written over some other way
+        Edited,
+        // Its partition is paged out, it's been asked for back the same as sample would
+        Unknown
+    }
+
+    // Part of TileRule, what has to be around a tile for the rule to change it
+    #[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    pub enum RuleCondition {
+        #[default]
+        Always,
+        // Open ground on at least one of its four sides
+        Exposed,
+        // Nothing of the value within distance tiles of its footprint, so buildings only decay with nobody around to see to them
+        NoneNearby { value: TileValue, distance: i64 }
+    }
+
+    // Slowly turns one kind of tile into another every time TileMap::apply_tile_rules runs
+    #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct TileRule {
+        pub from: TileValue,
+        // Written over the whole footprint when from is a building, terrain knocks down whatever is built on it
+        pub to: TileValue,
+        // Each time the rules are applied, for each tile they match
+        pub chance: f32,
+        #[serde(default)]
+        pub condition: RuleCondition
+    }
+
+    // How the world changes by itself over time, plain data so the rules can come from a file as easily as from code
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct TileRules {
+        pub rules: Vec<TileRule>,
+        // Only partitions with something built in them, and this many partitions around those, ever change
+        pub active_radius: i64
+    }
+
+    impl Default for TileRules {
+        // Exposed rock weathers away and abandoned modules crumble once nobody lives near them
+        fn default() -> TileRules {
+            TileRules {
+                rules: vec![
+                    TileRule { from: TileValue::Rock, to: TileValue::Empty, chance: 0.002, condition: RuleCondition::Exposed },
+                    TileRule { from: TileValue::AbandonedModule, to: TileValue::Empty, chance: 0.02, condition: RuleCondition::NoneNearby { value: TileValue::HabModule, distance: 16 } }
+                ],
+                active_radius: 2
+            }
+        }
+    }
+
+    // A rectangle of the map copied out by TileMap::copy_rect, in coordinates relative to its top left so it can be pasted anywhere
+    // Buildings are kept by their anchors rather than tile by tile, so pasting re-anchors their subtiles where they land
+    // Loaded blueprints go through from_parts the same as built ones, so a bad file can't make one that panics later
+    #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(try_from = "BlueprintParts")]
+    pub struct Blueprint {
+        size: GridCoord,
+        // Along rows from the top left, None where there was nothing to copy (still generating, or off the edge of the world)
+        terrain: Vec<Option<TileValue>>,
+        // Anchor offsets of every building that was entirely inside the rectangle
+        structures: Vec<(GridCoord, TileValue)>
+    }
+
+    // A blueprint as it's saved, before from_parts has checked it
+    #[derive(Deserialize)]
+    struct BlueprintParts {
+        size: GridCoord,
+        terrain: Vec<Option<TileValue>>,
+        structures: Vec<(GridCoord, TileValue)>
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum BlueprintError {
+        // Negative, or not the same number of tiles as there are terrain entries
+        TerrainSize { size: GridCoord, terrain_len: usize },
+        // A building anchored off the edge of the blueprint
+        StructureOutside(GridCoord)
+    }
+
+    // serde reports why a loaded blueprint was turned down with this
+    impl std::fmt::Display for BlueprintError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            match self {
+                BlueprintError::TerrainSize { size, terrain_len } => write!(f, "blueprint is {}x{} but has {} terrain tiles", size.x, size.y, terrain_len),
+                BlueprintError::StructureOutside(offset) => write!(f, "blueprint has a building at {}, {} outside it", offset.x, offset.y)
+            }
+        }
+    }
+
+    impl TryFrom<BlueprintParts> for Blueprint {
+        type Error = BlueprintError;
+
+        fn try_from(parts: BlueprintParts) -> Result<Blueprint, BlueprintError> {
+            Blueprint::from_parts(parts.size, parts.terrain, parts.structures)
+        }
+    }
+
+    impl Blueprint {
+        // For blueprints that come from somewhere other than copy_rect
+        // Terrain needs exactly one entry per tile and every building has to be anchored inside
+        pub fn from_parts(size: GridCoord, terrain: Vec<Option<TileValue>>, structures: Vec<(GridCoord, TileValue)>) -> Result<Blueprint, BlueprintError> {
+            let tile_count = size.x.checked_mul(size.y);
+            if size.x < 0 || size.y < 0 || tile_count != Some(terrain.len() as i64) {
+                return Err(BlueprintError::TerrainSize { size, terrain_len: terrain.len() });
+            }
+            if let Some((offset, _)) = structures.iter().find(|(offset, _)| !GridCoord::is_within_bounds(&GridCoord{x: 0, y: 0}, &size, offset)) {
+                return Err(BlueprintError::StructureOutside(*offset));
+            }
+            Ok(Blueprint { size, terrain, structures })
+        }
+
+        pub fn size(&self) -> GridCoord {
+            self.size
+        }
+
+        pub fn structures(&self) -> &[(GridCoord, TileValue)] {
+            &self.structures
+        }
+
+        pub fn terrain_at(&self, offset: &GridCoord) -> Option<TileValue> {
+            if !GridCoord::is_within_bounds(&GridCoord{x: 0, y: 0}, &self.size, offset) { return None; }
+            self.terrain.get((offset.y * self.size.x + offset.x) as usize).copied().flatten()
+        }
+    }
+
+    // Buffers writes and only applies them to the map when commit finds them valid
+    // Holds the map mutably for its whole life, so nothing else can change the map between the checks and the writes
+    pub struct EditTransaction<'a> {
+        map: &'a mut TileMap,
+        // In the order they were made, the same tile can appear more than once
+        writes: Vec<(GridCoord, TileValue)>,
+        // Latest buffered value of every written tile, so the transaction can see its own writes
+        pending: HashMap<GridCoord, TileValue>,
+        required_clear: Vec<(GridCoord, GridCoord)>
+    }
+
+    // A single tile write on one layer, None values mean that layer had no change stored for the tile
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct TileEdit {
+        pos: GridCoord,
+        layer: MapLayer,
+        old_value: Option<TileValue>,
+        new_value: Option<TileValue>
+    }
+
+    // Every tile write made by one make_change or set_area call, in the order they happened
+    struct UndoStep {
+        edits: Vec<TileEdit>,
+        // Shared with steps on other maps that have to be undone along with this one, see set_undo_group
+        group: Option<u64>
+    }
+
+    pub struct TileMap {
+        seed: u64,
+        generator_config: GeneratorConfig,
+        generator_passes: Vec<GeneratorPassBuilder>,
+        generator: GeneratorPipeline,
+        // Set while generating with a function instead of the passes, the seed and config don't change anything then
+        custom_generator: bool,
+        // Concept: Since changes will likely concentrated in a few areas, but there may be small changes all over the map
+        // Spatial partition by zeroing out the last ~4 bits of a position (16x16 groups) and then 
+        // for sparse changes (a few mined rocks) - do a hash table to find any changes within those 256 tiles (sparse storage, slower but less memory used)
+        // for dense areas (a base) - Keep a 2D array of all 256 tiles in this chunk (and save that whole thing)
+        // The exact switchover point should be tuned over time, but things to consider while doing that are:
+        //      - switch should be before the point where hash collisions start being likely
+        //      - should delay as much as sensible, 2D array will be much bigger memory hog and not scale well
+        //      - the main tiles that a players base is on should definitely be in the array once it expands, so looking at common bases seems like a good way to tune this
+        // Game saving thoughts: 
+        //      - Could also use this partitioning to not load whole save files on start up, load more lazily
+        //      - Alternatively, could ignore the partitioning for the save files to make it easier to tweak things like sizes and internal behavior later (don't save 2d arrays just a bunch o changes)
+        // One set of partitions per MapLayer, indexed by layer as usize
+        layers: [FastHashMap<PartitionKey, AreaChanges>; 4],
+        dense_pool: DenseBufferPool,
+        // Re-generating untouched space and/or re-querying the changes data is expensive, so lets not do that every frame for every visible tile
+        // Cache lives in a RefCell so sampling can stay &self, entries are invalidated whenever a tile is changed
+        // Sizing is dynamic, the game resizes it to fit the camera view (see resize_cache_for_view)
+        tile_cache: RefCell<LruCache<GridCoord, TileValue>>,
+        // Base terrain of whole partitions, generated the first time any tile in one is read
+        // Drawing a screen touches every tile in a handful of partitions, so this turns most noise evaluations into array reads
+        generated_partitions: RefCell<LruCache<PartitionKey, Vec<PackedTile>>>,
+        // When set, partitions are generated off thread and sample gives Generating until they arrive
+        background_generator: Option<BackgroundGenerator>,
+        // Infinite when None
+        bounds: Option<WorldBounds>,
+        // When set, far away partitions can be moved out to disk, reads of them give Generating until they're paged back in
+        pager: Option<PartitionPager>,
+        dirty_regions: DirtyRegions,
+        // Goes with whatever is on the tile, so changing the tile through make_change or set_area throws it away
+        tile_data: TileData,
+        undo_steps: VecDeque<UndoStep>,
+        redo_steps: Vec<UndoStep>,
+        // Writes of the make_change/set_area call in progress, nested calls add to the outermost one's step
+        recording_edit: Option<Vec<TileEdit>>,
+        caching_enabled: bool,
+        // The x/y size of tiles in grid coordinates
+        // If a tile type is not in this list, it is assumed to be 1x1
+        // When a tile of a given size is placed it will automatically set all tiles within its area to subtiles
+        // When it is removed all tiles within that area become "Empty"
+        tile_type_sizes: HashMap<TileValue, GridCoord> 
+    }
+
+    impl DenseBufferPool {
+        pub fn new() -> DenseBufferPool {
+            DenseBufferPool::default()
+        }
+
+        // Buffers come out with whatever capacity they grew to before, a PaletteBuffer clears and sizes them itself
+        pub(crate) fn take(&mut self) -> Vec<u64> {
+            match self.free_buffers.pop() {
+                Some(buffer) => {
+                    self.stats.reused += 1;
+                    buffer
+                }
+                None => {
+                    self.stats.allocated += 1;
+                    Vec::new()
+                }
+            }
+        }
+
+        pub(crate) fn give_back(&mut self, buffer: Vec<u64>) {
+            self.stats.returned += 1;
+            self.free_buffers.push(buffer);
+        }
+
+        pub fn stats(&self) -> DensePoolStats {
+            self.stats
+        }
+
+        pub fn free_count(&self) -> usize {
+            self.free_buffers.len()
+        }
+    }
+
+    impl AreaChanges {
+        pub fn new() -> AreaChanges {
+            AreaChanges { 
+                changes_map: FastHashMap::default(), 
+                changes_dense: None,
+                anchors: Vec::new(),
+                anchor_uses: Vec::new(),
+                change_count: 0,
+                using_dense_storage: false 
+            }
+        }
+
+        pub fn sample(&self, pos: &GridCoord) -> Option<TileValue> {
+            self.sample_packed(pos).map(|packed| self.unpack(packed))
+        }
+
+        pub fn add_change(&mut self, pos: &GridCoord, tile_value: &TileValue) {
+            // Nothing to share buffers with, so just use a throwaway pool
+            self.add_change_pooled(pos, tile_value, &mut DenseBufferPool::new());
+        }
+
+        pub fn add_change_pooled(&mut self, pos: &GridCoord, tile_value: &TileValue, pool: &mut DenseBufferPool) {
+            let internal_pos_x = (pos.x & (PARTITION_SIZE as i64 - 1)) as u8;
+            let internal_pos_y = (pos.y & (PARTITION_SIZE as i64 - 1)) as u8;
+
+            if !self.using_dense_storage && self.changes_map.len() > DENSE_SWITCH_POINT as usize {
+                self.switch_to_dense(pool);
+            }
+
+            // Whatever was here before no longer needs its anchor
+            match self.sample_packed(pos) {
+                Some(old_packed) => self.release_anchor(old_packed),
+                None => self.change_count += 1
+            }
+            let packed = self.pack(tile_value);
+
+            if let Some(dense) = self.changes_dense.as_mut() {
+                let index = internal_pos_x as usize + ((PARTITION_SIZE as usize) * (internal_pos_y as usize));
+                dense.set(index, packed);
+            }
+            else {
+                let internal_key = ((internal_pos_x as u16) << 8) | (internal_pos_y as u16);
+
+                // Insert will overwrite old values with that key, so this is just always the correct option
+                self.changes_map.insert(internal_key, packed);
+            }
+        }
+
+        // Calls func with the world position and value of every change stored in this partition
+        pub fn for_each_change<F>(&self, partition_coord: &GridCoord, mut func: F)
+            where F : FnMut(&GridCoord, &TileValue) {
+            for x in 0..PARTITION_SIZE {
+                for y in 0..PARTITION_SIZE {
+                    let pos = GridCoord{x: partition_coord.x + x as i64, y: partition_coord.y + y as i64};
+                    if let Some(value) = self.sample(&pos) {
+                        func(&pos, &value);
+                    }
+                }
+            }
+        }
+
+        fn sample_packed(&self, pos: &GridCoord) -> Option<PackedTile> {
+            let internal_pos_x = (pos.x & (PARTITION_SIZE as i64 - 1)) as u8;
+            let internal_pos_y = (pos.y & (PARTITION_SIZE as i64 - 1)) as u8;
+
+            if let Some(dense) = self.changes_dense.as_ref() {
+                let index = internal_pos_x as usize + ((PARTITION_SIZE as usize) * (internal_pos_y as usize));
+                match dense.get(index) {
+                    PACKED_UNKNOWN => None,
+                    packed => Some(packed)
+                }
+            }
+            else {
+                let internal_key = ((internal_pos_x as u16) << 8) | (internal_pos_y as u16);
+                self.changes_map.get(&internal_key).copied()
+            }
+        }
+
+        fn pack(&mut self, tile_value: &TileValue) -> PackedTile {
+            match tile_value {
+                TileValue::Subtile(anchor) => PACKED_SUBTILE_BASE + self.acquire_anchor(anchor),
+                _ => tile_value.pack()
+            }
+        }
+
+        fn unpack(&self, packed: PackedTile) -> TileValue {
+            TileValue::unpack(packed, self.anchor_for(packed))
+        }
+
+        fn anchor_for(&self, packed: PackedTile) -> Option<GridCoord> {
+            if packed < PACKED_SUBTILE_BASE { return None; }
+            let slot = (packed - PACKED_SUBTILE_BASE) as usize;
+            match self.anchor_uses.get(slot) {
+                Some(uses) if *uses > 0 => Some(self.anchors[slot]),
+                _ => None
+            }
+        }
+
+        fn acquire_anchor(&mut self, anchor: &GridCoord) -> PackedTile {
+            // Partitions only ever reference a handful of buildings, so a linear scan is plenty
+            let slot = match self.anchors.iter().zip(self.anchor_uses.iter()).position(|(existing, uses)| *uses > 0 && existing == anchor) {
+                Some(slot) => slot,
+                None => match self.anchor_uses.iter().position(|uses| *uses == 0) {
+                    Some(free_slot) => {
+                        self.anchors[free_slot] = *anchor;
+                        free_slot
+                    }
+                    None => {
+                        self.anchors.push(*anchor);
+                        self.anchor_uses.push(0);
+                        self.anchors.len() - 1
+                    }
+                }
+            };
+
+            self.anchor_uses[slot] += 1;
+            slot as PackedTile
+        }
+
+        fn release_anchor(&mut self, packed: PackedTile) {
+            if packed < PACKED_SUBTILE_BASE { return; }
+            let slot = (packed - PACKED_SUBTILE_BASE) as usize;
+            if let Some(uses) = self.anchor_uses.get_mut(slot) {
+                *uses = uses.saturating_sub(1);
+            }
+        }
+
+        // Reports cells whose packed value points at an anchor slot this partition doesn't have
+        fn find_dangling_anchors(&self, partition_coord: &GridCoord, errors: &mut Vec<IntegrityError>) {
+            for x in 0..PARTITION_SIZE {
+                for y in 0..PARTITION_SIZE {
+                    let pos = GridCoord{x: partition_coord.x + x as i64, y: partition_coord.y + y as i64};
+                    if let Some(packed) = self.sample_packed(&pos) {
+                        if packed >= PACKED_SUBTILE_BASE && self.anchor_for(packed).is_none() {
+                            errors.push(IntegrityError::DanglingAnchorSlot { pos });
+                        }
+                    }
+                }
+            }
+        }
+
+        pub fn anchor_slot_count(&self) -> usize {
+            self.anchors.len()
+        }
+
+        // Forgets the change at pos so the tile goes back to whatever is generated there
+        pub fn remove_change(&mut self, pos: &GridCoord) {
+            // Nothing to share buffers with, so just use a throwaway pool
+            self.remove_change_pooled(pos, &mut DenseBufferPool::new());
+        }
+
+        // Same as remove_change, handing the dense buffer back to the pool if the partition drops back to sparse storage
+        pub fn remove_change_pooled(&mut self, pos: &GridCoord, pool: &mut DenseBufferPool) {
+            let internal_pos_x = (pos.x & (PARTITION_SIZE as i64 - 1)) as u8;
+            let internal_pos_y = (pos.y & (PARTITION_SIZE as i64 - 1)) as u8;
+
+            match self.sample_packed(pos) {
+                Some(old_packed) => {
+                    self.release_anchor(old_packed);
+                    self.change_count -= 1;
+                }
+                None => return
+            }
+
+            if let Some(dense) = self.changes_dense.as_mut() {
+                let index = internal_pos_x as usize + ((PARTITION_SIZE as usize) * (internal_pos_y as usize));
+                dense.set(index, PACKED_UNKNOWN);
+            }
+            else {
+                let internal_key = ((internal_pos_x as u16) << 8) | (internal_pos_y as u16);
+                self.changes_map.remove(&internal_key);
+            }
+
+            if self.using_dense_storage && (self.change_count as u32) < SPARSE_SWITCH_POINT {
+                self.switch_to_sparse(pool);
+            }
+        }
+
+        pub fn change_count(&self) -> usize {
+            self.change_count as usize
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.change_count == 0
+        }
+
+        pub fn is_dense(&self) -> bool {
+            self.using_dense_storage
+        }
+
+        // Bits each tile takes up in dense storage, None while sparse
+        pub fn dense_bits_per_tile(&self) -> Option<u8> {
+            self.changes_dense.as_ref().map(|dense| dense.bits_per_cell as u8)
+        }
+
+        pub fn switch_to_dense(&mut self, pool: &mut DenseBufferPool) {
+            if self.using_dense_storage { return; }
+
+            let mut dense = PaletteBuffer::new(pool.take());
+
+            for (key, val) in self.changes_map.iter() {
+                let internal_pos_x = key >> 8;
+                let internal_pos_y = key & ((1 << 8) - 1);
+                let index = internal_pos_x as usize + ((PARTITION_SIZE as usize) * (internal_pos_y as usize));
+                dense.set(index, *val);
+            }
+
+            self.changes_dense = Some(dense);
+            self.changes_map.clear();
+            self.changes_map.shrink_to_fit();
+            self.using_dense_storage = true;
+        }
+
+        pub fn switch_to_sparse(&mut self, pool: &mut DenseBufferPool) {
+            let dense = match self.changes_dense.take() {
+                Some(dense) => dense,
+                None => return
+            };
+
+            for x in 0..PARTITION_SIZE {
+                for y in 0..PARTITION_SIZE {
+                    let index = x as usize + ((PARTITION_SIZE as usize) * (y as usize));
+                    // Unknown cells were never changed, so they don't belong in the sparse table
+                    let packed = dense.get(index);
+                    if packed != PACKED_UNKNOWN {
+                        let internal_key = ((x as u16) << 8) | (y as u16);
+                        self.changes_map.insert(internal_key, packed);
+                    }
+                }
+            }
+
+            pool.give_back(dense.words);
+            self.using_dense_storage = false;
+        }
+    }
+
+    impl TileMap {
+        pub fn new() -> TileMap {
+            TileMap::with_seed(0)
+        }
+
+        pub fn with_seed(seed: u64) -> TileMap {
+            TileMap::new_with_config(seed, GeneratorConfig::default())
+        }
+
+        pub fn new_with_config(seed: u64, generator_config: GeneratorConfig) -> TileMap {
+            TileMap::new_with_passes(seed, generator_config, STANDARD_GENERATOR_PASSES.to_vec())
+        }
+
+        // Generates untouched terrain with these passes in order instead of the standard ones
+        // Saves only have the seed and config, so a map using its own passes needs them set again after loading
+        pub fn new_with_passes(seed: u64, generator_config: GeneratorConfig, generator_passes: Vec<GeneratorPassBuilder>) -> TileMap {
+            let generator = GeneratorPipeline::build(&generator_passes, seed, &generator_config);
+
+            let mut tile_type_sizes: HashMap<TileValue, GridCoord> = HashMap::new();
+            tile_type_sizes.insert(TileValue::HabModule, GridCoord{x: 3, y: 3});
+            for kind in PointOfInterest::KINDS.iter() {
+                tile_type_sizes.insert(*kind, PointOfInterest::size_of(kind));
+            }
+
+            TileMap { 
+                generator_config,
+                generator_passes,
+                generator,
+                custom_generator: false,
+                seed,
+                layers: [FastHashMap::default(), FastHashMap::default(), FastHashMap::default(), FastHashMap::default()],
+                dense_pool: DenseBufferPool::new(),
+                tile_cache: RefCell::new(LruCache::new(MIN_TILE_CACHE_SIZE)),
+                generated_partitions: RefCell::new(LruCache::new(GENERATED_PARTITION_CACHE_SIZE)),
+                background_generator: None,
+                bounds: None,
+                pager: None,
+                dirty_regions: DirtyRegions::default(),
+                tile_data: TileData::new(),
+                undo_steps: VecDeque::new(),
+                redo_steps: Vec::new(),
+                recording_edit: None,
+                caching_enabled: true,
+                tile_type_sizes
+            }
+        }
+
+        // Generates every untouched tile with this function, ignoring the seed and config
+        // Saves can't hold the function, so it needs setting again after loading
+        pub fn with_generator(generator: GeneratorFunc) -> TileMap {
+            let mut map = TileMap::new_with_passes(0, GeneratorConfig::default(), Vec::new());
+            map.set_generator(generator);
+            map
+        }
+
+        // The landmark generated in the partition pos is in, if it got one, whether or not anything has been built over it since
+        pub fn point_of_interest(&self, pos: &GridCoord) -> Option<PointOfInterest> {
+            self.generator.point_of_interest(&TileMap::partition_of(&self.wrap(pos)))
+        }
+
+        pub fn seed(&self) -> u64 {
+            self.seed
+        }
+
+        pub fn generator_config(&self) -> GeneratorConfig {
+            self.generator_config
+        }
+
+        // Changes the terrain of every tile that hasn't been touched, placed tiles stay as they are
+        pub fn set_generator_config(&mut self, generator_config: GeneratorConfig) {
+            if generator_config == self.generator_config { return; }
+
+            self.generator_config = generator_config;
+            if !self.custom_generator {
+                self.rebuild_generator();
+            }
+        }
+
+        // Same as setting the config, untouched tiles change and placed ones don't
+        // Replaces a generator function if there was one
+        pub fn set_generator_passes(&mut self, generator_passes: Vec<GeneratorPassBuilder>) {
+            self.generator_passes = generator_passes;
+            self.rebuild_generator();
+        }
+
+        // Swaps the passes out for a function, setting passes again goes back to them
+        pub fn set_generator(&mut self, generator: GeneratorFunc) {
+            self.generator = GeneratorPipeline::custom(generator);
+            self.custom_generator = true;
+            self.generator_changed();
+        }
+
+        fn rebuild_generator(&mut self) {
+            self.generator = GeneratorPipeline::build(&self.generator_passes, self.seed, &self.generator_config);
+            self.custom_generator = false;
+            self.generator_changed();
+        }
+
+        fn generator_changed(&mut self) {
+            // Every generated value in the caches may be wrong now
+            self.tile_cache.borrow_mut().clear();
+            self.generated_partitions.borrow_mut().clear();
+            // Anything the old worker was still making is for the old config
+            if self.background_generator.is_some() {
+                self.enable_background_generation();
+            }
+            self.dirty_regions.everything = true;
+        }
+
+        // Limits the world to a rectangle, or makes it infinite again with None
+        // Changes already made outside the new bounds are kept, they just can't be seen or changed until the bounds cover them again
+        pub fn set_bounds(&mut self, bounds: Option<WorldBounds>) {
+            self.bounds = bounds;
+            self.tile_cache.borrow_mut().clear();
+            self.dirty_regions.everything = true;
+        }
+
+        pub fn bounds(&self) -> Option<WorldBounds> {
+            self.bounds
+        }
+
+        pub fn in_bounds(&self, pos: &GridCoord) -> bool {
+            match &self.bounds {
+                Some(bounds) => bounds.wrap || bounds.contains(pos),
+                None => true
+            }
+        }
+
+        // Where pos is stored, tiles are only ever kept under their wrapped position
+        fn wrap(&self, pos: &GridCoord) -> GridCoord {
+            match &self.bounds {
+                Some(bounds) => bounds.wrap(pos),
+                None => *pos
+            }
+        }
+
+        // On a wrapping world, whichever copy of pos is closest to near, otherwise just pos
+        pub fn nearest_copy(&self, pos: &GridCoord, near: &GridCoord) -> GridCoord {
+            match &self.bounds {
+                Some(bounds) => bounds.nearest_copy(pos, near),
+                None => *pos
+            }
+        }
+
+        // From now on untouched partitions get generated on a worker thread instead of on the first sample
+        // Call receive_generated_partitions regularly (once a frame) to pick up the finished ones
+        pub fn enable_background_generation(&mut self) {
+            self.background_generator = Some(BackgroundGenerator::spawn(self.generator.clone()));
+        }
+
+        // Stores every partition the worker has finished since last time, returns how many there were
+        pub fn receive_generated_partitions(&mut self) -> usize {
+            let generator = match &self.background_generator {
+                Some(generator) => generator,
+                None => return 0
+            };
+
+            let mut received = 0;
+            let mut generated_partitions = self.generated_partitions.borrow_mut();
+            for (partition_coord, generated) in generator.results.try_iter() {
+                generator.pending.borrow_mut().remove(&partition_coord);
+                generated_partitions.put(PartitionKey::new(&partition_coord), generated);
+                self.dirty_regions.partitions.insert(partition_coord);
+                received += 1;
+            }
+            received
+        }
+
+        // Hands back everything that changed since the last call and starts tracking afresh
+        // Meant to be drained once a frame by whatever keeps derived data (render caches, rooms, paths) up to date
+        pub fn take_dirty_regions(&mut self) -> DirtyRegions {
+            std::mem::take(&mut self.dirty_regions)
+        }
+
+        pub fn sample(&self, pos: &GridCoord) -> TileValue {
+            let pos = &self.wrap(pos);
+            if !self.caching_enabled {
+                return self.sample_uncached(pos);
+            }
+
+            let mut cache = self.tile_cache.borrow_mut();
+            if let Some(value) = cache.get(pos) {
+                return *value;
+            }
+
+            let value = self.sample_uncached(pos);
+            // Placeholders would otherwise stick around after the real tile arrives
+            if value != TileValue::Generating {
+                cache.put(*pos, value);
+            }
+            value
+        }
+
+        // Resizes the tile cache to hold everything visible in the given view
+        // prefetch_margin is how many extra tiles of width and height to keep room for, so the edge the camera is moving towards stays cached
+        pub fn resize_cache_for_view(&mut self, view: &Rectangle, prefetch_margin: f32) {
+            let width = (view.size.x + prefetch_margin.max(0.0)).ceil() as usize + 1;
+            let height = (view.size.y + prefetch_margin.max(0.0)).ceil() as usize + 1;
+            let wanted_size = (width * height).max(MIN_TILE_CACHE_SIZE);
+
+            let mut cache = self.tile_cache.borrow_mut();
+            // Grow straight away to avoid thrashing, but only shrink once the view is a lot smaller so small zoom changes don't churn the cache
+            if wanted_size > cache.cap() || wanted_size < cache.cap() / 2 {
+                cache.resize(wanted_size);
+            }
+        }
+
+        // Samples every tile within bounds so they are generated and cached before anything needs them
+        pub fn prefetch_rect(&self, bounds: &Rectangle) {
+            self.for_each_tile_rect(bounds, |_pos: &GridCoord, _value: &TileValue, _size: &GridCoord| {});
+        }
+
+        pub fn dense_pool_stats(&self) -> DensePoolStats {
+            self.dense_pool.stats()
+        }
+
+        // Partitions with at least one change stored on the layer
+        pub fn changed_partition_count(&self, layer: MapLayer) -> usize {
+            self.layers[layer as usize].len()
+        }
+
+        // Calls func with every tile on the layer that's been changed from what was generated, only within the area if one is given
+        // Partitions come in no particular order, ones that are paged out are read back from disk without paging them in
+        pub fn for_each_changed_tile<F>(&self, layer: MapLayer, area: Option<(&GridCoord, &GridCoord)>, mut func: F) -> Result<(), SaveError>
+            where F : FnMut(&GridCoord, &TileValue) {
+            let overlaps = |partition_coord: &GridCoord| match area {
+                Some((top_left, size)) => partition_coord.x + (PARTITION_SIZE as i64) > top_left.x && partition_coord.x < top_left.x + size.x
+                    && partition_coord.y + (PARTITION_SIZE as i64) > top_left.y && partition_coord.y < top_left.y + size.y,
+                None => true
+            };
+            let mut visit = |pos: &GridCoord, value: &TileValue| {
+                if area.map(|(top_left, size)| GridCoord::is_within_bounds(top_left, size, pos)).unwrap_or(true) {
+                    func(pos, value);
+                }
+            };
+
+            for (partition_key, partition) in self.layers[layer as usize].iter() {
+                let partition_coord = partition_key.partition();
+                if overlaps(&partition_coord) {
+                    partition.for_each_change(&partition_coord, &mut visit);
+                }
+            }
+            if let Some(pager) = &self.pager {
+                for partition_coord in pager.paged_out.iter().filter(|partition_coord| overlaps(partition_coord)) {
+                    for (change_layer, pos, value) in pager.read(partition_coord)?.changes.iter() {
+                        if *change_layer == layer { visit(pos, value); }
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        // Runs every rule once over the partitions near anything built, returns how many tiles (or buildings) it changed
+        // Meant to be called every so often rather than every frame, tick makes each run's rolls different but the same for the same world
+        // Changes aren't undo steps, the world weathering isn't something the player did
+        pub fn apply_tile_rules(&mut self, rules: &TileRules, tick: u64) -> usize {
+            let mut active: Vec<GridCoord> = Vec::new();
+            let radius = rules.active_radius.max(0) * PARTITION_SIZE as i64;
+            for partition_key in self.layers[MapLayer::Structure as usize].keys() {
+                let built = partition_key.partition();
+                for y in ((built.y - radius)..=(built.y + radius)).step_by(PARTITION_SIZE as usize) {
+                    for x in ((built.x - radius)..=(built.x + radius)).step_by(PARTITION_SIZE as usize) {
+                        active.push(self.wrap(&GridCoord{x, y}));
+                    }
+                }
+            }
+            // Sorted so the same world always changes the same way
+            active.sort_by_key(|partition_coord| (partition_coord.y, partition_coord.x));
+            active.dedup();
+
+            // Everything is decided first so one change can't set off another in the same run
+            let mut changes: Vec<(GridCoord, TileValue, TileValue)> = Vec::new();
+            let partition_size = GridCoord{x: PARTITION_SIZE as i64, y: PARTITION_SIZE as i64};
+            let tick_hash = mix_hash(self.seed ^ mix_hash(tick));
+            for partition_coord in active.iter() {
+                for (index, value) in self.sample_rect(partition_coord, &partition_size).tiles().iter().enumerate() {
+                    let pos = GridCoord{x: partition_coord.x + index as i64 % partition_size.x, y: partition_coord.y + index as i64 / partition_size.x};
+                    let tile_hash = mix_hash(tick_hash ^ mix_hash(((pos.x as u64) << 32) ^ (pos.y as u64 & 0xFFFF_FFFF)));
+                    // Each rule rolls for itself, otherwise a rule could only ever fire on rolls the ones before it had already turned down
+                    for (rule_index, rule) in rules.rules.iter().enumerate().filter(|(_, rule)| rule.from == *value) {
+                        let roll = (mix_hash(tile_hash ^ mix_hash(rule_index as u64)) >> 40) as f32 / (1u64 << 24) as f32;
+                        if roll < rule.chance && self.rule_condition_met(&rule.condition, &pos, value) {
+                            changes.push((pos, *value, rule.to));
+                            break;
+                        }
+                    }
+                }
+            }
+
+            for (pos, from, to) in changes.iter() {
+                let size = self.get_tile_size(from);
+                let top_left = GridCoord{x: pos.x - (size.x / 2), y: pos.y - (size.y / 2)};
+                for y in top_left.y..(top_left.y + size.y) {
+                    for x in top_left.x..(top_left.x + size.x) {
+                        self.make_single_tile_change(&GridCoord{x, y}, *to);
+                    }
+                }
+            }
+            changes.len()
+        }
+
+        fn rule_condition_met(&self, condition: &RuleCondition, pos: &GridCoord, value: &TileValue) -> bool {
+            match condition {
+                RuleCondition::Always => true,
+                RuleCondition::Exposed => [(0, -1), (1, 0), (0, 1), (-1, 0)].iter()
+                    .any(|(offset_x, offset_y)| self.sample(&GridCoord{x: pos.x + offset_x, y: pos.y + offset_y}) == TileValue::Empty),
+                RuleCondition::NoneNearby { value: nearby, distance } => {
+                    let size = self.get_tile_size(value);
+                    let top_left = GridCoord{x: pos.x - (size.x / 2) - distance, y: pos.y - (size.y / 2) - distance};
+                    let area = GridCoord{x: size.x + distance * 2, y: size.y + distance * 2};
+                    !self.tiles_in_area(&top_left, &area).any(|(_, found, _)| found == *nearby)
+                }
+            }
+        }
+
+        pub fn cache_capacity(&self) -> usize {
+            self.tile_cache.borrow().cap()
+        }
+
+        // Mostly for benchmarking and debugging, sampling gives the same values either way
+        pub fn set_caching_enabled(&mut self, enabled: bool) {
+            if !enabled {
+                self.tile_cache.borrow_mut().clear();
+                self.generated_partitions.borrow_mut().clear();
+            }
+            self.caching_enabled = enabled;
+        }
+
+        fn sample_uncached(&self, pos: &GridCoord) -> TileValue {
+            if !self.in_bounds(pos) {
+                return TileValue::Void;
+            }
+            if self.request_if_paged_out(&TileMap::partition_of(pos)) {
+                return TileValue::Generating;
+            }
+
+            // Check the history for a matching change, buildings sit on top of the terrain
+            if let Some(tile_value) = self.layer_change(MapLayer::Structure, pos) {
+                return tile_value;
+            }
+            if let Some(tile_value) = self.layer_change(MapLayer::Terrain, pos) {
+                return tile_value;
+            }
+
+            // If no edits have been applied to this tile, it's whatever was generated there
+            self.sample_generated(pos)
+        }
+
+        // Whether the tile at pos is still as generated, designations like forbidden zones don't count as edits
+        pub fn provenance(&self, pos: &GridCoord) -> Provenance {
+            if self.request_if_paged_out(&TileMap::partition_of(&self.wrap(pos))) {
+                return Provenance::Unknown;
+            }
+            if self.layer_change(MapLayer::Structure, pos).is_some() || self.layer_change(MapLayer::Terrain, pos).is_some() {
+                Provenance::Edited
+            }
+            else {
+                Provenance::Generated
+            }
+        }
+
+        // What a single layer has at pos, None if nothing is on that layer there
+        // Terrain always has something, tiles nobody has touched are whatever was generated
+        pub fn sample_layer(&self, layer: MapLayer, pos: &GridCoord) -> Option<TileValue> {
+            match layer {
+                MapLayer::Terrain => Some(self.layer_change(layer, pos).unwrap_or_else(|| self.sample_generated(pos))),
+                _ => self.layer_change(layer, pos)
+            }
+        }
+
+        // Everything in the area that has something on the given layer, along rows from the top left
+        pub fn layer_tiles_in_area(&self, layer: MapLayer, top_left: &GridCoord, size: &GridCoord) -> impl Iterator<Item = (GridCoord, TileValue)> + '_ {
+            let top_left = *top_left;
+            let size = *size;
+            (top_left.y..(top_left.y + size.y))
+                .flat_map(move |y| (top_left.x..(top_left.x + size.x)).map(move |x| GridCoord{x, y}))
+                .filter_map(move |pos| self.sample_layer(layer, &pos).map(|value| (pos, value)))
+        }
+
+        // What the generator puts at pos, whatever has been dug or built there since
+        // Generating while a background generated partition isn't ready yet, same as sample
+        pub fn sample_generated(&self, pos: &GridCoord) -> TileValue {
+            if !self.in_bounds(pos) {
+                return TileValue::Void;
+            }
+            let GridCoord { x, y } = self.wrap(pos);
+            let partition_coord = GridCoord { x: x & !(PARTITION_SIZE as i64 - 1), y: y & !(PARTITION_SIZE as i64 - 1) };
+
+            if !self.caching_enabled {
+                return self.generator.generate_tile(x, y);
+            }
+
+            let index = (x & (PARTITION_SIZE as i64 - 1)) as usize + (PARTITION_SIZE as usize) * ((y & (PARTITION_SIZE as i64 - 1)) as usize);
+            let mut generated_partitions = self.generated_partitions.borrow_mut();
+            match self.generated_partition(&mut generated_partitions, &partition_coord) {
+                Some(generated) => unpack_generated(generated[index], &partition_coord),
+                None => TileValue::Generating
+            }
+        }
+
+        // Generated terrain of a partition, making it first if needed
+        // None while it's still being made in the background
+        fn generated_partition<'c>(&self, generated_partitions: &'c mut LruCache<PartitionKey, Vec<PackedTile>>, partition_coord: &GridCoord) -> Option<&'c Vec<PackedTile>> {
+            let key = PartitionKey::new(partition_coord);
+            if !generated_partitions.contains(&key) {
+                if let Some(generator) = &self.background_generator {
+                    generator.request(partition_coord);
+                    return None;
+                }
+
+                let generated = generate_partition(&self.generator, partition_coord);
+                generated_partitions.put(key, generated);
+            }
+            generated_partitions.get(&key)
+        }
+
+        // Samples a whole rectangle in one go, looking up each partition once per row rather than once per tile
+        // Subtiles come back as they are, use for_each_tile or tiles_in_area to get whole buildings
+        pub fn sample_rect(&self, top_left: &GridCoord, size: &GridCoord) -> TileBuffer {
+            let mut buffer = TileBuffer::new();
+            self.sample_rect_into(top_left, size, &mut buffer);
+            buffer
+        }
+
+        // Same as sample_rect, reusing the buffer's allocation
+        pub fn sample_rect_into(&self, top_left: &GridCoord, size: &GridCoord, buffer: &mut TileBuffer) {
+            buffer.top_left = *top_left;
+            buffer.size = GridCoord{x: size.x.max(0), y: size.y.max(0)};
+            buffer.tiles.clear();
+            buffer.tiles.reserve((buffer.size.x * buffer.size.y) as usize);
+
+            let partition_mask = !(PARTITION_SIZE as i64 - 1);
+            let x_max = top_left.x + buffer.size.x;
+            let mut generated_partitions = self.generated_partitions.borrow_mut();
+
+            for y in top_left.y..(top_left.y + buffer.size.y) {
+                let mut x = top_left.x;
+                while x < x_max {
+                    let wrapped = self.wrap(&GridCoord{x, y});
+                    let partition_coord = GridCoord{x: wrapped.x & partition_mask, y: wrapped.y & partition_mask};
+                    let mut segment_end = (x + partition_coord.x + PARTITION_SIZE as i64 - wrapped.x).min(x_max);
+                    // The tile after the seam is back at the other edge, most likely in some other partition
+                    if let Some(bounds) = self.bounds.filter(|bounds| bounds.wrap) {
+                        segment_end = segment_end.min(x + bounds.top_left.x + bounds.size.x - wrapped.x);
+                    }
+
+                    if self.request_if_paged_out(&partition_coord) {
+                        buffer.tiles.extend((x..segment_end).map(|_| TileValue::Generating));
+                        x = segment_end;
+                        continue;
+                    }
+                    // Don't generate partitions nobody will ever see just to throw their tiles away
+                    if let Some(bounds) = self.bounds.filter(|bounds| !bounds.wrap) {
+                        let row_inside = y >= bounds.top_left.y && y < bounds.top_left.y + bounds.size.y;
+                        if !row_inside || segment_end <= bounds.top_left.x || x >= bounds.top_left.x + bounds.size.x {
+                            buffer.tiles.extend((x..segment_end).map(|_| TileValue::Void));
+                            x = segment_end;
+                            continue;
+                        }
+                    }
+
+                    let partition_key = PartitionKey::new(&partition_coord);
+                    let structures = self.layers[MapLayer::Structure as usize].get(&partition_key);
+                    let terrain = self.layers[MapLayer::Terrain as usize].get(&partition_key);
+                    let generated = if self.caching_enabled { self.generated_partition(&mut generated_partitions, &partition_coord) } else { None };
+
+                    for segment_x in x..segment_end {
+                        buffer.tiles.push(self.value_from_partition(&GridCoord{x: wrapped.x + (segment_x - x), y: wrapped.y}, structures, terrain, generated));
+                    }
+
+                    x = segment_end;
+                }
+            }
+        }
+
+        // Same answer as sample_uncached, from a partition's tables that the caller already looked up
+        fn value_from_partition(&self, pos: &GridCoord, structures: Option<&AreaChanges>, terrain: Option<&AreaChanges>, generated: Option<&Vec<PackedTile>>) -> TileValue {
+            if !self.in_bounds(pos) {
+                return TileValue::Void;
+            }
+            let changed_value = structures.and_then(|changes| changes.sample(pos))
+                .or_else(|| terrain.and_then(|changes| changes.sample(pos)));
+            match (changed_value, generated) {
+                (Some(value), _) => value,
+                (None, Some(generated)) => {
+                    let index = (pos.x & (PARTITION_SIZE as i64 - 1)) as usize + (PARTITION_SIZE as usize) * ((pos.y & (PARTITION_SIZE as i64 - 1)) as usize);
+                    unpack_generated(generated[index], &TileMap::partition_of(pos))
+                }
+                (None, None) if self.caching_enabled => TileValue::Generating,
+                (None, None) => self.generator.generate_tile(pos.x, pos.y)
+            }
+        }
+
+        // How many tiles of each kind are in the area, a building counts once for every tile it covers
+        // Tiles still generating or past the edge of the world are in there too, as Generating and Void
+        pub fn histogram_in_rect(&self, top_left: &GridCoord, size: &GridCoord) -> HashMap<TileValue, u64> {
+            let mut histogram: HashMap<TileValue, u64> = HashMap::new();
+            let mut buffer = TileBuffer::new();
+            let partition_mask = !(PARTITION_SIZE as i64 - 1);
+            let (x_max, y_max) = (top_left.x + size.x.max(0), top_left.y + size.y.max(0));
+
+            // A partition only has a handful of different tiles in it, so count into a short list and only hash once per partition
+            let mut counts: Vec<(TileValue, u64)> = Vec::new();
+            let mut partition_y = top_left.y & partition_mask;
+            while partition_y < y_max {
+                let mut partition_x = top_left.x & partition_mask;
+                while partition_x < x_max {
+                    let clip_top_left = GridCoord{x: partition_x.max(top_left.x), y: partition_y.max(top_left.y)};
+                    let clip_size = GridCoord{x: (partition_x + PARTITION_SIZE as i64).min(x_max) - clip_top_left.x, y: (partition_y + PARTITION_SIZE as i64).min(y_max) - clip_top_left.y};
+                    self.sample_rect_into(&clip_top_left, &clip_size, &mut buffer);
+
+                    counts.clear();
+                    for value in buffer.tiles().iter() {
+                        match counts.iter_mut().find(|(counted, _)| counted == value) {
+                            Some((_, count)) => *count += 1,
+                            None => counts.push((*value, 1))
+                        }
+                    }
+                    for (value, count) in counts.iter() {
+                        let value = match value {
+                            TileValue::Subtile(anchor) => self.sample(anchor),
+                            value => *value
+                        };
+                        *histogram.entry(value).or_insert(0) += count;
+                    }
+                    partition_x += PARTITION_SIZE as i64;
+                }
+                partition_y += PARTITION_SIZE as i64;
+            }
+            histogram
+        }
+
+        // Tiles in the area that pass matches, counted the same way as histogram_in_rect
+        pub fn count_in_rect(&self, top_left: &GridCoord, size: &GridCoord, matches: fn(&TileValue) -> bool) -> u64 {
+            self.histogram_in_rect(top_left, size).iter().filter(|(value, _)| matches(value)).map(|(_, count)| count).sum()
+        }
+
+        // Which of the 8 tiles around pos pass matches, as NEIGHBOR_* bits, for picking edge and corner sprite variants
+        pub fn neighbor_mask(&self, pos: &GridCoord, matches: fn(&TileValue) -> bool) -> u8 {
+            self.neighbor_masks_in_area(pos, &GridCoord{x: 1, y: 1}, matches)[0]
+        }
+
+        // neighbor_mask of every tile in the area, along rows from the top left
+        // Samples the area plus a one tile border in one go, so drawing a screen of autotiles doesn't cost 8 extra samples per tile
+        pub fn neighbor_masks_in_area(&self, top_left: &GridCoord, size: &GridCoord, matches: fn(&TileValue) -> bool) -> Vec<u8> {
+            let size = GridCoord{x: size.x.max(0), y: size.y.max(0)};
+            let border = self.sample_rect(&GridCoord{x: top_left.x - 1, y: top_left.y - 1}, &GridCoord{x: size.x + 2, y: size.y + 2});
+            let border_width = size.x + 2;
+            let matched: Vec<bool> = border.tiles().iter().map(matches).collect();
+
+            let mut masks: Vec<u8> = Vec::with_capacity((size.x * size.y) as usize);
+            for y in 1..(size.y + 1) {
+                for x in 1..(size.x + 1) {
+                    let mut mask = 0;
+                    for (bit, (offset_x, offset_y)) in NEIGHBOR_OFFSETS.iter().enumerate() {
+                        if matched[((y + offset_y) * border_width + x + offset_x) as usize] {
+                            mask |= 1 << bit;
+                        }
+                    }
+                    masks.push(mask);
+                }
+            }
+            masks
+        }
+
+        // First tile on the line between two tiles that isn't open ground, None if the way is clear
+        // from itself is skipped so a turret can see out of the building it's part of, tiles still generating count as blocking
+        // Walks the line a partition at a time so each partition's tables are only looked up once per visit
+        pub fn raycast(&self, from: &GridCoord, to: &GridCoord) -> Option<GridCoord> {
+            let partition_mask = !(PARTITION_SIZE as i64 - 1);
+            let mut generated_partitions = self.generated_partitions.borrow_mut();
+
+            let mut line = GridLine::new(from, to);
+            line.next();
+            let mut next = line.next();
+            while let Some(segment_start) = next {
+                let wrapped_start = self.wrap(&segment_start);
+                let partition_coord = GridCoord{x: wrapped_start.x & partition_mask, y: wrapped_start.y & partition_mask};
+                // Same as a partition that's still generating, there's no telling what's in it yet
+                if self.request_if_paged_out(&partition_coord) {
+                    return Some(segment_start);
+                }
+                let partition_key = PartitionKey::new(&partition_coord);
+                let structures = self.layers[MapLayer::Structure as usize].get(&partition_key);
+                let terrain = self.layers[MapLayer::Terrain as usize].get(&partition_key);
+                let generated = if self.caching_enabled { self.generated_partition(&mut generated_partitions, &partition_coord) } else { None };
+
+                let mut pos = segment_start;
+                loop {
+                    if self.value_from_partition(&self.wrap(&pos), structures, terrain, generated) != TileValue::Empty {
+                        return Some(pos);
+                    }
+
+                    next = line.next();
+                    match next.map(|next_pos| (next_pos, self.wrap(&next_pos))) {
+                        Some((next_pos, wrapped)) if wrapped.x & partition_mask == partition_coord.x && wrapped.y & partition_mask == partition_coord.y => pos = next_pos,
+                        _ => break
+                    }
+                }
+            }
+
+            None
+        }
+
+        // One pixel per tile along rows from the top left, for looking over generation settings or sharing a seed
+        // Buildings are coloured over their whole footprint, the palette gets the building rather than its subtiles
+        pub fn render_region_to_image(&self, top_left: &GridCoord, size: &GridCoord, palette: TilePalette) -> RgbaBuffer {
+            let size = GridCoord{x: size.x.max(0), y: size.y.max(0)};
+            let mut image = RgbaBuffer::new(size.x as u32, size.y as u32);
+            for (index, value) in self.sample_rect(top_left, &size).tiles().iter().enumerate() {
+                let value = match value {
+                    TileValue::Subtile(anchor) => self.sample(anchor),
+                    value => *value
+                };
+                image.set_pixel((index as i64 % size.x) as u32, (index as i64 / size.x) as u32, palette(&value));
+            }
+            image
+        }
+
+        // RGBA pixels along rows from the top left, coloured with default_palette by the most common tile within each tiles_per_pixel square
+        // Goes a partition at a time, looking each one up once for every sample that falls in it, so a corner minimap stays cheap to redraw
+        pub fn minimap(&self, center: &GridCoord, tiles_per_pixel: i64, size: &GridCoord) -> Vec<u8> {
+            let size = GridCoord{x: size.x.max(0), y: size.y.max(0)};
+            let tiles_per_pixel = tiles_per_pixel.max(1);
+            let samples_per_side = tiles_per_pixel.min(MINIMAP_SAMPLES_PER_SIDE);
+            let step = tiles_per_pixel / samples_per_side;
+            let top_left = GridCoord{x: center.x - size.x * tiles_per_pixel / 2, y: center.y - size.y * tiles_per_pixel / 2};
+
+            // Tiles with a vote along one axis, grouped into runs that stay in one partition, each with the pixel and slot it votes in
+            // Partition coordinate, then (wrapped position, pixel, slot) for each sample
+            type SampleRun = (i64, Vec<(i64, i64, i64)>);
+            let partition_mask = !(PARTITION_SIZE as i64 - 1);
+            let sample_runs = |pixels: i64, wrap_axis: &dyn Fn(i64) -> i64, start: i64| {
+                let mut runs: Vec<SampleRun> = Vec::new();
+                for pixel in 0..pixels {
+                    for sample in 0..samples_per_side {
+                        let wrapped = wrap_axis(start + pixel * tiles_per_pixel + sample * step + step / 2);
+                        match runs.last_mut() {
+                            Some((partition, run)) if *partition == wrapped & partition_mask => run.push((wrapped, pixel, sample)),
+                            _ => runs.push((wrapped & partition_mask, vec![(wrapped, pixel, sample)]))
+                        }
+                    }
+                }
+                runs
+            };
+            let column_runs = sample_runs(size.x, &|x| self.wrap(&GridCoord{x, y: top_left.y}).x, top_left.x);
+            let row_runs = sample_runs(size.y, &|y| self.wrap(&GridCoord{x: top_left.x, y}).y, top_left.y);
+
+            let votes_per_pixel = (samples_per_side * samples_per_side) as usize;
+            let vote_index = |pixel_x: i64, pixel_y: i64, sample_x: i64, sample_y: i64| ((pixel_y * size.x + pixel_x) as usize) * votes_per_pixel + (sample_y * samples_per_side + sample_x) as usize;
+            let mut votes = vec![TileValue::Generating; (size.x * size.y) as usize * votes_per_pixel];
+            {
+                let mut generated_partitions = self.generated_partitions.borrow_mut();
+                for (partition_y, row_run) in row_runs.iter() {
+                    for (partition_x, column_run) in column_runs.iter() {
+                        let partition_coord = GridCoord{x: *partition_x, y: *partition_y};
+                        if self.request_if_paged_out(&partition_coord) { continue; }
+                        // Don't generate partitions wholly outside the world, their tiles would all be Void anyway
+                        let outside = self.bounds.filter(|bounds| !bounds.wrap).filter(|bounds| {
+                            partition_coord.x + PARTITION_SIZE as i64 <= bounds.top_left.x || partition_coord.x >= bounds.top_left.x + bounds.size.x
+                                || partition_coord.y + PARTITION_SIZE as i64 <= bounds.top_left.y || partition_coord.y >= bounds.top_left.y + bounds.size.y
+                        }).is_some();
+
+                        let partition_key = PartitionKey::new(&partition_coord);
+                        let structures = self.layers[MapLayer::Structure as usize].get(&partition_key);
+                        let terrain = self.layers[MapLayer::Terrain as usize].get(&partition_key);
+                        let generated = if self.caching_enabled && !outside { self.generated_partition(&mut generated_partitions, &partition_coord) } else { None };
+                        for (y, pixel_y, sample_y) in row_run.iter() {
+                            for (x, pixel_x, sample_x) in column_run.iter() {
+                                votes[vote_index(*pixel_x, *pixel_y, *sample_x, *sample_y)] = if outside { TileValue::Void } else {
+                                    self.value_from_partition(&GridCoord{x: *x, y: *y}, structures, terrain, generated)
+                                };
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Every tile of a building counts for the building, sampled only now the generated partitions aren't borrowed
+            for vote in votes.iter_mut() {
+                if let TileValue::Subtile(anchor) = vote {
+                    *vote = self.sample(anchor);
+                }
+            }
+
+            let mut pixels: Vec<u8> = Vec::with_capacity((size.x * size.y) as usize * 4);
+            for pixel_votes in votes.chunks(votes_per_pixel) {
+                // Ties go to whichever came first along the pixel's rows
+                let mut majority = (TileValue::Generating, 0);
+                for (index, value) in pixel_votes.iter().enumerate() {
+                    let count = pixel_votes[index..].iter().filter(|other| *other == value).count();
+                    if count > majority.1 && !pixel_votes[..index].contains(value) {
+                        majority = (*value, count);
+                    }
+                }
+                pixels.extend_from_slice(&default_palette(&majority.0));
+            }
+            pixels
+        }
+
+        // Volume multiplier for a sound at emitter heard from listener, 1 with a clear line and OCCLUSION_PER_TILE less for every tile in the way
+        // The emitter's own tile doesn't count, so mining a rock face isn't muffled by the rock being mined
+        pub fn sound_attenuation(&self, listener: &GridCoord, emitter: &GridCoord) -> f32 {
+            let mut occluding_tiles = 0;
+            let mut from = *listener;
+            while occluding_tiles < MAX_OCCLUDING_TILES {
+                match self.raycast(&from, emitter) {
+                    Some(blocker) if blocker != *emitter => {
+                        occluding_tiles += 1;
+                        from = blocker;
+                    }
+                    _ => break
+                }
+            }
+            OCCLUSION_PER_TILE.powi(occluding_tiles)
+        }
+
+        pub fn area_clear(&mut self, top_left: &GridCoord, size: &GridCoord) -> bool {
+            let x_min = top_left.x;
+            let x_max = top_left.x + size.x;
+            let y_min = top_left.y;
+            let y_max = top_left.y + size.y;
+
+            let mut clear = true;
+
+            for y in y_min..y_max {
+                for x in x_min..x_max {
+                    clear = clear && (self.sample(&GridCoord{x, y}) == TileValue::Empty);
+
+                    if !clear { return clear; }
+                }
+            }
+
+            return clear;
+        }
+
+
+        pub fn for_each_tile_rect<F>(&self, bounds: &Rectangle, mut func: F)
+            where F : FnMut(&GridCoord, &TileValue, &GridCoord) {
+            for (pos, value, size) in self.tiles_in_rect(bounds) {
+                func(&pos, &value, &size);
+            }
+        }
+
+        pub fn for_each_tile<F>(&self, top_left: &GridCoord, size: &GridCoord, mut func: F)
+            where F : FnMut(&GridCoord, &TileValue, &GridCoord) {
+            for (pos, value, size) in self.tiles_in_area(top_left, size) {
+                func(&pos, &value, &size);
+            }
+        }
+
+        // Same tiles as for_each_tile_rect, as (position, value, size) for use with for loops and iterator adapters
+        pub fn tiles_in_rect(&self, bounds: &Rectangle) -> TilesInArea<'_> {
+            // Bounds to draw between
+            let x_min = bounds.pos.x.floor() as i64;
+            let x_size = bounds.size.x.ceil() as i64 + 1;
+            let y_min = bounds.pos.y.floor() as i64;
+            let y_size = bounds.size.y.ceil() as i64 + 1;
+            
+            self.tiles_in_area(&GridCoord{x: x_min, y: y_min}, &GridCoord{x: x_size, y: y_size})
+        }
+
+        pub fn tiles_in_area(&self, top_left: &GridCoord, size: &GridCoord) -> TilesInArea<'_> {
+            TilesInArea {
+                map: self,
+                top_left: *top_left,
+                size: *size,
+                next_pos: *top_left,
+                refed_tiles: HashSet::new()
+            }
+        }
+
+        // Every tile reachable from start through 4-connected tiles that pass the predicate, empty if start itself doesn't
+        // The map goes on forever, so an open region never finishes, use flood_fill_bounded unless the region is known to be enclosed
+        pub fn flood_fill<F>(&self, start: &GridCoord, predicate: F) -> HashSet<GridCoord>
+            where F : FnMut(&GridCoord, &TileValue) -> bool {
+            // Can't go over usize::MAX tiles, so this always comes back with the region
+            self.flood_fill_bounded(start, predicate, usize::MAX).unwrap_or_default()
+        }
+
+        // Same as flood_fill, but gives up and returns None once the region grows past max_tiles
+        // None doubles as the answer to "is this area sealed" for anything that only cares about enclosures up to that size
+        pub fn flood_fill_bounded<F>(&self, start: &GridCoord, mut predicate: F, max_tiles: usize) -> Option<HashSet<GridCoord>>
+            where F : FnMut(&GridCoord, &TileValue) -> bool {
+            let mut region: HashSet<GridCoord> = HashSet::new();
+            if !predicate(start, &self.sample(start)) {
+                return Some(region);
+            }
+
+            let mut to_visit: Vec<GridCoord> = vec![*start];
+            region.insert(*start);
+            while let Some(pos) = to_visit.pop() {
+                for (x, y) in [(0, -1), (1, 0), (0, 1), (-1, 0)].iter() {
+                    let neighbour = GridCoord{x: pos.x + x, y: pos.y + y};
+                    if region.contains(&neighbour) || !predicate(&neighbour, &self.sample(&neighbour)) {
+                        continue;
+                    }
+                    if region.len() >= max_tiles {
+                        return None;
+                    }
+                    region.insert(neighbour);
+                    to_visit.push(neighbour);
+                }
+            }
+
+            Some(region)
+        }
+
+        // Tiles connected to start that have the same value it does, or the whole building if start is part of one
+        pub fn connected_region(&self, start: &GridCoord) -> HashSet<GridCoord> {
+            self.flood_fill(start, self.same_region_as(start))
+        }
+
+        // Same as connected_region, None if it's bigger than max_tiles
+        pub fn connected_region_bounded(&self, start: &GridCoord, max_tiles: usize) -> Option<HashSet<GridCoord>> {
+            self.flood_fill_bounded(start, self.same_region_as(start), max_tiles)
+        }
+
+        fn same_region_as(&self, start: &GridCoord) -> impl Fn(&GridCoord, &TileValue) -> bool {
+            let start_value = self.sample(start);
+            // Subtiles all point at different anchors, so buildings are matched by anchor rather than by value
+            let building_anchor = match start_value {
+                TileValue::Subtile(anchor) => Some(anchor),
+                _ if self.is_metatile_anchor(start) => Some(*start),
+                _ => None
+            };
+            move |pos: &GridCoord, value: &TileValue| match building_anchor {
+                Some(anchor) => *pos == anchor || *value == TileValue::Subtile(anchor),
+                None => *value == start_value
+            }
+        }
+
+        pub fn pos_to_grid(&mut self, world_x: f32 , world_y: f32) -> GridCoord {
+            let pos = GridCoord { x: world_x as i64, y: world_y as i64};
+            match self.sample(&pos) {
+                TileValue::Subtile(ref_position) => ref_position,
+                _ => pos
+            }
+        }
+
+        // Does nothing and returns false if any of the new value's footprint would be out of bounds
+        pub fn make_change(&mut self, pos: &GridCoord, new_value: &TileValue) -> bool {
+            // Buildings over the seam of a wrapping world are anchored on whichever side their anchor wraps to
+            let pos = &self.wrap(pos);
+            let new_size = self.get_tile_size(new_value);
+            if let Some(bounds) = self.bounds.filter(|bounds| !bounds.wrap) {
+                if !bounds.contains_area(&GridCoord{x: pos.x - (new_size.x / 2), y: pos.y - (new_size.y / 2)}, &new_size) {
+                    return false;
+                }
+            }
+            // Designations mark the tile rather than build on it, so there's no footprint to clear or fill
+            if MapLayer::for_value(new_value) == MapLayer::Designation {
+                self.set_forbidden(pos, &GridCoord{x: 1, y: 1}, true);
+                return true;
+            }
+
+            let starts_undo_step = self.begin_undo_step();
+
+            // Any cell of a building stands for the whole building, so its footprint is cleared from its anchor
+            let old_anchor = match self.sample(pos) {
+                TileValue::Subtile(anchor) if self.is_metatile_anchor(&anchor) => anchor,
+                _ => *pos
+            };
+            let old_value = self.sample(&old_anchor);
+            let old_tile_size = self.get_tile_size(&old_value);
+
+            if old_tile_size.x > 1 && old_tile_size.y > 1 {
+                let x_min = old_anchor.x - (old_tile_size.x / 2);
+                let y_min = old_anchor.y - (old_tile_size.y / 2);
+
+                self.set_area(&GridCoord{x: x_min, y: y_min}, &old_tile_size, TileValue::Empty );
+            }
+
+            let tile_size = self.get_tile_size(new_value);
+
+            let x_min = pos.x - (tile_size.x / 2);
+            let y_min = pos.y - (tile_size.y / 2);
+
+            self.set_area(&GridCoord{x: x_min, y: y_min}, &tile_size, TileValue::Subtile(*pos) );
+            self.make_single_tile_change(pos, *new_value);
+
+            if starts_undo_step { self.end_undo_step(); }
+            true
+        }
+
+        pub fn set_area(&mut self, top_left: &GridCoord, size: &GridCoord, new_value: TileValue) {
+            let starts_undo_step = self.begin_undo_step();
+
+            let x_min = top_left.x;
+            let y_min = top_left.y;
+
+            let x_max = x_min + size.x;
+            let y_max = y_min + size.y;
+
+            for y in y_min..y_max {
+                for x in x_min..x_max {
+                    self.make_single_tile_change(&GridCoord{x, y}, new_value);
+                }
+            }
+
+            if starts_undo_step { self.end_undo_step(); }
+        }
+
+        // Whether try_place would succeed, without changing anything
+        pub fn can_place(&self, pos: &GridCoord, value: &TileValue) -> Result<(), PlacementError> {
+            match value {
+                TileValue::Subtile(_) | TileValue::InternalUnknown | TileValue::Generating | TileValue::Error | TileValue::Forbidden | TileValue::Void => {
+                    return Err(PlacementError::NotPlaceable(*value));
+                }
+                _ => {}
+            }
+
+            let size = self.get_tile_size(value);
+            let top_left = GridCoord{x: pos.x - (size.x / 2), y: pos.y - (size.y / 2)};
+            let footprint = self.sample_rect(&top_left, &size);
+            for (index, tile_value) in footprint.tiles().iter().enumerate() {
+                if *tile_value != TileValue::Empty {
+                    let blocked_pos = GridCoord{x: top_left.x + (index as i64 % size.x), y: top_left.y + (index as i64 / size.x)};
+                    return Err(PlacementError::Blocked { pos: blocked_pos, value: *tile_value });
+                }
+            }
+            Ok(())
+        }
+
+        // make_change that refuses to build over anything, including other buildings' subtiles
+        pub fn try_place(&mut self, pos: &GridCoord, value: &TileValue) -> Result<(), PlacementError> {
+            self.can_place(pos, value)?;
+            self.make_change(pos, value);
+            Ok(())
+        }
+
+        // Copies the terrain and every building entirely inside the rectangle
+        // Buildings cut by its edge and generated points of interest are left out, there would be no pasting them whole
+        pub fn copy_rect(&self, top_left: &GridCoord, size: &GridCoord) -> Blueprint {
+            let size = GridCoord{x: size.x.max(0), y: size.y.max(0)};
+            let mut blueprint = Blueprint { size, terrain: Vec::with_capacity((size.x * size.y) as usize), structures: Vec::new() };
+
+            for y in top_left.y..(top_left.y + size.y) {
+                for x in top_left.x..(top_left.x + size.x) {
+                    let pos = GridCoord{x, y};
+                    let offset = GridCoord{x: x - top_left.x, y: y - top_left.y};
+                    let terrain = self.sample_layer(MapLayer::Terrain, &pos)
+                        .filter(|value| MapLayer::for_value(value) == MapLayer::Terrain && !matches!(value, TileValue::Generating | TileValue::Void));
+                    blueprint.terrain.push(terrain);
+
+                    let structure = match self.sample_layer(MapLayer::Structure, &pos) {
+                        Some(TileValue::Subtile(_)) | None => continue,
+                        Some(structure) => structure
+                    };
+                    let structure_size = self.get_tile_size(&structure);
+                    let footprint_top_left = GridCoord{x: x - structure_size.x / 2, y: y - structure_size.y / 2};
+                    let footprint_inside = GridCoord::is_within_bounds(top_left, &size, &footprint_top_left)
+                        && GridCoord::is_within_bounds(top_left, &size, &GridCoord{x: footprint_top_left.x + structure_size.x - 1, y: footprint_top_left.y + structure_size.y - 1});
+                    if footprint_inside {
+                        blueprint.structures.push((offset, structure));
+                    }
+                }
+            }
+            blueprint
+        }
+
+        // Writes a blueprint with its top left at pos, as one undo step
+        // Fails and changes nothing if it would cut through a building that's already there
+        pub fn paste(&mut self, blueprint: &Blueprint, pos: &GridCoord) -> Result<(), EditError> {
+            let mut edit = self.begin_edit();
+            for y in 0..blueprint.size.y {
+                for x in 0..blueprint.size.x {
+                    if let Some(terrain) = blueprint.terrain_at(&GridCoord{x, y}) {
+                        edit.set(&GridCoord{x: pos.x + x, y: pos.y + y}, terrain);
+                    }
+                }
+            }
+            // After the terrain, since writing terrain knocks down whatever is on it
+            for (offset, structure) in blueprint.structures.iter() {
+                edit.place(&GridCoord{x: pos.x + offset.x, y: pos.y + offset.y}, structure);
+            }
+            edit.commit()
+        }
+
+        // Starts a batch of writes that either all happen or none do, see EditTransaction
+        pub fn begin_edit(&mut self) -> EditTransaction<'_> {
+            EditTransaction { map: self, writes: Vec::new(), pending: HashMap::new(), required_clear: Vec::new() }
+        }
+
+        // Reverts the last make_change or set_area, returns false if there was nothing to undo
+        pub fn undo(&mut self) -> bool {
+            let step = match self.undo_steps.pop_back() {
+                Some(step) => step,
+                None => return false
+            };
+
+            // Backwards so tiles written more than once in the step end up with their value from before it
+            for edit in step.edits.iter().rev() {
+                self.write_layer_tile(edit.layer, &edit.pos, edit.old_value);
+            }
+            self.redo_steps.push(step);
+            true
+        }
+
+        // Puts back the last undone step, returns false if there was nothing to redo
+        pub fn redo(&mut self) -> bool {
+            let step = match self.redo_steps.pop() {
+                Some(step) => step,
+                None => return false
+            };
+
+            for edit in step.edits.iter() {
+                self.write_layer_tile(edit.layer, &edit.pos, edit.new_value);
+            }
+            self.undo_steps.push_back(step);
+            true
+        }
+
+        pub fn can_undo(&self) -> bool {
+            !self.undo_steps.is_empty()
+        }
+
+        pub fn can_redo(&self) -> bool {
+            !self.redo_steps.is_empty()
+        }
+
+        // Links the last undo step to steps on other maps tagged with the same group, returns false if there's no step to tag
+        // The map can't undo the other maps itself, callers check undo_group and redo_group to undo and redo them together
+        pub fn set_undo_group(&mut self, group: u64) -> bool {
+            match self.undo_steps.back_mut() {
+                Some(step) => {
+                    step.group = Some(group);
+                    true
+                }
+                None => false
+            }
+        }
+
+        // Group of the step undo would revert next, None if it isn't linked to anything
+        pub fn undo_group(&self) -> Option<u64> {
+            self.undo_steps.back().and_then(|step| step.group)
+        }
+
+        // Group of the step redo would put back next
+        pub fn redo_group(&self) -> Option<u64> {
+            self.redo_steps.last().and_then(|step| step.group)
+        }
+
+        // Returns whether this call started the step and so has to end it
+        fn begin_undo_step(&mut self) -> bool {
+            if self.recording_edit.is_some() { return false; }
+            self.recording_edit = Some(Vec::new());
+            true
+        }
+
+        fn end_undo_step(&mut self) {
+            let edits = match self.recording_edit.take() {
+                Some(edits) => edits,
+                None => return
+            };
+            if edits.is_empty() { return; }
+
+            self.undo_steps.push_back(UndoStep { edits, group: None });
+            if self.undo_steps.len() > MAX_UNDO_STEPS {
+                self.undo_steps.pop_front();
+            }
+            // A new edit branches off from whatever was undone
+            self.redo_steps.clear();
+        }
+
+        // The value stored in a layer's change history for pos, None if it's never been changed
+        // Paged out partitions have nothing in them until they're back
+        fn layer_change(&self, layer: MapLayer, pos: &GridCoord) -> Option<TileValue> {
+            let pos = &self.wrap(pos);
+            let partition_coord = TileMap::partition_of(pos);
+            if !self.in_bounds(pos) || self.request_if_paged_out(&partition_coord) {
+                return None;
+            }
+            self.layers[layer as usize].get(&PartitionKey::new(&partition_coord)).and_then(|partition_changes| partition_changes.sample(pos))
+        }
+
+        // Writes straight to one layer, None clears it back to nothing (or to generated terrain)
+        // Values are stored on whatever layer they are given, Overlay is the only layer nothing else writes to
+        pub fn set_layer_tile(&mut self, layer: MapLayer, pos: &GridCoord, value: Option<TileValue>) {
+            let starts_undo_step = self.begin_undo_step();
+            self.write_layer_tile(layer, pos, value);
+            if starts_undo_step { self.end_undo_step(); }
+        }
+
+        // Takes the building at pos off the structure layer, leaving the terrain it was built on
+        // Returns the building so callers can refund it, None if there wasn't one there
+        pub fn demolish(&mut self, pos: &GridCoord) -> Option<TileValue> {
+            let pos = &self.wrap(pos);
+            self.page_in_for_write(pos);
+            let anchor = match self.layer_change(MapLayer::Structure, pos)? {
+                TileValue::Subtile(anchor) => anchor,
+                _ => *pos
+            };
+
+            let starts_undo_step = self.begin_undo_step();
+            let building = match self.layer_change(MapLayer::Structure, &anchor) {
+                Some(TileValue::Subtile(_)) | None => {
+                    // Subtile of nothing, clearing it is all that can be done
+                    self.write_layer_tile(MapLayer::Structure, pos, None);
+                    None
+                }
+                Some(building) => {
+                    let size = self.get_tile_size(&building);
+                    let footprint_top_left = GridCoord{x: anchor.x - (size.x / 2), y: anchor.y - (size.y / 2)};
+                    for y in footprint_top_left.y..(footprint_top_left.y + size.y) {
+                        for x in footprint_top_left.x..(footprint_top_left.x + size.x) {
+                            let cell = GridCoord{x, y};
+                            // Leave anything in the footprint that belongs to some other building alone
+                            if self.layer_change(MapLayer::Structure, &cell) == Some(TileValue::Subtile(anchor)) {
+                                self.write_layer_tile(MapLayer::Structure, &cell, None);
+                            }
+                        }
+                    }
+                    self.write_layer_tile(MapLayer::Structure, &anchor, None);
+                    // The name goes with the building, the terrain left behind keeps the rest of its data
+                    self.tile_data.remove(&anchor, STRUCTURE_NAME_KEY);
+                    Some(building)
+                }
+            };
+            if starts_undo_step { self.end_undo_step(); }
+
+            building
+        }
+
+        // Forgets what was dug or built at pos so it's generated terrain again, see revert_area
+        pub fn revert(&mut self, pos: &GridCoord) {
+            self.revert_area(pos, &GridCoord{x: 1, y: 1});
+        }
+
+        // Drops the terrain and structure changes in the area rather than writing generated values over them, as one undo step
+        // Partitions left with no changes lose their tables, a building partly inside comes down whole like demolish
+        // Designations stay, they aren't changes to the tile
+        pub fn revert_area(&mut self, top_left: &GridCoord, size: &GridCoord) {
+            let starts_undo_step = self.begin_undo_step();
+            for y in top_left.y..(top_left.y + size.y) {
+                for x in top_left.x..(top_left.x + size.x) {
+                    let pos = self.wrap(&GridCoord{x, y});
+                    if !self.in_bounds(&pos) { continue; }
+                    self.page_in_for_write(&pos);
+                    if self.layer_change(MapLayer::Structure, &pos).is_some() {
+                        self.demolish(&pos);
+                    }
+                    if self.layer_change(MapLayer::Terrain, &pos).is_some() {
+                        // Same as any other terrain change, mining progress and the like belong to what was there before
+                        self.tile_data.clear_tile(&pos);
+                        self.write_layer_tile(MapLayer::Terrain, &pos, None);
+                    }
+                }
+            }
+            if starts_undo_step { self.end_undo_step(); }
+        }
+
+        // Marks (or unmarks) an area as off limits, as one undo step
+        // Pathing and anything that picks tiles to use should check is_forbidden
+        pub fn set_forbidden(&mut self, top_left: &GridCoord, size: &GridCoord, forbidden: bool) {
+            let starts_undo_step = self.begin_undo_step();
+            let value = if forbidden { Some(TileValue::Forbidden) } else { None };
+            for y in top_left.y..(top_left.y + size.y) {
+                for x in top_left.x..(top_left.x + size.x) {
+                    let pos = GridCoord{x, y};
+                    if self.is_forbidden(&pos) != forbidden {
+                        self.write_layer_tile(MapLayer::Designation, &pos, value);
+                    }
+                }
+            }
+            if starts_undo_step { self.end_undo_step(); }
+        }
+
+        pub fn is_forbidden(&self, pos: &GridCoord) -> bool {
+            self.layer_change(MapLayer::Designation, pos) == Some(TileValue::Forbidden)
+        }
+
+        pub fn tile_data(&self, pos: &GridCoord, key: &str) -> Option<&TileDataValue> {
+            self.tile_data.get(&self.wrap(pos), key)
+        }
+
+        // Every piece of tile data on the map, sorted by position and then key
+        pub fn tile_data_entries(&self) -> Vec<(GridCoord, String, TileDataValue)> {
+            self.tile_data.entries()
+        }
+
+        // Tile data changes show up in take_dirty_regions too, paths read hazard costs out of it
+        pub fn set_tile_data<V: Into<TileDataValue>>(&mut self, pos: &GridCoord, key: &str, value: V) {
+            let pos = self.wrap(pos);
+            self.tile_data.set(&pos, key, value.into());
+            self.mark_tile_dirty(&pos);
+        }
+
+        pub fn remove_tile_data(&mut self, pos: &GridCoord, key: &str) -> Option<TileDataValue> {
+            let pos = self.wrap(pos);
+            let removed = self.tile_data.remove(&pos, key);
+            if removed.is_some() {
+                self.mark_tile_dirty(&pos);
+            }
+            removed
+        }
+
+        fn mark_tile_dirty(&mut self, pos: &GridCoord) {
+            self.dirty_regions.tiles.insert(*pos);
+            self.dirty_regions.partitions.insert(TileMap::partition_of(pos));
+        }
+
+        // Any cell of a structure names the whole structure, returns false if there's nothing built at pos
+        // An empty name takes the label off again
+        pub fn set_structure_name(&mut self, pos: &GridCoord, name: &str) -> bool {
+            let anchor = match self.structure_anchor(pos) {
+                Some(anchor) => anchor,
+                None => return false
+            };
+            let name = name.trim();
+            if name.is_empty() {
+                self.tile_data.remove(&anchor, STRUCTURE_NAME_KEY);
+            }
+            else {
+                self.tile_data.set(&anchor, STRUCTURE_NAME_KEY, name.into());
+            }
+            true
+        }
+
+        pub fn structure_name(&self, pos: &GridCoord) -> Option<&str> {
+            let anchor = self.structure_anchor(pos)?;
+            self.tile_data.get(&anchor, STRUCTURE_NAME_KEY).and_then(|name| name.as_text())
+        }
+
+        // Anchors of every named structure with query somewhere in its name, ignoring case, along rows from the top left
+        pub fn find_structures_named(&self, query: &str) -> Vec<GridCoord> {
+            let query = query.trim().to_lowercase();
+            self.tile_data.entries().into_iter()
+                .filter_map(|(pos, key, value)| match value {
+                    TileDataValue::Text(name) if key == STRUCTURE_NAME_KEY && name.to_lowercase().contains(&query) => Some(pos),
+                    _ => None
+                })
+                // Undo takes buildings away without going through demolish, which can leave a name behind on an empty tile
+                .filter(|pos| self.structure_anchor(pos) == Some(*pos))
+                .collect()
+        }
+
+        // Where the structure covering pos keeps its data, None if pos isn't part of a structure
+        fn structure_anchor(&self, pos: &GridCoord) -> Option<GridCoord> {
+            let pos = self.wrap(pos);
+            let anchor = match self.sample(&pos) {
+                TileValue::Subtile(anchor) => anchor,
+                _ => pos
+            };
+            match self.sample(&anchor) {
+                TileValue::Subtile(_) => None,
+                value if MapLayer::for_value(&value) == MapLayer::Structure => Some(anchor),
+                _ => None
+            }
+        }
+
+        // Writes a value onto the layer it belongs to
+        fn make_single_tile_change(&mut self, pos: &GridCoord, new_value: TileValue) {
+            if !self.in_bounds(pos) {
+                return;
+            }
+            let pos = &self.wrap(pos);
+            self.page_in_for_write(pos);
+            let layer = MapLayer::for_value(&new_value);
+            // State like mining progress belongs to what was there before, undo doesn't bring it back
+            if layer != MapLayer::Designation {
+                self.tile_data.clear_tile(pos);
+            }
+            // Changing the terrain knocks down whatever was built on that tile, same as writing over it did before layers
+            if layer == MapLayer::Terrain && self.layer_change(MapLayer::Structure, pos).is_some() {
+                self.write_layer_tile(MapLayer::Structure, pos, None);
+            }
+            self.write_layer_tile(layer, pos, Some(new_value));
+        }
+
+        // Writes out of bounds are dropped, set_area over the edge of the world only changes the part inside
+        fn write_layer_tile(&mut self, layer: MapLayer, pos: &GridCoord, new_value: Option<TileValue>) {
+            if !self.in_bounds(pos) {
+                return;
+            }
+            let pos = &self.wrap(pos);
+            self.page_in_for_write(pos);
+
+            if self.recording_edit.is_some() {
+                let old_value = self.layer_change(layer, pos);
+                if let Some(edit) = &mut self.recording_edit {
+                    edit.push(TileEdit { pos: *pos, layer, old_value, new_value });
+                }
+            }
+
+            // Unwrap values from struct
+            let x = pos.x;
+            let y = pos.y;
+
+            // Mask away the bits 
+            let partition_x = x & !(PARTITION_SIZE as i64 - 1);
+            let partition_y = y & !(PARTITION_SIZE as i64 - 1);
+            let partition_coord = GridCoord { x: partition_x, y: partition_y };
+            let partition_key = PartitionKey::new(&partition_coord);
+
+            let layer_changes = &mut self.layers[layer as usize];
+            match new_value {
+                Some(new_value) => {
+                    // Inserting an empty change table just hands back the existing one if there is one
+                    let partition_changes = layer_changes.entry(partition_key).or_insert_with(AreaChanges::new);
+                    partition_changes.add_change_pooled(pos, &new_value, &mut self.dense_pool);
+                }
+                None => {
+                    if let Some(partition_changes) = layer_changes.get_mut(&partition_key) {
+                        partition_changes.remove_change_pooled(pos, &mut self.dense_pool);
+                        // Demolished areas shouldn't keep an empty table around forever
+                        if partition_changes.is_empty() {
+                            layer_changes.remove(&partition_key);
+                        }
+                    }
+                }
+            }
+
+            // Whatever was cached for this tile is now out of date
+            self.tile_cache.borrow_mut().pop(pos);
+
+            self.dirty_regions.tiles.insert(*pos);
+            self.dirty_regions.partitions.insert(partition_coord);
+        }
+
+        // Lets page_out_far_from move partitions into files in directory, which gets created if it isn't there
+        // The files are only any use to this map, a save still has to be made to keep the world
+        pub fn enable_paging<P: Into<PathBuf>>(&mut self, directory: P) -> Result<(), SaveError> {
+            let directory = directory.into();
+            fs::create_dir_all(&directory)?;
+            self.pager.get_or_insert_with(PartitionPager::new).directory = Some(directory);
+            Ok(())
+        }
+
+        // Writes out and drops every changed partition more than keep_distance tiles from all of the anchors (camera, base)
+        // Returns how many partitions went out, does nothing unless enable_paging has been called
+        pub fn page_out_far_from(&mut self, anchors: &[Rectangle], keep_distance: f32) -> Result<usize, SaveError> {
+            let pager = match &mut self.pager {
+                Some(pager) if pager.directory.is_some() => pager,
+                _ => return Ok(0)
+            };
+
+            let bounds = self.bounds;
+            let mut far_partitions: HashSet<GridCoord> = HashSet::new();
+            for layer_changes in self.layers.iter() {
+                far_partitions.extend(layer_changes.keys().map(|key| key.partition()).filter(|partition_coord| {
+                    anchors.iter().all(|anchor| {
+                        // On a wrapping world the partition might be closer over the seam
+                        let near = GridCoord{x: (anchor.pos.x + anchor.size.x / 2.0) as i64, y: (anchor.pos.y + anchor.size.y / 2.0) as i64};
+                        let corner = match &bounds {
+                            Some(bounds) => bounds.nearest_copy(partition_coord, &near),
+                            None => *partition_coord
+                        };
+                        let partition_rect = Rectangle::new((corner.x as f32, corner.y as f32), (PARTITION_SIZE as f32, PARTITION_SIZE as f32));
+                        rect_gap(anchor, &partition_rect) > keep_distance
+                    })
+                }));
+            }
+
+            for partition_coord in far_partitions.iter() {
+                let partition_key = PartitionKey::new(partition_coord);
+                let mut paged = SavedPartition { changes: Vec::new() };
+                for layer in MapLayer::ALL.iter() {
+                    if let Some(partition) = self.layers[*layer as usize].get(&partition_key) {
+                        partition.for_each_change(partition_coord, |pos: &GridCoord, value: &TileValue| paged.changes.push((*layer, *pos, *value)));
+                    }
+                }
+                if let Some(path) = pager.file_for(partition_coord) {
+                    serde_cbor::to_writer(File::create(path)?, &paged)?;
+                }
+
+                for layer_changes in self.layers.iter_mut() {
+                    if let Some(mut partition) = layer_changes.remove(&partition_key) {
+                        // Dense buffers go back to the pool for whatever gets built next
+                        partition.switch_to_sparse(&mut self.dense_pool);
+                    }
+                }
+                pager.paged_out.insert(*partition_coord);
+            }
+            Ok(far_partitions.len())
+        }
+
+        // Brings back every paged out partition that was read since last time, returns how many there were
+        // Call regularly (once a frame) alongside receive_generated_partitions
+        pub fn update_paging(&mut self) -> Result<usize, SaveError> {
+            let wanted: Vec<GridCoord> = match &self.pager {
+                Some(pager) => pager.wanted.borrow_mut().drain().collect(),
+                None => return Ok(0)
+            };
+            for partition_coord in wanted.iter() {
+                self.page_in(partition_coord)?;
+            }
+            Ok(wanted.len())
+        }
+
+        pub fn paged_out_count(&self) -> usize {
+            self.pager.as_ref().map(|pager| pager.paged_out.len()).unwrap_or(0)
+        }
+
+        fn partition_of(pos: &GridCoord) -> GridCoord {
+            GridCoord { x: pos.x & !(PARTITION_SIZE as i64 - 1), y: pos.y & !(PARTITION_SIZE as i64 - 1) }
+        }
+
+        // True if the partition is out on disk, in which case it's queued for the next update_paging
+        fn request_if_paged_out(&self, partition_coord: &GridCoord) -> bool {
+            match &self.pager {
+                Some(pager) if pager.paged_out.contains(partition_coord) => {
+                    pager.wanted.borrow_mut().insert(*partition_coord);
+                    true
+                }
+                _ => false
+            }
+        }
+
+        fn page_in(&mut self, partition_coord: &GridCoord) -> Result<(), SaveError> {
+            let pager = match &mut self.pager {
+                Some(pager) if pager.paged_out.contains(partition_coord) => pager,
+                _ => return Ok(())
+            };
+            // Forgotten even if reading fails, an unreadable file would otherwise be retried forever
+            pager.paged_out.remove(partition_coord);
+            let paged = pager.read(partition_coord);
+            // Save records stay where they are, page files are done with once they're read
+            if pager.save_records.remove(partition_coord).is_none() {
+                if let Some(path) = pager.file_for(partition_coord) {
+                    let _ = fs::remove_file(path);
+                }
+            }
+            let paged = paged?;
+
+            // Straight back into the tables, these aren't new edits so they don't go in the undo history
+            let partition_key = PartitionKey::new(partition_coord);
+            for (layer, pos, value) in paged.changes.iter() {
+                let partition_changes = self.layers[*layer as usize].entry(partition_key).or_insert_with(AreaChanges::new);
+                partition_changes.add_change_pooled(pos, value, &mut self.dense_pool);
+            }
+            self.dirty_regions.partitions.insert(*partition_coord);
+            Ok(())
+        }
+
+        // Edits can't wait a frame for the partition to come back
+        // If it can't be read the edit still goes ahead, on top of generated terrain
+        fn page_in_for_write(&mut self, pos: &GridCoord) {
+            let _ = self.page_in(&TileMap::partition_of(pos));
+        }
+
+        // Checks every subtile in the change history points at a multi-tile value whose footprint covers it,
+        // and that every multi-tile value has a complete footprint that doesn't overlap any other
+        // Partitions that are paged out don't get checked
+        pub fn validate_integrity(&self) -> Result<(), Vec<IntegrityError>> {
+            let (errors, _repairs) = self.find_integrity_problems();
+            if errors.is_empty() { Ok(()) } else { Err(errors) }
+        }
+
+        // Same checks as validate_integrity, returning everything found
+        // When repair is set the problems also get fixed: broken subtiles become Empty, incomplete footprints get filled back in,
+        // and when meta-tiles overlap the one checked later (by y then x) is removed
+        pub fn validate_and_repair(&mut self, repair: bool) -> Vec<IntegrityError> {
+            let (errors, repairs) = self.find_integrity_problems();
+
+            if repair {
+                for (pos, value) in repairs.iter() {
+                    self.make_single_tile_change(pos, *value);
+                }
+                // Undoing past a repair could put the broken tiles back
+                if !repairs.is_empty() {
+                    self.undo_steps.clear();
+                    self.redo_steps.clear();
+                }
+            }
+
+            errors
+        }
+
+        // Returns the problems found along with the single tile changes that would fix them
+        fn find_integrity_problems(&self) -> (Vec<IntegrityError>, Vec<(GridCoord, TileValue)>) {
+            let mut errors: Vec<IntegrityError> = Vec::new();
+            let mut repairs: Vec<(GridCoord, TileValue)> = Vec::new();
+
+            let mut anchors: Vec<GridCoord> = Vec::new();
+            let mut subtiles: Vec<(GridCoord, GridCoord)> = Vec::new();
+
+            // Only buildings span more than one tile, so they are all on the structure layer
+            for (partition_key, partition) in self.layers[MapLayer::Structure as usize].iter() {
+                let partition_coord = partition_key.partition();
+                let dangling_start = errors.len();
+                partition.find_dangling_anchors(&partition_coord, &mut errors);
+                for error in errors[dangling_start..].iter() {
+                    if let IntegrityError::DanglingAnchorSlot { pos } = error {
+                        repairs.push((*pos, TileValue::Empty));
+                    }
+                }
+
+                partition.for_each_change(&partition_coord, |pos: &GridCoord, value: &TileValue| {
+                    match value {
+                        TileValue::Subtile(anchor) => subtiles.push((*pos, *anchor)),
+                        _ => {
+                            let size = self.get_tile_size(value);
+                            if size.x > 1 || size.y > 1 { anchors.push(*pos); }
+                        }
+                    }
+                });
+            }
+
+            // Partitions come out of a HashMap, sort so the same world always gets repaired the same way
+            anchors.sort_by_key(|anchor| (anchor.y, anchor.x));
+
+            let anchor_set: HashSet<GridCoord> = anchors.iter().cloned().collect();
+            let mut claimed_by: HashMap<GridCoord, GridCoord> = HashMap::new();
+            let mut surviving_anchors: HashSet<GridCoord> = HashSet::new();
+
+            for anchor in anchors.iter() {
+                let size = self.get_tile_size(&self.sample(anchor));
+                let footprint_top_left = GridCoord{x: anchor.x - (size.x / 2), y: anchor.y - (size.y / 2)};
+
+                let mut overlapping: Option<GridCoord> = None;
+                for y in footprint_top_left.y..(footprint_top_left.y + size.y) {
+                    for x in footprint_top_left.x..(footprint_top_left.x + size.x) {
+                        // Footprints over the seam of a wrapping world claim the tiles they're actually stored at
+                        let cell = self.wrap(&GridCoord{x, y});
+                        if overlapping.is_some() { break; }
+                        if let Some(other_anchor) = claimed_by.get(&cell) {
+                            overlapping = Some(*other_anchor);
+                        }
+                        else if cell != *anchor && anchor_set.contains(&cell) {
+                            overlapping = Some(cell);
+                        }
+                    }
+                }
+
+                if let Some(other_anchor) = overlapping {
+                    errors.push(IntegrityError::OverlappingMetatiles { anchor: *anchor, other_anchor });
+                    repairs.push((*anchor, TileValue::Empty));
+                    continue;
+                }
+
+                surviving_anchors.insert(*anchor);
+                for y in footprint_top_left.y..(footprint_top_left.y + size.y) {
+                    for x in footprint_top_left.x..(footprint_top_left.x + size.x) {
+                        let cell = self.wrap(&GridCoord{x, y});
+                        claimed_by.insert(cell, *anchor);
+
+                        if cell != *anchor && self.sample(&cell) != TileValue::Subtile(*anchor) {
+                            errors.push(IntegrityError::IncompleteFootprint { anchor: *anchor, missing: cell });
+                            repairs.push((cell, TileValue::Subtile(*anchor)));
+                        }
+                    }
+                }
+            }
+
+            for (pos, anchor) in subtiles.iter() {
+                if !anchor_set.contains(anchor) && !self.is_metatile_anchor(anchor) {
+                    errors.push(IntegrityError::MissingAnchor { subtile: *pos, anchor: *anchor });
+                    repairs.push((*pos, TileValue::Empty));
+                }
+                else if claimed_by.get(pos) != Some(anchor) {
+                    // Either outside the footprint, or the anchor was thrown out for overlapping and its subtiles go with it
+                    if surviving_anchors.contains(anchor) {
+                        errors.push(IntegrityError::OutsideFootprint { subtile: *pos, anchor: *anchor });
+                    }
+                    // Cells another building claimed get their correct subtile from that building's footprint repair instead
+                    if !claimed_by.contains_key(pos) {
+                        repairs.push((*pos, TileValue::Empty));
+                    }
+                }
+            }
+
+            (errors, repairs)
+        }
+
+        // An anchor normally lives in the change history, but check the real value in case it was generated that way
+        fn is_metatile_anchor(&self, pos: &GridCoord) -> bool {
+            let value = self.sample(pos);
+            let size = self.get_tile_size(&value);
+            (size.x > 1 || size.y > 1) && !matches!(value, TileValue::Subtile(_))
+        }
+
+        // Untouched tiles aren't saved, they get regenerated from the seed on load
+        // Partitions that are paged out or not read in yet are copied across from wherever they are,
+        // so don't save over the file a map was lazily loaded from, save beside it and move it over
+        pub fn save_to_writer<W: Write>(&self, writer: W) -> Result<(), SaveError> {
+            self.save_to_writer_with(writer, &SaveOptions::default())
+        }
+
+        pub fn save_to_writer_with<W: Write>(&self, writer: W, options: &SaveOptions) -> Result<(), SaveError> {
+            self.snapshot()?.write_to(writer, options)
+        }
+
+        // Copies out everything a save needs, so it can be written on another thread while the map carries on changing
+        // Partitions that are paged out get read back from disk for it
+        pub fn snapshot(&self) -> Result<SaveSnapshot, SaveError> {
+            let mut partitions: HashMap<GridCoord, Vec<(MapLayer, GridCoord, TileValue)>> = HashMap::new();
+            for layer in MapLayer::ALL.iter() {
+                for (partition_key, partition) in self.layers[*layer as usize].iter() {
+                    let partition_coord = partition_key.partition();
+                    let changes = partitions.entry(partition_coord).or_default();
+                    partition.for_each_change(&partition_coord, |pos: &GridCoord, value: &TileValue| changes.push((*layer, *pos, *value)));
+                }
+            }
+            if let Some(pager) = &self.pager {
+                for partition_coord in pager.paged_out.iter() {
+                    partitions.insert(*partition_coord, pager.read(partition_coord)?.changes);
+                }
+            }
+
+            // Partitions come out of a HashMap, sort so saving the same world always gives the same bytes
+            let mut partitions: Vec<(GridCoord, SavedPartition)> = partitions.into_iter()
+                .map(|(partition_coord, mut changes)| {
+                    changes.sort_by_key(|(layer, pos, _)| (*layer as usize, pos.y, pos.x));
+                    (partition_coord, SavedPartition { changes })
+                })
+                .collect();
+            partitions.sort_by_key(|(partition_coord, _)| (partition_coord.y, partition_coord.x));
+
+            Ok(SaveSnapshot {
+                seed: self.seed,
+                generator_config: self.generator_config,
+                tile_data: self.tile_data.entries(),
+                bounds: self.bounds,
+                partitions
+            })
+        }
+
+        pub fn load_from_reader<R: Read>(reader: R) -> Result<TileMap, SaveError> {
+            TileMap::load_from_reader_with(reader, &[])
+        }
+
+        // Same as load_from_reader, with migrations to try before the built in ones for saves from older versions
+        pub fn load_from_reader_with<R: Read>(reader: R, migrations: &[&dyn Migration]) -> Result<TileMap, SaveError> {
+            TileMap::load_from_reader_repairing(reader, migrations, true)
+        }
+
+        // Same as load_from_reader but broken buildings are left the way they were saved, for tools that need to see the damage
+        pub fn load_from_reader_unrepaired<R: Read>(reader: R) -> Result<TileMap, SaveError> {
+            TileMap::load_from_reader_repairing(reader, &[], false)
+        }
+
+        fn load_from_reader_repairing<R: Read>(mut reader: R, migrations: &[&dyn Migration], repair: bool) -> Result<TileMap, SaveError> {
+            let mut bytes: Vec<u8> = Vec::new();
+            reader.read_to_end(&mut bytes)?;
+
+            // Read as a single value rather than with from_slice, which would complain about the records after it
+            let mut deserializer = serde_cbor::Deserializer::from_slice(&bytes);
+            let header_value = serde_cbor::Value::deserialize(&mut deserializer)?;
+            let records = &bytes[deserializer.byte_offset()..];
+            let version = match &header_value {
+                serde_cbor::Value::Map(fields) => match fields.get(&serde_cbor::Value::Text("version".to_string())) {
+                    Some(serde_cbor::Value::Integer(version)) => *version as u32,
+                    _ => return Err(SaveError::UnsupportedVersion(0))
+                },
+                _ => return Err(SaveError::UnsupportedVersion(0))
+            };
+
+            let (world, partitions) = if version == SAVE_FORMAT_VERSION {
+                let header: SaveHeader = serde_cbor::value::from_value(header_value)?;
+                let records_length = header.partitions.iter().map(|entry| entry.offset.saturating_add(entry.length)).max().unwrap_or(0);
+                if (records.len() as u64) < records_length {
+                    return Err(SaveError::Truncated { expected_bytes: records_length, found_bytes: records.len() as u64 });
+                }
+
+                let mut partitions: Vec<SavedPartition> = Vec::with_capacity(header.partitions.len());
+                for entry in header.partitions.iter() {
+                    partitions.push(decode_record(record_bytes(records, entry)?, entry, header.compressed)?);
+                }
+                (SavedWorld { seed: header.seed, generator_config: header.generator_config, tile_data: header.tile_data, bounds: header.bounds }, partitions)
+            }
+            else {
+                let mut save = RawSave::from_bytes(version, header_value, records)?;
+                while save.version != SAVE_FORMAT_VERSION {
+                    let migration = migrations.iter().chain(BUILT_IN_MIGRATIONS.iter())
+                        .find(|migration| migration.source_version() == save.version)
+                        .ok_or(SaveError::UnsupportedVersion(save.version))?;
+                    save = migration.migrate(save)?;
+                }
+
+                let world: SavedWorld = serde_cbor::value::from_value(save.header)?;
+                let partitions = save.partitions.into_iter()
+                    .map(serde_cbor::value::from_value)
+                    .collect::<Result<Vec<SavedPartition>, serde_cbor::Error>>()?;
+                (world, partitions)
+            };
+
+            let mut map = TileMap::new_with_config(world.seed, world.generator_config);
+            for saved in partitions.iter() {
+                // Subtiles were saved along with their anchors, so every change goes back exactly as it was
+                for (layer, pos, value) in saved.changes.iter() {
+                    map.write_layer_tile(*layer, pos, Some(*value));
+                }
+            }
+            // Only once the changes are in, so none get dropped for being out of bounds
+            map.set_bounds(world.bounds);
+            // Saves from builds with footprint bugs would otherwise keep those bugs forever
+            if repair {
+                map.validate_and_repair(true);
+            }
+            map.finish_load(world.tile_data);
+            Ok(map)
+        }
+
+        // Only reads the partitions within load_distance tiles of spawn (where the camera starts), the rest are read
+        // the same way as paged out partitions, the first time something asks for them
+        // Buildings are only checked once they're all in, so unlike load_from_reader nothing gets repaired
+        // Older saves aren't migrated, load them with load_from_reader once and save them again first
+        pub fn load_lazy<P: Into<PathBuf>>(path: P, spawn: &Rectangle, load_distance: f32) -> Result<TileMap, SaveError> {
+            let path = path.into();
+            let mut file = File::open(&path)?;
+            let (header, records_start) = {
+                let mut counted = CountingReader { reader: &mut file, count: 0 };
+                let header = TileMap::read_save_header(&mut counted)?;
+                (header, counted.count)
+            };
+
+            let mut map = TileMap::new_with_config(header.seed, header.generator_config);
+            map.bounds = header.bounds;
+            let mut pager = PartitionPager::new();
+            pager.save_file = Some(RefCell::new(file));
+            pager.save_compressed = header.compressed;
+            for entry in header.partitions.iter() {
+                pager.save_records.insert(entry.partition, SavedRecordEntry { offset: records_start + entry.offset, ..*entry });
+                pager.paged_out.insert(entry.partition);
+            }
+            map.pager = Some(pager);
+
+            for partition_coord in header.partitions.iter().map(|entry| entry.partition) {
+                let partition_rect = Rectangle::new((partition_coord.x as f32, partition_coord.y as f32), (PARTITION_SIZE as f32, PARTITION_SIZE as f32));
+                if rect_gap(spawn, &partition_rect) <= load_distance {
+                    map.page_in(&partition_coord)?;
+                }
+            }
+            map.finish_load(header.tile_data);
+            Ok(map)
+        }
+
+        fn read_save_header<R: Read>(reader: R) -> Result<SaveHeader, SaveError> {
+            // Read as a single value rather than with from_reader, which would complain about the records after it
+            let mut deserializer = serde_cbor::Deserializer::from_reader(reader);
+            let header = SaveHeader::deserialize(&mut deserializer)?;
+            if header.version != SAVE_FORMAT_VERSION {
+                return Err(SaveError::UnsupportedVersion(header.version));
+            }
+            Ok(header)
+        }
+
+        fn finish_load(&mut self, tile_data: Vec<(GridCoord, String, TileDataValue)>) {
+            // After any repair, which would otherwise throw away the data of any tiles it fixed
+            for (pos, key, value) in tile_data.into_iter() {
+                self.tile_data.set(&pos, &key, value);
+            }
+            // Freshly loaded, anyone using it has to build from scratch anyway
+            self.take_dirty_regions();
+        }
+
+        pub fn to_bytes(&self) -> Result<Vec<u8>, SaveError> {
+            let mut bytes: Vec<u8> = Vec::new();
+            self.save_to_writer(&mut bytes)?;
+            Ok(bytes)
+        }
+
+        pub fn from_bytes(bytes: &[u8]) -> Result<TileMap, SaveError> {
+            TileMap::load_from_reader(bytes)
+        }
+
+        pub fn get_tile_size(&self, tile_type: &TileValue) -> GridCoord {
+            match self.tile_type_sizes.get(&tile_type) {
+                Some(size) => *size,
                 None => GridCoord{x: 1, y: 1}
             }
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::tile_world::{
-        TileMap, TileValue, GridCoord, AreaChanges, PARTITION_SIZE
-    };
+    impl<'a> EditTransaction<'a> {
+        // The map as it would be with everything buffered so far applied
+        pub fn sample(&self, pos: &GridCoord) -> TileValue {
+            match self.pending.get(&self.map.wrap(pos)) {
+                Some(value) => *value,
+                None => self.map.sample(pos)
+            }
+        }
+
+        pub fn set(&mut self, pos: &GridCoord, value: TileValue) {
+            let pos = self.map.wrap(pos);
+            self.writes.push((pos, value));
+            self.pending.insert(pos, value);
+        }
+
+        pub fn set_area(&mut self, top_left: &GridCoord, size: &GridCoord, value: TileValue) {
+            for y in top_left.y..(top_left.y + size.y) {
+                for x in top_left.x..(top_left.x + size.x) {
+                    self.set(&GridCoord{x, y}, value);
+                }
+            }
+        }
+
+        // Buffered version of TileMap::make_change, writes the whole footprint of multi-tile values
+        pub fn place(&mut self, pos: &GridCoord, value: &TileValue) {
+            let old_anchor = match self.sample(pos) {
+                TileValue::Subtile(anchor) if self.is_anchor(&anchor) => anchor,
+                _ => *pos
+            };
+            let old_value = self.sample(&old_anchor);
+            let old_tile_size = self.map.get_tile_size(&old_value);
+            if old_tile_size.x > 1 && old_tile_size.y > 1 {
+                self.set_area(&GridCoord{x: old_anchor.x - (old_tile_size.x / 2), y: old_anchor.y - (old_tile_size.y / 2)}, &old_tile_size, TileValue::Empty);
+            }
+
+            let tile_size = self.map.get_tile_size(value);
+            self.set_area(&GridCoord{x: pos.x - (tile_size.x / 2), y: pos.y - (tile_size.y / 2)}, &tile_size, TileValue::Subtile(*pos));
+            self.set(pos, *value);
+        }
+
+        // Commit fails unless the area is all Empty in the map as it was before this transaction
+        pub fn require_clear(&mut self, top_left: &GridCoord, size: &GridCoord) {
+            self.required_clear.push((*top_left, *size));
+        }
+
+        // Applies every buffered write as a single undo step, or none of them if the checks fail
+        pub fn commit(self) -> Result<(), EditError> {
+            for (top_left, size) in self.required_clear.iter() {
+                if !self.map.area_clear(top_left, size) {
+                    return Err(EditError::AreaNotClear { top_left: *top_left, size: *size });
+                }
+            }
+
+            let errors = self.find_broken_footprints();
+            if !errors.is_empty() {
+                return Err(EditError::Integrity(errors));
+            }
+
+            if self.writes.is_empty() { return Ok(()); }
+
+            let starts_undo_step = self.map.begin_undo_step();
+            for (pos, value) in self.writes.iter() {
+                self.map.make_single_tile_change(pos, *value);
+            }
+            if starts_undo_step { self.map.end_undo_step(); }
+            Ok(())
+        }
+
+        // Throws away everything buffered, dropping the transaction does the same
+        pub fn rollback(self) {}
+
+        // TileMap::is_metatile_anchor, including what's been buffered
+        fn is_anchor(&self, pos: &GridCoord) -> bool {
+            let value = self.sample(pos);
+            let size = self.map.get_tile_size(&value);
+            (size.x > 1 || size.y > 1) && !matches!(value, TileValue::Subtile(_))
+        }
+
+        // Only looks at the written tiles and the buildings they touch, so it stays cheap for small edits on a big map
+        fn find_broken_footprints(&self) -> Vec<IntegrityError> {
+            let mut errors: Vec<IntegrityError> = Vec::new();
+            let mut anchors_to_check: HashSet<GridCoord> = HashSet::new();
+
+            for pos in self.pending.keys() {
+                // Buildings that had a tile overwritten need checking as well as the new ones
+                if let TileValue::Subtile(old_anchor) = self.map.sample(pos) {
+                    anchors_to_check.insert(old_anchor);
+                }
+
+                match self.sample(pos) {
+                    TileValue::Subtile(anchor) => {
+                        let anchor_size = self.map.get_tile_size(&self.sample(&anchor));
+                        if !self.is_anchor(&anchor) {
+                            errors.push(IntegrityError::MissingAnchor { subtile: *pos, anchor });
+                            continue;
+                        }
+                        let footprint_top_left = GridCoord{x: anchor.x - (anchor_size.x / 2), y: anchor.y - (anchor_size.y / 2)};
+                        if !GridCoord::is_within_bounds(&footprint_top_left, &anchor_size, &self.map.nearest_copy(pos, &anchor)) {
+                            errors.push(IntegrityError::OutsideFootprint { subtile: *pos, anchor });
+                        }
+                    }
+                    value => {
+                        let size = self.map.get_tile_size(&value);
+                        if size.x > 1 || size.y > 1 { anchors_to_check.insert(*pos); }
+                    }
+                }
+            }
+
+            let mut anchors: Vec<GridCoord> = anchors_to_check.into_iter().collect();
+            anchors.sort_by_key(|anchor| (anchor.y, anchor.x));
+            for anchor in anchors.iter() {
+                let value = self.sample(anchor);
+                let size = self.map.get_tile_size(&value);
+                // Anchors that were removed by this edit have nothing left to check
+                if size.x <= 1 && size.y <= 1 { continue; }
+
+                for y in (anchor.y - (size.y / 2))..(anchor.y - (size.y / 2) + size.y) {
+                    for x in (anchor.x - (size.x / 2))..(anchor.x - (size.x / 2) + size.x) {
+                        let cell = GridCoord{x, y};
+                        if cell != *anchor && self.sample(&cell) != TileValue::Subtile(*anchor) {
+                            errors.push(IntegrityError::IncompleteFootprint { anchor: *anchor, missing: cell });
+                        }
+                    }
+                }
+            }
+
+            errors
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tile_world::{
+        TileMap, TileValue, TileBuffer, TileDataValue, GridLine, DirtyRegions, EditError, PlacementError, GridCoord, MapLayer, MAX_UNDO_STEPS, OCCLUSION_PER_TILE, AreaChanges, DenseBufferPool, IntegrityError, SaveError, WorldBounds, PartitionKey, SaveOptions, SaveWorker, TileRules, TileRule, RuleCondition, Provenance, RawSave, Migration, GeneratorConfig, GeneratorPass, GeneratorPassBuilder, PointOfInterest, Symmetry, Blueprint, BlueprintError, Minimap, STANDARD_GENERATOR_PASSES, PARTITION_SIZE, MIN_TILE_CACHE_SIZE, PACKED_UNKNOWN, SPARSE_SWITCH_POINT,
+        NEIGHBOR_NORTH, NEIGHBOR_SOUTH_WEST, NEIGHBOR_SIDES
+    };
+
+    use crate::raster::default_palette;
+
+    use quicksilver::{
+        geom::{Rectangle},
+    };
+
+    fn is_valid_generated_tile(value: &TileValue) -> bool {
+        value == &TileValue::Empty || value == &TileValue::Rock
+    }
+
+    #[test]
+    fn empty_map_access_gives_valid() {
+        let map = TileMap::new();
+        assert!(is_valid_generated_tile(&map.sample(&GridCoord{x: 0, y: 0})));
+    }
+
+    #[test]
+    fn same_seed_generates_same_world() {
+        let map = TileMap::with_seed(42);
+        let other_map = TileMap::with_seed(42);
+        assert_eq!(map.seed(), 42);
+
+        for y in -50..50 {
+            for x in -50..50 {
+                let pos = GridCoord{x, y};
+                assert_eq!(map.sample(&pos), other_map.sample(&pos), "Worlds with the same seed differ at {:?}", pos);
+            }
+        }
+    }
+
+    #[test]
+    fn different_seeds_generate_different_worlds() {
+        let map = TileMap::with_seed(1);
+        let other_map = TileMap::with_seed(2);
+
+        let mut differences = 0;
+        for y in -50..50 {
+            for x in -50..50 {
+                let pos = GridCoord{x, y};
+                if map.sample(&pos) != other_map.sample(&pos) { differences += 1; }
+            }
+        }
+        assert!(differences > 0, "Different seeds generated identical worlds");
+    }
+
+    #[test]
+    fn generator_config_only_changes_untouched_tiles() {
+        let mut map = TileMap::with_seed(7);
+        map.make_change(&GridCoord{x: 3, y: 3}, &TileValue::Error);
+
+        let config = GeneratorConfig { frequency: 0.5, octaves: 2, ..GeneratorConfig::default() };
+        map.set_generator_config(config);
+        let fresh_map = TileMap::new_with_config(7, config);
+
+        assert_eq!(map.generator_config(), config);
+        assert_eq!(map.sample(&GridCoord{x: 3, y: 3}), TileValue::Error);
+        for y in 10..30 {
+            for x in 10..30 {
+                assert_eq!(map.sample(&GridCoord{x, y}), fresh_map.sample(&GridCoord{x, y}), "Tile at ({}, {}) didn't follow the new config", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn untouched_map_no_errors() {
+        let map = TileMap::new();
+        
+        // Check the 1 million tiles closest to origin
+        let x_min: i64 = -500;
+        let x_max: i64 = 500;
+        let y_min: i64 = -500;
+        let y_max: i64 = 500;
+
+        for x in x_min..x_max {
+            for y in y_min..y_max {
+                assert!(is_valid_generated_tile(&map.sample(&GridCoord{x, y})), "Found invalid tile at ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn map_write_get_back_1() {
+        let mut map = TileMap::new();
+        map.make_change(&GridCoord{x: 0, y: 0}, &TileValue::Error);
+        assert_eq!(map.sample(&GridCoord{x: 0, y: 0}), TileValue::Error);
+    }
+
+    #[test]
+    fn map_write_get_back_dense_100() {
+        let mut map = TileMap::new();
+
+        // Large bounds but still within a single cache
+        let x_min: i64 = -5;
+        let x_max: i64 = 5;
+        let y_min: i64 = -5;
+        let y_max: i64 = 5;
+        
+        for x in x_min..x_max {
+            for y in y_min..y_max {
+                map.make_change(&GridCoord{x, y}, &TileValue::Error);
+            }
+        }
+
+        for x in x_min..x_max {
+            for y in y_min..y_max {
+                assert_eq!(map.sample(&GridCoord{x, y}), TileValue::Error);
+            }
+        }
+    }
+
+    #[test]
+    fn map_write_get_back_sparse_100() {
+        let mut map = TileMap::new();
+
+        // Large bounds but still within a single cache
+        let x_min: i64 = -5;
+        let x_max: i64 = 5;
+        let y_min: i64 = -5;
+        let y_max: i64 = 5;
+        
+        for x in x_min..x_max {
+            for y in y_min..y_max {
+                map.make_change(&GridCoord{x: 100 * x, y: 100 * y}, &TileValue::Error);
+            }
+        }
+
+        for x in x_min..x_max {
+            for y in y_min..y_max {
+                assert_eq!(map.sample(&GridCoord{x: 100 * x, y: 100 * y}), TileValue::Error);
+            }
+        }
+    }
+
+    #[test]
+    fn map_write_get_back_10000() {
+        let mut map = TileMap::new();
+
+        // Bounds big enough to be waaay beyond the cache
+        let x_min: i64 = -50;
+        let x_max: i64 = 50;
+        let y_min: i64 = -50;
+        let y_max: i64 = 50;
+        
+        for x in x_min..x_max {
+            for y in y_min..y_max {
+                map.make_change(&GridCoord{x, y}, &TileValue::Error);
+            }
+        }
+
+        for x in x_min..x_max {
+            for y in y_min..y_max {
+                assert_eq!(map.sample(&GridCoord{x, y}), TileValue::Error);
+            }
+        }
+    }
+
+    #[test]
+    fn pos_to_grid() {
+        let mut map = TileMap::new();
+        assert_eq!(map.pos_to_grid(0.0, 0.0), GridCoord{x: 0, y: 0});
+        assert_eq!(map.pos_to_grid(0.1, 0.1), GridCoord{x: 0, y: 0});
+        assert_eq!(map.pos_to_grid(-0.1, -0.1), GridCoord{x: 0, y: 0});
+        assert_eq!(map.pos_to_grid(0.6, -0.6), GridCoord{x: 0, y: 0});
+        assert_eq!(map.pos_to_grid(1.0, 1.0), GridCoord{x: 1, y: 1});
+    }
+
+    #[test]
+    fn pos_to_grid_subtiles() {
+        let mut map = TileMap::new();
+        map.make_change(&GridCoord{x: 0, y: 0}, &TileValue::Subtile(GridCoord{x: 1, y: 0}));
+        assert_eq!(map.pos_to_grid(0.0, 0.0), GridCoord{x: 1, y: 0});
+    }
+
+    #[test]
+    fn for_each_tile_bounds_gets_all() {
+        let map = TileMap::new();
+        // Create a rectangle from (0, 0) to (10, 10)
+        let bounds = Rectangle::new_sized((10, 10));
+        let mut tiles_hit: u32 = 0;
+
+        let min_val = 0;
+        let max_val = 10;
+
+        map.for_each_tile_rect(&bounds, |pos: &GridCoord, _value: &TileValue, _size: &GridCoord| {
+            tiles_hit += 1;
+            assert!(pos.x >= min_val, "Expected X greater than {}, got {}", min_val, pos.x);
+            assert!(pos.x <= max_val, "Expected X less than {}, got {}", max_val, pos.x);
+            assert!(pos.y >= min_val, "Expected Y greater than {}, got {}", min_val, pos.y);
+            assert!(pos.y <= max_val, "Expected Y less than {}, got {}", max_val, pos.y);
+        });
+
+        // One row past the end should be hit, so actual bounds are 11x11
+        assert_eq!(tiles_hit, 121);
+    }
+
+    #[test]
+    fn sample_from_switched_dense() {
+        let mut partition = AreaChanges::new();
+
+        // Change 3/4 of the tiles
+        for x in 0..PARTITION_SIZE as i64 {
+            for y in 0..PARTITION_SIZE as i64 {
+                if x % 4 == 0 {
+                    partition.add_change(&GridCoord{x, y}, &TileValue::Empty);
+                }
+            }
+        }
+
+        // Test all the tiles
+        for x in 0..PARTITION_SIZE as i64 {
+            for y in 0..PARTITION_SIZE as i64 {
+                if x % 4 == 0 {
+                    assert_eq!(partition.sample(&GridCoord{x, y}), Some(TileValue::Empty), "Tile value at ({}, {}) lost!", x, y);
+                }
+                else {
+                    assert_eq!(partition.sample(&GridCoord{x, y}), None, "Tile value at ({}, {}) invented from nothing!", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dense_switch_back_and_forth_keeps_values() {
+        let mut pool = DenseBufferPool::new();
+        let mut partition = AreaChanges::new();
+
+        for x in 0..PARTITION_SIZE as i64 {
+            partition.add_change_pooled(&GridCoord{x, y: 3}, &TileValue::Rock, &mut pool);
+        }
+
+        partition.switch_to_dense(&mut pool);
+        partition.switch_to_sparse(&mut pool);
+        assert!(!partition.is_dense());
+
+        for x in 0..PARTITION_SIZE as i64 {
+            for y in 0..PARTITION_SIZE as i64 {
+                let expected = if y == 3 { Some(TileValue::Rock) } else { None };
+                assert_eq!(partition.sample(&GridCoord{x, y}), expected, "Wrong value at ({}, {}) after switching modes", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn packed_tiles_round_trip() {
+        let values = [
+            TileValue::Empty,
+            TileValue::Rock,
+            TileValue::Error,
+            TileValue::HabModule,
+            TileValue::InternalUnknown,
+            TileValue::Generating,
+            TileValue::Forbidden,
+            TileValue::Void,
+            TileValue::Ladder,
+            TileValue::IronOre,
+            TileValue::IceDeposit,
+            TileValue::CrashedProbe,
+            TileValue::CrystalCluster,
+            TileValue::AbandonedModule,
+            TileValue::Subtile(GridCoord{x: 0, y: 0}),
+            TileValue::Subtile(GridCoord{x: -7, y: 12}),
+            TileValue::Subtile(GridCoord{x: i64::MAX, y: i64::MIN})
+        ];
+
+        for value in values.iter() {
+            let anchor = match value {
+                TileValue::Subtile(anchor) => Some(*anchor),
+                _ => None
+            };
+            assert_eq!(TileValue::unpack(value.pack(), anchor), *value, "{:?} didn't survive packing", value);
+        }
+
+        // Every distinct kind of tile needs its own id (subtiles share one until a partition assigns the anchor slot)
+        let mut ids: Vec<u16> = values.iter().map(|value| value.pack()).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 15);
+        assert_eq!(TileValue::InternalUnknown.pack(), PACKED_UNKNOWN);
+    }
+
+    #[test]
+    fn dense_storage_keeps_subtile_anchors() {
+        let mut partition = AreaChanges::new();
+        partition.switch_to_dense(&mut DenseBufferPool::new());
+
+        for x in 0..PARTITION_SIZE as i64 {
+            for y in 0..PARTITION_SIZE as i64 {
+                partition.add_change(&GridCoord{x, y}, &TileValue::Subtile(GridCoord{x: x * 100, y: -y}));
+            }
+        }
+        // Overwriting a subtile should drop its anchor
+        partition.add_change(&GridCoord{x: 2, y: 2}, &TileValue::Rock);
+
+        for x in 0..PARTITION_SIZE as i64 {
+            for y in 0..PARTITION_SIZE as i64 {
+                let expected = if x == 2 && y == 2 { TileValue::Rock } else { TileValue::Subtile(GridCoord{x: x * 100, y: -y}) };
+                assert_eq!(partition.sample(&GridCoord{x, y}), Some(expected));
+            }
+        }
+    }
+
+    #[test]
+    fn subtile_anchor_slots_are_shared_and_reused() {
+        let mut partition = AreaChanges::new();
+
+        // Two buildings worth of subtiles only need two anchors
+        for x in 0..3 {
+            partition.add_change(&GridCoord{x, y: 0}, &TileValue::Subtile(GridCoord{x: 1, y: 1}));
+            partition.add_change(&GridCoord{x, y: 5}, &TileValue::Subtile(GridCoord{x: 1, y: 6}));
+        }
+        assert_eq!(partition.anchor_slot_count(), 2);
+
+        // Once the first building is gone its slot should get picked up by the next new anchor
+        for x in 0..3 {
+            partition.add_change(&GridCoord{x, y: 0}, &TileValue::Empty);
+        }
+        partition.add_change(&GridCoord{x: 9, y: 9}, &TileValue::Subtile(GridCoord{x: 10, y: 10}));
+        assert_eq!(partition.anchor_slot_count(), 2);
+
+        assert_eq!(partition.sample(&GridCoord{x: 0, y: 5}), Some(TileValue::Subtile(GridCoord{x: 1, y: 6})));
+        assert_eq!(partition.sample(&GridCoord{x: 9, y: 9}), Some(TileValue::Subtile(GridCoord{x: 10, y: 10})));
+        assert_eq!(partition.sample(&GridCoord{x: 0, y: 0}), Some(TileValue::Empty));
+    }
+
+    #[test]
+    fn placed_buildings_pass_integrity_check() {
+        let mut map = TileMap::new();
+
+        // Straddle a partition boundary so the anchor lives in a different partition to some subtiles
+        map.make_change(&GridCoord{x: PARTITION_SIZE as i64, y: 1}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: -5, y: -5}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: -5, y: -5}, &TileValue::Empty);
+
+        assert_eq!(map.validate_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn integrity_check_finds_bad_subtiles() {
+        let mut map = TileMap::new();
+
+        map.make_change(&GridCoord{x: 1, y: 1}, &TileValue::HabModule);
+        map.set_area(&GridCoord{x: 20, y: 20}, &GridCoord{x: 1, y: 1}, TileValue::Subtile(GridCoord{x: 1, y: 1}));
+        map.set_area(&GridCoord{x: 30, y: 30}, &GridCoord{x: 1, y: 1}, TileValue::Subtile(GridCoord{x: 40, y: 40}));
+        map.set_area(&GridCoord{x: 40, y: 40}, &GridCoord{x: 1, y: 1}, TileValue::Rock);
+
+        let errors = map.validate_integrity().unwrap_err();
+        assert_eq!(errors.len(), 2, "Unexpected errors {:?}", errors);
+        assert!(errors.contains(&IntegrityError::OutsideFootprint { subtile: GridCoord{x: 20, y: 20}, anchor: GridCoord{x: 1, y: 1} }));
+        assert!(errors.contains(&IntegrityError::MissingAnchor { subtile: GridCoord{x: 30, y: 30}, anchor: GridCoord{x: 40, y: 40} }));
+    }
+
+    #[test]
+    fn repair_fixes_incomplete_footprint() {
+        let mut map = TileMap::new();
+
+        map.make_change(&GridCoord{x: 1, y: 1}, &TileValue::HabModule);
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 1, y: 1}, TileValue::Rock);
+
+        let errors = map.validate_and_repair(false);
+        assert_eq!(errors, vec![IntegrityError::IncompleteFootprint { anchor: GridCoord{x: 1, y: 1}, missing: GridCoord{x: 0, y: 0} }]);
+        // Report only mode shouldn't touch anything
+        assert_eq!(map.sample(&GridCoord{x: 0, y: 0}), TileValue::Rock);
+
+        map.validate_and_repair(true);
+        assert_eq!(map.sample(&GridCoord{x: 0, y: 0}), TileValue::Subtile(GridCoord{x: 1, y: 1}));
+        assert_eq!(map.validate_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn repair_removes_overlapping_and_dangling_tiles() {
+        let mut map = TileMap::new();
+
+        map.make_change(&GridCoord{x: 1, y: 1}, &TileValue::HabModule);
+        // Stamp a second module over the first without going through make_change's footprint handling
+        map.set_area(&GridCoord{x: 2, y: 1}, &GridCoord{x: 3, y: 3}, TileValue::Subtile(GridCoord{x: 3, y: 2}));
+        map.set_area(&GridCoord{x: 3, y: 2}, &GridCoord{x: 1, y: 1}, TileValue::HabModule);
+        map.set_area(&GridCoord{x: 30, y: 30}, &GridCoord{x: 1, y: 1}, TileValue::Subtile(GridCoord{x: 40, y: 40}));
+
+        let errors = map.validate_and_repair(true);
+        assert!(errors.contains(&IntegrityError::OverlappingMetatiles { anchor: GridCoord{x: 3, y: 2}, other_anchor: GridCoord{x: 1, y: 1} }), "Overlap not found in {:?}", errors);
+        assert!(errors.contains(&IntegrityError::MissingAnchor { subtile: GridCoord{x: 30, y: 30}, anchor: GridCoord{x: 40, y: 40} }));
+
+        assert_eq!(map.validate_integrity(), Ok(()));
+        assert_eq!(map.sample(&GridCoord{x: 1, y: 1}), TileValue::HabModule);
+        assert_eq!(map.sample(&GridCoord{x: 3, y: 2}), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 2, y: 2}), TileValue::Subtile(GridCoord{x: 1, y: 1}));
+        assert_eq!(map.sample(&GridCoord{x: 4, y: 3}), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 30, y: 30}), TileValue::Empty);
+    }
+
+    #[test]
+    fn dense_partition_goes_sparse_after_removals() {
+        let mut pool = DenseBufferPool::new();
+        let mut partition = AreaChanges::new();
+        for x in 0..(PARTITION_SIZE as i64) {
+            for y in 0..(PARTITION_SIZE as i64) {
+                partition.add_change_pooled(&GridCoord{x, y}, &TileValue::Rock, &mut pool);
+            }
+        }
+        assert!(partition.is_dense());
+        assert_eq!(partition.change_count(), 256);
+
+        // Removing something that was never changed doesn't count
+        let mut other = AreaChanges::new();
+        other.remove_change(&GridCoord{x: 1, y: 1});
+        assert!(other.is_empty());
+
+        let mut removed = 0;
+        'removing: for y in 0..(PARTITION_SIZE as i64) {
+            for x in 0..(PARTITION_SIZE as i64) {
+                if partition.change_count() as u32 <= SPARSE_SWITCH_POINT { break 'removing; }
+                assert!(partition.is_dense(), "Went sparse early with {} changes", partition.change_count());
+                partition.remove_change_pooled(&GridCoord{x, y}, &mut pool);
+                removed += 1;
+            }
+        }
+        partition.remove_change_pooled(&GridCoord{x: 15, y: 15}, &mut pool);
+        assert!(!partition.is_dense());
+        assert_eq!(pool.free_count(), 1);
+
+        assert_eq!(partition.sample(&GridCoord{x: 0, y: 0}), None);
+        assert_eq!(partition.sample(&GridCoord{x: 15, y: 15}), None);
+        assert_eq!(partition.sample(&GridCoord{x: 14, y: 15}), Some(TileValue::Rock));
+        assert_eq!(partition.change_count(), 256 - removed - 1);
+    }
+
+    #[test]
+    fn demolished_partitions_are_dropped() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 16, y: 16}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 5, y: 5}, &TileValue::HabModule);
+        assert_eq!(map.changed_partition_count(MapLayer::Structure), 1);
+
+        assert_eq!(map.demolish(&GridCoord{x: 5, y: 5}), Some(TileValue::HabModule));
+        assert_eq!(map.changed_partition_count(MapLayer::Structure), 0);
+
+        // Undoing the whole area goes back to generated terrain and gives the dense buffer back
+        assert!(map.undo());
+        assert!(map.undo());
+        assert!(map.undo());
+        assert_eq!(map.changed_partition_count(MapLayer::Terrain), 0);
+        assert_eq!(map.dense_pool_stats().returned, 1);
+    }
+
+    #[test]
+    fn dense_buffers_get_reused() {
+        let mut pool = DenseBufferPool::new();
+        let mut first = AreaChanges::new();
+        let mut second = AreaChanges::new();
+
+        first.switch_to_dense(&mut pool);
+        first.add_change_pooled(&GridCoord{x: 1, y: 1}, &TileValue::Rock, &mut pool);
+        first.switch_to_sparse(&mut pool);
+        assert_eq!(pool.free_count(), 1);
+
+        // Second partition should get the first one's old buffer, without any of its old values
+        second.switch_to_dense(&mut pool);
+        assert_eq!(second.sample(&GridCoord{x: 1, y: 1}), None);
+
+        let stats = pool.stats();
+        assert_eq!(stats.allocated, 1);
+        assert_eq!(stats.reused, 1);
+        assert_eq!(stats.returned, 1);
+    }
+
+    fn paging_directory(name: &str) -> std::path::PathBuf {
+        let directory = std::env::temp_dir().join(format!("tilemap_paging_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn far_partitions_page_out_and_back_in() {
+        let mut map = TileMap::new();
+        map.enable_paging(paging_directory("round_trip")).unwrap();
+        map.make_change(&GridCoord{x: 1, y: 1}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 1001, y: 1001}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 1005, y: 1005}, &TileValue::Rock);
+
+        let camera = Rectangle::new((-10, -10), (20, 20));
+        assert_eq!(map.page_out_far_from(&[camera], 64.0).unwrap(), 1);
+        assert_eq!(map.paged_out_count(), 1);
+        assert_eq!(map.changed_partition_count(MapLayer::Structure), 1);
+        assert_eq!(map.sample(&GridCoord{x: 1, y: 1}), TileValue::HabModule);
+
+        // Reading it asks for it back, it arrives on the next update
+        assert_eq!(map.sample(&GridCoord{x: 1001, y: 1001}), TileValue::Generating);
+        assert_eq!(map.update_paging().unwrap(), 1);
+        assert_eq!(map.paged_out_count(), 0);
+        assert_eq!(map.sample(&GridCoord{x: 1001, y: 1001}), TileValue::HabModule);
+        assert_eq!(map.sample(&GridCoord{x: 1000, y: 1002}), TileValue::Subtile(GridCoord{x: 1001, y: 1001}));
+        assert_eq!(map.sample(&GridCoord{x: 1005, y: 1005}), TileValue::Rock);
+        assert_eq!(map.validate_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn histograms_count_every_tile_in_the_area() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: -20, y: -20}, &GridCoord{x: 40, y: 40}, TileValue::Rock);
+        map.set_area(&GridCoord{x: -10, y: -10}, &GridCoord{x: 20, y: 20}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 0, y: 0}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 5, y: 5}, &TileValue::IronOre);
+
+        // Crosses partition edges on every side
+        let histogram = map.histogram_in_rect(&GridCoord{x: -15, y: -15}, &GridCoord{x: 30, y: 30});
+        assert_eq!(histogram.values().sum::<u64>(), 900);
+        assert_eq!(histogram.get(&TileValue::HabModule), Some(&9));
+        assert_eq!(histogram.get(&TileValue::IronOre), Some(&1));
+        assert_eq!(histogram.get(&TileValue::Empty), Some(&(400 - 9 - 1)));
+        assert_eq!(histogram.get(&TileValue::Rock), Some(&(900 - 400)));
+
+        assert_eq!(map.count_in_rect(&GridCoord{x: -15, y: -15}, &GridCoord{x: 30, y: 30}, TileValue::is_solid), 501);
+        assert_eq!(map.count_in_rect(&GridCoord{x: 0, y: 0}, &GridCoord{x: 0, y: 8}, TileValue::is_solid), 0);
+    }
+
+    #[test]
+    fn reverted_tiles_go_back_to_generated_terrain() {
+        let mut map = TileMap::new();
+        let generated = map.sample_rect(&GridCoord{x: 0, y: 0}, &GridCoord{x: 16, y: 16});
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 16, y: 16}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 8, y: 8}, &TileValue::HabModule);
+        map.set_forbidden(&GridCoord{x: 2, y: 2}, &GridCoord{x: 1, y: 1}, true);
+
+        // Only catches the corner of the module, the whole thing still goes
+        map.revert_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 8, y: 8});
+        assert_eq!(map.sample(&GridCoord{x: 9, y: 9}), TileValue::Empty);
+        assert_eq!(map.sample_layer(MapLayer::Structure, &GridCoord{x: 8, y: 8}), None);
+        assert_eq!(map.sample(&GridCoord{x: 3, y: 5}), generated.tiles()[5 * 16 + 3]);
+        assert!(map.is_forbidden(&GridCoord{x: 2, y: 2}));
+        assert_eq!(map.changed_partition_count(MapLayer::Structure), 0);
+        assert_eq!(map.validate_integrity(), Ok(()));
+
+        map.revert_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 16, y: 16});
+        assert_eq!(map.changed_partition_count(MapLayer::Terrain), 0);
+        assert_eq!(map.provenance(&GridCoord{x: 12, y: 12}), Provenance::Generated);
+
+        assert!(map.undo());
+        assert!(map.undo());
+        assert_eq!(map.sample(&GridCoord{x: 8, y: 8}), TileValue::HabModule);
+        assert_eq!(map.sample(&GridCoord{x: 3, y: 5}), TileValue::Empty);
+    }
+
+    #[test]
+    fn generated_terrain_is_known_under_edits() {
+        let mut map = TileMap::new();
+        let pos = GridCoord{x: 3, y: 7};
+        let generated = map.sample(&pos);
+        assert_eq!(map.provenance(&pos), Provenance::Generated);
+
+        map.make_change(&pos, &if generated.is_solid() { TileValue::Empty } else { TileValue::Rock });
+        assert_eq!(map.provenance(&pos), Provenance::Edited);
+        assert_eq!(map.sample_generated(&pos), generated);
+
+        // Marking a tile off limits doesn't change what's there
+        let other = GridCoord{x: 4, y: 7};
+        map.set_forbidden(&other, &GridCoord{x: 1, y: 1}, true);
+        assert_eq!(map.provenance(&other), Provenance::Generated);
+
+        map.make_change(&GridCoord{x: 1001, y: 1001}, &TileValue::Rock);
+        map.enable_paging(paging_directory("provenance")).unwrap();
+        map.page_out_far_from(&[Rectangle::new((0, 0), (10, 10))], 64.0).unwrap();
+        assert_eq!(map.provenance(&GridCoord{x: 1001, y: 1001}), Provenance::Unknown);
+        assert_eq!(map.update_paging().unwrap(), 1);
+        assert_eq!(map.provenance(&GridCoord{x: 1001, y: 1001}), Provenance::Edited);
+    }
+
+    #[test]
+    fn tile_rules_only_change_tiles_near_the_base() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 48, y: 16}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 5, y: 5}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 10, y: 5}, &TileValue::Ladder);
+        map.make_change(&GridCoord{x: 40, y: 5}, &TileValue::Ladder);
+        map.make_change(&GridCoord{x: 12, y: 12}, &TileValue::Rock);
+        map.set_area(&GridCoord{x: 200, y: 200}, &GridCoord{x: 4, y: 1}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 201, y: 200}, &TileValue::Rock);
+
+        let rules = TileRules {
+            rules: vec![
+                TileRule { from: TileValue::Rock, to: TileValue::Empty, chance: 1.0, condition: RuleCondition::Exposed },
+                TileRule { from: TileValue::Ladder, to: TileValue::Empty, chance: 1.0, condition: RuleCondition::NoneNearby { value: TileValue::HabModule, distance: 8 } }
+            ],
+            active_radius: 0
+        };
+        map.apply_tile_rules(&rules, 0);
+        assert_eq!(map.sample(&GridCoord{x: 12, y: 12}), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 10, y: 5}), TileValue::Ladder);
+        assert_eq!(map.sample(&GridCoord{x: 40, y: 5}), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 5, y: 5}), TileValue::HabModule);
+        // Nothing built anywhere near it
+        assert_eq!(map.sample(&GridCoord{x: 201, y: 200}), TileValue::Rock);
+        // Undo skips straight past the weathering to the player's last change
+        assert!(map.undo());
+        assert_eq!(map.sample(&GridCoord{x: 201, y: 200}), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 40, y: 5}), TileValue::Empty);
+
+        // Rules are data, they read back the same from JSON
+        let json = serde_json::to_string(&TileRules::default()).unwrap();
+        assert_eq!(serde_json::from_str::<TileRules>(&json).unwrap(), TileRules::default());
+        assert_eq!(map.apply_tile_rules(&TileRules { rules: Vec::new(), active_radius: 4 }, 1), 0);
+    }
+
+    #[test]
+    fn tile_rules_for_the_same_tile_roll_separately() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: PARTITION_SIZE as i64, y: PARTITION_SIZE as i64}, TileValue::Rock);
+        map.make_change(&GridCoord{x: 2, y: 2}, &TileValue::HabModule);
+
+        // Sharing one roll, the second rule could only fire on rolls the first had already taken
+        let rules = TileRules {
+            rules: vec![
+                TileRule { from: TileValue::Rock, to: TileValue::Empty, chance: 0.5, condition: RuleCondition::Always },
+                TileRule { from: TileValue::Rock, to: TileValue::IronOre, chance: 0.5, condition: RuleCondition::Always }
+            ],
+            active_radius: 0
+        };
+        map.apply_tile_rules(&rules, 3);
+        let histogram = map.histogram_in_rect(&GridCoord{x: 0, y: 0}, &GridCoord{x: PARTITION_SIZE as i64, y: PARTITION_SIZE as i64});
+        let count = |value: TileValue| histogram.get(&value).copied().unwrap_or(0);
+        let rock = count(TileValue::Rock) + count(TileValue::Empty) + count(TileValue::IronOre);
+        // Half go empty, half the rest become ore
+        assert!(count(TileValue::Empty) * 3 > rock, "{:?}", histogram);
+        assert!(count(TileValue::IronOre) * 6 > rock, "{:?}", histogram);
+    }
+
+    #[test]
+    fn changed_tiles_are_listed_without_paging_them_in() {
+        let mut map = TileMap::new();
+        map.enable_paging(paging_directory("changed_tiles")).unwrap();
+        map.make_change(&GridCoord{x: 1, y: 1}, &TileValue::Empty);
+        map.make_change(&GridCoord{x: 5, y: 5}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 1001, y: 1001}, &TileValue::Rock);
+        map.page_out_far_from(&[Rectangle::new((0, 0), (10, 10))], 64.0).unwrap();
+
+        let collect = |layer: MapLayer, area: Option<(&GridCoord, &GridCoord)>| {
+            let mut changed = Vec::new();
+            map.for_each_changed_tile(layer, area, |pos: &GridCoord, value: &TileValue| changed.push((*pos, *value))).unwrap();
+            changed.sort_by_key(|(pos, _)| (pos.y, pos.x));
+            changed
+        };
+        let terrain = collect(MapLayer::Terrain, None);
+        assert_eq!(terrain.len(), 2);
+        assert_eq!(terrain[0], (GridCoord{x: 1, y: 1}, TileValue::Empty));
+        assert_eq!(terrain[1], (GridCoord{x: 1001, y: 1001}, TileValue::Rock));
+        assert_eq!(map.paged_out_count(), 1);
+
+        // The hab module and its subtiles, cut down to the area
+        assert_eq!(collect(MapLayer::Structure, None).len(), 9);
+        let corner = collect(MapLayer::Structure, Some((&GridCoord{x: 5, y: 5}, &GridCoord{x: 10, y: 10})));
+        assert_eq!(corner.len(), 4);
+        assert!(corner.contains(&(GridCoord{x: 5, y: 5}, TileValue::HabModule)));
+        assert!(collect(MapLayer::Terrain, Some((&GridCoord{x: 2, y: 2}, &GridCoord{x: 500, y: 500}))).is_empty());
+    }
+
+    #[test]
+    fn paged_out_partitions_are_still_edited_and_saved() {
+        let mut map = TileMap::new();
+        map.enable_paging(paging_directory("edit_and_save")).unwrap();
+        map.make_change(&GridCoord{x: 1001, y: 1001}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 1005, y: 1005}, &TileValue::Rock);
+        map.page_out_far_from(&[Rectangle::new((0, 0), (1, 1))], 64.0).unwrap();
+
+        let loaded = TileMap::from_bytes(&map.to_bytes().unwrap()).unwrap();
+        assert_eq!(loaded.sample(&GridCoord{x: 1001, y: 1001}), TileValue::HabModule);
+
+        // Writing brings the partition straight back, without losing the rest of it
+        map.make_change(&GridCoord{x: 1006, y: 1005}, &TileValue::Rock);
+        assert_eq!(map.paged_out_count(), 0);
+        assert_eq!(map.sample(&GridCoord{x: 1001, y: 1001}), TileValue::HabModule);
+        assert_eq!(map.sample(&GridCoord{x: 1005, y: 1005}), TileValue::Rock);
+        assert!(map.undo());
+        assert_eq!(map.sample(&GridCoord{x: 1005, y: 1005}), TileValue::Rock);
+    }
+
+    #[test]
+    fn dense_storage_packs_few_values_tightly() {
+        let mut changes = AreaChanges::new();
+        changes.switch_to_dense(&mut DenseBufferPool::new());
+
+        changes.add_change(&GridCoord{x: 0, y: 0}, &TileValue::Rock);
+        changes.add_change(&GridCoord{x: 1, y: 0}, &TileValue::Empty);
+        assert_eq!(changes.dense_bits_per_tile(), Some(2));
+
+        // Each anchor gets its own palette slot, so this forces the cells to grow
+        for i in 0..40 {
+            changes.add_change(&GridCoord{x: i % 16, y: 1 + i / 16}, &TileValue::Subtile(GridCoord{x: 100 + i, y: 0}));
+        }
+        assert_eq!(changes.dense_bits_per_tile(), Some(8));
+
+        assert_eq!(changes.sample(&GridCoord{x: 0, y: 0}), Some(TileValue::Rock));
+        assert_eq!(changes.sample(&GridCoord{x: 1, y: 0}), Some(TileValue::Empty));
+        assert_eq!(changes.sample(&GridCoord{x: 2, y: 0}), None);
+        for i in 0..40 {
+            assert_eq!(changes.sample(&GridCoord{x: i % 16, y: 1 + i / 16}), Some(TileValue::Subtile(GridCoord{x: 100 + i, y: 0})));
+        }
+
+        changes.remove_change(&GridCoord{x: 0, y: 0});
+        assert_eq!(changes.sample(&GridCoord{x: 0, y: 0}), None);
+    }
+
+    #[test]
+    fn setting_large_object_works() {
+        let mut map = TileMap::new();
+
+        map.make_change(&GridCoord{x: 1, y: 1}, &TileValue::HabModule);
+
+        for x in -2..5 {
+            for y in -2..5 {
+                let value_here = map.sample(&GridCoord{x, y});
+                if x == 1 && y == 1 {
+                    assert_eq!(value_here, TileValue::HabModule);
+                }
+                else if x >= 0 && x < 3 && y >= 0 && y < 3 {
+                    assert_eq!(value_here, TileValue::Subtile(GridCoord{x: 1, y: 1}));
+                }
+                else {
+                    assert!(is_valid_generated_tile(&value_here), 
+                        "Expected untouched tile at ({}, {}), found not that",
+                        x, y
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn revoving_large_object_works() {
+        let mut map = TileMap::new();
+
+        map.make_change(&GridCoord{x: 1, y: 1}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 1, y: 1}, &TileValue::Empty);
+
+        for x in -2..5 {
+            for y in -2..5 {
+                let value_here = map.sample(&GridCoord{x, y});
+                if x >= 0 && x < 3 && y >= 0 && y < 3 {
+                    assert_eq!(value_here, TileValue::Empty);
+                }
+                else {
+                    assert!(is_valid_generated_tile(&value_here), 
+                        "Expected untouched tile at ({}, {}), found not that",
+                        x, y
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn buildings_keep_terrain_underneath() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 3, y: 3}, TileValue::Empty);
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 1, y: 3}, TileValue::Rock);
+        map.make_change(&GridCoord{x: 1, y: 1}, &TileValue::HabModule);
+
+        assert_eq!(map.sample(&GridCoord{x: 0, y: 2}), TileValue::Subtile(GridCoord{x: 1, y: 1}));
+        assert_eq!(map.sample_layer(MapLayer::Structure, &GridCoord{x: 0, y: 2}), Some(TileValue::Subtile(GridCoord{x: 1, y: 1})));
+        assert_eq!(map.sample_layer(MapLayer::Terrain, &GridCoord{x: 0, y: 2}), Some(TileValue::Rock));
+        assert_eq!(map.sample_layer(MapLayer::Terrain, &GridCoord{x: 1, y: 1}), Some(TileValue::Empty));
+
+        assert_eq!(map.demolish(&GridCoord{x: 2, y: 0}), Some(TileValue::HabModule));
+        assert_eq!(map.sample(&GridCoord{x: 0, y: 2}), TileValue::Rock);
+        assert_eq!(map.sample(&GridCoord{x: 1, y: 1}), TileValue::Empty);
+        assert_eq!(map.sample_layer(MapLayer::Structure, &GridCoord{x: 1, y: 1}), None);
+        assert_eq!(map.demolish(&GridCoord{x: 1, y: 1}), None);
+
+        assert!(map.undo());
+        assert_eq!(map.sample(&GridCoord{x: 1, y: 1}), TileValue::HabModule);
+        assert!(map.validate_integrity().is_ok());
+    }
+
+    #[test]
+    fn layer_tiles_only_gives_that_layer() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 10, y: 10}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 5, y: 5}, &TileValue::HabModule);
+        map.set_layer_tile(MapLayer::Overlay, &GridCoord{x: 1, y: 2}, Some(TileValue::Rock));
+        map.set_layer_tile(MapLayer::Overlay, &GridCoord{x: 3, y: 0}, Some(TileValue::Rock));
+
+        // Overlay sits over the top without changing what the tile is
+        assert_eq!(map.sample(&GridCoord{x: 1, y: 2}), TileValue::Empty);
+
+        let overlay: Vec<(GridCoord, TileValue)> = map.layer_tiles_in_area(MapLayer::Overlay, &GridCoord{x: 0, y: 0}, &GridCoord{x: 10, y: 10}).collect();
+        assert_eq!(overlay, vec![(GridCoord{x: 3, y: 0}, TileValue::Rock), (GridCoord{x: 1, y: 2}, TileValue::Rock)]);
+
+        assert_eq!(map.layer_tiles_in_area(MapLayer::Structure, &GridCoord{x: 0, y: 0}, &GridCoord{x: 10, y: 10}).count(), 9);
+        assert_eq!(map.layer_tiles_in_area(MapLayer::Terrain, &GridCoord{x: 0, y: 0}, &GridCoord{x: 10, y: 10}).count(), 100);
+
+        map.set_layer_tile(MapLayer::Overlay, &GridCoord{x: 3, y: 0}, None);
+        assert_eq!(map.layer_tiles_in_area(MapLayer::Overlay, &GridCoord{x: 0, y: 0}, &GridCoord{x: 10, y: 10}).count(), 1);
+    }
+
+    #[test]
+    fn forbidden_zones_sit_over_the_map() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 6, y: 6}, TileValue::Empty);
+        map.set_forbidden(&GridCoord{x: 1, y: 1}, &GridCoord{x: 3, y: 2}, true);
+
+        assert!(map.is_forbidden(&GridCoord{x: 3, y: 2}));
+        assert!(!map.is_forbidden(&GridCoord{x: 4, y: 2}));
+        // Designations don't change what's on the tile
+        assert_eq!(map.sample(&GridCoord{x: 3, y: 2}), TileValue::Empty);
+        assert!(map.area_clear(&GridCoord{x: 0, y: 0}, &GridCoord{x: 6, y: 6}));
+
+        map.set_forbidden(&GridCoord{x: 0, y: 0}, &GridCoord{x: 6, y: 6}, false);
+        assert_eq!(map.layer_tiles_in_area(MapLayer::Designation, &GridCoord{x: 0, y: 0}, &GridCoord{x: 6, y: 6}).count(), 0);
+
+        assert!(map.undo());
+        assert!(map.is_forbidden(&GridCoord{x: 1, y: 1}));
+    }
+
+    #[test]
+    fn make_change_forbidden_only_marks_the_tile() {
+        let mut map = TileMap::new();
+        let pos = GridCoord{x: 2, y: 2};
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 5, y: 5}, TileValue::Empty);
+        map.make_change(&pos, &TileValue::Forbidden);
+
+        assert!(map.is_forbidden(&pos));
+        assert_eq!(map.sample(&pos), TileValue::Empty);
+        assert_eq!(map.sample_layer(MapLayer::Structure, &pos), None);
+        assert_eq!(map.validate_integrity(), Ok(()));
+
+        assert!(map.undo());
+        assert!(!map.is_forbidden(&pos));
+    }
+
+    #[test]
+    fn tile_data_goes_with_the_tile() {
+        let mut map = TileMap::new();
+        let rock = GridCoord{x: 3, y: 4};
+        map.make_change(&rock, &TileValue::Rock);
+        map.set_tile_data(&rock, "mining_progress", 0.5);
+        map.set_tile_data(&rock, "hp", 10);
+
+        assert_eq!(map.tile_data(&rock, "mining_progress").and_then(|progress| progress.as_float()), Some(0.5));
+        assert_eq!(map.tile_data(&rock, "hp").and_then(|hp| hp.as_float()), None);
+        assert_eq!(map.remove_tile_data(&rock, "hp"), Some(TileDataValue::Int(10)));
+        assert_eq!(map.tile_data(&rock, "hp"), None);
+
+        // Designations sit over the tile rather than replacing it
+        map.set_forbidden(&rock, &GridCoord{x: 1, y: 1}, true);
+        assert!(map.tile_data(&rock, "mining_progress").is_some());
+
+        map.make_change(&rock, &TileValue::Empty);
+        assert_eq!(map.tile_data(&rock, "mining_progress"), None);
+    }
+
+    #[test]
+    fn structures_keep_their_names() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 12, y: 6}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 2, y: 2}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 7, y: 2}, &TileValue::HabModule);
+
+        // Any cell of the building will do, the name lives on the anchor
+        assert!(map.set_structure_name(&GridCoord{x: 3, y: 1}, "  Greenhouse "));
+        assert!(map.set_structure_name(&GridCoord{x: 7, y: 2}, "Cryo Storage"));
+        assert!(!map.set_structure_name(&GridCoord{x: 10, y: 5}, "Nothing here"));
+        assert_eq!(map.structure_name(&GridCoord{x: 1, y: 3}), Some("Greenhouse"));
+        assert_eq!(map.find_structures_named("STORAGE"), vec![GridCoord{x: 7, y: 2}]);
+        assert_eq!(map.find_structures_named("e"), vec![GridCoord{x: 2, y: 2}, GridCoord{x: 7, y: 2}]);
+
+        let mut loaded = TileMap::from_bytes(&map.to_bytes().unwrap()).unwrap();
+        assert_eq!(loaded.structure_name(&GridCoord{x: 2, y: 2}), Some("Greenhouse"));
+
+        assert_eq!(loaded.demolish(&GridCoord{x: 2, y: 2}), Some(TileValue::HabModule));
+        assert_eq!(loaded.structure_name(&GridCoord{x: 2, y: 2}), None);
+        assert!(loaded.set_structure_name(&GridCoord{x: 7, y: 2}, ""));
+        assert!(loaded.find_structures_named("").is_empty());
+    }
+
+    #[test]
+    fn changing_any_cell_of_a_building_removes_all_of_it() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 8, y: 8}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 2, y: 2}, &TileValue::HabModule);
+
+        map.make_change(&GridCoord{x: 3, y: 1}, &TileValue::Empty);
+        assert!(map.area_clear(&GridCoord{x: 0, y: 0}, &GridCoord{x: 8, y: 8}));
+        assert!(map.validate_integrity().is_ok());
+
+        // Same through a transaction, with a new building going on the cell that was picked
+        map.make_change(&GridCoord{x: 2, y: 2}, &TileValue::HabModule);
+        let mut edit = map.begin_edit();
+        edit.place(&GridCoord{x: 1, y: 3}, &TileValue::HabModule);
+        assert_eq!(edit.commit(), Ok(()));
+        assert_eq!(map.sample(&GridCoord{x: 1, y: 3}), TileValue::HabModule);
+        assert_eq!(map.sample(&GridCoord{x: 3, y: 1}), TileValue::Empty);
+        assert!(map.validate_integrity().is_ok());
+    }
+
+    #[test]
+    fn try_place_refuses_to_overlap() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 10, y: 10}, TileValue::Empty);
+        assert_eq!(map.try_place(&GridCoord{x: 2, y: 2}, &TileValue::HabModule), Ok(()));
+
+        assert_eq!(map.try_place(&GridCoord{x: 4, y: 3}, &TileValue::HabModule),
+            Err(PlacementError::Blocked { pos: GridCoord{x: 3, y: 2}, value: TileValue::Subtile(GridCoord{x: 2, y: 2}) }));
+        assert_eq!(map.try_place(&GridCoord{x: 3, y: 3}, &TileValue::Rock),
+            Err(PlacementError::Blocked { pos: GridCoord{x: 3, y: 3}, value: TileValue::Subtile(GridCoord{x: 2, y: 2}) }));
+        assert_eq!(map.try_place(&GridCoord{x: 7, y: 7}, &TileValue::Subtile(GridCoord{x: 2, y: 2})),
+            Err(PlacementError::NotPlaceable(TileValue::Subtile(GridCoord{x: 2, y: 2}))));
+        assert_eq!(map.sample(&GridCoord{x: 4, y: 3}), TileValue::Empty);
+
+        assert_eq!(map.try_place(&GridCoord{x: 5, y: 2}, &TileValue::HabModule), Ok(()));
+        assert!(map.validate_integrity().is_ok());
+    }
+
+    #[test]
+    fn for_each_tile_bounds_gets_right_edge_of_screen_building() {
+        let mut map = TileMap::new();
+        map.make_change(&GridCoord{x: 5, y: 0}, &TileValue::HabModule);
+        let bounds = Rectangle::new_sized((4, 4));
+        let mut hab_hit = 0;
+        map.for_each_tile_rect(&bounds, |pos: &GridCoord, value: &TileValue, _size: &GridCoord| {
+            if *value == TileValue::HabModule {
+                assert_eq!(*pos, GridCoord{x: 5, y: 0}, "Found building in wrong place");
+                hab_hit += 1;
+            }
+        });
+        assert!(hab_hit > 0, "Failed to find building on edge of screen");
+        assert!(hab_hit == 1, "Found building too many times");
+    }
+    #[test]
+    fn for_each_tile_bounds_gets_left_edge_of_screen_building() {
+        let mut map = TileMap::new();
+        map.make_change(&GridCoord{x: -1, y: 0}, &TileValue::HabModule);
+        let bounds = Rectangle::new_sized((4, 4));
+        let mut hab_hit = 0;
+        map.for_each_tile_rect(&bounds, |pos: &GridCoord, value: &TileValue, _size: &GridCoord| {
+            if *value == TileValue::HabModule {
+                assert_eq!(*pos, GridCoord{x: -1, y: 0}, "Found building in wrong place");
+                hab_hit += 1;
+            }
+        });
+        assert!(hab_hit > 0, "Failed to find building on edge of screen");
+        assert!(hab_hit == 1, "Found building too many times");
+    }
+    #[test]
+    fn for_each_tile_bounds_gets_top_edge_of_screen_building() {
+        let mut map = TileMap::new();
+        map.make_change(&GridCoord{x: 0, y: 5}, &TileValue::HabModule);
+        let bounds = Rectangle::new_sized((4, 4));
+        let mut hab_hit = 0;
+        map.for_each_tile_rect(&bounds, |pos: &GridCoord, value: &TileValue, _size: &GridCoord| {
+            if *value == TileValue::HabModule {
+                assert_eq!(*pos, GridCoord{x: 0, y: 5}, "Found building in wrong place");
+                hab_hit += 1;
+            }
+        });
+        assert!(hab_hit > 0, "Failed to find building on edge of screen");
+        assert!(hab_hit == 1, "Found building too many times");
+    }
+    #[test]
+    fn for_each_tile_bounds_gets_bottom_edge_of_screen_building() {
+        let mut map = TileMap::new();
+        map.make_change(&GridCoord{x: 0, y: -1}, &TileValue::HabModule);
+        let bounds = Rectangle::new_sized((4, 4));
+        let mut hab_hit = 0;
+        map.for_each_tile_rect(&bounds, |pos: &GridCoord, value: &TileValue, _size: &GridCoord| {
+            if *value == TileValue::HabModule {
+                assert_eq!(*pos, GridCoord{x: 0, y: -1}, "Found building in wrong place");
+                hab_hit += 1;
+            }
+        });
+        assert!(hab_hit > 0, "Failed to find building on edge of screen");
+        assert!(hab_hit == 1, "Found building too many times");
+    }
+
+    #[test]
+    fn for_each_tile_bounds_gets_pos_corner_of_screen_building() {
+        let mut map = TileMap::new();
+
+        map.make_change(&GridCoord{x: 5, y: 5}, &TileValue::HabModule);
+        let bounds = Rectangle::new_sized((4, 4));
+
+        let mut hab_hit = 0;
+
+        map.for_each_tile_rect(&bounds, |pos: &GridCoord, value: &TileValue, _size: &GridCoord| {
+            if *value == TileValue::HabModule {
+                assert_eq!(*pos, GridCoord{x: 5, y: 5}, "Found building in wrong place");
+                hab_hit += 1;
+            }
+        });
+
+        assert!(hab_hit > 0, "Failed to find building on edge of screen");
+        assert!(hab_hit == 1, "Found building too many times");
+    }
+
+    #[test]
+    fn for_each_tile_bounds_gets_neg_corner_of_screen_building() {
+        let mut map = TileMap::new();
+
+        map.make_change(&GridCoord{x: -1, y: -1}, &TileValue::HabModule);
+        let bounds = Rectangle::new_sized((4, 4));
+
+        let mut hab_hit = 0;
+
+        map.for_each_tile_rect(&bounds, |pos: &GridCoord, value: &TileValue, _size: &GridCoord| {
+            if *value == TileValue::HabModule {
+                assert_eq!(*pos, GridCoord{x: -1, y: -1}, "Found building in wrong place");
+                hab_hit += 1;
+            }
+        });
+
+        assert!(hab_hit > 0, "Failed to find building on edge of screen");
+        assert!(hab_hit == 1, "Found building too many times");
+    }
+
+    #[test]
+    fn cached_tile_updates_after_change() {
+        let mut map = TileMap::new();
+
+        // Sample first so the generated value ends up in the cache
+        map.sample(&GridCoord{x: 3, y: 3});
+        map.make_change(&GridCoord{x: 3, y: 3}, &TileValue::Error);
+        assert_eq!(map.sample(&GridCoord{x: 3, y: 3}), TileValue::Error);
+    }
+
+    #[test]
+    fn cached_and_uncached_sampling_agree() {
+        let mut map = TileMap::new();
+        let mut uncached_map = TileMap::new();
+        uncached_map.set_caching_enabled(false);
+
+        for map in [&mut map, &mut uncached_map].iter_mut() {
+            map.for_each_tile(&GridCoord{x: -20, y: -20}, &GridCoord{x: 40, y: 40}, |_, _, _| {});
+            map.make_change(&GridCoord{x: 0, y: 0}, &TileValue::HabModule);
+            map.make_change(&GridCoord{x: 10, y: -7}, &TileValue::Rock);
+        }
+
+        for y in -20..20 {
+            for x in -20..20 {
+                assert_eq!(map.sample(&GridCoord{x, y}), uncached_map.sample(&GridCoord{x, y}), "Cache disagrees at ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn partition_generation_matches_per_tile_noise() {
+        let map = TileMap::with_seed(99);
+        let mut uncached_map = TileMap::with_seed(99);
+        uncached_map.set_caching_enabled(false);
 
-    use quicksilver::{
-        geom::{Rectangle},
-    };
+        // Backwards so partitions get generated from their far corner first
+        for y in (-40..40).rev() {
+            for x in (-40..40).rev() {
+                assert_eq!(map.sample(&GridCoord{x, y}), uncached_map.sample(&GridCoord{x, y}), "Generated partition disagrees at ({}, {})", x, y);
+            }
+        }
+    }
 
-    fn is_valid_generated_tile(value: &TileValue) -> bool {
-        value == &TileValue::Empty || value == &TileValue::Rock
+    #[test]
+    fn background_generation_fills_in_placeholders() {
+        let mut map = TileMap::with_seed(5);
+        let sync_map = TileMap::with_seed(5);
+        map.enable_background_generation();
+
+        let area_top_left = GridCoord{x: -20, y: -20};
+        let area_size = GridCoord{x: 40, y: 40};
+        map.for_each_tile(&area_top_left, &area_size, |_pos: &GridCoord, value: &TileValue, _size: &GridCoord| {
+            assert_eq!(*value, TileValue::Generating, "Tile was generated before the worker got to it");
+        });
+
+        // 40x40 starting off the partition grid touches 4x4 partitions
+        let mut received = 0;
+        let started = std::time::Instant::now();
+        while received < 16 {
+            assert!(started.elapsed() < std::time::Duration::from_secs(10), "Background generation never finished");
+            received += map.receive_generated_partitions();
+            std::thread::yield_now();
+        }
+
+        map.for_each_tile(&area_top_left, &area_size, |pos: &GridCoord, value: &TileValue, _size: &GridCoord| {
+            assert_eq!(*value, sync_map.sample(pos), "Background generated tile differs at {:?}", pos);
+        });
     }
 
     #[test]
-    fn empty_map_access_gives_valid() {
-        let map = TileMap::new();
-        assert!(is_valid_generated_tile(&map.sample(&GridCoord{x: 0, y: 0})));
+    fn tile_iterator_matches_for_each_tile() {
+        let mut map = TileMap::new();
+        map.make_change(&GridCoord{x: 0, y: 0}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 9, y: 4}, &TileValue::HabModule);
+
+        // Cuts through both buildings so they have to come out through their subtiles
+        let top_left = GridCoord{x: 1, y: -3};
+        let size = GridCoord{x: 8, y: 9};
+
+        let mut callback_tiles: Vec<(GridCoord, TileValue, GridCoord)> = Vec::new();
+        map.for_each_tile(&top_left, &size, |pos: &GridCoord, value: &TileValue, size: &GridCoord| callback_tiles.push((*pos, *value, *size)));
+        let iterated_tiles: Vec<(GridCoord, TileValue, GridCoord)> = map.tiles_in_area(&top_left, &size).collect();
+
+        assert_eq!(iterated_tiles, callback_tiles);
+        assert_eq!(iterated_tiles.iter().filter(|(_, value, _)| *value == TileValue::HabModule).count(), 2);
+        assert_eq!(map.tiles_in_area(&top_left, &GridCoord{x: 0, y: 5}).count(), 0);
     }
 
     #[test]
-    fn untouched_map_no_errors() {
-        let map = TileMap::new();
-        
-        // Check the 1 million tiles closest to origin
-        let x_min: i64 = -500;
-        let x_max: i64 = 500;
-        let y_min: i64 = -500;
-        let y_max: i64 = 500;
+    fn sample_rect_matches_sample() {
+        let mut map = TileMap::with_seed(3);
+        map.make_change(&GridCoord{x: 0, y: 0}, &TileValue::HabModule);
+        map.set_area(&GridCoord{x: 20, y: -10}, &GridCoord{x: 16, y: 16}, TileValue::Empty);
+        map.make_change(&GridCoord{x: -30, y: 12}, &TileValue::Rock);
 
-        for x in x_min..x_max {
-            for y in y_min..y_max {
-                assert!(is_valid_generated_tile(&map.sample(&GridCoord{x, y})), "Found invalid tile at ({}, {})", x, y);
+        let mut buffer = TileBuffer::new();
+        // Reused with a different rectangle to make sure nothing from the last fill is left behind
+        map.sample_rect_into(&GridCoord{x: 100, y: 100}, &GridCoord{x: 5, y: 5}, &mut buffer);
+
+        let top_left = GridCoord{x: -37, y: -21};
+        let size = GridCoord{x: 77, y: 45};
+        map.sample_rect_into(&top_left, &size, &mut buffer);
+
+        assert_eq!(buffer.tiles().len(), (size.x * size.y) as usize);
+        assert_eq!(buffer.rows().count(), size.y as usize);
+        for y in top_left.y..(top_left.y + size.y) {
+            for x in top_left.x..(top_left.x + size.x) {
+                let pos = GridCoord{x, y};
+                assert_eq!(buffer.get(&pos), Some(map.sample(&pos)), "Buffer disagrees with sample at {:?}", pos);
             }
         }
+        assert_eq!(buffer.get(&GridCoord{x: top_left.x - 1, y: 0}), None);
+        assert_eq!(buffer.get(&GridCoord{x: 0, y: top_left.y + size.y}), None);
     }
 
     #[test]
-    fn map_write_get_back_1() {
+    fn dirty_regions_report_changes_once() {
         let mut map = TileMap::new();
-        map.make_change(&GridCoord{x: 0, y: 0}, &TileValue::Error);
-        assert_eq!(map.sample(&GridCoord{x: 0, y: 0}), TileValue::Error);
+        assert!(map.take_dirty_regions().is_empty());
+
+        map.make_change(&GridCoord{x: 15, y: 0}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: -40, y: 3}, &TileValue::Rock);
+
+        let dirty = map.take_dirty_regions();
+        assert_eq!(dirty.tiles.len(), 10);
+        assert!(dirty.tiles.contains(&GridCoord{x: 16, y: 1}));
+        assert!(dirty.tiles.contains(&GridCoord{x: -40, y: 3}));
+        // The hab module straddles two partition edges, so touches 4 partitions, plus one for the rock
+        assert_eq!(dirty.partitions.len(), 5);
+        assert!(dirty.partitions.contains(&GridCoord{x: 16, y: -16}));
+        assert!(!dirty.everything);
+
+        assert_eq!(map.take_dirty_regions(), DirtyRegions::default());
+
+        let config = GeneratorConfig { rock_density: 1.0, ..GeneratorConfig::default() };
+        map.set_generator_config(config);
+        assert!(map.take_dirty_regions().everything);
     }
 
     #[test]
-    fn map_write_get_back_dense_100() {
+    fn undo_reverts_whole_placement() {
         let mut map = TileMap::new();
+        let untouched_map = TileMap::new();
+        assert!(!map.can_undo());
 
-        // Large bounds but still within a single cache
-        let x_min: i64 = -5;
-        let x_max: i64 = 5;
-        let y_min: i64 = -5;
-        let y_max: i64 = 5;
-        
-        for x in x_min..x_max {
-            for y in y_min..y_max {
-                map.make_change(&GridCoord{x, y}, &TileValue::Error);
+        map.make_change(&GridCoord{x: 4, y: 4}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 9, y: 4}, &TileValue::HabModule);
+
+        assert!(map.undo());
+        assert_eq!(map.sample(&GridCoord{x: 4, y: 4}), TileValue::HabModule);
+        for y in 3..6 {
+            for x in 8..11 {
+                assert_eq!(map.sample(&GridCoord{x, y}), untouched_map.sample(&GridCoord{x, y}), "({}, {}) wasn't restored", x, y);
             }
         }
 
-        for x in x_min..x_max {
-            for y in y_min..y_max {
-                assert_eq!(map.sample(&GridCoord{x, y}), TileValue::Error);
+        assert!(map.undo());
+        assert!(!map.undo());
+        for y in 0..12 {
+            for x in 0..12 {
+                assert_eq!(map.sample(&GridCoord{x, y}), untouched_map.sample(&GridCoord{x, y}), "({}, {}) wasn't restored", x, y);
             }
         }
+        assert!(map.validate_integrity().is_ok());
+
+        assert!(map.redo());
+        assert!(map.redo());
+        assert!(!map.redo());
+        assert_eq!(map.sample(&GridCoord{x: 9, y: 4}), TileValue::HabModule);
+        assert_eq!(map.sample(&GridCoord{x: 10, y: 5}), TileValue::Subtile(GridCoord{x: 9, y: 4}));
+        assert!(map.validate_integrity().is_ok());
     }
 
     #[test]
-    fn map_write_get_back_sparse_100() {
+    fn undo_restores_replaced_tiles_and_new_edits_clear_redo() {
         let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 3, y: 3}, TileValue::Rock);
+        // Replaces the rock it lands on, undo should bring back the rock rather than generated terrain
+        map.make_change(&GridCoord{x: 2, y: 2}, &TileValue::HabModule);
 
-        // Large bounds but still within a single cache
-        let x_min: i64 = -5;
-        let x_max: i64 = 5;
-        let y_min: i64 = -5;
-        let y_max: i64 = 5;
-        
-        for x in x_min..x_max {
-            for y in y_min..y_max {
-                map.make_change(&GridCoord{x: 100 * x, y: 100 * y}, &TileValue::Error);
+        assert!(map.undo());
+        for y in 0..3 {
+            for x in 0..3 {
+                assert_eq!(map.sample(&GridCoord{x, y}), TileValue::Rock);
+            }
+        }
+
+        map.make_change(&GridCoord{x: 20, y: 20}, &TileValue::Error);
+        assert!(!map.can_redo());
+
+        for i in 0..(MAX_UNDO_STEPS as i64 + 10) {
+            map.make_change(&GridCoord{x: i, y: 40}, &TileValue::Error);
+        }
+        let mut undone = 0;
+        while map.undo() { undone += 1; }
+        assert_eq!(undone, MAX_UNDO_STEPS);
+    }
+
+    #[test]
+    fn undo_groups_follow_their_step() {
+        let mut map = TileMap::new();
+        assert!(!map.set_undo_group(1));
+
+        map.make_change(&GridCoord{x: 0, y: 0}, &TileValue::Ladder);
+        assert!(map.set_undo_group(7));
+        map.make_change(&GridCoord{x: 5, y: 0}, &TileValue::Rock);
+        assert_eq!(map.undo_group(), None);
+
+        assert!(map.undo());
+        assert_eq!(map.undo_group(), Some(7));
+        assert!(map.undo());
+        assert_eq!(map.redo_group(), Some(7));
+        assert!(map.redo());
+        assert_eq!(map.undo_group(), Some(7));
+        assert_eq!(map.redo_group(), None);
+    }
+
+    #[test]
+    fn failed_transaction_leaves_map_untouched() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 10, y: 10}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 2, y: 2}, &TileValue::Rock);
+        let undo_available = map.can_undo();
+
+        let mut edit = map.begin_edit();
+        edit.require_clear(&GridCoord{x: 1, y: 1}, &GridCoord{x: 3, y: 3});
+        edit.place(&GridCoord{x: 2, y: 2}, &TileValue::HabModule);
+        assert_eq!(edit.sample(&GridCoord{x: 3, y: 3}), TileValue::Subtile(GridCoord{x: 2, y: 2}));
+        assert_eq!(edit.commit(), Err(EditError::AreaNotClear { top_left: GridCoord{x: 1, y: 1}, size: GridCoord{x: 3, y: 3} }));
+
+        assert_eq!(map.sample(&GridCoord{x: 2, y: 2}), TileValue::Rock);
+        assert_eq!(map.sample(&GridCoord{x: 3, y: 3}), TileValue::Empty);
+        assert_eq!(map.can_undo(), undo_available);
+
+        // Half a building would be left behind, so this is refused too
+        let mut edit = map.begin_edit();
+        edit.place(&GridCoord{x: 6, y: 6}, &TileValue::HabModule);
+        edit.set(&GridCoord{x: 7, y: 7}, TileValue::Empty);
+        match edit.commit() {
+            Err(EditError::Integrity(errors)) => assert_eq!(errors, vec![IntegrityError::IncompleteFootprint { anchor: GridCoord{x: 6, y: 6}, missing: GridCoord{x: 7, y: 7} }]),
+            result => panic!("Broken footprint was committed: {:?}", result)
+        }
+        assert_eq!(map.sample(&GridCoord{x: 6, y: 6}), TileValue::Empty);
+
+        let mut edit = map.begin_edit();
+        edit.place(&GridCoord{x: 6, y: 6}, &TileValue::HabModule);
+        edit.rollback();
+        assert_eq!(map.sample(&GridCoord{x: 6, y: 6}), TileValue::Empty);
+    }
+
+    #[test]
+    fn committed_transaction_is_one_undo_step() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 10, y: 10}, TileValue::Empty);
+
+        let mut edit = map.begin_edit();
+        edit.require_clear(&GridCoord{x: 1, y: 1}, &GridCoord{x: 3, y: 3});
+        edit.place(&GridCoord{x: 2, y: 2}, &TileValue::HabModule);
+        edit.place(&GridCoord{x: 6, y: 2}, &TileValue::HabModule);
+        assert_eq!(edit.commit(), Ok(()));
+
+        assert_eq!(map.sample(&GridCoord{x: 2, y: 2}), TileValue::HabModule);
+        assert_eq!(map.sample(&GridCoord{x: 7, y: 3}), TileValue::Subtile(GridCoord{x: 6, y: 2}));
+        assert!(map.validate_integrity().is_ok());
+
+        assert!(map.undo());
+        assert_eq!(map.sample(&GridCoord{x: 2, y: 2}), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 6, y: 2}), TileValue::Empty);
+    }
+
+    #[test]
+    fn cached_tiles_follow_rock_density() {
+        let mut map = TileMap::new();
+        let mut fresh_map = TileMap::new();
+
+        // Fill the cache, then change the density underneath it
+        map.for_each_tile(&GridCoord{x: 0, y: 0}, &GridCoord{x: 10, y: 10}, |_, _, _| {});
+        let config = GeneratorConfig { rock_density: 5.0, ..GeneratorConfig::default() };
+        map.set_generator_config(config);
+        fresh_map.set_generator_config(config);
+
+        for x in 0..10 {
+            for y in 0..10 {
+                assert_eq!(map.sample(&GridCoord{x, y}), fresh_map.sample(&GridCoord{x, y}), "Stale cached tile at ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn cache_resizes_to_fit_view() {
+        let mut map = TileMap::new();
+
+        map.resize_cache_for_view(&Rectangle::new_sized((80, 50)), 10.0);
+        assert_eq!(map.cache_capacity(), 91 * 61);
+
+        // Small zoom changes shouldn't shrink the cache
+        map.resize_cache_for_view(&Rectangle::new_sized((70, 45)), 10.0);
+        assert_eq!(map.cache_capacity(), 91 * 61);
+
+        map.resize_cache_for_view(&Rectangle::new_sized((4, 4)), 0.0);
+        assert_eq!(map.cache_capacity(), MIN_TILE_CACHE_SIZE);
+    }
+
+    #[test]
+    fn saved_map_loads_back_the_same() {
+        let config = GeneratorConfig { octaves: 3, rock_density: 0.4, ..GeneratorConfig::default() };
+        let mut map = TileMap::new_with_config(1234, config);
+        // Enough changes to push a partition into dense storage as well as a few sparse ones
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 16, y: 16}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 5, y: 5}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: -40, y: 70}, &TileValue::Rock);
+        map.make_change(&GridCoord{x: 100, y: -3}, &TileValue::HabModule);
+        map.set_layer_tile(MapLayer::Overlay, &GridCoord{x: 5, y: 5}, Some(TileValue::Rock));
+        map.set_tile_data(&GridCoord{x: 5, y: 5}, "hp", 80);
+        map.set_tile_data(&GridCoord{x: -40, y: 70}, "owner", "colony");
+
+        let bytes = map.to_bytes().unwrap();
+        let loaded = TileMap::from_bytes(&bytes).unwrap();
+
+        assert_eq!(loaded.seed(), 1234);
+        assert_eq!(loaded.generator_config(), config);
+        for y in -80..120 {
+            for x in -80..120 {
+                let pos = GridCoord{x, y};
+                assert_eq!(loaded.sample(&pos), map.sample(&pos), "Tile at {:?} changed across save and load", pos);
+            }
+        }
+        assert!(loaded.validate_integrity().is_ok());
+        assert_eq!(loaded.sample_layer(MapLayer::Overlay, &GridCoord{x: 5, y: 5}), Some(TileValue::Rock));
+        assert_eq!(loaded.tile_data(&GridCoord{x: 5, y: 5}, "hp"), Some(&TileDataValue::Int(80)));
+        assert_eq!(loaded.tile_data(&GridCoord{x: -40, y: 70}, "owner").and_then(|owner| owner.as_text()), Some("colony"));
+
+        // Same world should always save to the same bytes
+        assert_eq!(loaded.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn lazy_load_reads_partitions_near_spawn_first() {
+        let mut map = TileMap::new();
+        map.make_change(&GridCoord{x: 1, y: 1}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 1001, y: 1001}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: -500, y: 20}, &TileValue::Rock);
+        map.set_tile_data(&GridCoord{x: 1001, y: 1001}, "hp", 50);
+
+        let path = paging_directory("lazy_load").with_extension("save");
+        map.save_to_writer(std::fs::File::create(&path).unwrap()).unwrap();
+
+        let mut loaded = TileMap::load_lazy(&path, &Rectangle::new((-10, -10), (20, 20)), 32.0).unwrap();
+        assert_eq!(loaded.paged_out_count(), 2);
+        assert_eq!(loaded.sample(&GridCoord{x: 1, y: 1}), TileValue::HabModule);
+        assert_eq!(loaded.sample(&GridCoord{x: 1001, y: 1001}), TileValue::Generating);
+        assert_eq!(loaded.tile_data(&GridCoord{x: 1001, y: 1001}, "hp"), Some(&TileDataValue::Int(50)));
+
+        assert_eq!(loaded.update_paging().unwrap(), 1);
+        assert_eq!(loaded.sample(&GridCoord{x: 1001, y: 1001}), TileValue::HabModule);
+
+        // Saving copies across the partitions that were never read
+        let resaved = TileMap::from_bytes(&loaded.to_bytes().unwrap()).unwrap();
+        assert_eq!(resaved.sample(&GridCoord{x: -500, y: 20}), TileValue::Rock);
+        assert_eq!(loaded.to_bytes().unwrap(), map.to_bytes().unwrap());
+
+        // Saving over the file moves every record, the map still has the old save open and reads from that
+        loaded.snapshot().unwrap().write_to_file(&path, &SaveOptions { compress: true }).unwrap();
+        assert_eq!(loaded.sample(&GridCoord{x: -500, y: 20}), TileValue::Generating);
+        assert_eq!(loaded.update_paging().unwrap(), 1);
+        assert_eq!(loaded.sample(&GridCoord{x: -500, y: 20}), TileValue::Rock);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn compressed_saves_load_back_and_are_smaller() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 64, y: 64}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 5, y: 5}, &TileValue::HabModule);
+
+        let plain = map.to_bytes().unwrap();
+        let mut compressed: Vec<u8> = Vec::new();
+        map.save_to_writer_with(&mut compressed, &SaveOptions { compress: true }).unwrap();
+        assert!(compressed.len() * 4 < plain.len(), "Compressed to {} bytes from {}", compressed.len(), plain.len());
+
+        let loaded = TileMap::from_bytes(&compressed).unwrap();
+        assert_eq!(loaded.sample(&GridCoord{x: 5, y: 5}), TileValue::HabModule);
+        assert_eq!(loaded.sample(&GridCoord{x: 60, y: 60}), TileValue::Empty);
+        assert_eq!(loaded.to_bytes().unwrap(), plain);
+    }
+
+    #[test]
+    fn snapshots_save_on_the_worker_while_the_map_moves_on() {
+        let mut map = TileMap::new();
+        map.make_change(&GridCoord{x: 5, y: 5}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 300, y: -40}, &TileValue::Ladder);
+        let snapshot = map.snapshot().unwrap();
+        let bytes = map.to_bytes().unwrap();
+        let mut written: Vec<u8> = Vec::new();
+        snapshot.write_to(&mut written, &SaveOptions::default()).unwrap();
+        assert_eq!(written, bytes);
+
+        // Changes after the snapshot don't end up in it
+        map.make_change(&GridCoord{x: 9, y: 9}, &TileValue::Ladder);
+        let directory = paging_directory("save_worker");
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("world.sav");
+        let mut worker = SaveWorker::spawn();
+        worker.save(snapshot, path.clone(), SaveOptions { compress: true }).unwrap();
+        assert!(worker.is_saving());
+
+        let mut finished = Vec::new();
+        while finished.is_empty() {
+            finished = worker.finished();
+            std::thread::yield_now();
+        }
+        assert!(!worker.is_saving());
+        assert_eq!(finished[0].path, path);
+        assert!(finished[0].result.is_ok());
+
+        let loaded = TileMap::from_bytes(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(loaded.sample(&GridCoord{x: 5, y: 5}), TileValue::HabModule);
+        assert_eq!(loaded.sample(&GridCoord{x: 300, y: -40}), TileValue::Ladder);
+        assert_ne!(loaded.sample(&GridCoord{x: 9, y: 9}), TileValue::Ladder);
+        assert!(!path.with_extension("partial").exists());
+    }
+
+    #[test]
+    fn corrupted_saves_are_caught() {
+        let mut map = TileMap::new();
+        map.make_change(&GridCoord{x: 5, y: 5}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 500, y: 5}, &TileValue::Rock);
+        let bytes = map.to_bytes().unwrap();
+
+        // Last byte belongs to the last partition's record
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 0x01;
+        match TileMap::from_bytes(&flipped) {
+            Err(SaveError::ChecksumMismatch { partition }) => assert_eq!(partition, GridCoord{x: 496, y: 0}),
+            _ => panic!("Flipped bit went unnoticed")
+        }
+
+        match TileMap::from_bytes(&bytes[..bytes.len() - 3]) {
+            Err(SaveError::Truncated { expected_bytes, found_bytes }) => assert_eq!(expected_bytes, found_bytes + 3),
+            _ => panic!("Cut off save loaded")
+        }
+    }
+
+    #[test]
+    fn records_pointing_past_the_end_are_caught() {
+        let mut map = TileMap::new();
+        map.make_change(&GridCoord{x: 5, y: 5}, &TileValue::HabModule);
+        let bytes = map.to_bytes().unwrap();
+        let mut deserializer = serde_cbor::Deserializer::from_slice(&bytes);
+        let header = <serde_cbor::Value as serde::Deserialize>::deserialize(&mut deserializer).unwrap();
+        let records = &bytes[deserializer.byte_offset()..];
+        let field = |name: &str| serde_cbor::Value::Text(name.to_string());
+
+        // The one record moved right up against the end of u64, so offset + length overflows
+        let mut current = header.clone();
+        let mut partition = serde_cbor::Value::Null;
+        if let serde_cbor::Value::Map(fields) = &mut current {
+            if let Some(serde_cbor::Value::Array(entries)) = fields.get_mut(&field("partitions")) {
+                if let serde_cbor::Value::Map(entry) = &mut entries[0] {
+                    partition = entry[&field("partition")].clone();
+                    entry.insert(field("offset"), serde_cbor::Value::Integer(u64::MAX as i128));
+                    entry.insert(field("length"), serde_cbor::Value::Integer(2));
+                }
+            }
+        }
+        // Version 5 indexed the same record as (partition, offset, length)
+        let mut version_5 = header;
+        if let serde_cbor::Value::Map(fields) = &mut version_5 {
+            fields.insert(field("version"), serde_cbor::Value::Integer(5));
+            fields.insert(field("partitions"), serde_cbor::Value::Array(vec![
+                serde_cbor::Value::Array(vec![partition, serde_cbor::Value::Integer(u64::MAX as i128), serde_cbor::Value::Integer(2)])
+            ]));
+        }
+
+        for header in [current, version_5].iter() {
+            let mut broken = serde_cbor::to_vec(header).unwrap();
+            broken.extend_from_slice(records);
+            match TileMap::from_bytes(&broken) {
+                Err(SaveError::Truncated { expected_bytes, .. }) => assert_eq!(expected_bytes, u64::MAX),
+                _ => panic!("Record past the end of the save loaded")
+            }
+        }
+    }
+
+    // Laid out the way version 4 and older wrote saves, tile values are left raw so tests can write ones that don't exist any more
+    #[derive(serde::Serialize)]
+    struct FlatSave {
+        version: u32,
+        seed: u64,
+        generator_config: GeneratorConfig,
+        changes: Vec<(MapLayer, GridCoord, serde_cbor::Value)>,
+        tile_data: Vec<(GridCoord, String, TileDataValue)>
+    }
+
+    fn flat_save(version: u32, changes: Vec<(MapLayer, GridCoord, serde_cbor::Value)>) -> Vec<u8> {
+        let save = FlatSave { version, seed: 77, generator_config: GeneratorConfig::default(), changes, tile_data: vec![(GridCoord{x: 3, y: 3}, "hp".to_string(), TileDataValue::Int(5))] };
+        serde_cbor::to_vec(&save).unwrap()
+    }
+
+    fn tile(value: TileValue) -> serde_cbor::Value {
+        serde_cbor::value::to_value(value).unwrap()
+    }
+
+    #[test]
+    fn old_saves_are_migrated_on_load() {
+        let bytes = flat_save(4, vec![
+            (MapLayer::Structure, GridCoord{x: 1, y: 1}, tile(TileValue::HabModule)),
+            (MapLayer::Terrain, GridCoord{x: 300, y: -2}, tile(TileValue::Rock))
+        ]);
+        let loaded = TileMap::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.seed(), 77);
+        assert_eq!(loaded.sample(&GridCoord{x: 300, y: -2}), TileValue::Rock);
+        // Footprint gets filled in by the repair, since version 4 saves are old enough to have footprint bugs
+        assert_eq!(loaded.sample(&GridCoord{x: 1, y: 1}), TileValue::HabModule);
+        assert_eq!(loaded.sample(&GridCoord{x: 0, y: 2}), TileValue::Subtile(GridCoord{x: 1, y: 1}));
+        assert_eq!(loaded.tile_data(&GridCoord{x: 3, y: 3}, "hp"), Some(&TileDataValue::Int(5)));
+        assert!(loaded.validate_integrity().is_ok());
+
+        // Left the way it was saved, the missing footprint is still there to be found
+        let unrepaired = TileMap::load_from_reader_unrepaired(&bytes[..]).unwrap();
+        assert!(unrepaired.validate_integrity().is_err());
+        assert_eq!(unrepaired.sample(&GridCoord{x: 1, y: 1}), TileValue::HabModule);
+    }
+
+    // Laid out the way version 1 wrote saves, before generator configs, layers or tile data
+    #[derive(serde::Serialize)]
+    struct FirstSave {
+        version: u32,
+        seed: u64,
+        rock_density: f64,
+        changes: Vec<(GridCoord, TileValue)>
+    }
+
+    #[test]
+    fn first_version_saves_are_migrated_on_load() {
+        let save = FirstSave { version: 1, seed: 31, rock_density: 0.4, changes: vec![
+            (GridCoord{x: 1, y: 1}, TileValue::HabModule),
+            (GridCoord{x: 6, y: -3}, TileValue::Empty)
+        ] };
+        let loaded = TileMap::from_bytes(&serde_cbor::to_vec(&save).unwrap()).unwrap();
+        assert_eq!(loaded.seed(), 31);
+        assert_eq!(loaded.generator_config().rock_density, 0.4);
+        assert_eq!(loaded.generator_config().octaves, GeneratorConfig::default().octaves);
+
+        // Each change lands on its own layer, so the module sits on top of generated ground
+        assert_eq!(loaded.sample_layer(MapLayer::Terrain, &GridCoord{x: 6, y: -3}), Some(TileValue::Empty));
+        assert_eq!(loaded.sample_layer(MapLayer::Structure, &GridCoord{x: 1, y: 1}), Some(TileValue::HabModule));
+        assert_eq!(loaded.sample_layer(MapLayer::Terrain, &GridCoord{x: 1, y: 1}), Some(loaded.sample_generated(&GridCoord{x: 1, y: 1})));
+        assert_eq!(loaded.sample(&GridCoord{x: 0, y: 2}), TileValue::Subtile(GridCoord{x: 1, y: 1}));
+        assert!(loaded.tile_data_entries().is_empty());
+    }
+
+    // Pretend version 3 had a Boulder tile that's since been folded into Rock
+    struct BouldersToRock;
+
+    impl Migration for BouldersToRock {
+        fn source_version(&self) -> u32 { 3 }
+
+        fn migrate(&self, mut save: RawSave) -> Result<RawSave, SaveError> {
+            if let serde_cbor::Value::Map(fields) = &mut save.header {
+                if let Some(serde_cbor::Value::Array(changes)) = fields.get_mut(&serde_cbor::Value::Text("changes".to_string())) {
+                    for change in changes.iter_mut() {
+                        if let serde_cbor::Value::Array(parts) = change {
+                            if parts[2] == serde_cbor::Value::Text("Boulder".to_string()) {
+                                parts[2] = tile(TileValue::Rock);
+                            }
+                        }
+                    }
+                }
             }
+            save.version = 4;
+            Ok(save)
+        }
+    }
+
+    #[test]
+    fn custom_migrations_run_before_built_in_ones() {
+        let bytes = flat_save(3, vec![(MapLayer::Terrain, GridCoord{x: 2, y: 2}, serde_cbor::Value::Text("Boulder".to_string()))]);
+
+        // The built in migrations bring it up to date but have no idea what a Boulder is
+        match TileMap::from_bytes(&bytes) {
+            Err(SaveError::Encoding(_)) => {},
+            _ => panic!("Loaded a version 3 save with a tile that doesn't exist")
         }
 
-        for x in x_min..x_max {
-            for y in y_min..y_max {
-                assert_eq!(map.sample(&GridCoord{x: 100 * x, y: 100 * y}), TileValue::Error);
-            }
+        let loaded = TileMap::load_from_reader_with(&bytes[..], &[&BouldersToRock]).unwrap();
+        assert_eq!(loaded.sample(&GridCoord{x: 2, y: 2}), TileValue::Rock);
+    }
+
+    #[test]
+    fn loading_garbage_fails() {
+        match TileMap::from_bytes(&[0xff, 0x00, 0x12]) {
+            Err(SaveError::Encoding(_)) => {},
+            _ => panic!("Garbage bytes loaded as a map")
+        }
+    }
+
+    #[test]
+    fn flood_fill_stays_inside_walls() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 8, y: 8}, TileValue::Rock);
+        map.set_area(&GridCoord{x: 1, y: 1}, &GridCoord{x: 6, y: 6}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 3, y: 3}, &TileValue::HabModule);
+
+        let room = map.connected_region(&GridCoord{x: 1, y: 1});
+        assert_eq!(room.len(), 36 - 9);
+        assert!(!room.contains(&GridCoord{x: 3, y: 3}));
+        assert!(room.contains(&GridCoord{x: 6, y: 6}));
+
+        let building = map.connected_region(&GridCoord{x: 2, y: 4});
+        assert_eq!(building.len(), 9);
+        assert!(building.contains(&GridCoord{x: 3, y: 3}));
+
+        // Empty or not, anything inside the walls
+        let inside = map.flood_fill(&GridCoord{x: 1, y: 1}, |_pos: &GridCoord, value: &TileValue| *value != TileValue::Rock);
+        assert_eq!(inside.len(), 36);
+        assert!(map.flood_fill(&GridCoord{x: 0, y: 0}, |_pos: &GridCoord, value: &TileValue| *value == TileValue::Empty).is_empty());
+    }
+
+    #[test]
+    fn bounded_flood_fill_detects_leaks() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 8, y: 8}, TileValue::Rock);
+        map.set_area(&GridCoord{x: 1, y: 1}, &GridCoord{x: 6, y: 6}, TileValue::Empty);
+        assert_eq!(map.connected_region_bounded(&GridCoord{x: 1, y: 1}, 36).map(|room| room.len()), Some(36));
+        assert_eq!(map.connected_region_bounded(&GridCoord{x: 1, y: 1}, 35), None);
+
+        // Knock a hole in the wall out to a long empty corridor
+        map.set_area(&GridCoord{x: 7, y: 3}, &GridCoord{x: 200, y: 1}, TileValue::Empty);
+        map.set_area(&GridCoord{x: 7, y: 2}, &GridCoord{x: 200, y: 1}, TileValue::Rock);
+        map.set_area(&GridCoord{x: 7, y: 4}, &GridCoord{x: 200, y: 1}, TileValue::Rock);
+        assert_eq!(map.connected_region_bounded(&GridCoord{x: 1, y: 1}, 100), None);
+    }
+
+    #[test]
+    fn grid_line_hits_both_ends() {
+        let line: Vec<GridCoord> = GridLine::new(&GridCoord{x: 0, y: 0}, &GridCoord{x: 4, y: -2}).collect();
+        assert_eq!(line.len(), 5);
+        assert_eq!(line[0], GridCoord{x: 0, y: 0});
+        assert_eq!(line[4], GridCoord{x: 4, y: -2});
+
+        assert_eq!(GridLine::new(&GridCoord{x: 3, y: 3}, &GridCoord{x: 3, y: 3}).count(), 1);
+        assert_eq!(GridLine::new(&GridCoord{x: 0, y: 5}, &GridCoord{x: 0, y: -5}).count(), 11);
+    }
+
+    #[test]
+    fn blueprints_paste_buildings_whole_somewhere_else() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: -10, y: -10}, &GridCoord{x: 60, y: 30}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 2, y: 2}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 4, y: 0}, &TileValue::Rock);
+        // Cut in half by the copied rectangle, so it gets left out
+        map.make_change(&GridCoord{x: 6, y: 2}, &TileValue::HabModule);
+
+        let blueprint = map.copy_rect(&GridCoord{x: 0, y: 0}, &GridCoord{x: 6, y: 4});
+        assert_eq!(blueprint.size(), GridCoord{x: 6, y: 4});
+        assert_eq!(blueprint.structures(), &[(GridCoord{x: 2, y: 2}, TileValue::HabModule)][..]);
+        assert_eq!(blueprint.terrain_at(&GridCoord{x: 4, y: 0}), Some(TileValue::Rock));
+        assert_eq!(blueprint.terrain_at(&GridCoord{x: 6, y: 0}), None);
+
+        // Subtiles point at the pasted anchor, not the copied one
+        assert!(map.paste(&blueprint, &GridCoord{x: 30, y: 5}).is_ok());
+        assert_eq!(map.sample(&GridCoord{x: 32, y: 7}), TileValue::HabModule);
+        assert_eq!(map.sample(&GridCoord{x: 31, y: 6}), TileValue::Subtile(GridCoord{x: 32, y: 7}));
+        assert_eq!(map.sample(&GridCoord{x: 34, y: 5}), TileValue::Rock);
+        assert_eq!(map.sample(&GridCoord{x: 35, y: 7}), TileValue::Empty);
+        assert!(map.validate_integrity().is_ok());
+
+        // The whole paste is one undo step
+        assert!(map.undo());
+        assert_eq!(map.sample(&GridCoord{x: 32, y: 7}), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 34, y: 5}), TileValue::Empty);
+
+        // Landing half over an existing building would break it, so nothing is written
+        assert!(map.paste(&blueprint, &GridCoord{x: 4, y: 3}).is_err());
+        assert_eq!(map.sample(&GridCoord{x: 6, y: 2}), TileValue::HabModule);
+        assert_eq!(map.sample(&GridCoord{x: 8, y: 3}), TileValue::Empty);
+        assert!(map.validate_integrity().is_ok());
+
+        // Blueprints are plain data, so prefabs can be saved out
+        let encoded = serde_cbor::to_vec(&blueprint).unwrap();
+        assert_eq!(serde_cbor::from_slice::<Blueprint>(&encoded).unwrap(), blueprint);
+    }
+
+    #[test]
+    fn blueprints_are_checked_when_built_or_loaded() {
+        let size = GridCoord{x: 3, y: 2};
+        assert!(Blueprint::from_parts(size, vec![Some(TileValue::Rock); 6], vec![(GridCoord{x: 2, y: 1}, TileValue::HabModule)]).is_ok());
+        assert_eq!(Blueprint::from_parts(size, vec![None; 5], Vec::new()), Err(BlueprintError::TerrainSize { size, terrain_len: 5 }));
+        assert_eq!(Blueprint::from_parts(GridCoord{x: -3, y: -2}, vec![None; 6], Vec::new()), Err(BlueprintError::TerrainSize { size: GridCoord{x: -3, y: -2}, terrain_len: 6 }));
+        assert_eq!(Blueprint::from_parts(size, vec![None; 6], vec![(GridCoord{x: 3, y: 0}, TileValue::HabModule)]), Err(BlueprintError::StructureOutside(GridCoord{x: 3, y: 0})));
+
+        // Saved blueprints get the same checks, rather than loading and then panicking on the first lookup
+        #[derive(serde::Serialize)]
+        struct SavedBlueprint {
+            size: GridCoord,
+            terrain: Vec<Option<TileValue>>,
+            structures: Vec<(GridCoord, TileValue)>
         }
+        let short_terrain = serde_cbor::to_vec(&SavedBlueprint { size, terrain: vec![None; 2], structures: Vec::new() }).unwrap();
+        assert!(serde_cbor::from_slice::<Blueprint>(&short_terrain).is_err());
+        let stray_building = serde_cbor::to_vec(&SavedBlueprint { size, terrain: vec![None; 6], structures: vec![(GridCoord{x: 0, y: 9}, TileValue::HabModule)] }).unwrap();
+        assert!(serde_cbor::from_slice::<Blueprint>(&stray_building).is_err());
+        let fine = serde_cbor::to_vec(&SavedBlueprint { size, terrain: vec![Some(TileValue::Empty); 6], structures: Vec::new() }).unwrap();
+        assert_eq!(serde_cbor::from_slice::<Blueprint>(&fine).unwrap().terrain_at(&GridCoord{x: 2, y: 1}), Some(TileValue::Empty));
     }
 
     #[test]
-    fn map_write_get_back_10000() {
+    fn raycast_finds_first_blocking_tile() {
         let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: -20, y: -20}, &GridCoord{x: 60, y: 40}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 30, y: 0}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 12, y: 0}, &TileValue::Rock);
 
-        // Bounds big enough to be waaay beyond the cache
-        let x_min: i64 = -50;
-        let x_max: i64 = 50;
-        let y_min: i64 = -50;
-        let y_max: i64 = 50;
-        
-        for x in x_min..x_max {
-            for y in y_min..y_max {
-                map.make_change(&GridCoord{x, y}, &TileValue::Error);
-            }
-        }
+        // Crosses a partition edge on the way to the rock
+        assert_eq!(map.raycast(&GridCoord{x: 0, y: 0}, &GridCoord{x: 35, y: 0}), Some(GridCoord{x: 12, y: 0}));
+        assert_eq!(map.raycast(&GridCoord{x: 35, y: 0}, &GridCoord{x: 0, y: 0}), Some(GridCoord{x: 31, y: 0}));
+        assert_eq!(map.raycast(&GridCoord{x: 0, y: 5}, &GridCoord{x: 35, y: 5}), None);
+        // Looking out from inside something doesn't hit itself
+        assert_eq!(map.raycast(&GridCoord{x: 12, y: 0}, &GridCoord{x: -15, y: -10}), None);
 
-        for x in x_min..x_max {
-            for y in y_min..y_max {
-                assert_eq!(map.sample(&GridCoord{x, y}), TileValue::Error);
-            }
+        let mut uncached = TileMap::new();
+        uncached.set_caching_enabled(false);
+        uncached.set_area(&GridCoord{x: -20, y: -20}, &GridCoord{x: 60, y: 40}, TileValue::Empty);
+        uncached.make_change(&GridCoord{x: 12, y: 0}, &TileValue::Rock);
+        assert_eq!(uncached.raycast(&GridCoord{x: 0, y: 0}, &GridCoord{x: 35, y: 0}), Some(GridCoord{x: 12, y: 0}));
+    }
+
+    #[test]
+    fn rendered_regions_have_a_pixel_per_tile() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 5, y: 4}, TileValue::Rock);
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 4, y: 4}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 2, y: 2}, &TileValue::HabModule);
+
+        let image = map.render_region_to_image(&GridCoord{x: 0, y: 0}, &GridCoord{x: 5, y: 4}, default_palette);
+        assert_eq!((image.width(), image.height()), (5, 4));
+        assert_eq!(image.pixel(0, 0), Some(default_palette(&TileValue::Empty)));
+        assert_eq!(image.pixel(4, 3), Some(default_palette(&TileValue::Rock)));
+        // Every tile of the footprint, not just the anchor
+        for (x, y) in [(1, 1), (2, 2), (3, 3)].iter() {
+            assert_eq!(image.pixel(*x, *y), Some(default_palette(&TileValue::HabModule)));
         }
     }
 
     #[test]
-    fn pos_to_grid() {
+    fn minimap_pixels_take_the_most_common_tile() {
         let mut map = TileMap::new();
-        assert_eq!(map.pos_to_grid(0.0, 0.0), GridCoord{x: 0, y: 0});
-        assert_eq!(map.pos_to_grid(0.1, 0.1), GridCoord{x: 0, y: 0});
-        assert_eq!(map.pos_to_grid(-0.1, -0.1), GridCoord{x: 0, y: 0});
-        assert_eq!(map.pos_to_grid(0.6, -0.6), GridCoord{x: 0, y: 0});
-        assert_eq!(map.pos_to_grid(1.0, 1.0), GridCoord{x: 1, y: 1});
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 32, y: 32}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 5, y: 5}, &TileValue::HabModule);
+        let empty = default_palette(&TileValue::Empty);
+        let hab = default_palette(&TileValue::HabModule);
+
+        // One tile a pixel is just the rendered region
+        let center = GridCoord{x: 16, y: 16};
+        let full = map.minimap(&center, 1, &GridCoord{x: 32, y: 32});
+        assert_eq!(&full[..], map.render_region_to_image(&GridCoord{x: 0, y: 0}, &GridCoord{x: 32, y: 32}, default_palette).pixels());
+
+        // Every tile from 4,4 to 5,5 is part of the hab module
+        let halved = map.minimap(&center, 2, &GridCoord{x: 16, y: 16});
+        assert_eq!(halved.len(), 16 * 16 * 4);
+        let pixel = |pixels: &[u8], width: usize, x: usize, y: usize| pixels[((y * width + x) * 4)..((y * width + x) * 4 + 4)].to_vec();
+        assert_eq!(pixel(&halved, 16, 2, 2), hab.to_vec());
+        assert_eq!(pixel(&halved, 16, 10, 10), empty.to_vec());
+
+        // Zoomed right out the module is only a few of the tiles in its pixel
+        let zoomed_out = map.minimap(&center, 8, &GridCoord{x: 4, y: 4});
+        assert_eq!(pixel(&zoomed_out, 4, 0, 0), empty.to_vec());
+        assert!(map.minimap(&center, 8, &GridCoord{x: 0, y: 4}).is_empty());
     }
 
     #[test]
-    fn pos_to_grid_subtiles() {
+    fn minimaps_redraw_only_what_changed() {
         let mut map = TileMap::new();
-        map.make_change(&GridCoord{x: 0, y: 0}, &TileValue::Subtile(GridCoord{x: 1, y: 0}));
-        assert_eq!(map.pos_to_grid(0.0, 0.0), GridCoord{x: 1, y: 0});
+        map.set_area(&GridCoord{x: -40, y: -40}, &GridCoord{x: 80, y: 80}, TileValue::Empty);
+        let center = GridCoord{x: 3, y: -5};
+        let size = GridCoord{x: 20, y: 14};
+        let mut minimap = Minimap::new(&map, &center, 3, &size);
+        map.take_dirty_regions();
+        let dirty = map.take_dirty_regions();
+        assert!(!minimap.update(&map, &dirty));
+
+        // Across partition edges, and partly off the edge of the minimap
+        map.make_change(&GridCoord{x: 15, y: 0}, &TileValue::HabModule);
+        map.set_area(&GridCoord{x: -12, y: -4}, &GridCoord{x: 7, y: 9}, TileValue::Rock);
+        map.make_change(&GridCoord{x: -28, y: -25}, &TileValue::HabModule);
+        // Nowhere near the minimap
+        map.make_change(&GridCoord{x: 200, y: 200}, &TileValue::Rock);
+        let dirty = map.take_dirty_regions();
+        assert!(minimap.update(&map, &dirty));
+        assert_eq!(minimap.pixels(), &map.minimap(&center, 3, &size)[..]);
+        assert_ne!(minimap.pixels(), Minimap::new(&TileMap::new(), &center, 3, &size).pixels());
+
+        // Buildings that come down take their whole footprint with them
+        map.make_change(&GridCoord{x: 16, y: 1}, &TileValue::Empty);
+        let dirty = map.take_dirty_regions();
+        assert!(minimap.update(&map, &dirty));
+        assert_eq!(minimap.pixels(), &map.minimap(&center, 3, &size)[..]);
+
+        // Pixels bigger than a partition still line up
+        let mut zoomed_out = Minimap::new(&map, &center, PARTITION_SIZE as i64 + 5, &GridCoord{x: 6, y: 6});
+        map.set_area(&GridCoord{x: 20, y: 20}, &GridCoord{x: 9, y: 9}, TileValue::Rock);
+        let dirty = map.take_dirty_regions();
+        assert!(zoomed_out.update(&map, &dirty));
+        assert_eq!(zoomed_out.pixels(), &map.minimap(&center, PARTITION_SIZE as i64 + 5, &GridCoord{x: 6, y: 6})[..]);
+
+        minimap.rebuild(&map, &GridCoord{x: 100, y: 100});
+        assert_eq!(minimap.center(), GridCoord{x: 100, y: 100});
+        assert_eq!(minimap.pixels(), &map.minimap(&GridCoord{x: 100, y: 100}, 3, &size)[..]);
     }
 
     #[test]
-    fn for_each_tile_bounds_gets_all() {
-        let map = TileMap::new();
-        // Create a rectangle from (0, 0) to (10, 10)
-        let bounds = Rectangle::new_sized((10, 10));
-        let mut tiles_hit: u32 = 0;
+    fn sounds_are_muffled_by_each_tile_in_the_way() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: -20, y: -20}, &GridCoord{x: 60, y: 40}, TileValue::Empty);
+        map.set_area(&GridCoord{x: 10, y: 0}, &GridCoord{x: 2, y: 1}, TileValue::Rock);
+        let listener = GridCoord{x: 0, y: 0};
 
-        let min_val = 0;
-        let max_val = 10;
+        assert_eq!(map.sound_attenuation(&listener, &GridCoord{x: 5, y: 0}), 1.0);
+        assert_eq!(map.sound_attenuation(&listener, &GridCoord{x: 20, y: 0}), OCCLUSION_PER_TILE * OCCLUSION_PER_TILE);
+        // Digging at the wall is only muffled by what's between it and the listener
+        assert_eq!(map.sound_attenuation(&listener, &GridCoord{x: 11, y: 0}), OCCLUSION_PER_TILE);
+        assert_eq!(map.sound_attenuation(&listener, &GridCoord{x: 10, y: 0}), 1.0);
 
-        map.for_each_tile_rect(&bounds, |pos: &GridCoord, _value: &TileValue, _size: &GridCoord| {
-            tiles_hit += 1;
-            assert!(pos.x >= min_val, "Expected X greater than {}, got {}", min_val, pos.x);
-            assert!(pos.x <= max_val, "Expected X less than {}, got {}", max_val, pos.x);
-            assert!(pos.y >= min_val, "Expected Y greater than {}, got {}", min_val, pos.y);
-            assert!(pos.y <= max_val, "Expected Y less than {}, got {}", max_val, pos.y);
-        });
+        // Thick enough rock cuts it off to next to nothing rather than counting every tile
+        map.set_area(&GridCoord{x: 0, y: 5}, &GridCoord{x: 30, y: 1}, TileValue::Rock);
+        assert!(map.sound_attenuation(&GridCoord{x: -5, y: 5}, &GridCoord{x: 35, y: 5}) < 0.01);
+    }
 
-        // One row past the end should be hit, so actual bounds are 11x11
-        assert_eq!(tiles_hit, 121);
+    fn is_rock(value: &TileValue) -> bool {
+        *value == TileValue::Rock
     }
 
     #[test]
-    fn sample_from_switched_dense() {
-        let mut partition = AreaChanges::new();
+    fn neighbor_mask_sees_adjacent_rock() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 5, y: 5}, TileValue::Empty);
+        map.make_change(&GridCoord{x: 2, y: 1}, &TileValue::Rock);
+        map.make_change(&GridCoord{x: 1, y: 3}, &TileValue::Rock);
 
-        // Change 3/4 of the tiles
-        for x in 0..PARTITION_SIZE as i64 {
-            for y in 0..PARTITION_SIZE as i64 {
-                if x % 4 == 0 {
-                    partition.add_change(&GridCoord{x, y}, &TileValue::Empty);
-                }
-            }
-        }
+        let mask = map.neighbor_mask(&GridCoord{x: 2, y: 2}, is_rock);
+        assert_eq!(mask, NEIGHBOR_NORTH | NEIGHBOR_SOUTH_WEST);
+        assert_eq!(mask & NEIGHBOR_SIDES, NEIGHBOR_NORTH);
+        assert_eq!(map.neighbor_mask(&GridCoord{x: 3, y: 3}, is_rock), 0);
 
-        // Test all the tiles
-        for x in 0..PARTITION_SIZE as i64 {
-            for y in 0..PARTITION_SIZE as i64 {
-                if x % 4 == 0 {
-                    assert_eq!(partition.sample(&GridCoord{x, y}), Some(TileValue::Empty), "Tile value at ({}, {}) lost!", x, y);
-                }
-                else {
-                    assert_eq!(partition.sample(&GridCoord{x, y}), None, "Tile value at ({}, {}) invented from nothing!", x, y);
-                }
+        // Bulk version gives the same masks, including along partition edges
+        map.set_area(&GridCoord{x: 14, y: 14}, &GridCoord{x: 4, y: 4}, TileValue::Rock);
+        let top_left = GridCoord{x: -3, y: -3};
+        let size = GridCoord{x: 24, y: 22};
+        let masks = map.neighbor_masks_in_area(&top_left, &size, is_rock);
+        assert_eq!(masks.len(), 24 * 22);
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let pos = GridCoord{x: top_left.x + x, y: top_left.y + y};
+                assert_eq!(masks[(y * size.x + x) as usize], map.neighbor_mask(&pos, is_rock), "Mask differs at {:?}", pos);
             }
         }
     }
 
     #[test]
-    fn setting_large_object_works() {
+    fn clear_space_is_clear() {
         let mut map = TileMap::new();
 
-        map.make_change(&GridCoord{x: 1, y: 1}, &TileValue::HabModule);
+        map.set_area(&GridCoord{x: -3, y: -6}, &GridCoord{x: 9, y: 9}, TileValue::Rock);
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 3, y: 3}, TileValue::Empty);
 
-        for x in -2..5 {
-            for y in -2..5 {
-                let value_here = map.sample(&GridCoord{x, y});
-                if x == 1 && y == 1 {
-                    assert_eq!(value_here, TileValue::HabModule);
-                }
-                else if x >= 0 && x < 3 && y >= 0 && y < 3 {
-                    assert_eq!(value_here, TileValue::Subtile(GridCoord{x: 1, y: 1}));
-                }
-                else {
-                    assert!(is_valid_generated_tile(&value_here), 
-                        "Expected untouched tile at ({}, {}), found not that",
-                        x, y
-                    );
-                }
-            }
-        }
+        assert_eq!(map.area_clear(&GridCoord{x: 0, y: 0}, &GridCoord{x: 3, y: 3}), true, "Clear area wasn't");
+        assert_eq!(map.area_clear(&GridCoord{x: -1, y: 1}, &GridCoord{x: 3, y: 3}), false, "Unclear area wasn't");
+        assert_eq!(map.area_clear(&GridCoord{x: 1, y: 1}, &GridCoord{x: 3, y: 3}), false, "Unclear area wasn't");
+        assert_eq!(map.area_clear(&GridCoord{x: 1, y: -1}, &GridCoord{x: 3, y: 3}), false, "Unclear area wasn't");
+        assert_eq!(map.area_clear(&GridCoord{x: -1, y: 1}, &GridCoord{x: 3, y: 3}), false, "Unclear area wasn't");
     }
 
     #[test]
-    fn revoving_large_object_works() {
+    fn bounded_worlds_stop_at_the_edge() {
         let mut map = TileMap::new();
+        map.set_bounds(Some(WorldBounds { top_left: GridCoord{x: 0, y: 0}, size: GridCoord{x: 10, y: 10}, wrap: false }));
+        map.set_area(&GridCoord{x: -5, y: -5}, &GridCoord{x: 20, y: 20}, TileValue::Empty);
 
-        map.make_change(&GridCoord{x: 1, y: 1}, &TileValue::HabModule);
-        map.make_change(&GridCoord{x: 1, y: 1}, &TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 0, y: 0}), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 9, y: 9}), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: -1, y: 4}), TileValue::Void);
+        assert_eq!(map.sample(&GridCoord{x: 4, y: 10}), TileValue::Void);
+        assert_eq!(map.sample(&GridCoord{x: 1000, y: 1000}), TileValue::Void);
 
-        for x in -2..5 {
-            for y in -2..5 {
-                let value_here = map.sample(&GridCoord{x, y});
-                if x >= 0 && x < 3 && y >= 0 && y < 3 {
-                    assert_eq!(value_here, TileValue::Empty);
-                }
-                else {
-                    assert!(is_valid_generated_tile(&value_here), 
-                        "Expected untouched tile at ({}, {}), found not that",
-                        x, y
-                    );
-                }
-            }
+        // Nothing can go half over the edge
+        assert!(!map.area_clear(&GridCoord{x: 8, y: 8}, &GridCoord{x: 3, y: 3}));
+        assert_eq!(map.can_place(&GridCoord{x: 9, y: 5}, &TileValue::HabModule), Err(PlacementError::Blocked { pos: GridCoord{x: 10, y: 4}, value: TileValue::Void }));
+        map.make_change(&GridCoord{x: 9, y: 5}, &TileValue::HabModule);
+        assert_eq!(map.sample(&GridCoord{x: 9, y: 5}), TileValue::Empty);
+        map.make_change(&GridCoord{x: 10, y: 5}, &TileValue::Rock);
+        assert_eq!(map.sample(&GridCoord{x: 10, y: 5}), TileValue::Void);
+        assert!(map.validate_integrity().is_ok());
+        map.set_forbidden(&GridCoord{x: 8, y: 0}, &GridCoord{x: 4, y: 1}, true);
+
+        // Making the world infinite again shows the edits inside the old bounds, and nothing was written outside them
+        map.set_bounds(None);
+        assert_eq!(map.sample(&GridCoord{x: 9, y: 9}), TileValue::Empty);
+        assert!(map.is_forbidden(&GridCoord{x: 9, y: 0}));
+        assert!(!map.is_forbidden(&GridCoord{x: 10, y: 0}));
+    }
+
+    #[test]
+    fn partition_keys_round_trip() {
+        let size = PARTITION_SIZE as i64;
+        for partition_coord in [GridCoord{x: 0, y: 0}, GridCoord{x: -size, y: size}, GridCoord{x: 37 * size, y: -1000 * size}, GridCoord{x: (i32::MAX as i64) * size, y: (i32::MIN as i64) * size}].iter() {
+            assert_eq!(PartitionKey::new(partition_coord).partition(), *partition_coord);
         }
+
+        // Each 2x2 block of partitions gets 4 keys in a row
+        let block: Vec<PartitionKey> = [GridCoord{x: 0, y: 0}, GridCoord{x: size, y: 0}, GridCoord{x: 0, y: size}, GridCoord{x: size, y: size}]
+            .iter().map(PartitionKey::new).collect();
+        assert!(block.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(PartitionKey::new(&GridCoord{x: -size, y: -size}) < block[0]);
     }
 
     #[test]
-    fn for_each_tile_bounds_gets_right_edge_of_screen_building() {
+    fn wrapping_worlds_join_up_at_the_seams() {
         let mut map = TileMap::new();
-        map.make_change(&GridCoord{x: 5, y: 0}, &TileValue::HabModule);
-        let bounds = Rectangle::new_sized((4, 4));
-        let mut hab_hit = 0;
-        map.for_each_tile_rect(&bounds, |pos: &GridCoord, value: &TileValue, _size: &GridCoord| {
-            if *value == TileValue::HabModule {
-                assert_eq!(*pos, GridCoord{x: 5, y: 0}, "Found building in wrong place");
-                hab_hit += 1;
-            }
-        });
-        assert!(hab_hit > 0, "Failed to find building on edge of screen");
-        assert!(hab_hit == 1, "Found building too many times");
+        map.set_bounds(Some(WorldBounds { top_left: GridCoord{x: 0, y: 0}, size: GridCoord{x: 20, y: 20}, wrap: true }));
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 20, y: 20}, TileValue::Empty);
+
+        map.make_change(&GridCoord{x: 15, y: 3}, &TileValue::Rock);
+        assert_eq!(map.sample(&GridCoord{x: 35, y: 3}), TileValue::Rock);
+        assert_eq!(map.sample(&GridCoord{x: -5, y: 3}), TileValue::Rock);
+        assert_eq!(map.sample(&GridCoord{x: 15, y: -17}), TileValue::Rock);
+        map.make_change(&GridCoord{x: -1, y: 5}, &TileValue::Rock);
+        assert_eq!(map.sample(&GridCoord{x: 19, y: 5}), TileValue::Rock);
+
+        // Bulk reads agree with sample across the seam
+        let buffer = map.sample_rect(&GridCoord{x: -3, y: 3}, &GridCoord{x: 26, y: 3});
+        for (index, value) in buffer.tiles().iter().enumerate() {
+            let pos = GridCoord{x: -3 + (index as i64 % 26), y: 3 + (index as i64 / 26)};
+            assert_eq!(*value, map.sample(&pos), "Differs at {:?}", pos);
+        }
+
+        // A building over the seam shows up next to whichever side is being looked at
+        map.make_change(&GridCoord{x: 19, y: 10}, &TileValue::HabModule);
+        assert_eq!(map.sample(&GridCoord{x: 20, y: 10}), TileValue::Subtile(GridCoord{x: 19, y: 10}));
+        let near_left_edge: Vec<(GridCoord, TileValue)> = map.tiles_in_area(&GridCoord{x: 0, y: 9}, &GridCoord{x: 3, y: 3})
+            .filter(|(_, value, _)| *value == TileValue::HabModule)
+            .map(|(pos, value, _)| (pos, value))
+            .collect();
+        assert_eq!(near_left_edge, vec![(GridCoord{x: -1, y: 10}, TileValue::HabModule)]);
+        assert!(map.validate_integrity().is_ok());
+
+        // Rays go over the seam too
+        map.make_change(&GridCoord{x: 0, y: 7}, &TileValue::Rock);
+        assert_eq!(map.raycast(&GridCoord{x: 16, y: 7}, &GridCoord{x: 23, y: 7}), Some(GridCoord{x: 20, y: 7}));
+    }
+
+    // Walls off every tenth column, whatever the passes before it made
+    struct ColumnWalls;
+
+    impl GeneratorPass for ColumnWalls {
+        fn apply(&self, x: i64, _y: i64, tile: TileValue) -> TileValue {
+            if x.rem_euclid(10) == 0 { TileValue::Rock } else { tile }
+        }
+    }
+
+    fn column_walls(_seed: u64, _config: &GeneratorConfig) -> Box<dyn GeneratorPass> {
+        Box::new(ColumnWalls)
     }
+
     #[test]
-    fn for_each_tile_bounds_gets_left_edge_of_screen_building() {
-        let mut map = TileMap::new();
-        map.make_change(&GridCoord{x: -1, y: 0}, &TileValue::HabModule);
-        let bounds = Rectangle::new_sized((4, 4));
-        let mut hab_hit = 0;
-        map.for_each_tile_rect(&bounds, |pos: &GridCoord, value: &TileValue, _size: &GridCoord| {
-            if *value == TileValue::HabModule {
-                assert_eq!(*pos, GridCoord{x: -1, y: 0}, "Found building in wrong place");
-                hab_hit += 1;
-            }
-        });
-        assert!(hab_hit > 0, "Failed to find building on edge of screen");
-        assert!(hab_hit == 1, "Found building too many times");
+    fn generator_passes_run_in_order() {
+        // Too little rock density for any rock at all
+        let config = GeneratorConfig { rock_density: 5.0, ..GeneratorConfig::default() };
+        let mut map = TileMap::new_with_config(0, config);
+        assert_eq!(map.sample(&GridCoord{x: 0, y: 0}), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 1, y: 0}), TileValue::Empty);
+
+        // Passes added after the standard ones get the last word
+        let mut passes: Vec<GeneratorPassBuilder> = STANDARD_GENERATOR_PASSES.to_vec();
+        passes.push(column_walls);
+        map.set_generator_passes(passes);
+        assert_eq!(map.sample(&GridCoord{x: 0, y: 0}), TileValue::Rock);
+        assert_eq!(map.sample(&GridCoord{x: 1, y: 0}), TileValue::Empty);
+
+        // With no passes at all there's nothing to generate
+        let mut empty_map = TileMap::new_with_passes(0, config, Vec::new());
+        empty_map.make_change(&GridCoord{x: 5, y: 5}, &TileValue::Rock);
+        assert_eq!(empty_map.sample(&GridCoord{x: 0, y: 4}), TileValue::Empty);
+        assert_eq!(empty_map.sample(&GridCoord{x: 5, y: 5}), TileValue::Rock);
     }
+
     #[test]
-    fn for_each_tile_bounds_gets_top_edge_of_screen_building() {
-        let mut map = TileMap::new();
-        map.make_change(&GridCoord{x: 0, y: 5}, &TileValue::HabModule);
-        let bounds = Rectangle::new_sized((4, 4));
-        let mut hab_hit = 0;
-        map.for_each_tile_rect(&bounds, |pos: &GridCoord, value: &TileValue, _size: &GridCoord| {
-            if *value == TileValue::HabModule {
-                assert_eq!(*pos, GridCoord{x: 0, y: 5}, "Found building in wrong place");
-                hab_hit += 1;
-            }
-        });
-        assert!(hab_hit > 0, "Failed to find building on edge of screen");
-        assert!(hab_hit == 1, "Found building too many times");
+    fn spawn_area_always_generates_open() {
+        // Almost nothing but rock, with extra walls over the top that would run straight through the spawn area
+        let spawn_point = GridCoord{x: 20, y: -8};
+        let config = GeneratorConfig { rock_density: -1.9, spawn_clearing_radius: 3.0, spawn_point, ..GeneratorConfig::default() };
+        let mut passes: Vec<GeneratorPassBuilder> = STANDARD_GENERATOR_PASSES.to_vec();
+        passes.push(column_walls);
+        let mut map = TileMap::new_with_passes(0, config, passes);
+
+        assert_eq!(map.sample(&spawn_point), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 22, y: -6}), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 20, y: -4}), TileValue::Rock);
+        assert_eq!(map.sample(&GridCoord{x: 0, y: 0}), TileValue::Rock);
+
+        // Still only untouched tiles, anything built there stays
+        map.make_change(&spawn_point, &TileValue::Rock);
+        assert_eq!(map.sample(&spawn_point), TileValue::Rock);
+
+        // Moves with the config like the rest of the terrain
+        map.set_generator_config(GeneratorConfig { spawn_point: GridCoord{x: 0, y: 0}, ..config });
+        assert_eq!(map.sample(&GridCoord{x: 0, y: 0}), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 21, y: -8}), TileValue::Rock);
     }
+
     #[test]
-    fn for_each_tile_bounds_gets_bottom_edge_of_screen_building() {
-        let mut map = TileMap::new();
-        map.make_change(&GridCoord{x: 0, y: -1}, &TileValue::HabModule);
-        let bounds = Rectangle::new_sized((4, 4));
-        let mut hab_hit = 0;
-        map.for_each_tile_rect(&bounds, |pos: &GridCoord, value: &TileValue, _size: &GridCoord| {
-            if *value == TileValue::HabModule {
-                assert_eq!(*pos, GridCoord{x: 0, y: -1}, "Found building in wrong place");
-                hab_hit += 1;
+    fn points_of_interest_generate_whole_footprints() {
+        let config = GeneratorConfig { point_of_interest_chance: 0.5, ..GeneratorConfig::default() };
+        let map = TileMap::new_with_config(11, config);
+        let mut uncached = TileMap::new_with_config(11, config);
+        uncached.set_caching_enabled(false);
+
+        let mut found = 0;
+        for partition_y in -4..4 {
+            for partition_x in -4..4 {
+                let partition_coord = GridCoord{x: partition_x * PARTITION_SIZE as i64, y: partition_y * PARTITION_SIZE as i64};
+                let point = match map.point_of_interest(&partition_coord) {
+                    Some(point) => point,
+                    None => continue
+                };
+                found += 1;
+                // Same one every time it's asked for, and the same with or without the caches
+                assert_eq!(TileMap::new_with_config(11, config).point_of_interest(&partition_coord), Some(point));
+                assert_eq!(map.sample(&point.anchor), point.value);
+                assert_eq!(uncached.sample(&point.anchor), point.value);
+
+                let size = PointOfInterest::size_of(&point.value);
+                assert_eq!(map.get_tile_size(&point.value), size);
+                let top_left = point.footprint_top_left();
+                for y in top_left.y..(top_left.y + size.y) {
+                    for x in top_left.x..(top_left.x + size.x) {
+                        let pos = GridCoord{x, y};
+                        let pos_partition = GridCoord{x: x & !(PARTITION_SIZE as i64 - 1), y: y & !(PARTITION_SIZE as i64 - 1)};
+                        assert_eq!(pos_partition, partition_coord, "Footprint leaves its partition");
+                        if pos != point.anchor {
+                            assert_eq!(map.sample(&pos), TileValue::Subtile(point.anchor));
+                            assert_eq!(uncached.sample(&pos), TileValue::Subtile(point.anchor));
+                        }
+                    }
+                }
+                // Drawn once, as the whole building
+                let drawn: Vec<(GridCoord, TileValue, GridCoord)> = map.tiles_in_area(&partition_coord, &GridCoord{x: PARTITION_SIZE as i64, y: PARTITION_SIZE as i64})
+                    .filter(|(_, value, _)| *value == point.value)
+                    .collect();
+                assert_eq!(drawn, vec![(point.anchor, point.value, size)]);
             }
-        });
-        assert!(hab_hit > 0, "Failed to find building on edge of screen");
-        assert!(hab_hit == 1, "Found building too many times");
+        }
+        assert!(found > 0, "No points of interest generated");
+
+        // Off by default, so existing worlds don't change
+        assert!(TileMap::with_seed(11).point_of_interest(&GridCoord{x: 0, y: 0}).is_none());
     }
 
     #[test]
-    fn for_each_tile_bounds_gets_pos_corner_of_screen_building() {
-        let mut map = TileMap::new();
+    fn symmetric_worlds_generate_matching_halves() {
+        let center = GridCoord{x: 32, y: -16};
+        let base = GeneratorConfig { cave_density: 0.3, iron_ore_density: 0.3, symmetry_center: center, ..GeneratorConfig::default() };
 
-        map.make_change(&GridCoord{x: 5, y: 5}, &TileValue::HabModule);
-        let bounds = Rectangle::new_sized((4, 4));
+        let mirrored = TileMap::new_with_config(4, GeneratorConfig { symmetry: Symmetry::MirrorX, ..base });
+        let rotated = TileMap::new_with_config(4, GeneratorConfig { symmetry: Symmetry::Rotational, ..base });
+        for y in -40..40 {
+            for x in 0..32 {
+                let pos = GridCoord{x, y};
+                assert_eq!(mirrored.sample(&pos), mirrored.sample(&GridCoord{x: 2 * center.x - 1 - x, y}), "Mirror doesn't match at {:?}", pos);
+                let turned = GridCoord{x: 2 * center.x - 1 - x, y: 2 * center.y - 1 - y};
+                assert_eq!(rotated.sample(&pos), rotated.sample(&turned), "Rotation doesn't match at {:?}", pos);
+            }
+        }
+        // The original side is the same terrain there would be without symmetry
+        let plain = TileMap::new_with_config(4, base);
+        assert_eq!(mirrored.sample_rect(&GridCoord{x: 0, y: 0}, &GridCoord{x: 32, y: 8}).tiles(), plain.sample_rect(&GridCoord{x: 0, y: 0}, &GridCoord{x: 32, y: 8}).tiles());
 
-        let mut hab_hit = 0;
+        // Points of interest get flipped over whole, not cut up
+        let landmarks = TileMap::new_with_config(9, GeneratorConfig { point_of_interest_chance: 1.0, symmetry: Symmetry::MirrorX, ..base });
+        let original = landmarks.point_of_interest(&GridCoord{x: 16, y: 0}).unwrap();
+        let copy = landmarks.point_of_interest(&GridCoord{x: 32, y: 0}).unwrap();
+        assert_eq!(copy.value, original.value);
+        let size = PointOfInterest::size_of(&copy.value);
+        assert_eq!(copy.footprint_top_left(), GridCoord{x: 2 * center.x - original.footprint_top_left().x - size.x, y: original.footprint_top_left().y});
+        assert_eq!(landmarks.sample(&copy.anchor), copy.value);
+    }
 
-        map.for_each_tile_rect(&bounds, |pos: &GridCoord, value: &TileValue, _size: &GridCoord| {
-            if *value == TileValue::HabModule {
-                assert_eq!(*pos, GridCoord{x: 5, y: 5}, "Found building in wrong place");
-                hab_hit += 1;
+    #[test]
+    fn spawn_clearing_holds_on_the_copied_side() {
+        let center = GridCoord{x: 32, y: -16};
+        let rocky = GeneratorConfig { rock_density: -1.9, spawn_clearing_radius: 3.0, point_of_interest_chance: 1.0, symmetry_center: center, ..GeneratorConfig::default() };
+
+        for (symmetry, spawn_point, flip_y) in [(Symmetry::MirrorX, GridCoord{x: 40, y: 8}, false), (Symmetry::Rotational, GridCoord{x: 0, y: 40}, true)] {
+            let map = TileMap::new_with_config(4, GeneratorConfig { symmetry, spawn_point, ..rocky });
+            for y in -3..=3 {
+                for x in -3..=3 {
+                    if x * x + y * y > 9 { continue; }
+                    let pos = GridCoord{x: spawn_point.x + x, y: spawn_point.y + y};
+                    assert_eq!(map.sample(&pos), TileValue::Empty, "{:?} spawn not cleared at {:?}", symmetry, pos);
+                    // The start it mirrors is just as open
+                    let flipped = GridCoord{x: 2 * center.x - 1 - pos.x, y: if flip_y { 2 * center.y - 1 - pos.y } else { pos.y }};
+                    assert_eq!(map.sample(&flipped), TileValue::Empty, "{:?} matching start not cleared at {:?}", symmetry, flipped);
+                }
             }
-        });
+        }
 
-        assert!(hab_hit > 0, "Failed to find building on edge of screen");
-        assert!(hab_hit == 1, "Found building too many times");
+        // Spawning right on the mirror line clears around it on both sides, and the halves still match
+        let centered = TileMap::new_with_config(4, GeneratorConfig { symmetry: Symmetry::MirrorX, symmetry_center: GridCoord{x: 0, y: 0}, ..rocky });
+        for x in -3..=3 {
+            assert_eq!(centered.sample(&GridCoord{x, y: 0}), TileValue::Empty, "Centered spawn not cleared at x = {}", x);
+        }
+        for y in -8..8 {
+            for x in 0..8 {
+                assert_eq!(centered.sample(&GridCoord{x, y}), centered.sample(&GridCoord{x: -1 - x, y}), "Centered spawn broke the mirror at {}, {}", x, y);
+            }
+        }
     }
 
     #[test]
-    fn for_each_tile_bounds_gets_neg_corner_of_screen_building() {
-        let mut map = TileMap::new();
+    fn caves_only_ever_open_up_rock() {
+        let rocky = GeneratorConfig { rock_density: -1.5, ..GeneratorConfig::default() };
+        let solid_map = TileMap::new_with_config(3, rocky);
+        let caved_map = TileMap::new_with_config(3, GeneratorConfig { cave_density: 0.3, ..rocky });
 
-        map.make_change(&GridCoord{x: -1, y: -1}, &TileValue::HabModule);
-        let bounds = Rectangle::new_sized((4, 4));
+        let top_left = GridCoord{x: -20, y: -20};
+        let size = GridCoord{x: 40, y: 40};
+        let solid_tiles = solid_map.sample_rect(&top_left, &size);
+        let caved_tiles = caved_map.sample_rect(&top_left, &size);
+        let mut opened = 0;
+        for (solid, caved) in solid_tiles.tiles().iter().zip(caved_tiles.tiles().iter()) {
+            if solid != caved {
+                assert_eq!((*solid, *caved), (TileValue::Rock, TileValue::Empty));
+                opened += 1;
+            }
+        }
+        assert!(opened > 0, "Caves didn't open anything up");
+        assert!(caved_tiles.tiles().contains(&TileValue::Rock), "Caves opened up everything");
+    }
 
-        let mut hab_hit = 0;
+    #[test]
+    fn ore_veins_only_replace_rock() {
+        let rocky = GeneratorConfig { rock_density: 0.0, ..GeneratorConfig::default() };
+        let plain_map = TileMap::new_with_config(5, rocky);
+        let ore_map = TileMap::new_with_config(5, GeneratorConfig { iron_ore_density: 0.6, ice_density: 0.6, ..rocky });
 
-        map.for_each_tile_rect(&bounds, |pos: &GridCoord, value: &TileValue, _size: &GridCoord| {
-            if *value == TileValue::HabModule {
-                assert_eq!(*pos, GridCoord{x: -1, y: -1}, "Found building in wrong place");
-                hab_hit += 1;
+        let top_left = GridCoord{x: -64, y: -64};
+        let size = GridCoord{x: 128, y: 128};
+        let plain_tiles = plain_map.sample_rect(&top_left, &size);
+        let ore_tiles = ore_map.sample_rect(&top_left, &size);
+        for (plain, ore) in plain_tiles.tiles().iter().zip(ore_tiles.tiles().iter()) {
+            if plain != ore {
+                assert_eq!(*plain, TileValue::Rock);
+                assert!(ore.is_solid());
             }
-        });
-
-        assert!(hab_hit > 0, "Failed to find building on edge of screen");
-        assert!(hab_hit == 1, "Found building too many times");
+        }
+        assert!(ore_tiles.tiles().contains(&TileValue::IronOre), "No iron ore generated");
+        assert!(ore_tiles.tiles().contains(&TileValue::IceDeposit), "No ice generated");
+        assert!(ore_tiles.tiles().contains(&TileValue::Rock), "Ore replaced all the rock");
     }
 
     #[test]
-    fn clear_space_is_clear() {
-        let mut map = TileMap::new();
+    fn generator_functions_make_all_the_terrain() {
+        let checkerboard = |pos: GridCoord| if (pos.x + pos.y) % 2 == 0 { TileValue::Rock } else { TileValue::Empty };
+        let mut map = TileMap::with_generator(Box::new(checkerboard));
+        assert_eq!(map.sample(&GridCoord{x: 0, y: 0}), TileValue::Rock);
+        assert_eq!(map.sample(&GridCoord{x: 1, y: 0}), TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: -3, y: 5}), TileValue::Rock);
 
-        map.set_area(&GridCoord{x: -3, y: -6}, &GridCoord{x: 9, y: 9}, TileValue::Rock);
-        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 3, y: 3}, TileValue::Empty);
+        // Changes still go over the top of it
+        map.make_change(&GridCoord{x: 0, y: 0}, &TileValue::Empty);
+        assert_eq!(map.sample(&GridCoord{x: 0, y: 0}), TileValue::Empty);
 
-        assert_eq!(map.area_clear(&GridCoord{x: 0, y: 0}, &GridCoord{x: 3, y: 3}), true, "Clear area wasn't");
-        assert_eq!(map.area_clear(&GridCoord{x: -1, y: 1}, &GridCoord{x: 3, y: 3}), false, "Unclear area wasn't");
-        assert_eq!(map.area_clear(&GridCoord{x: 1, y: 1}, &GridCoord{x: 3, y: 3}), false, "Unclear area wasn't");
-        assert_eq!(map.area_clear(&GridCoord{x: 1, y: -1}, &GridCoord{x: 3, y: 3}), false, "Unclear area wasn't");
-        assert_eq!(map.area_clear(&GridCoord{x: -1, y: 1}, &GridCoord{x: 3, y: 3}), false, "Unclear area wasn't");
+        // The config has nothing to do with it
+        map.set_generator_config(GeneratorConfig { rock_density: -1.9, ..GeneratorConfig::default() });
+        assert_eq!(map.sample(&GridCoord{x: 1, y: 0}), TileValue::Empty);
+
+        // Swappable like anything else, setting passes goes back to them
+        map.set_generator(Box::new(|_| TileValue::Rock));
+        assert_eq!(map.sample(&GridCoord{x: 1, y: 0}), TileValue::Rock);
+        map.set_generator_passes(Vec::new());
+        assert_eq!(map.sample(&GridCoord{x: 2, y: 0}), TileValue::Empty);
+
+        // The background generator can share it with the main thread
+        map.set_generator(Box::new(checkerboard));
+        map.enable_background_generation();
+        let area = map.sample_rect(&GridCoord{x: 0, y: 0}, &GridCoord{x: 4, y: 1});
+        assert!(area.tiles().iter().all(|tile| matches!(tile, TileValue::Rock | TileValue::Empty | TileValue::Generating)));
     }
 }
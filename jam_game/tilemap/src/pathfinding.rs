@@ -0,0 +1,738 @@
+use crate::tile_world::{TileMap, TileValue, GridCoord, MapLayer, DirtyRegions, PARTITION_SIZE};
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::cmp::Reverse;
+use std::hash::Hash;
+
+// Step costs are kept as integers so the open list can be a plain heap, diagonals are roughly sqrt(2) times a straight step
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+// Each tile of rock a corridor has to be dug through counts as this many tiles of open ground
+const DIG_COST: u32 = 3 * STRAIGHT_COST;
+// Going up or down a ladder, slower than walking a tile
+const LADDER_COST: u32 = 2 * STRAIGHT_COST;
+
+// Tile data holding how much extra it costs to step onto a tile, in the same units as a straight step
+// Whatever spreads fire, floods or vents to vacuum writes it, paths route round the tile when it's cheaper to
+pub const HAZARD_COST_KEY: &str = "hazard_cost";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PathOptions {
+    // Diagonal steps are never allowed to cut the corner of a blocked tile
+    pub allow_diagonal: bool,
+    // Treat tiles the player has marked forbidden as blocked
+    pub avoid_forbidden: bool,
+    // Add each tile's HAZARD_COST_KEY to the cost of stepping onto it
+    pub avoid_hazards: bool,
+    // Gives up after expanding this many tiles, so asking for an unreachable goal on an infinite map still comes back
+    pub max_expanded: usize
+}
+
+impl Default for PathOptions {
+    fn default() -> PathOptions {
+        PathOptions { allow_diagonal: false, avoid_forbidden: true, avoid_hazards: true, max_expanded: 10000 }
+    }
+}
+
+// Rock and buildings block, so do tiles that haven't been generated yet since there's no telling what's there
+// Ladders are the one structure that can be walked over
+pub fn is_walkable(map: &TileMap, pos: &GridCoord, options: &PathOptions) -> bool {
+    if options.avoid_forbidden && map.is_forbidden(pos) {
+        return false;
+    }
+    matches!(map.sample(pos), TileValue::Empty | TileValue::Ladder)
+}
+
+// Nothing for tiles without a hazard, negative costs count as none since a hazard never makes a tile quicker to cross
+pub fn hazard_cost(map: &TileMap, pos: &GridCoord) -> u32 {
+    map.tile_data(pos, HAZARD_COST_KEY)
+        .and_then(|value| value.as_int())
+        .map_or(0, |cost| cost.clamp(0, u32::MAX as i64 / 2) as u32)
+}
+
+// Tiles written since the path was found anywhere in the box around it (one tile bigger on each side) might block it or open a shorter way
+// dirty comes from TileMap::take_dirty_regions
+pub fn path_is_stale(path: &[GridCoord], dirty: &DirtyRegions) -> bool {
+    if dirty.everything {
+        return true;
+    }
+    let (first, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return false
+    };
+    let (mut min, mut max) = (*first, *first);
+    for pos in rest.iter() {
+        min = GridCoord{x: min.x.min(pos.x), y: min.y.min(pos.y)};
+        max = GridCoord{x: max.x.max(pos.x), y: max.y.max(pos.y)};
+    }
+    dirty.tiles.iter().any(|pos| pos.x >= min.x - 1 && pos.x <= max.x + 1 && pos.y >= min.y - 1 && pos.y <= max.y + 1)
+}
+
+// A* over walkable tiles, the path includes both start and goal
+// The start tile doesn't have to be walkable (a colonist standing in a doorway that just got built over can still walk out)
+// On a wrapping world the path heads for whichever copy of goal is nearest, so it can run over the seam and end outside the bounds
+pub fn find_path(map: &TileMap, start: &GridCoord, goal: &GridCoord, options: &PathOptions) -> Option<Vec<GridCoord>> {
+    let goal = &map.nearest_copy(goal, start);
+    if start == goal {
+        return Some(vec![*start]);
+    }
+    if !is_walkable(map, goal, options) {
+        return None;
+    }
+
+    // Heap entries are (estimated total, cost so far, tile), Reverse makes it pop the cheapest first
+    let mut open: BinaryHeap<Reverse<(u32, u32, i64, i64)>> = BinaryHeap::new();
+    let mut best_cost: HashMap<GridCoord, u32> = HashMap::new();
+    let mut came_from: HashMap<GridCoord, GridCoord> = HashMap::new();
+    let mut expanded = 0;
+
+    open.push(Reverse((heuristic(start, goal, options), 0, start.x, start.y)));
+    best_cost.insert(*start, 0);
+
+    while let Some(Reverse((_, cost, x, y))) = open.pop() {
+        let current = GridCoord{x, y};
+        if current == *goal {
+            return Some(rebuild_path(&came_from, goal));
+        }
+        // Already reached this tile more cheaply since this entry was pushed
+        if let Some(best) = best_cost.get(&current) {
+            if *best < cost { continue; }
+        }
+
+        expanded += 1;
+        if expanded > options.max_expanded {
+            return None;
+        }
+
+        for (neighbour, step_cost) in neighbours(map, &current, options) {
+            let neighbour_cost = cost + step_cost;
+            let improves = match best_cost.get(&neighbour) {
+                Some(best) => neighbour_cost < *best,
+                None => true
+            };
+            if improves {
+                best_cost.insert(neighbour, neighbour_cost);
+                came_from.insert(neighbour, current);
+                open.push(Reverse((neighbour_cost + heuristic(&neighbour, goal, options), neighbour_cost, neighbour.x, neighbour.y)));
+            }
+        }
+    }
+
+    None
+}
+
+// A tile on one of a stack of maps, level 0 is the top one (the surface)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LevelCoord {
+    pub level: usize,
+    pub pos: GridCoord
+}
+
+// Levels next to each other are joined wherever both have a Ladder on the same tile
+pub fn ladder_destinations(levels: &[TileMap], from: &LevelCoord) -> Vec<LevelCoord> {
+    let has_ladder = |level: usize| levels[level].sample(&from.pos) == TileValue::Ladder;
+    let mut result: Vec<LevelCoord> = Vec::with_capacity(2);
+    if from.level >= levels.len() || !has_ladder(from.level) {
+        return result;
+    }
+    if from.level > 0 && has_ladder(from.level - 1) {
+        result.push(LevelCoord { level: from.level - 1, pos: from.pos });
+    }
+    if from.level + 1 < levels.len() && has_ladder(from.level + 1) {
+        result.push(LevelCoord { level: from.level + 1, pos: from.pos });
+    }
+    result
+}
+
+// Same as find_path's heap entries with the level before the tile
+type LevelOpenEntry = Reverse<(u32, u32, usize, i64, i64)>;
+
+// find_path over a stack of levels, climbing ladders to get from one to another
+// max_expanded counts tiles on every level together
+pub fn find_path_across_levels(levels: &[TileMap], start: &LevelCoord, goal: &LevelCoord, options: &PathOptions) -> Option<Vec<LevelCoord>> {
+    if start.level >= levels.len() || goal.level >= levels.len() {
+        return None;
+    }
+    let goal = &LevelCoord { level: goal.level, pos: levels[goal.level].nearest_copy(&goal.pos, &start.pos) };
+    if start == goal {
+        return Some(vec![*start]);
+    }
+    if !is_walkable(&levels[goal.level], &goal.pos, options) {
+        return None;
+    }
+
+    // Every level between here and the goal takes at least one ladder, so this still never overestimates
+    let estimate = |from: &LevelCoord| heuristic(&from.pos, &goal.pos, options) + LADDER_COST * (from.level as i64 - goal.level as i64).unsigned_abs() as u32;
+
+    let mut open: BinaryHeap<LevelOpenEntry> = BinaryHeap::new();
+    let mut best_cost: HashMap<LevelCoord, u32> = HashMap::new();
+    let mut came_from: HashMap<LevelCoord, LevelCoord> = HashMap::new();
+    let mut expanded = 0;
+
+    open.push(Reverse((estimate(start), 0, start.level, start.pos.x, start.pos.y)));
+    best_cost.insert(*start, 0);
+
+    while let Some(Reverse((_, cost, level, x, y))) = open.pop() {
+        let current = LevelCoord { level, pos: GridCoord{x, y} };
+        if current == *goal {
+            return Some(rebuild_path(&came_from, goal));
+        }
+        if let Some(best) = best_cost.get(&current) {
+            if *best < cost { continue; }
+        }
+
+        expanded += 1;
+        if expanded > options.max_expanded {
+            return None;
+        }
+
+        let walks = neighbours(&levels[level], &current.pos, options).into_iter()
+            .map(|(pos, step_cost)| (LevelCoord { level, pos }, step_cost));
+        let climbs = ladder_destinations(levels, &current).into_iter()
+            .filter(|destination| !options.avoid_forbidden || !levels[destination.level].is_forbidden(&destination.pos))
+            .map(|destination| (destination, LADDER_COST + step_hazard_cost(&levels[destination.level], &destination.pos, options)));
+        for (neighbour, step_cost) in walks.chain(climbs) {
+            let neighbour_cost = cost + step_cost;
+            let improves = match best_cost.get(&neighbour) {
+                Some(best) => neighbour_cost < *best,
+                None => true
+            };
+            if improves {
+                best_cost.insert(neighbour, neighbour_cost);
+                came_from.insert(neighbour, current);
+                open.push(Reverse((neighbour_cost + estimate(&neighbour), neighbour_cost, neighbour.level, neighbour.pos.x, neighbour.pos.y)));
+            }
+        }
+    }
+
+    None
+}
+
+// Tiles a corridor between two buildings would run over, in order from the first building to the second
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorridorPlan {
+    pub tiles: Vec<GridCoord>,
+    // Open ground counts STRAIGHT_COST per tile, rock that has to be dug out counts DIG_COST
+    pub cost: u32,
+    pub rock_tiles: usize
+}
+
+// Open ground or rock (and ore) that can be dug out, never buildings, forbidden zones or tiles that haven't been generated yet
+pub fn is_buildable(map: &TileMap, pos: &GridCoord) -> bool {
+    if map.is_forbidden(pos) {
+        return false;
+    }
+    let value = map.sample(pos);
+    value == TileValue::Empty || value.is_solid()
+}
+
+fn build_cost(map: &TileMap, pos: &GridCoord) -> u32 {
+    if map.sample(pos).is_solid() { DIG_COST } else { STRAIGHT_COST }
+}
+
+// Cheapest run of buildable tiles from beside one building to beside another, preferring to go round rock rather than dig
+// from and to can be any tile of the buildings, None if they're the same building or nothing is found within max_expanded tiles
+pub fn plan_corridor(map: &TileMap, from: &GridCoord, to: &GridCoord, max_expanded: usize) -> Option<CorridorPlan> {
+    let (from_top_left, from_size) = building_footprint(map, from)?;
+    let (to_top_left, to_size) = building_footprint(map, to)?;
+    // Same as find_path, the nearest copy of the second building on a wrapping world
+    let to_top_left = map.nearest_copy(&to_top_left, &from_top_left);
+    if from_top_left == to_top_left {
+        return None;
+    }
+
+    let goals = footprint_border(&to_top_left, &to_size);
+
+    // Same shape of search as find_path, except every tile around the first building is a start
+    let mut open: BinaryHeap<Reverse<(u32, u32, i64, i64)>> = BinaryHeap::new();
+    let mut best_cost: HashMap<GridCoord, u32> = HashMap::new();
+    let mut came_from: HashMap<GridCoord, GridCoord> = HashMap::new();
+    let mut expanded = 0;
+
+    for start in footprint_border(&from_top_left, &from_size).iter().filter(|pos| is_buildable(map, pos)) {
+        let cost = build_cost(map, start);
+        best_cost.insert(*start, cost);
+        open.push(Reverse((cost + corridor_heuristic(start, &to_top_left, &to_size), cost, start.x, start.y)));
+    }
+
+    while let Some(Reverse((_, cost, x, y))) = open.pop() {
+        let current = GridCoord{x, y};
+        if goals.contains(&current) {
+            let tiles = rebuild_path(&came_from, &current);
+            let rock_tiles = tiles.iter().filter(|pos| map.sample(pos).is_solid()).count();
+            return Some(CorridorPlan { tiles, cost, rock_tiles });
+        }
+        if let Some(best) = best_cost.get(&current) {
+            if *best < cost { continue; }
+        }
+
+        expanded += 1;
+        if expanded > max_expanded {
+            return None;
+        }
+
+        for (x, y) in [(0, -1), (1, 0), (0, 1), (-1, 0)].iter() {
+            let neighbour = GridCoord{x: current.x + x, y: current.y + y};
+            if !is_buildable(map, &neighbour) { continue; }
+
+            let neighbour_cost = cost + build_cost(map, &neighbour);
+            let improves = match best_cost.get(&neighbour) {
+                Some(best) => neighbour_cost < *best,
+                None => true
+            };
+            if improves {
+                best_cost.insert(neighbour, neighbour_cost);
+                came_from.insert(neighbour, current);
+                open.push(Reverse((neighbour_cost + corridor_heuristic(&neighbour, &to_top_left, &to_size), neighbour_cost, neighbour.x, neighbour.y)));
+            }
+        }
+    }
+
+    None
+}
+
+// Top left and size of the building covering pos, None if there's no building there
+fn building_footprint(map: &TileMap, pos: &GridCoord) -> Option<(GridCoord, GridCoord)> {
+    let anchor = match map.sample(pos) {
+        TileValue::Subtile(anchor) => anchor,
+        _ => *pos
+    };
+    let value = map.sample(&anchor);
+    if MapLayer::for_value(&value) != MapLayer::Structure {
+        return None;
+    }
+
+    let size = map.get_tile_size(&value);
+    Some((GridCoord{x: anchor.x - (size.x / 2), y: anchor.y - (size.y / 2)}, size))
+}
+
+// The tiles straight above, below and to either side of a footprint, corners left out since corridors join on a side
+fn footprint_border(top_left: &GridCoord, size: &GridCoord) -> Vec<GridCoord> {
+    let mut border = Vec::with_capacity(2 * (size.x + size.y) as usize);
+    for x in top_left.x..(top_left.x + size.x) {
+        border.push(GridCoord{x, y: top_left.y - 1});
+        border.push(GridCoord{x, y: top_left.y + size.y});
+    }
+    for y in top_left.y..(top_left.y + size.y) {
+        border.push(GridCoord{x: top_left.x - 1, y});
+        border.push(GridCoord{x: top_left.x + size.x, y});
+    }
+    border
+}
+
+// Steps to the nearest tile beside the footprint if it were all open ground, so never an overestimate
+fn corridor_heuristic(from: &GridCoord, top_left: &GridCoord, size: &GridCoord) -> u32 {
+    let dx = (top_left.x - from.x).max(from.x - (top_left.x + size.x - 1)).max(0);
+    let dy = (top_left.y - from.y).max(from.y - (top_left.y + size.y - 1)).max(0);
+    ((dx + dy - 1).max(0) * STRAIGHT_COST as i64) as u32
+}
+
+// Steps from every reached tile to the nearest goal, stored a partition at a time
+// Many agents heading for the nearest of something can all read off one of these instead of each running find_path
+pub struct DistanceField {
+    chunks: HashMap<GridCoord, Vec<u16>>,
+    max_range: u16
+}
+
+// Marks tiles in a chunk that the fill never got to
+const UNREACHED: u16 = u16::MAX;
+
+impl DistanceField {
+    fn new(max_range: u16) -> DistanceField {
+        DistanceField { chunks: HashMap::new(), max_range }
+    }
+
+    fn chunk_index(pos: &GridCoord) -> (GridCoord, usize) {
+        let partition_mask = !(PARTITION_SIZE as i64 - 1);
+        let chunk_coord = GridCoord{x: pos.x & partition_mask, y: pos.y & partition_mask};
+        let index = (pos.x & (PARTITION_SIZE as i64 - 1)) as usize + (PARTITION_SIZE as usize) * ((pos.y & (PARTITION_SIZE as i64 - 1)) as usize);
+        (chunk_coord, index)
+    }
+
+    fn set(&mut self, pos: &GridCoord, distance: u16) {
+        let (chunk_coord, index) = DistanceField::chunk_index(pos);
+        let chunk = self.chunks.entry(chunk_coord).or_insert_with(|| vec![UNREACHED; (PARTITION_SIZE as usize) * (PARTITION_SIZE as usize)]);
+        chunk[index] = distance;
+    }
+
+    // None if pos can't reach a goal within max_range
+    pub fn distance(&self, pos: &GridCoord) -> Option<u16> {
+        let (chunk_coord, index) = DistanceField::chunk_index(pos);
+        match self.chunks.get(&chunk_coord).map(|chunk| chunk[index]) {
+            Some(UNREACHED) | None => None,
+            Some(distance) => Some(distance)
+        }
+    }
+
+    // Neighbour of pos that is one step closer to a goal, None on a goal or anywhere out of range
+    pub fn next_step(&self, pos: &GridCoord) -> Option<GridCoord> {
+        let distance = self.distance(pos)?;
+        if distance == 0 { return None; }
+        [(0, -1), (1, 0), (0, 1), (-1, 0)].iter()
+            .map(|(x, y)| GridCoord{x: pos.x + x, y: pos.y + y})
+            .find(|neighbour| self.distance(neighbour) == Some(distance - 1))
+    }
+
+    pub fn max_range(&self) -> u16 {
+        self.max_range
+    }
+
+    // Partitions with at least one reached tile, 512 bytes each
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    // True if anything changed in or next to a partition the field reached, so it needs filling again
+    // Partitions beside the reached ones count because a wall coming down there can let the fill further out
+    pub fn is_stale(&self, dirty: &DirtyRegions) -> bool {
+        if dirty.everything {
+            return true;
+        }
+        let size = PARTITION_SIZE as i64;
+        dirty.partitions.iter().any(|partition| {
+            [(0, 0), (0, -size), (size, 0), (0, size), (-size, 0)].iter()
+                .any(|(x, y)| self.chunks.contains_key(&GridCoord{x: partition.x + x, y: partition.y + y}))
+        })
+    }
+}
+
+impl TileMap {
+    // Breadth first fill out from all the goals at once, over the same walkable tiles as find_path with default options
+    // Distances are steps, hazards don't lengthen them, but a field is stale as soon as a hazard near it changes
+    // Goals themselves don't need to be walkable, so a building can be a goal that agents path up next to
+    // Stops max_range steps out, which is capped just short of u16::MAX
+    pub fn distance_field(&self, goals: &[GridCoord], max_range: u16) -> DistanceField {
+        let max_range = max_range.min(UNREACHED - 1);
+        let options = PathOptions::default();
+        let mut field = DistanceField::new(max_range);
+        let mut to_visit: VecDeque<(GridCoord, u16)> = VecDeque::new();
+
+        for goal in goals.iter() {
+            if field.distance(goal).is_none() {
+                field.set(goal, 0);
+                to_visit.push_back((*goal, 0));
+            }
+        }
+
+        while let Some((pos, distance)) = to_visit.pop_front() {
+            if distance >= max_range { continue; }
+            for (neighbour, _) in neighbours(self, &pos, &options) {
+                if field.distance(&neighbour).is_none() {
+                    field.set(&neighbour, distance + 1);
+                    to_visit.push_back((neighbour, distance + 1));
+                }
+            }
+        }
+
+        field
+    }
+}
+
+fn neighbours(map: &TileMap, pos: &GridCoord, options: &PathOptions) -> Vec<(GridCoord, u32)> {
+    let mut result: Vec<(GridCoord, u32)> = Vec::with_capacity(8);
+    let walkable = |x: i64, y: i64| is_walkable(map, &GridCoord{x: pos.x + x, y: pos.y + y}, options);
+
+    for (x, y) in [(0, -1), (1, 0), (0, 1), (-1, 0)].iter() {
+        if walkable(*x, *y) {
+            let neighbour = GridCoord{x: pos.x + x, y: pos.y + y};
+            result.push((neighbour, STRAIGHT_COST + step_hazard_cost(map, &neighbour, options)));
+        }
+    }
+
+    if options.allow_diagonal {
+        for (x, y) in [(1, -1), (1, 1), (-1, 1), (-1, -1)].iter() {
+            // Both tiles beside the diagonal have to be open, otherwise the path squeezes between two touching walls
+            if walkable(*x, *y) && walkable(*x, 0) && walkable(0, *y) {
+                let neighbour = GridCoord{x: pos.x + x, y: pos.y + y};
+                result.push((neighbour, DIAGONAL_COST + step_hazard_cost(map, &neighbour, options)));
+            }
+        }
+    }
+
+    result
+}
+
+fn step_hazard_cost(map: &TileMap, pos: &GridCoord, options: &PathOptions) -> u32 {
+    if options.avoid_hazards { hazard_cost(map, pos) } else { 0 }
+}
+
+// Never overestimates, so the first path found to the goal is a shortest one
+fn heuristic(from: &GridCoord, to: &GridCoord, options: &PathOptions) -> u32 {
+    let dx = (from.x - to.x).abs();
+    let dy = (from.y - to.y).abs();
+    let estimate = if options.allow_diagonal {
+        let diagonal = dx.min(dy);
+        DIAGONAL_COST as i64 * diagonal + STRAIGHT_COST as i64 * (dx.max(dy) - diagonal)
+    }
+    else {
+        STRAIGHT_COST as i64 * (dx + dy)
+    };
+    estimate as u32
+}
+
+fn rebuild_path<T: Copy + Eq + Hash>(came_from: &HashMap<T, T>, goal: &T) -> Vec<T> {
+    let mut path = vec![*goal];
+    let mut current = *goal;
+    while let Some(previous) = came_from.get(&current) {
+        path.push(*previous);
+        current = *previous;
+    }
+    path.reverse();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tile_world::{TileMap, TileValue, GridCoord, WorldBounds};
+    use crate::pathfinding::{find_path, find_path_across_levels, plan_corridor, path_is_stale, LevelCoord, PathOptions, HAZARD_COST_KEY};
+
+    fn open_map() -> TileMap {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 10, y: 10}, TileValue::Empty);
+        // Wall everything else off so searches can't wander into generated terrain
+        map.set_area(&GridCoord{x: -1, y: -1}, &GridCoord{x: 12, y: 1}, TileValue::Rock);
+        map.set_area(&GridCoord{x: -1, y: 10}, &GridCoord{x: 12, y: 1}, TileValue::Rock);
+        map.set_area(&GridCoord{x: -1, y: 0}, &GridCoord{x: 1, y: 10}, TileValue::Rock);
+        map.set_area(&GridCoord{x: 10, y: 0}, &GridCoord{x: 1, y: 10}, TileValue::Rock);
+        map
+    }
+
+    fn assert_connected(path: &[GridCoord]) {
+        for step in path.windows(2) {
+            assert_eq!((step[0].x - step[1].x).abs() + (step[0].y - step[1].y).abs(), 1, "Path jumps from {:?} to {:?}", step[0], step[1]);
+        }
+    }
+
+    #[test]
+    fn straight_path_on_open_ground() {
+        let map = open_map();
+        let path = find_path(&map, &GridCoord{x: 1, y: 1}, &GridCoord{x: 6, y: 1}, &PathOptions::default()).unwrap();
+        assert_eq!(path.len(), 6);
+        assert_eq!(path[0], GridCoord{x: 1, y: 1});
+        assert_eq!(path[5], GridCoord{x: 6, y: 1});
+        assert_connected(&path);
+    }
+
+    #[test]
+    fn path_goes_around_rock_and_buildings() {
+        let mut map = open_map();
+        map.set_area(&GridCoord{x: 4, y: 0}, &GridCoord{x: 1, y: 8}, TileValue::Rock);
+        map.make_change(&GridCoord{x: 7, y: 8}, &TileValue::HabModule);
+
+        let path = find_path(&map, &GridCoord{x: 1, y: 1}, &GridCoord{x: 8, y: 1}, &PathOptions::default()).unwrap();
+        assert_connected(&path);
+        for pos in path.iter() {
+            assert_eq!(map.sample(pos), TileValue::Empty, "Path goes through {:?}", pos);
+        }
+        // Down to the only gap at the bottom of the wall and back up, squeezing between the wall and the hab module
+        assert!(path.contains(&GridCoord{x: 4, y: 8}));
+        assert!(path.contains(&GridCoord{x: 5, y: 8}));
+    }
+
+    #[test]
+    fn no_path_to_sealed_off_goal() {
+        let mut map = open_map();
+        map.set_area(&GridCoord{x: 5, y: 0}, &GridCoord{x: 1, y: 10}, TileValue::Rock);
+
+        assert_eq!(find_path(&map, &GridCoord{x: 1, y: 1}, &GridCoord{x: 8, y: 8}, &PathOptions::default()), None);
+        assert_eq!(find_path(&map, &GridCoord{x: 1, y: 1}, &GridCoord{x: 5, y: 5}, &PathOptions::default()), None);
+    }
+
+    #[test]
+    fn forbidden_zones_are_avoided_unless_asked_not_to() {
+        let mut map = open_map();
+        map.set_forbidden(&GridCoord{x: 3, y: 0}, &GridCoord{x: 1, y: 9}, true);
+
+        let path = find_path(&map, &GridCoord{x: 1, y: 1}, &GridCoord{x: 6, y: 1}, &PathOptions::default()).unwrap();
+        assert!(path.contains(&GridCoord{x: 3, y: 9}));
+
+        let ignore_forbidden = PathOptions { avoid_forbidden: false, ..PathOptions::default() };
+        assert_eq!(find_path(&map, &GridCoord{x: 1, y: 1}, &GridCoord{x: 6, y: 1}, &ignore_forbidden).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn hazards_are_routed_round_when_its_cheaper() {
+        let mut map = open_map();
+        let start = GridCoord{x: 1, y: 1};
+        let goal = GridCoord{x: 6, y: 1};
+        // A fire across the top row, only going round the end of it is cheaper than walking through
+        for x in 2..6 {
+            map.set_tile_data(&GridCoord{x, y: 1}, HAZARD_COST_KEY, 100);
+        }
+
+        let path = find_path(&map, &start, &goal, &PathOptions::default()).unwrap();
+        assert_connected(&path);
+        assert!(path.iter().all(|pos| map.tile_data(pos, HAZARD_COST_KEY).is_none()), "Walked into the fire: {:?}", path);
+
+        let ignore_hazards = PathOptions { avoid_hazards: false, ..PathOptions::default() };
+        assert_eq!(find_path(&map, &start, &goal, &ignore_hazards).unwrap().len(), 6);
+
+        // A hazard too cheap to be worth the detour is walked straight through
+        for x in 2..6 {
+            map.set_tile_data(&GridCoord{x, y: 1}, HAZARD_COST_KEY, 1);
+        }
+        assert_eq!(find_path(&map, &start, &goal, &PathOptions::default()).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn cached_paths_and_fields_go_stale_when_something_changes_near_them() {
+        let mut map = open_map();
+        let path = find_path(&map, &GridCoord{x: 1, y: 1}, &GridCoord{x: 6, y: 1}, &PathOptions::default()).unwrap();
+        let field = map.distance_field(&[GridCoord{x: 1, y: 1}], 100);
+        map.take_dirty_regions();
+
+        // Well away from both
+        map.set_tile_data(&GridCoord{x: 500, y: 500}, HAZARD_COST_KEY, 50);
+        let dirty = map.take_dirty_regions();
+        assert!(!path_is_stale(&path, &dirty));
+        assert!(!field.is_stale(&dirty));
+
+        // Flooding right beside the path
+        map.set_tile_data(&GridCoord{x: 4, y: 2}, HAZARD_COST_KEY, 50);
+        let dirty = map.take_dirty_regions();
+        assert!(path_is_stale(&path, &dirty));
+        assert!(field.is_stale(&dirty));
+
+        // A zone forbidden in the room but out of the path's way only affects the field
+        map.set_forbidden(&GridCoord{x: 8, y: 8}, &GridCoord{x: 1, y: 1}, true);
+        let dirty = map.take_dirty_regions();
+        assert!(!path_is_stale(&path, &dirty));
+        assert!(field.is_stale(&dirty));
+
+        map.remove_tile_data(&GridCoord{x: 4, y: 2}, HAZARD_COST_KEY);
+        assert!(path_is_stale(&path, &map.take_dirty_regions()));
+        // Nothing there to remove, so nothing changed
+        map.remove_tile_data(&GridCoord{x: 4, y: 2}, HAZARD_COST_KEY);
+        assert!(map.take_dirty_regions().is_empty());
+    }
+
+    #[test]
+    fn diagonal_paths_are_shorter_but_dont_cut_corners() {
+        let mut map = open_map();
+        let diagonal = PathOptions { allow_diagonal: true, ..PathOptions::default() };
+        assert_eq!(find_path(&map, &GridCoord{x: 1, y: 1}, &GridCoord{x: 5, y: 5}, &diagonal).unwrap().len(), 5);
+
+        map.make_change(&GridCoord{x: 2, y: 1}, &TileValue::Rock);
+        map.make_change(&GridCoord{x: 1, y: 2}, &TileValue::Rock);
+        let path = find_path(&map, &GridCoord{x: 1, y: 1}, &GridCoord{x: 2, y: 2}, &diagonal).unwrap();
+        assert!(path.len() > 2, "Cut between two rocks: {:?}", path);
+    }
+
+    #[test]
+    fn distance_field_leads_to_nearest_goal() {
+        let mut map = open_map();
+        map.set_area(&GridCoord{x: 4, y: 0}, &GridCoord{x: 1, y: 8}, TileValue::Rock);
+        let goals = [GridCoord{x: 1, y: 1}, GridCoord{x: 8, y: 1}];
+        let field = map.distance_field(&goals, 100);
+
+        assert_eq!(field.distance(&GridCoord{x: 1, y: 1}), Some(0));
+        assert_eq!(field.distance(&GridCoord{x: 3, y: 1}), Some(2));
+        assert_eq!(field.distance(&GridCoord{x: 5, y: 1}), Some(3));
+        assert_eq!(field.distance(&GridCoord{x: 4, y: 4}), None);
+        assert_eq!(field.distance(&GridCoord{x: 40, y: 40}), None);
+        // Everything in the walled off room fits in one partition
+        assert_eq!(field.chunk_count(), 1);
+
+        // Following it downhill gives a path as short as find_path's
+        let mut pos = GridCoord{x: 4, y: 9};
+        let mut steps = 0;
+        while let Some(next) = field.next_step(&pos) {
+            pos = next;
+            steps += 1;
+        }
+        assert!(goals.contains(&pos));
+        assert_eq!(steps, find_path(&map, &GridCoord{x: 4, y: 9}, &pos, &PathOptions::default()).unwrap().len() - 1);
+    }
+
+    #[test]
+    fn distance_field_stops_at_max_range() {
+        let map = open_map();
+        let field = map.distance_field(&[GridCoord{x: 5, y: 5}], 2);
+        assert_eq!(field.distance(&GridCoord{x: 5, y: 7}), Some(2));
+        assert_eq!(field.distance(&GridCoord{x: 5, y: 8}), None);
+        assert_eq!(field.next_step(&GridCoord{x: 5, y: 8}), None);
+    }
+
+    #[test]
+    fn search_gives_up_past_limit() {
+        let map = open_map();
+        let limited = PathOptions { max_expanded: 3, ..PathOptions::default() };
+        assert_eq!(find_path(&map, &GridCoord{x: 1, y: 1}, &GridCoord{x: 8, y: 8}, &limited), None);
+    }
+
+    #[test]
+    fn corridor_runs_between_buildings_and_avoids_digging() {
+        let mut map = open_map();
+        map.make_change(&GridCoord{x: 2, y: 2}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 8, y: 2}, &TileValue::HabModule);
+        // Straight across is through rock, the way round is longer but all open ground
+        map.set_area(&GridCoord{x: 4, y: 0}, &GridCoord{x: 3, y: 5}, TileValue::Rock);
+
+        // Any tile of either building will do
+        let plan = plan_corridor(&map, &GridCoord{x: 1, y: 3}, &GridCoord{x: 8, y: 2}, 1000).unwrap();
+        assert_connected(&plan.tiles);
+        assert_eq!(plan.rock_tiles, 0);
+        assert_eq!(plan.tiles.first(), Some(&GridCoord{x: 3, y: 4}));
+        assert_eq!(plan.tiles.last(), Some(&GridCoord{x: 7, y: 4}));
+        assert_eq!(plan.cost, 70);
+
+        // Sealing the way round means digging through
+        map.set_area(&GridCoord{x: 4, y: 5}, &GridCoord{x: 3, y: 5}, TileValue::Rock);
+        let plan = plan_corridor(&map, &GridCoord{x: 2, y: 2}, &GridCoord{x: 8, y: 2}, 1000).unwrap();
+        assert_connected(&plan.tiles);
+        assert_eq!(plan.rock_tiles, 3);
+        assert_eq!(plan.tiles.len(), 3);
+        assert_eq!(plan.cost, 90);
+    }
+
+    #[test]
+    fn corridor_needs_two_different_buildings() {
+        let mut map = open_map();
+        map.make_change(&GridCoord{x: 2, y: 2}, &TileValue::HabModule);
+        assert_eq!(plan_corridor(&map, &GridCoord{x: 2, y: 2}, &GridCoord{x: 1, y: 1}, 1000), None);
+        assert_eq!(plan_corridor(&map, &GridCoord{x: 2, y: 2}, &GridCoord{x: 7, y: 7}, 1000), None);
+    }
+
+    #[test]
+    fn paths_go_over_the_seam_of_wrapping_worlds() {
+        let mut map = TileMap::new();
+        map.set_bounds(Some(WorldBounds { top_left: GridCoord{x: 0, y: 0}, size: GridCoord{x: 20, y: 8}, wrap: true }));
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 20, y: 8}, TileValue::Empty);
+
+        // Five steps left over the seam rather than fifteen right
+        let path = find_path(&map, &GridCoord{x: 2, y: 3}, &GridCoord{x: 17, y: 3}, &PathOptions::default()).unwrap();
+        assert_eq!(path.len(), 6);
+        assert_eq!(path[5], GridCoord{x: -3, y: 3});
+        assert_connected(&path);
+    }
+
+    #[test]
+    fn paths_climb_ladders_between_levels() {
+        let mut surface = open_map();
+        let mut cavern = open_map();
+        // Only way across the surface is down into the cavern and back up
+        surface.set_area(&GridCoord{x: 5, y: 0}, &GridCoord{x: 1, y: 10}, TileValue::Rock);
+        for level in [&mut surface, &mut cavern].iter_mut() {
+            level.make_change(&GridCoord{x: 3, y: 5}, &TileValue::Ladder);
+            level.make_change(&GridCoord{x: 7, y: 5}, &TileValue::Ladder);
+        }
+        let levels = vec![surface, cavern];
+
+        let start = LevelCoord { level: 0, pos: GridCoord{x: 1, y: 5} };
+        let goal = LevelCoord { level: 0, pos: GridCoord{x: 8, y: 5} };
+        let path = find_path_across_levels(&levels, &start, &goal, &PathOptions::default()).unwrap();
+        assert_eq!(path.first(), Some(&start));
+        assert_eq!(path.last(), Some(&goal));
+        assert!(path.contains(&LevelCoord { level: 1, pos: GridCoord{x: 5, y: 5} }));
+        for step in path.windows(2) {
+            if step[0].level != step[1].level {
+                assert_eq!(step[0].pos, step[1].pos, "Changed level off a ladder");
+                assert_eq!(levels[step[0].level].sample(&step[0].pos), TileValue::Ladder);
+            }
+        }
+
+        // A ladder with nothing on the other end doesn't go anywhere
+        let mut levels = levels;
+        levels[1].make_change(&GridCoord{x: 7, y: 5}, &TileValue::Empty);
+        assert_eq!(find_path_across_levels(&levels, &start, &goal, &PathOptions::default()), None);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::tile_world::{TileValue, crc32};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+// Colour a tile is drawn as in a rendered region, buildings are passed in for every tile of their footprint
+pub type TilePalette = fn(&TileValue) -> [u8; 4];
+
+// Close to how the game draws each tile, flat colours stand in for the textured ones
+pub fn default_palette(value: &TileValue) -> [u8; 4] {
+    match value {
+        TileValue::Empty => [96, 84, 72, 255],
+        TileValue::Rock => [140, 120, 100, 255],
+        TileValue::IronOre => [150, 80, 50, 255],
+        TileValue::IceDeposit => [170, 220, 240, 255],
+        TileValue::HabModule => [220, 220, 230, 255],
+        TileValue::Ladder => [190, 150, 90, 255],
+        TileValue::CrashedProbe => [200, 200, 210, 255],
+        TileValue::CrystalCluster => [170, 90, 220, 255],
+        TileValue::AbandonedModule => [110, 130, 110, 255],
+        TileValue::Generating => [30, 30, 36, 255],
+        // Past the edge of a bounded world
+        TileValue::Void => [0, 0, 0, 0],
+        _ => [255, 0, 255, 255]
+    }
+}
+
+// 8 bit RGBA pixels along rows from the top left
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RgbaBuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>
+}
+
+impl RgbaBuffer {
+    // Starts out fully transparent
+    pub fn new(width: u32, height: u32) -> RgbaBuffer {
+        RgbaBuffer { width, height, pixels: vec![0; width as usize * height as usize * 4] }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        let index = self.index_of(x, y)?;
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&self.pixels[index..(index + 4)]);
+        Some(pixel)
+    }
+
+    // Ignores pixels outside the buffer
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: [u8; 4]) {
+        if let Some(index) = self.index_of(x, y) {
+            self.pixels[index..(index + 4)].copy_from_slice(&color);
+        }
+    }
+
+    fn index_of(&self, x: u32, y: u32) -> Option<usize> {
+        if x >= self.width || y >= self.height { return None; }
+        Some((y as usize * self.width as usize + x as usize) * 4)
+    }
+
+    // Unfiltered rows squeezed with the same deflate the save files use, plenty for images of a map
+    pub fn write_png<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        if self.width == 0 || self.height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "PNG images need at least one pixel"));
+        }
+        writer.write_all(&PNG_SIGNATURE)?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        // 8 bits per channel, RGBA, deflate, standard filtering, not interlaced
+        header.extend_from_slice(&[8, 6, 0, 0, 0]);
+        write_chunk(&mut writer, b"IHDR", &header)?;
+
+        // Every row starts with its filter type, 0 leaves the row as it is
+        let row_bytes = self.width as usize * 4;
+        let mut rows = Vec::with_capacity((row_bytes + 1) * self.height as usize);
+        for row in self.pixels.chunks(row_bytes) {
+            rows.push(0);
+            rows.extend_from_slice(row);
+        }
+        write_chunk(&mut writer, b"IDAT", &miniz_oxide::deflate::compress_to_vec_zlib(&rows, 6))?;
+        write_chunk(&mut writer, b"IEND", &[])
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_png(&mut writer)?;
+        writer.flush()
+    }
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    // Covers the chunk type as well as its data
+    writer.write_all(&crc32(&[&kind[..], data].concat()).to_be_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::raster::RgbaBuffer;
+
+    fn read_u32(bytes: &[u8]) -> u32 {
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    #[test]
+    fn pngs_hold_every_pixel() {
+        let mut image = RgbaBuffer::new(3, 2);
+        image.set_pixel(0, 0, [255, 0, 0, 255]);
+        image.set_pixel(2, 1, [0, 0, 255, 128]);
+        image.set_pixel(5, 5, [1, 2, 3, 4]);
+        assert_eq!(image.pixel(2, 1), Some([0, 0, 255, 128]));
+        assert_eq!(image.pixel(3, 0), None);
+
+        let mut png = Vec::new();
+        image.write_png(&mut png).unwrap();
+        assert_eq!(&png[0..8], &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n']);
+
+        // Walk the chunks, IHDR first and IEND last with its well known CRC
+        let mut chunks = Vec::new();
+        let mut offset = 8;
+        while offset < png.len() {
+            let length = read_u32(&png[offset..]) as usize;
+            chunks.push((png[(offset + 4)..(offset + 8)].to_vec(), png[(offset + 8)..(offset + 8 + length)].to_vec(), read_u32(&png[(offset + 8 + length)..])));
+            offset += 12 + length;
+        }
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].0, b"IHDR");
+        assert_eq!((read_u32(&chunks[0].1), read_u32(&chunks[0].1[4..])), (3, 2));
+        assert_eq!(chunks[2].0, b"IEND");
+        assert_eq!(chunks[2].2, 0xAE42_6082);
+
+        let rows = miniz_oxide::inflate::decompress_to_vec_zlib(&chunks[1].1).unwrap();
+        assert_eq!(rows.len(), 2 * (1 + 3 * 4));
+        assert_eq!(&rows[0..5], &[0, 255, 0, 0, 255]);
+        assert_eq!(&rows[22..26], &[0, 0, 255, 128]);
+
+        assert!(RgbaBuffer::new(0, 4).write_png(&mut Vec::new()).is_err());
+    }
+}
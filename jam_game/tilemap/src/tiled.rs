@@ -0,0 +1,295 @@
+use crate::tile_world::{TileMap, TileValue, GridCoord, MapLayer, Blueprint, EditError};
+use serde::{Serialize, Deserialize};
+
+// Tiled keeps its flip and rotation flags in the top bits of every gid, none of them mean anything for our tiles
+const GID_FLAG_BITS: u32 = 0xF000_0000;
+// Size Tiled draws each tile at, only affects how the map looks in the editor
+const TILE_PIXELS: u32 = 32;
+
+const TERRAIN_LAYER_NAME: &str = "Terrain";
+const STRUCTURE_LAYER_NAME: &str = "Structures";
+
+// Which tile of the Tiled tileset stands for which TileValue
+// Ids are the tile's index within the tileset, the same number the Tiled tileset editor shows
+#[derive(Clone, Debug, PartialEq)]
+pub struct TiledMapping {
+    // Tileset file written into exported maps, relative to wherever the map gets saved
+    pub tileset_source: String,
+    ids: Vec<(TileValue, u32)>
+}
+
+impl Default for TiledMapping {
+    fn default() -> TiledMapping {
+        let mut mapping = TiledMapping::new("jam_game.tsj");
+        let values = [
+            TileValue::Empty, TileValue::Rock, TileValue::HabModule, TileValue::Ladder, TileValue::IronOre, TileValue::IceDeposit,
+            TileValue::CrashedProbe, TileValue::CrystalCluster, TileValue::AbandonedModule
+        ];
+        for (id, value) in values.iter().enumerate() {
+            mapping.set(*value, id as u32);
+        }
+        mapping
+    }
+}
+
+impl TiledMapping {
+    // Nothing mapped yet, use default for the tileset that ships with the game
+    pub fn new(tileset_source: &str) -> TiledMapping {
+        TiledMapping { tileset_source: tileset_source.to_string(), ids: Vec::new() }
+    }
+
+    // Replaces whatever either the value or the id was mapped to before, so the mapping always goes both ways
+    pub fn set(&mut self, value: TileValue, id: u32) {
+        self.ids.retain(|(mapped_value, mapped_id)| *mapped_value != value && *mapped_id != id);
+        self.ids.push((value, id));
+    }
+
+    pub fn id_of(&self, value: &TileValue) -> Option<u32> {
+        self.ids.iter().find(|(mapped_value, _)| mapped_value == value).map(|(_, id)| *id)
+    }
+
+    pub fn value_of(&self, id: u32) -> Option<TileValue> {
+        self.ids.iter().find(|(_, mapped_id)| *mapped_id == id).map(|(value, _)| *value)
+    }
+}
+
+#[derive(Debug)]
+pub enum TiledError {
+    Json(serde_json::Error),
+    // Infinite maps keep their tiles in chunks rather than one grid, turn it off under Map Properties before exporting
+    InfiniteMap,
+    // Only tile layers are read, so a map with none has nothing to import
+    NoTileLayers,
+    // A tile layer that isn't the same size as the map
+    LayerSize { layer: String },
+    // A tile that isn't from the first tileset or has nothing mapped to it
+    UnknownTile { layer: String, gid: u32 },
+    // Something in the map that the mapping has no tile for
+    UnmappedValue(TileValue),
+    Edit(EditError)
+}
+
+impl From<serde_json::Error> for TiledError {
+    fn from(error: serde_json::Error) -> TiledError {
+        TiledError::Json(error)
+    }
+}
+
+impl From<EditError> for TiledError {
+    fn from(error: EditError) -> TiledError {
+        TiledError::Edit(error)
+    }
+}
+
+// Only the parts of Tiled's JSON map format we read or write, anything else in the file is ignored
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct TiledMap {
+    #[serde(rename = "type")]
+    kind: String,
+    version: String,
+    orientation: String,
+    renderorder: String,
+    width: i64,
+    height: i64,
+    tilewidth: u32,
+    tileheight: u32,
+    infinite: bool,
+    nextlayerid: u32,
+    nextobjectid: u32,
+    layers: Vec<TiledLayer>,
+    tilesets: Vec<TiledTileset>
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct TiledLayer {
+    id: u32,
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    x: i64,
+    y: i64,
+    width: i64,
+    height: i64,
+    opacity: f32,
+    visible: bool,
+    // One gid per tile along rows from the top left, 0 where the layer is empty
+    // Only the CSV layer format comes out as a list, base64 layers fail to parse
+    data: Vec<u32>
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct TiledTileset {
+    firstgid: u32,
+    source: Option<String>
+}
+
+// A rectangle of the map as a Tiled JSON map, ground on one layer and buildings on another
+// Buildings are a single tile at their anchor, Tiled has no idea how big they are
+pub fn export_tiled(map: &TileMap, top_left: &GridCoord, size: &GridCoord, mapping: &TiledMapping) -> Result<String, TiledError> {
+    blueprint_to_tiled(&map.copy_rect(top_left, size), mapping)
+}
+
+// Reads a Tiled JSON map and pastes it with its top left at pos, as one undo step
+pub fn import_tiled(map: &mut TileMap, json: &str, pos: &GridCoord, mapping: &TiledMapping) -> Result<(), TiledError> {
+    let blueprint = blueprint_from_tiled(json, mapping)?;
+    map.paste(&blueprint, pos)?;
+    Ok(())
+}
+
+pub fn blueprint_to_tiled(blueprint: &Blueprint, mapping: &TiledMapping) -> Result<String, TiledError> {
+    let size = blueprint.size();
+    let first_gid = 1;
+    let gid_of = |value: &TileValue| mapping.id_of(value).map(|id| first_gid + id).ok_or(TiledError::UnmappedValue(*value));
+
+    let mut terrain = Vec::with_capacity((size.x * size.y) as usize);
+    for y in 0..size.y {
+        for x in 0..size.x {
+            // Anything that couldn't be copied is left as a hole in the layer
+            terrain.push(match blueprint.terrain_at(&GridCoord{x, y}) {
+                Some(value) => gid_of(&value)?,
+                None => 0
+            });
+        }
+    }
+    let mut structures = vec![0; (size.x * size.y) as usize];
+    for (offset, value) in blueprint.structures().iter() {
+        if let Some(gid) = structures.get_mut((offset.y * size.x + offset.x) as usize) {
+            *gid = gid_of(value)?;
+        }
+    }
+
+    let layer = |id: u32, name: &str, data: Vec<u32>| TiledLayer {
+        id, name: name.to_string(), kind: "tilelayer".to_string(), x: 0, y: 0, width: size.x, height: size.y, opacity: 1.0, visible: true, data
+    };
+    let tiled_map = TiledMap {
+        kind: "map".to_string(),
+        version: "1.10".to_string(),
+        orientation: "orthogonal".to_string(),
+        renderorder: "right-down".to_string(),
+        width: size.x,
+        height: size.y,
+        tilewidth: TILE_PIXELS,
+        tileheight: TILE_PIXELS,
+        infinite: false,
+        nextlayerid: 3,
+        nextobjectid: 1,
+        layers: vec![layer(1, TERRAIN_LAYER_NAME, terrain), layer(2, STRUCTURE_LAYER_NAME, structures)],
+        tilesets: vec![TiledTileset { firstgid: first_gid, source: Some(mapping.tileset_source.clone()) }]
+    };
+    Ok(serde_json::to_string_pretty(&tiled_map)?)
+}
+
+// Tile layers are read bottom to top and can be named anything, ground from a higher layer replaces ground from a lower one
+// Buildings go wherever their tile is, whichever layer it's on, and are anchored on that tile
+// Object layers and image layers are skipped, so reference art can stay in the file
+pub fn blueprint_from_tiled(json: &str, mapping: &TiledMapping) -> Result<Blueprint, TiledError> {
+    let tiled_map: TiledMap = serde_json::from_str(json)?;
+    if tiled_map.infinite {
+        return Err(TiledError::InfiniteMap);
+    }
+    let size = GridCoord{x: tiled_map.width.max(0), y: tiled_map.height.max(0)};
+    // Every tile is looked up in the first tileset, its firstgid is usually 1
+    let first_gid = tiled_map.tilesets.first().map(|tileset| tileset.firstgid).unwrap_or(1);
+
+    // Sizes come straight from the file, so they're checked against every layer's data before anything is allocated for them
+    let tile_count = size.x.checked_mul(size.y).ok_or(TiledError::LayerSize { layer: String::new() })? as usize;
+    let tile_layers: Vec<&TiledLayer> = tiled_map.layers.iter().filter(|layer| layer.kind == "tilelayer").collect();
+    if tile_layers.is_empty() {
+        return Err(TiledError::NoTileLayers);
+    }
+    if let Some(layer) = tile_layers.iter().find(|layer| layer.width != size.x || layer.height != size.y || layer.data.len() != tile_count) {
+        return Err(TiledError::LayerSize { layer: layer.name.clone() });
+    }
+
+    let mut terrain: Vec<Option<TileValue>> = vec![None; tile_count];
+    let mut structures: Vec<(GridCoord, TileValue)> = Vec::new();
+    for layer in tile_layers.iter() {
+        for (index, gid) in layer.data.iter().enumerate() {
+            let gid = gid & !GID_FLAG_BITS;
+            if gid == 0 {
+                continue;
+            }
+            let value = gid.checked_sub(first_gid).and_then(|id| mapping.value_of(id))
+                .ok_or_else(|| TiledError::UnknownTile { layer: layer.name.clone(), gid })?;
+            if MapLayer::for_value(&value) == MapLayer::Structure {
+                structures.push((GridCoord{x: index as i64 % size.x, y: index as i64 / size.x}, value));
+            }
+            else {
+                terrain[index] = Some(value);
+            }
+        }
+    }
+
+    // Only fails if the layer sizes were wrong, and those were all checked above
+    Blueprint::from_parts(size, terrain, structures).map_err(|_| TiledError::LayerSize { layer: String::new() })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tile_world::{TileMap, TileValue, GridCoord};
+    use crate::tiled::{export_tiled, import_tiled, blueprint_from_tiled, TiledMapping, TiledError};
+
+    #[test]
+    fn exported_maps_import_back_the_same() {
+        let mut map = TileMap::new();
+        map.set_area(&GridCoord{x: 0, y: 0}, &GridCoord{x: 8, y: 6}, TileValue::Empty);
+        map.set_area(&GridCoord{x: 6, y: 0}, &GridCoord{x: 2, y: 6}, TileValue::Rock);
+        map.make_change(&GridCoord{x: 7, y: 3}, &TileValue::IronOre);
+        map.make_change(&GridCoord{x: 2, y: 2}, &TileValue::HabModule);
+        map.make_change(&GridCoord{x: 4, y: 4}, &TileValue::Ladder);
+
+        let json = export_tiled(&map, &GridCoord{x: 0, y: 0}, &GridCoord{x: 8, y: 6}, &TiledMapping::default()).unwrap();
+        let mut copy = TileMap::new();
+        import_tiled(&mut copy, &json, &GridCoord{x: 20, y: 10}, &TiledMapping::default()).unwrap();
+
+        for y in 0..6 {
+            for x in 0..8 {
+                let expected = match map.sample(&GridCoord{x, y}) {
+                    TileValue::Subtile(anchor) => TileValue::Subtile(GridCoord{x: anchor.x + 20, y: anchor.y + 10}),
+                    value => value
+                };
+                assert_eq!(copy.sample(&GridCoord{x: x + 20, y: y + 10}), expected, "Tile {}, {} differs", x, y);
+            }
+        }
+        assert!(copy.validate_integrity().is_ok());
+    }
+
+    #[test]
+    fn hand_made_maps_go_through_the_mapping() {
+        let mut mapping = TiledMapping::new("scenario.tsj");
+        mapping.set(TileValue::Empty, 4);
+        mapping.set(TileValue::Rock, 7);
+        mapping.set(TileValue::HabModule, 12);
+
+        // Tileset starting at gid 10, a flipped rock tile, a hab module on a layer of its own and an object layer that's skipped
+        let json = r#"{
+            "width": 4, "height": 3, "infinite": false,
+            "tilesets": [{"firstgid": 10, "source": "scenario.tsj"}],
+            "layers": [
+                {"type": "tilelayer", "name": "Ground", "width": 4, "height": 3, "data": [14, 14, 14, 2147483665, 14, 14, 14, 17, 14, 14, 14, 17]},
+                {"type": "tilelayer", "name": "Buildings", "width": 4, "height": 3, "data": [0, 0, 0, 0, 0, 22, 0, 0, 0, 0, 0, 0]},
+                {"type": "objectgroup", "name": "Notes", "objects": []}
+            ]
+        }"#;
+        let blueprint = blueprint_from_tiled(json, &mapping).unwrap();
+        assert_eq!(blueprint.size(), GridCoord{x: 4, y: 3});
+        assert_eq!(blueprint.terrain_at(&GridCoord{x: 3, y: 0}), Some(TileValue::Rock));
+        assert_eq!(blueprint.terrain_at(&GridCoord{x: 0, y: 2}), Some(TileValue::Empty));
+        assert_eq!(blueprint.structures(), &[(GridCoord{x: 1, y: 1}, TileValue::HabModule)]);
+
+        // The default mapping stops short of id 12
+        match blueprint_from_tiled(json, &TiledMapping::default()) {
+            Err(TiledError::UnknownTile { layer, gid: 22 }) => assert_eq!(layer, "Buildings"),
+            other => panic!("Expected an unknown tile, got {:?}", other)
+        }
+        assert!(matches!(blueprint_from_tiled(r#"{"width": 2, "height": 2, "infinite": true}"#, &mapping), Err(TiledError::InfiniteMap)));
+        // Sizes too big to allocate are turned away instead of overflowing or running out of memory
+        assert!(matches!(blueprint_from_tiled(r#"{"width": 4000000000, "height": 4000000000, "layers": []}"#, &mapping), Err(TiledError::LayerSize { .. })));
+        assert!(matches!(blueprint_from_tiled(r#"{"width": 100000, "height": 100000, "layers": [{"type": "objectgroup", "name": "Notes"}]}"#, &mapping), Err(TiledError::NoTileLayers)));
+        let short_layer = r#"{"width": 100000, "height": 100000, "layers": [{"type": "tilelayer", "name": "Ground", "width": 100000, "height": 100000, "data": [14]}]}"#;
+        assert!(matches!(blueprint_from_tiled(short_layer, &mapping), Err(TiledError::LayerSize { layer }) if layer == "Ground"));
+    }
+}